@@ -1,9 +1,39 @@
+-- マイグレーションで作成したオブジェクトを全て削除する
+-- schema_migrationsも削除する為、次回の起動時に全てのマイグレーションを再適用する
+
 -- インデックスを削除
 DROP INDEX IF EXISTS idx_packets_node_timestamp_included;
 DROP INDEX IF EXISTS idx_packets_recent;
+DROP INDEX IF EXISTS idx_processed_packets_node_packet;
+DROP INDEX IF EXISTS idx_processed_packets_node_processed_at;
+DROP INDEX IF EXISTS idx_firewall_stats_node_timestamp;
+DROP INDEX IF EXISTS idx_firewall_rules_node;
+DROP INDEX IF EXISTS idx_firewall_blocks_expires_at;
+DROP INDEX IF EXISTS idx_idps_alerts_node_timestamp;
+DROP INDEX IF EXISTS idx_idps_alerts_category_timestamp;
+DROP INDEX IF EXISTS idx_dns_log_node_timestamp;
+DROP INDEX IF EXISTS idx_dns_log_query;
+DROP INDEX IF EXISTS idx_http_log_node_timestamp;
+DROP INDEX IF EXISTS idx_http_log_host;
+DROP INDEX IF EXISTS idx_flows_node_start_time;
+DROP INDEX IF EXISTS idx_flows_src_ip_start_time;
 
 -- 外部キー制約の為
 DROP TABLE IF EXISTS packet_details CASCADE;
 
 -- ハイパーテーブルの削除（packetsテーブルも同時に削除される）
 DROP TABLE IF EXISTS packets CASCADE;
+DROP TABLE IF EXISTS processed_packets CASCADE;
+DROP TABLE IF EXISTS firewall_stats CASCADE;
+DROP TABLE IF EXISTS firewall_rules CASCADE;
+DROP TABLE IF EXISTS firewall_rule_version CASCADE;
+DROP TABLE IF EXISTS firewall_blocks CASCADE;
+DROP TABLE IF EXISTS idps_alerts CASCADE;
+DROP TABLE IF EXISTS dns_log CASCADE;
+DROP TABLE IF EXISTS http_log CASCADE;
+DROP TABLE IF EXISTS flows CASCADE;
+DROP TABLE IF EXISTS anomaly_baselines CASCADE;
+DROP FUNCTION IF EXISTS bump_firewall_rule_version();
+
+-- 適用履歴を削除
+DROP TABLE IF EXISTS schema_migrations;
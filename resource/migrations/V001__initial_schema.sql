@@ -0,0 +1,199 @@
+-- 初期スキーマ
+-- 手動で作成したデータベースにも適用できるよう、既存のオブジェクトがあれば作成しない
+
+-- メインテーブル作成
+CREATE TABLE IF NOT EXISTS packets
+(
+    id          BIGINT GENERATED BY DEFAULT AS IDENTITY (START WITH 1),
+    timestamp   TIMESTAMPTZ NOT NULL,
+    node_id     SMALLINT    NOT NULL,
+    src_mac     MACADDR     NOT NULL,
+    dst_mac     MACADDR     NOT NULL,
+    ether_type  INTEGER     NOT NULL,
+    ip_protocol INTEGER     NOT NULL,
+    src_ip      INET        NOT NULL,
+    dst_ip      INET        NOT NULL,
+    src_port    INTEGER     NOT NULL,
+    dst_port    INTEGER     NOT NULL,
+    raw_packet  BYTEA       NOT NULL,
+    CONSTRAINT packets_pkey PRIMARY KEY (id, timestamp)
+);
+
+CREATE TABLE IF NOT EXISTS processed_packets (
+    packet_id BIGINT,
+    node_id SMALLINT,
+    processed_at TIMESTAMPTZ DEFAULT NOW(),
+    PRIMARY KEY (packet_id, node_id)
+);
+
+-- ファイアウォールのルール毎の統計 (カウンタはノード起動時からの累積値)
+CREATE TABLE IF NOT EXISTS firewall_stats
+(
+    timestamp TIMESTAMPTZ NOT NULL,
+    node_id   SMALLINT    NOT NULL,
+    direction TEXT        NOT NULL,
+    rule      TEXT        NOT NULL,
+    priority  SMALLINT,
+    packets   BIGINT      NOT NULL,
+    bytes     BIGINT      NOT NULL
+);
+
+-- 全ノードで共有するファイアウォールルール (node_idがNULLの場合は全ノード共通)
+-- direction: ingress(データベース→LAN), egress(LAN→データベース)
+CREATE TABLE IF NOT EXISTS firewall_rules
+(
+    id           BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    node_id      SMALLINT,
+    direction    TEXT        NOT NULL DEFAULT 'egress' CHECK (direction IN ('ingress', 'egress')),
+    filter_type  TEXT        NOT NULL,
+    filter_value TEXT        NOT NULL,
+    priority     SMALLINT    NOT NULL CHECK (priority BETWEEN 1 AND 255),
+    enabled      BOOLEAN     NOT NULL DEFAULT TRUE,
+    updated_at   TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- ルールの変更を各ノードに通知する為のバージョンカウンタ
+CREATE TABLE IF NOT EXISTS firewall_rule_version
+(
+    id      BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    version BIGINT  NOT NULL DEFAULT 0
+);
+INSERT INTO firewall_rule_version (id, version) VALUES (TRUE, 0) ON CONFLICT DO NOTHING;
+
+CREATE OR REPLACE FUNCTION bump_firewall_rule_version() RETURNS TRIGGER AS
+$$
+BEGIN
+    UPDATE firewall_rule_version SET version = version + 1;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS firewall_rules_version_trigger ON firewall_rules;
+CREATE TRIGGER firewall_rules_version_trigger
+    AFTER INSERT OR UPDATE OR DELETE OR TRUNCATE ON firewall_rules
+    FOR EACH STATEMENT
+EXECUTE FUNCTION bump_firewall_rule_version();
+
+CREATE INDEX IF NOT EXISTS idx_firewall_rules_node ON firewall_rules (node_id) WHERE enabled;
+
+-- 検知器が追加した期限付きの遮断ルール (sharedがTRUEの場合は全ノード、FALSEの場合は追加したノードのみに適用する)
+-- 期限前に行を削除すると各ノードのファイアウォールからも削除される (手動で追加する場合はorigin_node_idをNULLとする)
+CREATE TABLE IF NOT EXISTS firewall_blocks
+(
+    id             BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    origin_node_id SMALLINT,
+    shared         BOOLEAN     NOT NULL DEFAULT TRUE,
+    direction      TEXT        NOT NULL DEFAULT 'egress' CHECK (direction IN ('ingress', 'egress')),
+    filter_type    TEXT        NOT NULL,
+    filter_value   TEXT        NOT NULL,
+    reason         TEXT        NOT NULL DEFAULT '',
+    created_at     TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at     TIMESTAMPTZ NOT NULL,
+    UNIQUE (origin_node_id, direction, filter_type, filter_value)
+);
+
+CREATE INDEX IF NOT EXISTS idx_firewall_blocks_expires_at ON firewall_blocks (expires_at);
+
+-- IDPSが検知したアラート (packet_idは契機となったパケットが保存された場合のみ)
+CREATE TABLE IF NOT EXISTS idps_alerts
+(
+    timestamp    TIMESTAMPTZ NOT NULL,
+    node_id      SMALLINT    NOT NULL,
+    severity     TEXT        NOT NULL CHECK (severity IN ('low', 'medium', 'high')),
+    category     TEXT        NOT NULL,
+    signature_id BIGINT,
+    message      TEXT        NOT NULL,
+    src_ip       INET        NOT NULL,
+    dst_ip       INET        NOT NULL,
+    src_port     INTEGER     NOT NULL,
+    dst_port     INTEGER     NOT NULL,
+    ip_protocol  INTEGER     NOT NULL,
+    packet_id    BIGINT
+);
+
+-- 解析したDNSの問い合わせと応答 (DNS_LOG_QUERIES=trueの場合のみ書き込む)
+CREATE TABLE IF NOT EXISTS dns_log
+(
+    timestamp      TIMESTAMPTZ NOT NULL,
+    node_id        SMALLINT    NOT NULL,
+    src_ip         INET        NOT NULL,
+    dst_ip         INET        NOT NULL,
+    src_port       INTEGER     NOT NULL,
+    dst_port       INTEGER     NOT NULL,
+    transaction_id INTEGER     NOT NULL,
+    query          TEXT        NOT NULL,
+    query_type     TEXT        NOT NULL,
+    response       BOOLEAN     NOT NULL,
+    rcode          SMALLINT,
+    answers        TEXT[]      NOT NULL DEFAULT '{}'
+);
+
+CREATE TABLE IF NOT EXISTS http_log
+(
+    timestamp   TIMESTAMPTZ NOT NULL,
+    node_id     SMALLINT    NOT NULL,
+    src_ip      INET        NOT NULL,
+    dst_ip      INET        NOT NULL,
+    src_port    INTEGER     NOT NULL,
+    dst_port    INTEGER     NOT NULL,
+    response    BOOLEAN     NOT NULL,
+    version     TEXT        NOT NULL,
+    method      TEXT,
+    host        TEXT,
+    uri         TEXT,
+    user_agent  TEXT,
+    status_code SMALLINT
+);
+
+-- 5-tuple毎に集計した双方向のフロー (src側は最初に観測したパケットの送信元)
+-- end_reason: idle_timeout, active_timeout(継続中のフローの途中経過), end_of_flow(FIN/RST), lack_of_resources
+CREATE TABLE IF NOT EXISTS flows
+(
+    start_time    TIMESTAMPTZ NOT NULL,
+    end_time      TIMESTAMPTZ NOT NULL,
+    node_id       SMALLINT    NOT NULL,
+    src_ip        INET        NOT NULL,
+    dst_ip        INET        NOT NULL,
+    src_port      INTEGER     NOT NULL,
+    dst_port      INTEGER     NOT NULL,
+    ip_protocol   INTEGER     NOT NULL,
+    packets       BIGINT      NOT NULL,
+    bytes         BIGINT      NOT NULL,
+    reply_packets BIGINT      NOT NULL,
+    reply_bytes   BIGINT      NOT NULL,
+    tcp_flags     SMALLINT    NOT NULL,
+    end_reason    TEXT        NOT NULL
+);
+
+-- 異常検知で学習したホスト毎のベースライン (再起動後も学習結果を引き継ぐ)
+-- *_ratioはTCP, UDP, ICMP, その他のパケットの割合の指数移動平均
+CREATE TABLE IF NOT EXISTS anomaly_baselines
+(
+    node_id                  SMALLINT         NOT NULL,
+    host                     INET             NOT NULL,
+    samples                  BIGINT           NOT NULL,
+    packets_per_sec_mean     DOUBLE PRECISION NOT NULL,
+    packets_per_sec_variance DOUBLE PRECISION NOT NULL,
+    bytes_per_sec_mean       DOUBLE PRECISION NOT NULL,
+    bytes_per_sec_variance   DOUBLE PRECISION NOT NULL,
+    peers_mean               DOUBLE PRECISION NOT NULL,
+    peers_variance           DOUBLE PRECISION NOT NULL,
+    tcp_ratio                DOUBLE PRECISION NOT NULL,
+    udp_ratio                DOUBLE PRECISION NOT NULL,
+    icmp_ratio               DOUBLE PRECISION NOT NULL,
+    other_ratio              DOUBLE PRECISION NOT NULL,
+    updated_at               TIMESTAMPTZ      NOT NULL,
+    PRIMARY KEY (node_id, host)
+);
+
+-- 主要な検索パターン用のインデックス
+CREATE INDEX IF NOT EXISTS idx_packets_node_timestamp_included ON packets (node_id, timestamp DESC) INCLUDE (raw_packet);
+CREATE INDEX IF NOT EXISTS idx_firewall_stats_node_timestamp ON firewall_stats (node_id, timestamp DESC);
+CREATE INDEX IF NOT EXISTS idx_idps_alerts_node_timestamp ON idps_alerts (node_id, timestamp DESC);
+CREATE INDEX IF NOT EXISTS idx_idps_alerts_category_timestamp ON idps_alerts (category, timestamp DESC);
+CREATE INDEX IF NOT EXISTS idx_dns_log_node_timestamp ON dns_log (node_id, timestamp DESC);
+CREATE INDEX IF NOT EXISTS idx_dns_log_query ON dns_log (query);
+CREATE INDEX IF NOT EXISTS idx_http_log_node_timestamp ON http_log (node_id, timestamp DESC);
+CREATE INDEX IF NOT EXISTS idx_http_log_host ON http_log (host);
+CREATE INDEX IF NOT EXISTS idx_flows_node_start_time ON flows (node_id, start_time DESC);
+CREATE INDEX IF NOT EXISTS idx_flows_src_ip_start_time ON flows (src_ip, start_time DESC);
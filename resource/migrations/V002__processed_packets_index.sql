@@ -0,0 +1,2 @@
+-- 他ノードのパケットを取得する際、ノード毎に処理済みのパケットを除外する為のインデックス
+CREATE INDEX IF NOT EXISTS idx_processed_packets_node_packet ON processed_packets (node_id, packet_id);
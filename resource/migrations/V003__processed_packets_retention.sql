@@ -0,0 +1,2 @@
+-- ノード毎の最新の処理時刻を求め、全ノードが処理し終えた行を削除する為のインデックス
+CREATE INDEX IF NOT EXISTS idx_processed_packets_node_processed_at ON processed_packets (node_id, processed_at DESC);
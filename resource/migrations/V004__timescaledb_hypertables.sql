@@ -0,0 +1,32 @@
+-- ハイパーテーブルへの変換 (TimescaleDBがない場合は通常のテーブルのまま使用する)
+-- 手動で作成したデータベースで既にハイパーテーブルである場合は何も変更しない
+DO
+$$
+BEGIN
+    IF EXISTS (SELECT 1 FROM pg_extension WHERE extname = 'timescaledb') THEN
+        PERFORM create_hypertable('packets', 'timestamp', chunk_time_interval => INTERVAL '1 hour', if_not_exists => TRUE);
+        PERFORM create_hypertable('firewall_stats', 'timestamp', chunk_time_interval => INTERVAL '1 day', if_not_exists => TRUE);
+        PERFORM create_hypertable('idps_alerts', 'timestamp', chunk_time_interval => INTERVAL '1 day', if_not_exists => TRUE);
+        PERFORM create_hypertable('dns_log', 'timestamp', chunk_time_interval => INTERVAL '1 day', if_not_exists => TRUE);
+        PERFORM create_hypertable('http_log', 'timestamp', chunk_time_interval => INTERVAL '1 day', if_not_exists => TRUE);
+        PERFORM create_hypertable('flows', 'start_time', chunk_time_interval => INTERVAL '1 day', if_not_exists => TRUE);
+    END IF;
+END
+$$;
+
+-- 圧縮設定とポリシー（オプション、TimescaleDBがある場合のみ）
+-- 圧縮済みのチャンクがある場合は設定を変更できない為、未設定の場合のみ設定する
+DO
+$$
+BEGIN
+    IF EXISTS (SELECT 1 FROM pg_extension WHERE extname = 'timescaledb') THEN
+        IF NOT EXISTS (SELECT 1 FROM timescaledb_information.hypertables WHERE hypertable_name = 'packets' AND compression_enabled) THEN
+            ALTER TABLE packets SET (timescaledb.compress, timescaledb.compress_segmentby = 'node_id', timescaledb.compress_orderby = 'timestamp DESC');
+        END IF;
+        PERFORM add_compression_policy('packets', INTERVAL '7 days', if_not_exists => TRUE);
+    END IF;
+END
+$$;
+
+-- ハイパーテーブルへの変換で作成されるtimestampのみのインデックスを削除
+DROP INDEX IF EXISTS packets_timestamp_idx;
@@ -1,5 +1,27 @@
 use crate::config::error::ConfigError;
 use dotenv::dotenv;
+use std::fmt::Display;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrationMode {
+    // 起動時に未適用のマイグレーションを適用する
+    Apply,
+    // 適用せず、未適用のマイグレーションがあれば起動しない
+    Verify,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageBackend {
+    // TimescaleDBのハイパーテーブルへ保存する (保持期間はTimescaleDBの保持ポリシーで管理する)
+    TimescaleDb,
+    // TimescaleDBのない素のPostgreSQLへ保存する
+    Postgres,
+    // パケットのみプロセス内のメモリに保存する (ノード間でパケットを共有しない)
+    // ルールやログなどは引き続きデータベースを使用する
+    MemoryPackets,
+}
 
 #[derive(Debug, Clone)]
 pub struct DatabaseConfig {
@@ -8,6 +30,8 @@ pub struct DatabaseConfig {
     pub user: String,
     pub password: String,
     pub database: String,
+    pub migration_mode: MigrationMode,
+    pub storage_backend: StorageBackend,
 }
 
 #[derive(Debug, Clone)]
@@ -23,6 +47,353 @@ pub struct LoggerConfig {
     pub idps_log_mode: String,
     pub normal_path_style: String,
     pub idps_path_style: String,
+    // text(従来の形式) または eve(EVE形式のJSON Lines)
+    pub idps_log_format: String,
+    // file, stdout, unix_socket
+    pub eve_output: String,
+    pub eve_file: String,
+    pub eve_socket_path: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct FirewallConfig {
+    pub stats_interval: u64,
+    pub sync_interval: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct RetentionConfig {
+    // packetsテーブルのデータを保持する日数 (未設定の場合は削除しない)
+    pub packets_days: Option<i32>,
+    // processed_packetsテーブルを整理する間隔(秒)
+    pub interval: u64,
+    // 全ノードが処理し終えた後もprocessed_packetsの行を残す時間(秒)
+    pub processed_grace: u64,
+    // この時間(秒)パケットを処理していないノードは、処理し終えるのを待たない
+    pub node_timeout: u64,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            packets_days: None,
+            interval: 60,
+            processed_grace: 300,
+            node_timeout: 3600,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitAction {
+    // 制限を超えたパケットを全て破棄する
+    Drop,
+    // 制限を超えたパケットのうちN個に1個だけを通過させる
+    Sample(u64),
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+    pub per_source_pps: Option<f64>,
+    pub per_flow_pps: Option<f64>,
+    pub per_node_pps: Option<f64>,
+    pub burst_seconds: f64,
+    pub action: RateLimitAction,
+    // 制限毎に保持するバケット数の上限 (超えた分は共有のバケットで制限する)
+    pub max_buckets: usize,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            per_source_pps: None,
+            per_flow_pps: None,
+            per_node_pps: None,
+            burst_seconds: 1.0,
+            action: RateLimitAction::Drop,
+            max_buckets: 65536,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumAction {
+    // 検証結果を使用しない
+    Ignore,
+    // 不一致の件数のみを数える
+    Count,
+    // 件数を数え、IDPSログへ出力する
+    Log,
+    // 件数を数え、IDPSログへ出力した上でパケットを破棄する
+    Drop,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChecksumConfig {
+    pub action: ChecksumAction,
+    // 自身のMACアドレスから送信されたフレームはNICのオフロードにより未計算の可能性がある為、不一致を無視する
+    pub offload_aware: bool,
+}
+
+impl Default for ChecksumConfig {
+    fn default() -> Self {
+        Self {
+            action: ChecksumAction::Count,
+            offload_aware: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamOverlapPolicy {
+    // 先に受信したデータを優先する
+    First,
+    // 後から受信したデータで上書きする
+    Last,
+}
+
+#[derive(Debug, Clone)]
+pub struct StreamConfig {
+    // 重複するセグメントのどちらのデータを採用するか
+    pub overlap_policy: StreamOverlapPolicy,
+    // 全フローで保持するデータの合計(バイト)
+    pub memcap: usize,
+    // 追跡するフロー数の上限
+    pub max_flows: usize,
+    // 通信のないフローを破棄するまでの時間(秒)
+    pub timeout: u64,
+}
+
+impl Default for StreamConfig {
+    fn default() -> Self {
+        Self {
+            overlap_policy: StreamOverlapPolicy::First,
+            memcap: 64 * 1024 * 1024,
+            max_flows: 65536,
+            timeout: 300,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FlowConfig {
+    // 5-tuple毎に集計したフローをflowsテーブルへ書き込むか
+    pub enabled: bool,
+    // 通信のないフローを終了とみなすまでの時間(秒)
+    pub idle_timeout: u64,
+    // 継続中のフローを途中で書き出す間隔(秒)
+    pub active_timeout: u64,
+    // 集計するフロー数の上限
+    pub max_flows: usize,
+    // IPFIXで送信するコレクタのアドレス (host:port、未設定の場合は送信しない)
+    pub ipfix_collector: Option<String>,
+}
+
+impl Default for FlowConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            idle_timeout: 60,
+            active_timeout: 1800,
+            max_flows: 65536,
+            ipfix_collector: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PortScanConfig {
+    // 宛先を集計する時間窓(秒)
+    pub window: u64,
+    // 1つの宛先ホストに対する異なるポート数の閾値 (垂直スキャン)
+    pub port_threshold: usize,
+    // 同じポートに対する異なる宛先ホスト数の閾値 (水平スキャン)
+    pub host_threshold: usize,
+}
+
+impl Default for PortScanConfig {
+    fn default() -> Self {
+        Self {
+            window: 60,
+            port_threshold: 20,
+            host_threshold: 20,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SynFloodConfig {
+    // 未完了のハンドシェイクを保持する時間(秒)
+    pub window: u64,
+    // 1つの宛先に対する未完了のハンドシェイク数の閾値
+    pub half_open_threshold: usize,
+    // 検知時にファイアウォールへ一時的な遮断ルールを追加するか
+    pub block: bool,
+    // 一時的な遮断ルールの有効期間(秒)
+    pub block_duration: u64,
+}
+
+impl Default for SynFloodConfig {
+    fn default() -> Self {
+        Self {
+            window: 10,
+            half_open_threshold: 100,
+            block: false,
+            block_duration: 300,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ArpWatchConfig {
+    // Gratuitous ARPを集計する時間窓(秒)と、MACアドレス毎の閾値
+    pub gratuitous_window: u64,
+    pub gratuitous_threshold: usize,
+    // 1つのMACアドレスが名乗るIPアドレス数の閾値
+    pub max_ips_per_mac: usize,
+}
+
+impl Default for ArpWatchConfig {
+    fn default() -> Self {
+        Self {
+            gratuitous_window: 10,
+            gratuitous_threshold: 10,
+            max_ips_per_mac: 16,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DnsConfig {
+    // 解析した問い合わせをdns_logテーブルへ書き込むか
+    pub log_queries: bool,
+    // TXT/NULLの問い合わせとNXDOMAINの応答を集計する時間窓(秒)
+    pub window: u64,
+    // トンネリングとみなすラベルの長さとエントロピー(ビット)の閾値
+    pub tunnel_label_length: usize,
+    pub tunnel_entropy: f64,
+    // 送信元毎のTXT/NULLの問い合わせ数の閾値
+    pub txt_threshold: usize,
+    // DGAとみなすドメインのラベルのエントロピー(ビット)の閾値
+    pub dga_entropy: f64,
+    // 宛先毎のNXDOMAINの応答数の閾値
+    pub nxdomain_threshold: usize,
+}
+
+impl Default for DnsConfig {
+    fn default() -> Self {
+        Self {
+            log_queries: false,
+            window: 60,
+            tunnel_label_length: 40,
+            tunnel_entropy: 4.0,
+            txt_threshold: 50,
+            dga_entropy: 3.0,
+            nxdomain_threshold: 20,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SignatureConfig {
+    // ルールファイル、または*.rulesを含むディレクトリ (未設定の場合はシグネチャ検知を行わない)
+    pub rules_path: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TlsConfig {
+    // 既知の不正なJA3/JA4のフィンガープリントを列挙したファイル (未設定の場合は照合しない)
+    pub fingerprints_path: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct HttpConfig {
+    // 解析したリクエストとレスポンスをhttp_logテーブルへ書き込むか
+    pub log_transactions: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct AnomalyConfig {
+    // ホスト毎の通信量のベースラインを学習し、逸脱を検知するか
+    pub enabled: bool,
+    // 通信量を集計する間隔(秒)
+    pub interval: u64,
+    // 指数移動平均の平滑化係数 (0~1、大きいほど直近の値を重視する)
+    pub alpha: f64,
+    // アラートを出力する前に学習する集計間隔の数
+    pub warmup_intervals: u64,
+    // 平均からの乖離が標準偏差の何倍を超えた場合に検知するか
+    pub threshold: f64,
+    // プロトコル構成の変化 (全変動距離、0~1) の閾値
+    pub mix_threshold: f64,
+    // ベースラインを保持するホスト数の上限 (超えた場合は最も長く通信のないホストを破棄する)
+    pub max_hosts: usize,
+}
+
+impl Default for AnomalyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval: 60,
+            alpha: 0.1,
+            warmup_intervals: 30,
+            threshold: 4.0,
+            mix_threshold: 0.5,
+            max_hosts: 4096,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PreventionConfig {
+    // 検知時に送信元を遮断する一時的なルールをファイアウォールへ追加するか
+    pub auto_block: bool,
+    // 一時的な遮断ルールの有効期間(秒)
+    pub block_duration: u64,
+    // 遮断の対象とするアラートの最小の重大度 (low, medium, high)
+    pub min_severity: String,
+    // 遮断ルールを全ノードで共有するか (falseの場合は検知したノードのみに適用する)
+    pub publish: bool,
+    // 遮断しない送信元 (ゲートウェイなど)
+    pub exclude: Vec<IpAddr>,
+}
+
+impl Default for PreventionConfig {
+    fn default() -> Self {
+        Self {
+            auto_block: false,
+            block_duration: 600,
+            min_severity: "high".to_string(),
+            publish: false,
+            exclude: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AlertConfig {
+    // 検知したアラートをidps_alertsテーブルへ書き込むか
+    pub store: bool,
+}
+
+impl Default for AlertConfig {
+    fn default() -> Self {
+        Self { store: true }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct IdpsConfig {
+    pub port_scan: PortScanConfig,
+    pub syn_flood: SynFloodConfig,
+    pub arp_watch: ArpWatchConfig,
+    pub signature: SignatureConfig,
+    pub dns: DnsConfig,
+    pub tls: TlsConfig,
+    pub http: HttpConfig,
+    pub anomaly: AnomalyConfig,
+    pub prevention: PreventionConfig,
+    pub alert: AlertConfig,
 }
 
 #[derive(Debug, Clone)]
@@ -31,6 +402,13 @@ pub struct AppConfig {
     pub database: DatabaseConfig,
     pub network: NetworkConfig,
     pub logger_config: LoggerConfig,
+    pub firewall: FirewallConfig,
+    pub rate_limit: RateLimitConfig,
+    pub checksum: ChecksumConfig,
+    pub stream: StreamConfig,
+    pub flow: FlowConfig,
+    pub retention: RetentionConfig,
+    pub idps: IdpsConfig,
 }
 
 impl AppConfig {
@@ -51,6 +429,22 @@ impl AppConfig {
                 user: get_env_var("TIMESCALE_DB_USER")?,
                 password: get_env_var("TIMESCALE_DB_PASSWORD")?,
                 database: get_env_var("TIMESCALE_DB_DATABASE")?,
+                migration_mode: match dotenv::var("MIGRATION_MODE").unwrap_or_default().to_lowercase().as_str() {
+                    "verify" => MigrationMode::Verify,
+                    _ => MigrationMode::Apply,
+                },
+                storage_backend: match dotenv::var("STORAGE_BACKEND").unwrap_or_default().to_lowercase().as_str() {
+                    "postgres" | "postgresql" => StorageBackend::Postgres,
+                    "memory_packets" => StorageBackend::MemoryPackets,
+                    // データベースなしで動作するバックエンドはない
+                    "memory" => {
+                        return Err(ConfigError::EnvVarParseError(
+                            "STORAGE_BACKEND: memoryは指定できません (パケット以外はデータベースへ保存する為、パケットのみメモリに保存する場合はmemory_packetsを指定してください)"
+                                .to_string(),
+                        ))
+                    },
+                    _ => StorageBackend::TimescaleDb,
+                },
             },
             network: NetworkConfig {
                 docker_mode: dotenv::var("DOCKER_MODE").map(|v| v.to_lowercase() == "true").unwrap_or(false),
@@ -62,7 +456,139 @@ impl AppConfig {
                 idps_log_mode: get_env_var("IDPS_LOG_MODE")?,
                 normal_path_style: get_env_var("NORMAL_PATH_STYLE")?,
                 idps_path_style: get_env_var("IDPS_PATH_STYLE")?,
+                idps_log_format: dotenv::var("IDPS_LOG_FORMAT").unwrap_or("text".to_string()).to_lowercase(),
+                eve_output: dotenv::var("EVE_OUTPUT").unwrap_or("file".to_string()).to_lowercase(),
+                eve_file: dotenv::var("EVE_FILE").unwrap_or("./logs/eve.json".to_string()),
+                eve_socket_path: dotenv::var("EVE_SOCKET_PATH").unwrap_or_default(),
+            },
+            firewall: FirewallConfig {
+                stats_interval: get_optional_env_var("FIREWALL_STATS_INTERVAL")?.unwrap_or(60),
+                sync_interval: get_optional_env_var("FIREWALL_SYNC_INTERVAL")?.unwrap_or(10),
+            },
+            rate_limit: RateLimitConfig {
+                per_source_pps: get_optional_env_var("RATE_LIMIT_PER_SOURCE_PPS")?,
+                per_flow_pps: get_optional_env_var("RATE_LIMIT_PER_FLOW_PPS")?,
+                per_node_pps: get_optional_env_var("RATE_LIMIT_PER_NODE_PPS")?,
+                burst_seconds: get_optional_env_var("RATE_LIMIT_BURST_SECONDS")?.unwrap_or(1.0),
+                action: match dotenv::var("RATE_LIMIT_ACTION").unwrap_or_default().to_lowercase().as_str() {
+                    "sample" => RateLimitAction::Sample(get_optional_env_var("RATE_LIMIT_SAMPLE_RATE")?.unwrap_or(100)),
+                    _ => RateLimitAction::Drop,
+                },
+                max_buckets: get_optional_env_var("RATE_LIMIT_MAX_BUCKETS")?.unwrap_or(65536),
+            },
+            checksum: ChecksumConfig {
+                action: match dotenv::var("CHECKSUM_ACTION").unwrap_or_default().to_lowercase().as_str() {
+                    "ignore" => ChecksumAction::Ignore,
+                    "log" => ChecksumAction::Log,
+                    "drop" => ChecksumAction::Drop,
+                    _ => ChecksumAction::Count,
+                },
+                offload_aware: dotenv::var("CHECKSUM_OFFLOAD_AWARE").map(|v| v.to_lowercase() != "false").unwrap_or(true),
+            },
+            stream: StreamConfig {
+                overlap_policy: match dotenv::var("STREAM_OVERLAP_POLICY").unwrap_or_default().to_lowercase().as_str() {
+                    "last" => StreamOverlapPolicy::Last,
+                    _ => StreamOverlapPolicy::First,
+                },
+                memcap: get_optional_env_var("STREAM_MEMCAP")?.unwrap_or(64 * 1024 * 1024),
+                max_flows: get_optional_env_var("STREAM_MAX_FLOWS")?.unwrap_or(65536),
+                timeout: get_optional_env_var("STREAM_TIMEOUT")?.unwrap_or(300),
+            },
+            flow: FlowConfig {
+                enabled: dotenv::var("FLOW_ENABLED").map(|v| v.to_lowercase() == "true").unwrap_or(true),
+                idle_timeout: get_optional_env_var("FLOW_IDLE_TIMEOUT")?.unwrap_or(60),
+                active_timeout: get_optional_env_var("FLOW_ACTIVE_TIMEOUT")?.unwrap_or(1800),
+                max_flows: get_optional_env_var("FLOW_MAX_FLOWS")?.unwrap_or(65536),
+                ipfix_collector: dotenv::var("FLOW_IPFIX_COLLECTOR").ok().filter(|v| !v.is_empty()),
+            },
+            retention: RetentionConfig {
+                packets_days: get_optional_env_var("RETENTION_PACKETS_DAYS")?,
+                interval: get_optional_env_var("RETENTION_INTERVAL")?.unwrap_or(60),
+                processed_grace: get_optional_env_var("RETENTION_PROCESSED_GRACE")?.unwrap_or(300),
+                node_timeout: get_optional_env_var("RETENTION_NODE_TIMEOUT")?.unwrap_or(3600),
+            },
+            idps: IdpsConfig {
+                port_scan: PortScanConfig {
+                    window: get_optional_env_var("PORT_SCAN_WINDOW")?.unwrap_or(60),
+                    port_threshold: get_optional_env_var("PORT_SCAN_PORT_THRESHOLD")?.unwrap_or(20),
+                    host_threshold: get_optional_env_var("PORT_SCAN_HOST_THRESHOLD")?.unwrap_or(20),
+                },
+                syn_flood: SynFloodConfig {
+                    window: get_optional_env_var("SYN_FLOOD_WINDOW")?.unwrap_or(10),
+                    half_open_threshold: get_optional_env_var("SYN_FLOOD_HALF_OPEN_THRESHOLD")?.unwrap_or(100),
+                    block: dotenv::var("SYN_FLOOD_BLOCK").map(|v| v.to_lowercase() == "true").unwrap_or(false),
+                    block_duration: get_optional_env_var("SYN_FLOOD_BLOCK_DURATION")?.unwrap_or(300),
+                },
+                arp_watch: ArpWatchConfig {
+                    gratuitous_window: get_optional_env_var("ARP_GRATUITOUS_WINDOW")?.unwrap_or(10),
+                    gratuitous_threshold: get_optional_env_var("ARP_GRATUITOUS_THRESHOLD")?.unwrap_or(10),
+                    max_ips_per_mac: get_optional_env_var("ARP_MAX_IPS_PER_MAC")?.unwrap_or(16),
+                },
+                signature: SignatureConfig {
+                    rules_path: dotenv::var("SIGNATURE_RULES_PATH").ok().filter(|v| !v.is_empty()),
+                },
+                dns: DnsConfig {
+                    log_queries: dotenv::var("DNS_LOG_QUERIES").map(|v| v.to_lowercase() == "true").unwrap_or(false),
+                    window: get_optional_env_var("DNS_WINDOW")?.unwrap_or(60),
+                    tunnel_label_length: get_optional_env_var("DNS_TUNNEL_LABEL_LENGTH")?.unwrap_or(40),
+                    tunnel_entropy: get_optional_env_var("DNS_TUNNEL_ENTROPY")?.unwrap_or(4.0),
+                    txt_threshold: get_optional_env_var("DNS_TXT_THRESHOLD")?.unwrap_or(50),
+                    dga_entropy: get_optional_env_var("DNS_DGA_ENTROPY")?.unwrap_or(3.0),
+                    nxdomain_threshold: get_optional_env_var("DNS_NXDOMAIN_THRESHOLD")?.unwrap_or(20),
+                },
+                tls: TlsConfig {
+                    fingerprints_path: dotenv::var("TLS_FINGERPRINTS_PATH").ok().filter(|v| !v.is_empty()),
+                },
+                http: HttpConfig {
+                    log_transactions: dotenv::var("HTTP_LOG_TRANSACTIONS").map(|v| v.to_lowercase() == "true").unwrap_or(false),
+                },
+                anomaly: AnomalyConfig {
+                    enabled: dotenv::var("ANOMALY_ENABLED").map(|v| v.to_lowercase() == "true").unwrap_or(true),
+                    interval: get_optional_env_var("ANOMALY_INTERVAL")?.unwrap_or(60),
+                    alpha: get_optional_env_var("ANOMALY_ALPHA")?.unwrap_or(0.1),
+                    warmup_intervals: get_optional_env_var("ANOMALY_WARMUP_INTERVALS")?.unwrap_or(30),
+                    threshold: get_optional_env_var("ANOMALY_THRESHOLD")?.unwrap_or(4.0),
+                    mix_threshold: get_optional_env_var("ANOMALY_MIX_THRESHOLD")?.unwrap_or(0.5),
+                    max_hosts: get_optional_env_var("ANOMALY_MAX_HOSTS")?.unwrap_or(4096),
+                },
+                prevention: PreventionConfig {
+                    auto_block: dotenv::var("AUTO_BLOCK").map(|v| v.to_lowercase() == "true").unwrap_or(false),
+                    block_duration: get_optional_env_var("AUTO_BLOCK_DURATION")?.unwrap_or(600),
+                    min_severity: dotenv::var("AUTO_BLOCK_MIN_SEVERITY").unwrap_or("high".to_string()).to_lowercase(),
+                    publish: dotenv::var("AUTO_BLOCK_PUBLISH").map(|v| v.to_lowercase() == "true").unwrap_or(false),
+                    exclude: get_list_env_var("AUTO_BLOCK_EXCLUDE")?,
+                },
+                alert: AlertConfig {
+                    store: dotenv::var("ALERT_STORE").map(|v| v.to_lowercase() == "true").unwrap_or(true),
+                },
             },
         })
     }
 }
+
+// カンマ区切りの値を解析する (未設定の場合は空)
+fn get_list_env_var<T>(var_name: &str) -> Result<Vec<T>, ConfigError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    dotenv::var(var_name)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(|value| value.parse::<T>().map_err(|e| ConfigError::EnvVarParseError(format!("{}: {}", var_name, e))))
+        .collect()
+}
+
+// 未設定の場合はNoneを返し、設定されている場合のみ解析する
+fn get_optional_env_var<T>(var_name: &str) -> Result<Option<T>, ConfigError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match dotenv::var(var_name) {
+        Ok(value) if !value.trim().is_empty() => value.trim().parse::<T>().map(Some).map_err(|e| ConfigError::EnvVarParseError(format!("{}: {}", var_name, e))),
+        _ => Ok(None),
+    }
+}
@@ -2,4 +2,12 @@ mod app_config;
 mod error;
 
 pub use app_config::AppConfig;
+pub use app_config::FlowConfig;
 pub use app_config::LoggerConfig;
+pub use app_config::MigrationMode;
+pub use app_config::RetentionConfig;
+pub use app_config::StorageBackend;
+pub use app_config::{AnomalyConfig, ArpWatchConfig, DnsConfig, HttpConfig, IdpsConfig, PortScanConfig, PreventionConfig, SynFloodConfig, TlsConfig};
+pub use app_config::{ChecksumAction, ChecksumConfig};
+pub use app_config::{RateLimitAction, RateLimitConfig};
+pub use app_config::{StreamConfig, StreamOverlapPolicy};
@@ -32,4 +32,13 @@ pub enum DatabaseError {
 
     #[error("トランザクション処理に失敗しました: {0}")]
     TransactionError(String),
+
+    #[error("マイグレーションの適用に失敗しました: {0}")]
+    MigrationError(String),
+
+    #[error("データベースのスキーマが一致しません: {0}")]
+    SchemaMismatch(String),
+
+    #[error("パケットの保存先のロックに失敗しました")]
+    StoreLockError,
 }
@@ -0,0 +1,366 @@
+use crate::config::{MigrationMode, StorageBackend};
+use crate::database::client::{Database, ExecuteQuery};
+use crate::database::error::DatabaseError;
+use log::info;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use tokio_postgres::Transaction;
+
+// 複数のノードが同時に起動した場合にマイグレーションを直列化するアドバイザリロックのキー
+const MIGRATION_LOCK_KEY: i64 = 0x7264_625f_6d69_6772;
+
+const CREATE_SCHEMA_MIGRATIONS: &str = "
+    CREATE TABLE IF NOT EXISTS schema_migrations
+    (
+        version    BIGINT PRIMARY KEY,
+        name       TEXT        NOT NULL,
+        checksum   TEXT        NOT NULL,
+        applied_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+    )";
+
+/// バイナリに埋め込んだスキーマのマイグレーション (適用済みのファイルは変更せず、新しいバージョンを追加する)
+struct Migration {
+    version: i64,
+    name: &'static str,
+    sql: &'static str,
+}
+
+impl Migration {
+    fn checksum(&self) -> String {
+        format!("{:x}", Sha256::digest(self.sql.as_bytes()))
+    }
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial_schema",
+        sql: include_str!("../../resource/migrations/V001__initial_schema.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "processed_packets_index",
+        sql: include_str!("../../resource/migrations/V002__processed_packets_index.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "processed_packets_retention",
+        sql: include_str!("../../resource/migrations/V003__processed_packets_retention.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "timescaledb_hypertables",
+        sql: include_str!("../../resource/migrations/V004__timescaledb_hypertables.sql"),
+    },
+];
+
+// リポジトリが読み書きするテーブルと列
+const EXPECTED_COLUMNS: &[(&str, &[&str])] = &[
+    (
+        "packets",
+        &[
+            "id",
+            "timestamp",
+            "node_id",
+            "src_mac",
+            "dst_mac",
+            "ether_type",
+            "ip_protocol",
+            "src_ip",
+            "dst_ip",
+            "src_port",
+            "dst_port",
+            "raw_packet",
+        ],
+    ),
+    ("processed_packets", &["packet_id", "node_id", "processed_at"]),
+    ("firewall_stats", &["timestamp", "node_id", "direction", "rule", "priority", "packets", "bytes"]),
+    (
+        "firewall_rules",
+        &[
+            "id",
+            "node_id",
+            "direction",
+            "filter_type",
+            "filter_value",
+            "priority",
+            "enabled",
+        ],
+    ),
+    ("firewall_rule_version", &["version"]),
+    (
+        "firewall_blocks",
+        &[
+            "id",
+            "origin_node_id",
+            "shared",
+            "direction",
+            "filter_type",
+            "filter_value",
+            "reason",
+            "created_at",
+            "expires_at",
+        ],
+    ),
+    (
+        "idps_alerts",
+        &[
+            "timestamp",
+            "node_id",
+            "severity",
+            "category",
+            "signature_id",
+            "message",
+            "src_ip",
+            "dst_ip",
+            "src_port",
+            "dst_port",
+            "ip_protocol",
+            "packet_id",
+        ],
+    ),
+    (
+        "dns_log",
+        &[
+            "timestamp",
+            "node_id",
+            "src_ip",
+            "dst_ip",
+            "src_port",
+            "dst_port",
+            "transaction_id",
+            "query",
+            "query_type",
+            "response",
+            "rcode",
+            "answers",
+        ],
+    ),
+    (
+        "http_log",
+        &[
+            "timestamp",
+            "node_id",
+            "src_ip",
+            "dst_ip",
+            "src_port",
+            "dst_port",
+            "response",
+            "version",
+            "method",
+            "host",
+            "uri",
+            "user_agent",
+            "status_code",
+        ],
+    ),
+    (
+        "flows",
+        &[
+            "start_time",
+            "end_time",
+            "node_id",
+            "src_ip",
+            "dst_ip",
+            "src_port",
+            "dst_port",
+            "ip_protocol",
+            "packets",
+            "bytes",
+            "reply_packets",
+            "reply_bytes",
+            "tcp_flags",
+            "end_reason",
+        ],
+    ),
+    (
+        "anomaly_baselines",
+        &[
+            "node_id",
+            "host",
+            "samples",
+            "packets_per_sec_mean",
+            "packets_per_sec_variance",
+            "bytes_per_sec_mean",
+            "bytes_per_sec_variance",
+            "peers_mean",
+            "peers_variance",
+            "tcp_ratio",
+            "udp_ratio",
+            "icmp_ratio",
+            "other_ratio",
+            "updated_at",
+        ],
+    ),
+];
+
+pub struct Migrator;
+
+impl Migrator {
+    /// 未適用のマイグレーションを適用し、スキーマがこのバイナリの想定と一致するか確認する
+    /// verifyモードでは適用せず、未適用のマイグレーションがあればエラーとする
+    pub async fn run(mode: MigrationMode, backend: StorageBackend) -> Result<(), DatabaseError> {
+        let db = Database::get_database();
+
+        // 拡張がない場合はハイパーテーブルを作成せずに適用されてしまう為、先に確認する
+        if backend == StorageBackend::TimescaleDb && !Self::has_timescaledb().await? {
+            return Err(DatabaseError::SchemaMismatch(
+                "timescaledb拡張が有効になっていません (TimescaleDBを使用しない場合はSTORAGE_BACKEND=postgresを指定してください)".to_string(),
+            ));
+        }
+
+        if mode == MigrationMode::Apply {
+            // 他のノードと同時にテーブルを作成しないよう、ロックを取得してから作成する
+            db.transaction(|tx| {
+                Box::pin(async move {
+                    Self::lock(tx).await?;
+                    tx.batch_execute(CREATE_SCHEMA_MIGRATIONS).await.map_err(|e| DatabaseError::MigrationError(e.to_string()))
+                })
+            })
+            .await?;
+        }
+
+        Self::check_unknown_versions().await?;
+
+        for migration in MIGRATIONS {
+            let applied = db
+                .transaction(|tx| {
+                    Box::pin(async move {
+                        // 他のノードが適用し終えるのを待ってから、適用済みか確認する
+                        Self::lock(tx).await?;
+                        Self::apply(tx, migration, mode).await
+                    })
+                })
+                .await?;
+            if applied {
+                info!("マイグレーションを適用しました: V{:03}__{}", migration.version, migration.name);
+            }
+        }
+
+        Self::check_columns().await?;
+        info!("データベースのスキーマを確認しました: version={}", MIGRATIONS.last().map(|m| m.version).unwrap_or(0));
+        Ok(())
+    }
+
+    async fn lock(tx: &mut Transaction<'_>) -> Result<(), DatabaseError> {
+        tx.execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK_KEY]).await.map_err(|e| DatabaseError::MigrationError(e.to_string()))?;
+        Ok(())
+    }
+
+    // 適用した場合はtrueを返す
+    async fn apply(tx: &mut Transaction<'_>, migration: &Migration, mode: MigrationMode) -> Result<bool, DatabaseError> {
+        let name = format!("V{:03}__{}", migration.version, migration.name);
+        let exists: bool =
+            tx.query_one("SELECT to_regclass('schema_migrations') IS NOT NULL AS exists", &[]).await.map_err(|e| DatabaseError::MigrationError(e.to_string()))?.get("exists");
+        let rows = if exists {
+            tx.query("SELECT checksum FROM schema_migrations WHERE version = $1", &[&migration.version]).await.map_err(|e| DatabaseError::MigrationError(e.to_string()))?
+        } else {
+            Vec::new()
+        };
+
+        if let Some(row) = rows.first() {
+            let checksum: String = row.get("checksum");
+            if checksum != migration.checksum() {
+                return Err(DatabaseError::SchemaMismatch(format!("適用済みの{}が変更されています (checksum={})", name, checksum)));
+            }
+            return Ok(false);
+        }
+        if mode == MigrationMode::Verify {
+            return Err(DatabaseError::SchemaMismatch(format!("{}が適用されていません", name)));
+        }
+
+        tx.batch_execute(migration.sql).await.map_err(|e| DatabaseError::MigrationError(format!("{}: {}", name, e)))?;
+        tx.execute(
+            "INSERT INTO schema_migrations (version, name, checksum) VALUES ($1, $2, $3)",
+            &[&migration.version, &migration.name, &migration.checksum()],
+        )
+        .await
+        .map_err(|e| DatabaseError::MigrationError(e.to_string()))?;
+        Ok(true)
+    }
+
+    // 新しいバイナリで適用されたデータベースに古いバイナリで接続した場合は起動しない
+    async fn check_unknown_versions() -> Result<(), DatabaseError> {
+        // verifyモードでschema_migrationsがない場合は、最初のマイグレーションの確認でエラーとする
+        if !Self::has_schema_migrations().await? {
+            return Ok(());
+        }
+
+        let db = Database::get_database();
+        let known: Vec<i64> = MIGRATIONS.iter().map(|m| m.version).collect();
+        let rows = db.query("SELECT version, name FROM schema_migrations WHERE NOT (version = ANY($1)) ORDER BY version", &[&known]).await?;
+
+        let unknown: Vec<String> = rows
+            .iter()
+            .map(|row| {
+                let version: i64 = row.get("version");
+                let name: String = row.get("name");
+                format!("V{:03}__{}", version, name)
+            })
+            .collect();
+        if !unknown.is_empty() {
+            return Err(DatabaseError::SchemaMismatch(format!(
+                "このバイナリが知らないマイグレーションが適用されています: {}",
+                unknown.join(", ")
+            )));
+        }
+        Ok(())
+    }
+
+    async fn has_schema_migrations() -> Result<bool, DatabaseError> {
+        let db = Database::get_database();
+        let rows = db.query("SELECT to_regclass('schema_migrations') IS NOT NULL AS exists", &[]).await?;
+        Ok(rows.first().is_some_and(|row| row.get("exists")))
+    }
+
+    async fn has_timescaledb() -> Result<bool, DatabaseError> {
+        let db = Database::get_database();
+        let rows = db.query("SELECT EXISTS (SELECT 1 FROM pg_extension WHERE extname = 'timescaledb') AS exists", &[]).await?;
+        Ok(rows.first().is_some_and(|row| row.get("exists")))
+    }
+
+    async fn check_columns() -> Result<(), DatabaseError> {
+        let db = Database::get_database();
+        let rows = db
+            .query(
+                "SELECT table_name::TEXT AS table_name, column_name::TEXT AS column_name FROM information_schema.columns WHERE table_schema = current_schema()",
+                &[],
+            )
+            .await?;
+        let columns: HashSet<(String, String)> = rows.iter().map(|row| (row.get("table_name"), row.get("column_name"))).collect();
+
+        let missing: Vec<String> = EXPECTED_COLUMNS
+            .iter()
+            .flat_map(|(table, expected)| expected.iter().map(move |column| (*table, *column)))
+            .filter(|(table, column)| !columns.contains(&(table.to_string(), column.to_string())))
+            .map(|(table, column)| format!("{}.{}", table, column))
+            .collect();
+        if !missing.is_empty() {
+            return Err(DatabaseError::SchemaMismatch(format!("必要な列がありません: {}", missing.join(", "))));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_applied_migrations_unchanged() {
+        // 適用済みのデータベースで一致しなくなる為、V001のファイルは変更しない
+        assert_eq!(MIGRATIONS[0].checksum(), "62c729310e2eb21b80aacefa8e7a6f1d8dff5ccf6b492a78d38ae7dabdee53cb");
+    }
+
+    #[test]
+    fn calls_timescaledb_only_when_installed() {
+        // TimescaleDBの関数はV004で拡張の有無を確認してから呼び出す
+        for migration in MIGRATIONS.iter().filter(|migration| migration.version != 4) {
+            assert!(
+                !migration.sql.contains("create_hypertable") && !migration.sql.contains("timescaledb"),
+                "V{:03}",
+                migration.version
+            );
+        }
+    }
+}
@@ -1,8 +1,10 @@
 mod client;
 mod error;
+mod migration;
 mod pool;
 
 pub use client::Database;
 pub use error::DatabaseError;
+pub use migration::Migrator;
 
 pub(crate) use client::ExecuteQuery;
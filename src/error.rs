@@ -14,6 +14,9 @@ pub enum InitProcessError {
     #[error("データベース接続エラー: {0}")]
     DatabaseConnectionError(String),
 
+    #[error("データベースのマイグレーションに失敗しました: {0}")]
+    DatabaseMigrationError(String),
+
     #[error("タスクの実行処理に失敗しました: {0}")]
     TaskExecutionProcessError(String),
 }
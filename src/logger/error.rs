@@ -7,4 +7,7 @@ pub enum LoggerError {
 
     #[error("ロガーのロックに失敗しました: {0}")]
     LoggerLockError(String),
+
+    #[error("EVEの書き込みスレッドの起動に失敗しました: {0}")]
+    FailedSpawnEveWriter(String),
 }
@@ -0,0 +1,155 @@
+use crate::logger::error::LoggerError;
+use crate::logger::idps_logger::create_log_file;
+use log::warn;
+use serde_json::Value;
+use std::fs::File;
+use std::io::{BufWriter, Stdout, Write};
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Unixソケットへの再接続を試みる間隔
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+// 受信側が読み取らない場合に書き込みを諦めるまでの時間
+const SOCKET_WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+// 書き込みを待つイベント数の上限 (超えた場合は解析を止めないようイベントを破棄する)
+const EVE_QUEUE_CAPACITY: usize = 10_000;
+
+/// EVE形式のイベントの出力先
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EveOutput {
+    File(String),
+    Stdout,
+    // 受信側が待ち受けているストリーム型のUnixソケット
+    UnixSocket(String),
+}
+
+enum EveSink {
+    File(BufWriter<File>),
+    Stdout(BufWriter<Stdout>),
+    UnixSocket {
+        path: String,
+        stream: Option<BufWriter<UnixStream>>,
+        last_attempt: Instant,
+    },
+}
+
+impl EveSink {
+    fn write(&mut self, line: &str) {
+        match self {
+            EveSink::File(file) => {
+                let _ = file.write_all(line.as_bytes());
+            },
+            EveSink::Stdout(stdout) => {
+                let _ = stdout.write_all(line.as_bytes());
+            },
+            EveSink::UnixSocket { path, stream, last_attempt } => {
+                if stream.is_none() && last_attempt.elapsed() >= RECONNECT_INTERVAL {
+                    *last_attempt = Instant::now();
+                    *stream = connect(path).ok();
+                }
+                // 受信側が切断した場合は次回の再接続まで破棄する
+                if stream.as_mut().is_some_and(|socket| socket.write_all(line.as_bytes()).is_err()) {
+                    *stream = None;
+                    *last_attempt = Instant::now();
+                }
+            },
+        }
+    }
+
+    fn flush(&mut self) {
+        match self {
+            EveSink::File(file) => {
+                let _ = file.flush();
+            },
+            EveSink::Stdout(stdout) => {
+                let _ = stdout.flush();
+            },
+            EveSink::UnixSocket { stream, last_attempt, .. } => {
+                if stream.as_mut().is_some_and(|socket| socket.flush().is_err()) {
+                    *stream = None;
+                    *last_attempt = Instant::now();
+                }
+            },
+        }
+    }
+}
+
+// 解析を行うタスクから書き込みを切り離す為、イベントは専用のスレッドで書き込む
+static EVE_SENDER: OnceLock<SyncSender<String>> = OnceLock::new();
+// キューが一杯で破棄したイベント数 (書き込みスレッドが定期的に報告する)
+static DROPPED_EVENTS: AtomicU64 = AtomicU64::new(0);
+
+fn connect(path: &str) -> std::io::Result<BufWriter<UnixStream>> {
+    let stream = UnixStream::connect(path)?;
+    stream.set_write_timeout(Some(SOCKET_WRITE_TIMEOUT))?;
+    Ok(BufWriter::new(stream))
+}
+
+pub fn set_eve_settings(output: EveOutput) -> Result<(), LoggerError> {
+    let sink = match output {
+        EveOutput::File(file_path) => EveSink::File(BufWriter::new(create_log_file(&file_path)?)),
+        EveOutput::Stdout => EveSink::Stdout(BufWriter::new(std::io::stdout())),
+        EveOutput::UnixSocket(path) => {
+            // 受信側が起動していなくても開始できるよう、接続できない場合は書き込み時に再接続する
+            let stream = match connect(&path) {
+                Ok(stream) => Some(stream),
+                Err(e) => {
+                    println!("EVEの出力先のUnixソケットに接続できません: {}: {}", path, e);
+                    None
+                },
+            };
+            EveSink::UnixSocket {
+                path,
+                stream,
+                last_attempt: Instant::now(),
+            }
+        },
+    };
+
+    let (sender, receiver) = mpsc::sync_channel(EVE_QUEUE_CAPACITY);
+    thread::Builder::new().name("eve-writer".to_string()).spawn(move || run_writer(sink, receiver)).map_err(|e| LoggerError::FailedSpawnEveWriter(e.to_string()))?;
+    // 2回目以降の設定では送信側が破棄され、起動したスレッドはすぐに終了する
+    if EVE_SENDER.set(sender).is_err() {
+        warn!("EVEロガーの設定は既に適用されています");
+    }
+    Ok(())
+}
+
+// キューに届いたイベントを書き込み、キューが空になった時点でまとめてフラッシュする
+fn run_writer(mut sink: EveSink, receiver: Receiver<String>) {
+    while let Ok(line) = receiver.recv() {
+        sink.write(&line);
+        while let Ok(line) = receiver.try_recv() {
+            sink.write(&line);
+        }
+        sink.flush();
+
+        let dropped = DROPPED_EVENTS.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            warn!("EVEの書き込みが追いつかない為、イベントを{}件破棄しました", dropped);
+        }
+    }
+}
+
+/// EVE形式の出力が有効か (無効な場合はイベントを組み立てる必要がない)
+pub fn is_enabled() -> bool {
+    EVE_SENDER.get().is_some()
+}
+
+/// 1件のイベントを1行のJSONとして書き込みを待つキューへ追加する
+/// キューが一杯の場合は解析を止めないようイベントを破棄する
+pub fn write_event(event: &Value) {
+    let Some(sender) = EVE_SENDER.get() else {
+        return;
+    };
+    let mut line = event.to_string();
+    line.push('\n');
+
+    if let Err(TrySendError::Full(_)) = sender.try_send(line) {
+        DROPPED_EVENTS.fetch_add(1, Ordering::Relaxed);
+    }
+}
@@ -31,7 +31,7 @@ static LOGGER: Lazy<Mutex<LogConfig>> = Lazy::new(|| {
     })
 });
 
-fn create_log_file(file_path: &str) -> Result<File, LoggerError> {
+pub(super) fn create_log_file(file_path: &str) -> Result<File, LoggerError> {
     let path = Path::new(file_path);
 
     if let Some(parent) = path.parent() {
@@ -1,3 +1,4 @@
 mod error;
+pub mod eve_logger;
 pub mod idps_logger;
 pub mod setup_logger;
@@ -1,11 +1,15 @@
 use crate::config::LoggerConfig;
-use crate::logger::idps_logger;
+use crate::logger::eve_logger::EveOutput;
+use crate::logger::{eve_logger, idps_logger};
 use env_logger::{Builder, Target};
 use log::LevelFilter;
 use std::io::Write;
 
 pub fn setup_logger(logger_config: LoggerConfig) -> Result<(), Box<dyn std::error::Error>> {
+    // EVE形式を選択した場合は従来の形式のIDPSログを出力しない
+    let eve_format = logger_config.idps_log_format == "eve";
     let log_mode = match logger_config.idps_log_mode.as_str() {
+        _ if eve_format => idps_logger::OutputMode::None,
         "all" => idps_logger::OutputMode::All,
         "file" => idps_logger::OutputMode::FileOnly,
         "console" => idps_logger::OutputMode::ConsoleOnly,
@@ -16,6 +20,20 @@ pub fn setup_logger(logger_config: LoggerConfig) -> Result<(), Box<dyn std::erro
     // IDPSロガーの設定
     idps_logger::set_idps_settings(log_mode, &format!("../../{}", logger_config.idps_logger_file), &*logger_config.idps_path_style).expect("IDPSロガーの設定に失敗しました");
 
+    // EVE形式を標準出力へ書き込む場合は、JSON Linesに混ざらないよう通常のログを標準エラー出力へ書き込む
+    let mut target = Target::Stdout;
+    if eve_format {
+        let output = match logger_config.eve_output.as_str() {
+            "stdout" => EveOutput::Stdout,
+            "unix_socket" => EveOutput::UnixSocket(logger_config.eve_socket_path.clone()),
+            _ => EveOutput::File(format!("../../{}", logger_config.eve_file)),
+        };
+        if output == EveOutput::Stdout {
+            target = Target::Stderr;
+        }
+        eve_logger::set_eve_settings(output).expect("EVEロガーの設定に失敗しました");
+    }
+
     Builder::new()
         .filter_level(LevelFilter::Info)
         .format(move |buf, record| {
@@ -34,7 +52,7 @@ pub fn setup_logger(logger_config: LoggerConfig) -> Result<(), Box<dyn std::erro
                 record.args(),
             )
         })
-        .target(Target::Stdout)
+        .target(target)
         .init();
 
     Ok(())
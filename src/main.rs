@@ -8,10 +8,11 @@ mod tasks;
 mod utils;
 
 use crate::config::AppConfig;
-use crate::database::Database;
+use crate::database::{Database, Migrator};
 use crate::error::InitProcessError;
 use crate::interface::select_interface;
 use crate::logger::setup_logger::setup_logger;
+use crate::packet::store::configure_packet_store;
 use crate::tasks::TaskScheduler;
 use log::{error, info};
 
@@ -28,7 +29,7 @@ async fn main() -> Result<(), InitProcessError> {
 
     info!("Node IDは{}に指定されています", config.node_id);
 
-    // データベース接続
+    // データベース接続 (STORAGE_BACKEND=memory_packetsでもルールやログなどの保存に使用する)
     Database::connect(
         &config.database.host,
         config.database.port,
@@ -41,6 +42,13 @@ async fn main() -> Result<(), InitProcessError> {
 
     info!("データベースに接続できました: address:{}, port:{}", config.database.host, config.database.port);
 
+    // スキーマのマイグレーション (一致しない場合は起動しない)
+    Migrator::run(config.database.migration_mode, config.database.storage_backend).await.map_err(|e| InitProcessError::DatabaseMigrationError(e.to_string()))?;
+
+    // パケットの保存先の設定
+    configure_packet_store(config.database.storage_backend);
+    info!("パケットの保存先: {:?}", config.database.storage_backend);
+
     // ネットワークインターフェースの選択
     let interface = select_interface(config.network.docker_mode, &config.network.docker_interface_name).map_err(|e| InitProcessError::InterfaceSelectionError(e.to_string()))?;
     info!("デバイスの選択に成功しました: {}", interface.name);
@@ -1,13 +1,27 @@
+use crate::config::{ChecksumAction, ChecksumConfig, FlowConfig, IdpsConfig, StreamConfig};
 use crate::idps_log;
-use crate::packet::analysis::ethernet::parse_ethernet_header;
-use crate::packet::analysis::firewall::{Filter, FirewallPacket, IpFirewall, Policy};
-use crate::packet::analysis::ip::parse_ip_packet;
+use crate::packet::analysis::checksum::{ChecksumCounters, ChecksumFailure, ChecksumStats};
+use crate::packet::analysis::ethernet::{parse_ethernet_header, EthernetHeader};
+use crate::packet::analysis::firewall::{
+    related_flow, ConnState, ConnectionTracker, Filter, FirewallDirection, FirewallError, FirewallPacket, FirewallStats, IpFirewall, Policy, TemporaryBlockStatus,
+};
+use crate::packet::analysis::flow::{FlowRecord, FlowTable};
+use crate::packet::analysis::fragment::{FragmentReassembler, Reassembly};
+use crate::packet::analysis::http::parse_http_messages;
+use crate::packet::analysis::idps::{Alert, DnsLogEntry, HostBaseline, HttpLogEntry, IdpsEngine, InspectContext, TemporaryBlock};
+use crate::packet::analysis::ip::{parse_ip_packet, IpPacket};
+use crate::packet::analysis::stream::{StreamChunk, StreamReassembler};
+use crate::packet::analysis::tls::{parse_tls_hello, TlsHello, TlsHelloKind};
+use crate::packet::analysis::transport::TransportHeader;
 use crate::packet::types::EtherType;
-use crate::packet::{InetAddr, PacketData};
+use crate::packet::{InetAddr, MacAddr, PacketData};
 use chrono::Utc;
 use lazy_static::lazy_static;
-use log::{info, trace};
+use log::{error, info, trace, warn};
+use std::collections::HashMap;
 use std::net::IpAddr;
+use std::ops::Range;
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
 
 #[derive(Clone, Copy)]
 pub struct IpHeader {
@@ -18,64 +32,422 @@ pub struct IpHeader {
 }
 
 pub enum AnalyzeResult {
-    Accept(PacketData),
+    Accept(Box<PacketData>),
+    // 再構築したデータグラムが許可された為、全てのフラグメントを受け入れる
+    AcceptFragments(Vec<PacketData>),
+    // 残りのフラグメントを待っている
+    Held,
     Reject,
 }
 
+/// データベースから取得したフレームの注入可否
+pub enum IngressVerdict {
+    Accept,
+    // 再構築したデータグラムが許可された為、保持していたフラグメントを含めて全て注入する
+    AcceptFragments(Vec<Vec<u8>>),
+    // 残りのフラグメントを待っている
+    Held,
+    Reject,
+}
+
+// フレームの解析結果
+struct Inspection {
+    ethernet_header: EthernetHeader,
+    firewall_packet: FirewallPacket,
+    checksum_failures: Vec<ChecksumFailure>,
+    // L4ペイロードのフレーム内での範囲
+    payload: Range<usize>,
+}
+
+#[derive(Debug, Default)]
+struct ChecksumPolicy {
+    config: ChecksumConfig,
+    // 監視しているインターフェースのMACアドレス
+    local_mac: Option<MacAddr>,
+}
+
+// チェックサム不一致の扱い (未設定の場合はデフォルト値を使用する)
+static CHECKSUM_POLICY: OnceLock<ChecksumPolicy> = OnceLock::new();
+
+// TCPストリームの再構築 (未設定の場合はデフォルト値を使用する)
+static STREAM_REASSEMBLER: OnceLock<StreamReassembler> = OnceLock::new();
+
+// フローの集計 (未設定の場合はデフォルト値を使用する)
+static FLOW_TABLE: OnceLock<FlowTable> = OnceLock::new();
+
+// 侵入検知 (未設定の場合はデフォルト値を使用する)
+static IDPS_ENGINE: OnceLock<IdpsEngine> = OnceLock::new();
+
+// firewall_blocksテーブルへの書き込みを待つ遮断ルールの上限
+const MAX_PENDING_BLOCKS: usize = 4096;
+
 lazy_static! {
-    static ref FIREWALL: IpFirewall = {
-        let mut fw = IpFirewall::new(Policy::Whitelist);
-        fw.add_rule(Filter::DstIpAddress("192.168.0.1".parse().unwrap()), 100);
-        fw.add_rule(Filter::SrcIpAddress("192.168.0.1".parse().unwrap()), 99);
-        fw.add_rule(Filter::DstIpAddress("192.168.0.30".parse().unwrap()), 98);
-        fw.add_rule(Filter::SrcIpAddress("192.168.0.30".parse().unwrap()), 97);
-        fw.add_rule(Filter::DstIpAddress("192.168.0.155".parse().unwrap()), 96);
-        fw.add_rule(Filter::SrcIpAddress("192.168.0.155".parse().unwrap()), 95);
-        fw
+    static ref REASSEMBLER: FragmentReassembler = FragmentReassembler::new();
+
+    // データベースから取得したフラグメントの再構築 (LANで収集したフラグメントとは別に管理する)
+    static ref INGRESS_REASSEMBLER: FragmentReassembler = FragmentReassembler::new();
+
+    static ref CHECKSUM_COUNTERS: ChecksumCounters = ChecksumCounters::default();
+
+    // 検知器が追加・延長し、firewall_blocksテーブルへの書き込みを待っている遮断ルール
+    // (同じフィルタは期間の長い方にまとめる)
+    static ref PENDING_BLOCKS: Mutex<HashMap<(FirewallDirection, Filter), TemporaryBlock>> = Mutex::new(HashMap::new());
+
+    // 送信と受信で共有するコネクション追跡テーブル
+    // (LAN側から開始したフローの戻りの通信をingress側でESTABLISHEDとして判定する為)
+    static ref CONNTRACK: Arc<ConnectionTracker> = Arc::new(ConnectionTracker::new());
+
+    // LANで収集したパケットに適用するファイアウォール (wire→DB)
+    static ref EGRESS_FIREWALL: RwLock<IpFirewall> = {
+        let mut fw = IpFirewall::new(Policy::Whitelist, Arc::clone(&CONNTRACK));
+        for (filter, priority) in builtin_rules(FirewallDirection::Egress).into_iter().chain(default_rules(FirewallDirection::Egress)) {
+            fw.add_rule(filter, priority);
+        }
+        RwLock::new(fw)
     };
+
+    // データベースから取得したパケットをLANへ注入する前に適用するファイアウォール (DB→wire)
+    // 従来は無条件に注入していた為、ルールがなければ全て許可するブラックリスト方式とする
+    static ref INGRESS_FIREWALL: RwLock<IpFirewall> = RwLock::new(IpFirewall::new(Policy::Blacklist, Arc::clone(&CONNTRACK)));
+}
+
+// データベースのルールに関わらず常に有効なルール
+fn builtin_rules(direction: FirewallDirection) -> Vec<(Filter, u8)> {
+    match direction {
+        FirewallDirection::Egress => vec![
+            // 許可済みのフローに対する戻りの通信を許可
+            (Filter::ConnectionState(ConnState::Established), 110),
+            (Filter::ConnectionState(ConnState::Related), 109),
+        ],
+        FirewallDirection::Ingress => Vec::new(),
+    }
+}
+
+// データベースにその方向のルールがない場合に使用するルール
+fn default_rules(direction: FirewallDirection) -> Vec<(Filter, u8)> {
+    match direction {
+        FirewallDirection::Egress => vec![
+            (Filter::DstIpAddress("192.168.0.1".parse().unwrap()), 100),
+            (Filter::SrcIpAddress("192.168.0.1".parse().unwrap()), 99),
+            (Filter::DstIpAddress("192.168.0.30".parse().unwrap()), 98),
+            (Filter::SrcIpAddress("192.168.0.30".parse().unwrap()), 97),
+            (Filter::DstIpAddress("192.168.0.155".parse().unwrap()), 96),
+            (Filter::SrcIpAddress("192.168.0.155".parse().unwrap()), 95),
+        ],
+        FirewallDirection::Ingress => Vec::new(),
+    }
+}
+
+fn firewall(direction: FirewallDirection) -> &'static RwLock<IpFirewall> {
+    match direction {
+        FirewallDirection::Ingress => &INGRESS_FIREWALL,
+        FirewallDirection::Egress => &EGRESS_FIREWALL,
+    }
 }
 
 pub struct PacketAnalyzer {}
 
 impl PacketAnalyzer {
-    pub async fn analyze_packet(ethernet_frame: &[u8]) -> AnalyzeResult {
-        // 基本的な長さチェック
-        if ethernet_frame.len() < 14 + 20 {
-            idps_log!("パケットが短すぎます: パケット長={}、期待値={}", ethernet_frame.len(), 14 + 20);
-            return AnalyzeResult::Reject;
+    pub fn firewall_stats(direction: FirewallDirection) -> Result<FirewallStats, FirewallError> {
+        firewall(direction).read().map(|fw| fw.stats()).map_err(|_| FirewallError::LockError)
+    }
+
+    pub fn replace_firewall_rules(direction: FirewallDirection, rules: Vec<(Filter, u8)>) -> Result<(), FirewallError> {
+        let mut fw = firewall(direction).write().map_err(|_| FirewallError::LockError)?;
+        fw.replace_rules(builtin_rules(direction).into_iter().chain(rules).collect());
+        Ok(())
+    }
+
+    /// データベースにルールがない場合に使用する既定のルール
+    pub fn default_firewall_rules(direction: FirewallDirection) -> Vec<(Filter, u8)> {
+        default_rules(direction)
+    }
+
+    /// チェックサム不一致時の扱いを設定する (最初の1回のみ有効)
+    pub fn configure_checksum(config: ChecksumConfig, local_mac: Option<MacAddr>) {
+        if CHECKSUM_POLICY.set(ChecksumPolicy { config, local_mac }).is_err() {
+            warn!("チェックサムの設定は既に適用されています");
         }
+    }
 
-        // Ethernetヘッダーの解析
-        let ethernet_header = match parse_ethernet_header(ethernet_frame) {
+    /// TCPストリーム再構築の設定を適用する (最初の1回のみ有効)
+    pub fn configure_stream(config: &StreamConfig) {
+        if STREAM_REASSEMBLER.set(StreamReassembler::new(config)).is_err() {
+            warn!("TCPストリーム再構築の設定は既に適用されています");
+        }
+    }
+
+    /// フローの集計の設定を適用する (最初の1回のみ有効)
+    pub fn configure_flows(config: &FlowConfig) {
+        if FLOW_TABLE.set(FlowTable::new(config)).is_err() {
+            warn!("フローの集計の設定は既に適用されています");
+        }
+    }
+
+    /// 侵入検知の設定を適用する (最初の1回のみ有効)
+    pub fn configure_idps(config: &IdpsConfig) {
+        if IDPS_ENGINE.set(IdpsEngine::new(config)).is_err() {
+            warn!("侵入検知の設定は既に適用されています");
+        }
+    }
+
+    pub fn checksum_stats() -> ChecksumStats {
+        CHECKSUM_COUNTERS.snapshot()
+    }
+
+    /// flowsテーブルへ書き込む、終了またはタイムアウトしたフローを取り出す
+    pub fn expire_flows() -> Vec<FlowRecord> {
+        FLOW_TABLE.get_or_init(FlowTable::default).expire()
+    }
+
+    /// flowsテーブルへの書き込みに失敗したフローを次回の書き出しに戻す
+    pub fn requeue_flows(records: Vec<FlowRecord>) {
+        FLOW_TABLE.get_or_init(FlowTable::default).requeue(records);
+    }
+
+    /// dns_logテーブルへの書き込みを待っている問い合わせを取り出す
+    pub fn drain_dns_log() -> Vec<DnsLogEntry> {
+        IDPS_ENGINE.get_or_init(IdpsEngine::default).drain_dns_log()
+    }
+
+    /// dns_logテーブルへの書き込みに失敗した問い合わせを書き込み待ちに戻す
+    pub fn requeue_dns_log(entries: Vec<DnsLogEntry>) {
+        IDPS_ENGINE.get_or_init(IdpsEngine::default).requeue_dns_log(entries);
+    }
+
+    /// http_logテーブルへの書き込みを待っているメッセージを取り出す
+    pub fn drain_http_log() -> Vec<HttpLogEntry> {
+        IDPS_ENGINE.get_or_init(IdpsEngine::default).drain_http_log()
+    }
+
+    /// http_logテーブルへの書き込みに失敗したメッセージを書き込み待ちに戻す
+    pub fn requeue_http_log(entries: Vec<HttpLogEntry>) {
+        IDPS_ENGINE.get_or_init(IdpsEngine::default).requeue_http_log(entries);
+    }
+
+    /// anomaly_baselinesテーブルへ保存する、前回の保存以降に更新されたベースラインを返す
+    pub fn updated_anomaly_baselines() -> Vec<HostBaseline> {
+        IDPS_ENGINE.get_or_init(IdpsEngine::default).updated_baselines()
+    }
+
+    /// anomaly_baselinesテーブルへ保存したベースラインを保存済みにする
+    pub fn mark_anomaly_baselines_saved(baselines: &[HostBaseline]) {
+        IDPS_ENGINE.get_or_init(IdpsEngine::default).mark_baselines_saved(baselines);
+    }
+
+    /// データベースに保存されていたベースラインから異常検知の学習を再開する
+    pub fn restore_anomaly_baselines(baselines: Vec<HostBaseline>) {
+        IDPS_ENGINE.get_or_init(IdpsEngine::default).restore_baselines(baselines);
+    }
+
+    /// 保存されないパケットのアラートをパケットIDなしで書き込むよう登録する
+    pub fn queue_alerts(alerts: Vec<Alert>) {
+        IDPS_ENGINE.get_or_init(IdpsEngine::default).queue_alerts(alerts);
+    }
+
+    /// idps_alertsテーブルへの書き込みを待っているアラートを取り出す
+    pub fn drain_alerts() -> Vec<Alert> {
+        IDPS_ENGINE.get_or_init(IdpsEngine::default).drain_alerts()
+    }
+
+    /// 一時的な遮断ルールを期限より前に削除する
+    pub fn remove_temporary_block(direction: FirewallDirection, filter: &Filter) -> Result<bool, FirewallError> {
+        let mut fw = firewall(direction).write().map_err(|_| FirewallError::LockError)?;
+        Ok(fw.remove_temporary_block(filter))
+    }
+
+    pub fn temporary_blocks(direction: FirewallDirection) -> Result<Vec<TemporaryBlockStatus>, FirewallError> {
+        firewall(direction).read().map(|fw| fw.temporary_blocks()).map_err(|_| FirewallError::LockError)
+    }
+
+    /// firewall_blocksテーブルへの書き込みを待っている遮断ルールを取り出す
+    pub fn drain_blocks() -> Vec<(FirewallDirection, TemporaryBlock)> {
+        match PENDING_BLOCKS.lock() {
+            Ok(mut pending) => pending.drain().map(|((direction, _), block)| (direction, block)).collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// 書き込みに失敗した遮断ルールを書き込み待ちに戻す
+    pub fn requeue_blocks(blocks: Vec<(FirewallDirection, TemporaryBlock)>) {
+        for (direction, block) in blocks {
+            Self::queue_block(direction, block);
+        }
+    }
+
+    fn queue_block(direction: FirewallDirection, block: TemporaryBlock) {
+        let Ok(mut pending) = PENDING_BLOCKS.lock() else {
+            return;
+        };
+        let key = (direction, block.filter.clone());
+        let available = pending.len() < MAX_PENDING_BLOCKS;
+        match pending.get_mut(&key) {
+            Some(queued) => {
+                queued.duration = queued.duration.max(block.duration);
+                queued.reason = block.reason;
+            },
+            None if available => {
+                pending.insert(key, block);
+            },
+            None => warn!("遮断ルールの書き込み待ちが上限に達した為、書き込みません: {}", block.filter),
+        }
+    }
+
+    /// データベースから取得したフレームをLANへ注入してよいか判定する
+    /// IPv4フラグメントは先頭以外にポートがなくポートのルールを回避できる為、全て揃ってからデータグラム全体で判定する
+    pub async fn check_ingress(ethernet_frame: &[u8], src_node_id: i16) -> IngressVerdict {
+        Self::check_ingress_with(&INGRESS_FIREWALL, &INGRESS_REASSEMBLER, ethernet_frame, src_node_id).await
+    }
+
+    async fn check_ingress_with(fw: &RwLock<IpFirewall>, reassembler: &FragmentReassembler, ethernet_frame: &[u8], src_node_id: i16) -> IngressVerdict {
+        if !Self::is_ipv4_fragment(ethernet_frame) {
+            return if Self::check_ingress_frame(fw, ethernet_frame, src_node_id).await {
+                IngressVerdict::Accept
+            } else {
+                IngressVerdict::Reject
+            };
+        }
+
+        match reassembler.process(ethernet_frame) {
+            Reassembly::Incomplete => IngressVerdict::Held,
+            // 受信側では検知を行わない為、アラートはIDPSログへの出力のみとする
+            Reassembly::Invalid(_) => IngressVerdict::Reject,
+            Reassembly::Complete { datagram, fragments } => {
+                if Self::check_ingress_frame(fw, &datagram, src_node_id).await {
+                    IngressVerdict::AcceptFragments(fragments)
+                } else {
+                    IngressVerdict::Reject
+                }
+            },
+        }
+    }
+
+    async fn check_ingress_frame(fw: &RwLock<IpFirewall>, ethernet_frame: &[u8], src_node_id: i16) -> bool {
+        let mut inspection = match Self::inspect(ethernet_frame, Some(src_node_id)).await {
             Ok(result) => result,
-            Err(_) => return AnalyzeResult::Reject,
+            Err(_) => return false,
         };
+        // 受信側ではストリームを再構築しない為、1つのセグメントに収まったClientHelloのみを対象とする
+        if let Some(hello) = Self::parse_tls(&inspection.firewall_packet, &ethernet_frame[inspection.payload.clone()], None) {
+            inspection.firewall_packet.sni = hello.sni;
+        }
 
-        // IPパケットの解析
-        let (src_ip, dst_ip, ip_protocol, src_port, dst_port, flags) = match parse_ip_packet(ethernet_frame, ethernet_header.ether_type).await {
+        Self::check_firewall(fw, FirewallDirection::Ingress, &inspection.firewall_packet)
+    }
+
+    fn is_ipv4_fragment(ethernet_frame: &[u8]) -> bool {
+        let is_ipv4 = ethernet_frame.len() > 14 && u16::from_be_bytes([ethernet_frame[12], ethernet_frame[13]]) == EtherType::IP_V4.value();
+        is_ipv4 && FragmentReassembler::is_fragment(&ethernet_frame[14..])
+    }
+
+    pub async fn analyze_packet(ethernet_frame: &[u8]) -> AnalyzeResult {
+        // IPv4フラグメントは全て揃ってからデータグラム全体で判定する
+        if !Self::is_ipv4_fragment(ethernet_frame) {
+            return Self::analyze_frame(ethernet_frame).await;
+        }
+
+        match REASSEMBLER.process(ethernet_frame) {
+            Reassembly::Incomplete => AnalyzeResult::Held,
+            Reassembly::Invalid(alert) => {
+                if let Some(alert) = alert {
+                    Self::apply_blocks(IDPS_ENGINE.get_or_init(IdpsEngine::default).report(vec![alert]));
+                }
+                AnalyzeResult::Reject
+            },
+            Reassembly::Complete { datagram, fragments } => match Self::analyze_frame(&datagram).await {
+                // 転送先でMTUを超えないよう、再構築したデータグラムではなく元のフラグメントを保存する
+                // (アラートは先頭のフラグメントに関連付ける)
+                AnalyzeResult::Accept(mut packet_data) => {
+                    let alerts = std::mem::take(&mut packet_data.alerts);
+                    let mut fragments: Vec<PacketData> = fragments
+                        .into_iter()
+                        .map(|raw_packet| PacketData {
+                            raw_packet,
+                            ..(*packet_data).clone()
+                        })
+                        .collect();
+                    if let Some(first) = fragments.first_mut() {
+                        first.alerts = alerts;
+                    }
+                    AnalyzeResult::AcceptFragments(fragments)
+                },
+                result => result,
+            },
+        }
+    }
+
+    async fn analyze_frame(ethernet_frame: &[u8]) -> AnalyzeResult {
+        let Inspection {
+            ethernet_header,
+            mut firewall_packet,
+            checksum_failures,
+            payload,
+        } = match Self::inspect(ethernet_frame, None).await {
             Ok(result) => result,
             Err(e) => return e,
         };
 
-        // Firewallチェック
-        let firewall_packet = FirewallPacket::from_packet(
-            ethernet_header.src_mac.clone(),
-            ethernet_header.dst_mac.clone(),
-            ethernet_header.ether_type,
-            src_ip,
-            dst_ip,
-            ip_protocol,
-            src_port,
-            dst_port,
-        );
-        if !FIREWALL.check(&firewall_packet) {
+        // チェックサムの不一致があればファイアウォールより前に処理する
+        // (破棄するパケットでコネクション追跡の状態を更新しない為)
+        if !Self::check_checksum(&firewall_packet, &checksum_failures) {
             return AnalyzeResult::Reject;
         }
 
-        if ethernet_header.ether_type == EtherType::IP_V6 {
+        // ファイアウォールで拒否されるパケットも含めて観測した通信量を集計する (Ethernetヘッダを除いたIP層の長さ)
+        FLOW_TABLE.get_or_init(FlowTable::default).update(&firewall_packet, ethernet_frame.len().saturating_sub(14));
+
+        // ファイアウォールで拒否されるパケットも検知の対象とする
+        let payload = &ethernet_frame[payload];
+        let mut alerts = Vec::new();
+        let reassembler = STREAM_REASSEMBLER.get_or_init(StreamReassembler::default);
+        let stream = reassembler.process(&firewall_packet, payload, &mut alerts);
+        // ハンドシェイクはフローの方向毎に1度だけ解析し、SNIは再送を含む以降の全てのパケットに適用する
+        let tls = match &stream {
+            Some(stream) if stream.hello_parsed => None,
+            _ => Self::parse_tls(&firewall_packet, payload, stream.as_ref()),
+        };
+        firewall_packet.sni = match tls.as_ref().filter(|hello| hello.kind == TlsHelloKind::Client) {
+            Some(hello) => hello.sni.clone(),
+            None => stream.as_ref().and_then(|stream| stream.sni.clone()),
+        };
+        // ファイアウォールの判定には常に使用し、ログとEVEのイベントは初回のみ出力する
+        let tls = tls.filter(|hello| stream.is_none() || reassembler.record_tls_hello(&firewall_packet, hello));
+        let http = match firewall_packet.transport {
+            TransportHeader::Tcp(_) => parse_http_messages(payload, stream.as_ref()),
+            _ => Vec::new(),
+        };
+        let conn_state = CONNTRACK.lookup(&firewall_packet);
+        let context = InspectContext {
+            packet: &firewall_packet,
+            ethernet_frame,
+            payload,
+            stream: stream.as_ref(),
+            tls: tls.as_ref(),
+            http: &http,
+            conn_state,
+            // 追跡していない新規の接続は送信元を接続の開始側とみなす
+            from_client: CONNTRACK.is_from_initiator(&firewall_packet).or(stream.as_ref().map(|stream| stream.from_client)).or((conn_state == ConnState::New).then_some(true)),
+        };
+        let engine = IDPS_ENGINE.get_or_init(IdpsEngine::default);
+        let verdict = engine.inspect(&context, &mut alerts);
+        Self::apply_blocks(verdict.blocks);
+
+        // シグネチャでdropが指定されたフレーム、Firewallで拒否されたフレームとIPv6はPacketBufferへ渡さない
+        // (保存されないフレームのアラートはパケットIDなしで書き込む)
+        if verdict.drop || !Self::check_firewall(&EGRESS_FIREWALL, FirewallDirection::Egress, &firewall_packet) || ethernet_header.ether_type == EtherType::IP_V6 {
+            engine.queue_alerts(alerts);
             return AnalyzeResult::Reject;
         }
 
+        let FirewallPacket {
+            src_ip,
+            dst_ip,
+            ip_protocol,
+            transport,
+            ..
+        } = firewall_packet;
+        let (src_port, dst_port, flags) = (transport.src_port(), transport.dst_port(), transport.tcp_flags());
+
         trace!(
             "Transport: {}:{} -> {}:{}, Flags: SYN={}, ACK={}, RST={}, FIN={}",
             src_ip,
@@ -88,7 +460,7 @@ impl PacketAnalyzer {
             flags & 0x01 != 0  // FIN
         );
 
-        AnalyzeResult::Accept(PacketData {
+        AnalyzeResult::Accept(Box::new(PacketData {
             src_mac: ethernet_header.src_mac,
             dst_mac: ethernet_header.dst_mac,
             ether_type: ethernet_header.ether_type,
@@ -97,8 +469,187 @@ impl PacketAnalyzer {
             src_port: src_port as i32,
             dst_port: dst_port as i32,
             ip_protocol,
+            transport,
             timestamp: Utc::now(),
             raw_packet: ethernet_frame.to_vec(),
+            alerts,
+        }))
+    }
+
+    // フレームを解析してファイアウォールで判定できる形に変換する
+    async fn inspect(ethernet_frame: &[u8], src_node_id: Option<i16>) -> Result<Inspection, AnalyzeResult> {
+        // 基本的な長さチェック
+        if ethernet_frame.len() < 14 + 20 {
+            idps_log!("パケットが短すぎます: パケット長={}、期待値={}", ethernet_frame.len(), 14 + 20);
+            return Err(AnalyzeResult::Reject);
+        }
+
+        // Ethernetヘッダーの解析
+        let ethernet_header = parse_ethernet_header(ethernet_frame)?;
+
+        // IPパケットの解析
+        let IpPacket {
+            src_ip,
+            dst_ip,
+            ip_protocol,
+            transport,
+            checksum_failures,
+            payload,
+        } = parse_ip_packet(ethernet_frame, ethernet_header.ether_type).await?;
+
+        let firewall_packet = FirewallPacket::from_packet(
+            ethernet_header.src_mac.clone(),
+            ethernet_header.dst_mac.clone(),
+            ethernet_header.ether_type,
+            src_ip,
+            dst_ip,
+            ip_protocol,
+            transport,
+            related_flow(&ethernet_frame[14..]),
+            ethernet_frame.len(),
+            src_node_id,
+        );
+
+        Ok(Inspection {
+            ethernet_header,
+            firewall_packet,
+            checksum_failures,
+            payload,
+        })
+    }
+
+    // TCPのペイロードからTLSのClientHello/ServerHelloを取り出す
+    fn parse_tls(firewall_packet: &FirewallPacket, payload: &[u8], stream: Option<&StreamChunk>) -> Option<TlsHello> {
+        if !matches!(firewall_packet.transport, TransportHeader::Tcp(_)) {
+            return None;
+        }
+        parse_tls_hello(payload, stream)
+    }
+
+    // チェックサムの不一致を設定に応じて記録し、パケットを通過させてよいかを返す
+    fn check_checksum(firewall_packet: &FirewallPacket, failures: &[ChecksumFailure]) -> bool {
+        if failures.is_empty() {
+            return true;
+        }
+
+        let policy = CHECKSUM_POLICY.get_or_init(ChecksumPolicy::default);
+        let action = policy.config.action;
+        if action == ChecksumAction::Ignore {
+            return true;
+        }
+
+        // 自身が送信したフレームはNICでチェックサムが計算される前に取得されている場合がある
+        if policy.config.offload_aware && policy.local_mac.as_ref() == Some(&firewall_packet.src_mac) {
+            CHECKSUM_COUNTERS.record_offloaded();
+            trace!("オフロードの可能性がある為チェックサム不一致を無視します: src_mac={}", firewall_packet.src_mac);
+            return true;
+        }
+
+        for failure in failures {
+            CHECKSUM_COUNTERS.record(failure.layer);
+
+            if matches!(action, ChecksumAction::Log | ChecksumAction::Drop) {
+                idps_log!(
+                    "{}: {} -> {}, {}:{} -> {}:{}, protocol={}, ip_version={}, フレーム長={}, 処理={}",
+                    failure,
+                    firewall_packet.src_mac,
+                    firewall_packet.dst_mac,
+                    firewall_packet.src_ip,
+                    firewall_packet.transport.src_port(),
+                    firewall_packet.dst_ip,
+                    firewall_packet.transport.dst_port(),
+                    firewall_packet.ip_protocol.value(),
+                    firewall_packet.ip_version,
+                    firewall_packet.length,
+                    if action == ChecksumAction::Drop { "破棄" } else { "通過" }
+                );
+            }
+        }
+
+        action != ChecksumAction::Drop
+    }
+
+    /// 期限付きの遮断ルールをファイアウォールへ追加する (既にある場合は期限を延長する)
+    pub fn add_temporary_block(direction: FirewallDirection, block: TemporaryBlock) {
+        let Ok(mut fw) = firewall(direction).write() else {
+            error!("ファイアウォールのロックに失敗しました: {}", direction);
+            return;
+        };
+
+        let TemporaryBlock { filter, duration, reason } = block;
+        let description = filter.to_string();
+        if fw.add_temporary_block(filter, duration, reason.clone()) {
+            idps_log!(
+                "{}ファイアウォールに一時的な遮断ルールを追加しました: {}, 期間={}秒, 理由={}",
+                direction,
+                description,
+                duration.as_secs(),
+                reason
+            );
+        }
+    }
+
+    // 検知器が要求した遮断ルールを送信側のファイアウォールへ追加し、firewall_blocksテーブルへの書き込みを待つ
+    fn apply_blocks(blocks: Vec<TemporaryBlock>) {
+        for block in blocks {
+            Self::add_temporary_block(FirewallDirection::Egress, block.clone());
+            Self::queue_block(FirewallDirection::Egress, block);
+        }
+    }
+
+    fn check_firewall(fw: &RwLock<IpFirewall>, direction: FirewallDirection, firewall_packet: &FirewallPacket) -> bool {
+        match fw.read() {
+            Ok(fw) => fw.check(firewall_packet),
+            Err(_) => {
+                error!("ファイアウォールのロックに失敗しました: {}", direction);
+                false
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 宛先ポートがdst_portのUDPデータグラムを2つのフラグメントに分割する
+    fn udp_fragments(id: u16, dst_port: u16) -> [Vec<u8>; 2] {
+        let mut udp = [0u8; 16];
+        udp[0..2].copy_from_slice(&5353u16.to_be_bytes());
+        udp[2..4].copy_from_slice(&dst_port.to_be_bytes());
+        udp[4..6].copy_from_slice(&24u16.to_be_bytes());
+        let second = [0x41u8; 8];
+
+        [(0u16, true, &udp[..]), (2, false, &second[..])].map(|(offset, more_fragments, payload)| {
+            let mut frame = vec![0u8; 12];
+            frame.extend_from_slice(&[0x08, 0x00, 0x45, 0x00]);
+            frame.extend_from_slice(&((20 + payload.len()) as u16).to_be_bytes());
+            frame.extend_from_slice(&id.to_be_bytes());
+            frame.extend_from_slice(&(offset | if more_fragments { 0x2000 } else { 0 }).to_be_bytes());
+            frame.extend_from_slice(&[64, 17, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2]);
+            frame.extend_from_slice(payload);
+            frame
         })
     }
+
+    #[tokio::test]
+    async fn checks_ingress_fragments_against_port_rules_after_reassembly() {
+        // 他のテストと共有しないよう、グローバルなファイアウォールではなくローカルのものを使用する
+        let mut ingress = IpFirewall::new(Policy::Blacklist, Arc::new(ConnectionTracker::new()));
+        ingress.add_rule(Filter::DstPort(22), 10);
+        let fw = RwLock::new(ingress);
+        let reassembler = FragmentReassembler::new();
+
+        // ポートを持たない後続のフラグメントだけでは許可しない
+        let [first, second] = udp_fragments(1, 22);
+        assert!(matches!(PacketAnalyzer::check_ingress_with(&fw, &reassembler, &second, 2).await, IngressVerdict::Held));
+        assert!(matches!(PacketAnalyzer::check_ingress_with(&fw, &reassembler, &first, 2).await, IngressVerdict::Reject));
+
+        let [first, second] = udp_fragments(2, 53);
+        assert!(matches!(PacketAnalyzer::check_ingress_with(&fw, &reassembler, &first, 2).await, IngressVerdict::Held));
+        match PacketAnalyzer::check_ingress_with(&fw, &reassembler, &second, 2).await {
+            IngressVerdict::AcceptFragments(fragments) => assert_eq!(fragments, vec![first, second]),
+            _ => panic!("フラグメントが許可されていません"),
+        }
+    }
 }
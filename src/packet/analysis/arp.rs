@@ -0,0 +1,125 @@
+use crate::idps_log;
+use crate::packet::types::MacAddr;
+use std::net::Ipv4Addr;
+
+// Ethernet/IPv4のARPパケット長
+const ARP_PACKET_LENGTH: usize = 28;
+const HARDWARE_TYPE_ETHERNET: u16 = 1;
+const PROTOCOL_TYPE_IPV4: u16 = 0x0800;
+
+#[derive(Debug, Clone)]
+pub struct ArpPacket {
+    // 1: 要求, 2: 応答
+    pub operation: u16,
+    pub sender_mac: MacAddr,
+    pub sender_ip: Ipv4Addr,
+    pub target_ip: Ipv4Addr,
+}
+
+impl ArpPacket {
+    /// 送信元と宛先のIPが同じ場合はGratuitous ARP
+    pub fn is_gratuitous(&self) -> bool {
+        self.sender_ip == self.target_ip && !self.sender_ip.is_unspecified()
+    }
+
+    /// 重複アドレス検出(DAD)のプローブは送信元IPが0.0.0.0
+    pub fn is_probe(&self) -> bool {
+        self.sender_ip.is_unspecified()
+    }
+}
+
+/// Ethernetヘッダ以降のデータをEthernet/IPv4のARPとして解析する
+pub fn parse_arp_packet(data: &[u8]) -> Option<ArpPacket> {
+    if data.len() < ARP_PACKET_LENGTH {
+        idps_log!("ARPパケットが短すぎます: {} バイト < 必要な{}バイト", data.len(), ARP_PACKET_LENGTH);
+        return None;
+    }
+
+    let hardware_type = u16::from_be_bytes([data[0], data[1]]);
+    let protocol_type = u16::from_be_bytes([data[2], data[3]]);
+    if hardware_type != HARDWARE_TYPE_ETHERNET || protocol_type != PROTOCOL_TYPE_IPV4 || data[4] != 6 || data[5] != 4 {
+        idps_log!(
+            "未対応のARPパケットです: hardware_type={}, protocol_type=0x{:04x}, hlen={}, plen={}",
+            hardware_type,
+            protocol_type,
+            data[4],
+            data[5]
+        );
+        return None;
+    }
+
+    Some(ArpPacket {
+        operation: u16::from_be_bytes([data[6], data[7]]),
+        sender_mac: MacAddr([data[8], data[9], data[10], data[11], data[12], data[13]]),
+        sender_ip: Ipv4Addr::new(data[14], data[15], data[16], data[17]),
+        // data[18..24]は宛先MAC (要求では未設定の為使用しない)
+        target_ip: Ipv4Addr::new(data[24], data[25], data[26], data[27]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Ethernet/IPv4のARPパケットを組み立てる
+    fn arp(operation: u16, sender_ip: [u8; 4], target_ip: [u8; 4]) -> Vec<u8> {
+        let mut data = vec![0, 1, 0x08, 0x00, 6, 4];
+        data.extend_from_slice(&operation.to_be_bytes());
+        data.extend_from_slice(&[0x02, 0x00, 0x00, 0xaa, 0xbb, 0xcc]);
+        data.extend_from_slice(&sender_ip);
+        data.extend_from_slice(&[0; 6]);
+        data.extend_from_slice(&target_ip);
+        data
+    }
+
+    #[test]
+    fn parses_request_and_reply() {
+        let request = parse_arp_packet(&arp(1, [192, 168, 0, 10], [192, 168, 0, 1])).unwrap();
+        assert_eq!(request.operation, 1);
+        assert_eq!(request.sender_mac, MacAddr([0x02, 0x00, 0x00, 0xaa, 0xbb, 0xcc]));
+        assert_eq!(request.sender_ip, Ipv4Addr::new(192, 168, 0, 10));
+        assert_eq!(request.target_ip, Ipv4Addr::new(192, 168, 0, 1));
+        assert!(!request.is_gratuitous());
+        assert!(!request.is_probe());
+
+        let reply = parse_arp_packet(&arp(2, [192, 168, 0, 1], [192, 168, 0, 10])).unwrap();
+        assert_eq!(reply.operation, 2);
+    }
+
+    #[test]
+    fn detects_gratuitous_arp_and_probe() {
+        let gratuitous = parse_arp_packet(&arp(1, [192, 168, 0, 10], [192, 168, 0, 10])).unwrap();
+        assert!(gratuitous.is_gratuitous());
+        assert!(!gratuitous.is_probe());
+
+        let probe = parse_arp_packet(&arp(1, [0, 0, 0, 0], [192, 168, 0, 10])).unwrap();
+        assert!(probe.is_probe());
+        assert!(!probe.is_gratuitous());
+    }
+
+    #[test]
+    fn ignores_ethernet_padding() {
+        let mut data = arp(1, [192, 168, 0, 10], [192, 168, 0, 1]);
+        data.resize(46, 0);
+        assert!(parse_arp_packet(&data).is_some());
+    }
+
+    #[test]
+    fn rejects_truncated_packet() {
+        let data = arp(1, [192, 168, 0, 10], [192, 168, 0, 1]);
+        for length in 0..ARP_PACKET_LENGTH {
+            assert!(parse_arp_packet(&data[..length]).is_none(), "length {}", length);
+        }
+    }
+
+    #[test]
+    fn rejects_unsupported_hardware_and_protocol() {
+        let valid = arp(1, [192, 168, 0, 10], [192, 168, 0, 1]);
+        // hardware_type, protocol_type, hlen, plenをそれぞれ書き換える
+        for (index, value) in [(1, 6), (2, 0x86), (4, 8), (5, 16)] {
+            let mut data = valid.clone();
+            data[index] = value;
+            assert!(parse_arp_packet(&data).is_none(), "index {}", index);
+        }
+    }
+}
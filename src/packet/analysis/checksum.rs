@@ -0,0 +1,215 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// チェックサムを検証したプロトコル層
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumLayer {
+    Ipv4,
+    Tcp,
+    Udp,
+}
+
+impl fmt::Display for ChecksumLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChecksumLayer::Ipv4 => write!(f, "IPv4"),
+            ChecksumLayer::Tcp => write!(f, "TCP"),
+            ChecksumLayer::Udp => write!(f, "UDP"),
+        }
+    }
+}
+
+/// チェックサムの不一致
+#[derive(Debug, Clone, Copy)]
+pub struct ChecksumFailure {
+    pub layer: ChecksumLayer,
+    // パケットに格納されていた値
+    pub packet_checksum: u16,
+    // 受信したデータから計算した値
+    pub calculated_checksum: u16,
+}
+
+impl fmt::Display for ChecksumFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}チェックサム不一致 (パケット内=0x{:04x}, 計算値=0x{:04x})",
+            self.layer, self.packet_checksum, self.calculated_checksum
+        )
+    }
+}
+
+/// プロトコル層毎のチェックサム不一致件数
+#[derive(Debug, Default)]
+pub struct ChecksumCounters {
+    ipv4: AtomicU64,
+    tcp: AtomicU64,
+    udp: AtomicU64,
+    // オフロードの可能性がある為に無視した件数
+    offloaded: AtomicU64,
+}
+
+impl ChecksumCounters {
+    pub fn record(&self, layer: ChecksumLayer) {
+        let counter = match layer {
+            ChecksumLayer::Ipv4 => &self.ipv4,
+            ChecksumLayer::Tcp => &self.tcp,
+            ChecksumLayer::Udp => &self.udp,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_offloaded(&self) {
+        self.offloaded.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> ChecksumStats {
+        ChecksumStats {
+            ipv4: self.ipv4.load(Ordering::Relaxed),
+            tcp: self.tcp.load(Ordering::Relaxed),
+            udp: self.udp.load(Ordering::Relaxed),
+            offloaded: self.offloaded.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ChecksumStats {
+    pub ipv4: u64,
+    pub tcp: u64,
+    pub udp: u64,
+    pub offloaded: u64,
+}
+
+/// RFC 1071 のインターネットチェックサムを計算する
+/// (チェックサムフィールドを含めたまま計算した場合、正しければ0になる)
+pub fn internet_checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum = 0u32;
+    for data in parts {
+        for chunk in data.chunks(2) {
+            if chunk.len() == 2 {
+                sum += u16::from_be_bytes([chunk[0], chunk[1]]) as u32;
+            } else {
+                sum += (chunk[0] as u32) << 8;
+            }
+        }
+
+        // キャリーの処理
+        while sum >> 16 != 0 {
+            sum = (sum & 0xFFFF) + (sum >> 16);
+        }
+    }
+
+    !(sum as u16)
+}
+
+/// TCP/UDPのチェックサム計算に使用する疑似ヘッダを生成する
+pub fn pseudo_header(src_ip: IpAddr, dst_ip: IpAddr, ip_protocol: u8, length: usize) -> Vec<u8> {
+    let mut header = Vec::with_capacity(40);
+    match (src_ip, dst_ip) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            header.extend_from_slice(&src.octets());
+            header.extend_from_slice(&dst.octets());
+            header.push(0); // 予約済み
+            header.push(ip_protocol);
+            header.extend_from_slice(&(length as u16).to_be_bytes());
+        },
+        (IpAddr::V6(src), IpAddr::V6(dst)) => {
+            header.extend_from_slice(&src.octets());
+            header.extend_from_slice(&dst.octets());
+            header.extend_from_slice(&(length as u32).to_be_bytes());
+            header.extend_from_slice(&[0, 0, 0]); // 予約済み
+            header.push(ip_protocol);
+        },
+        // 送信元と宛先でバージョンが異なることはない
+        _ => {},
+    }
+    header
+}
+
+/// チェックサムフィールドを0として計算し、パケット内の値と比較する
+/// チェックサムフィールドを含まない長さのデータは検証できない為、不一致とする (パケット内の値は0とする)
+pub fn verify(layer: ChecksumLayer, pseudo_header: &[u8], data: &[u8], checksum_offset: usize) -> Option<ChecksumFailure> {
+    if data.len() < checksum_offset + 2 {
+        return Some(ChecksumFailure {
+            layer,
+            packet_checksum: 0,
+            calculated_checksum: internet_checksum(&[pseudo_header, data]),
+        });
+    }
+
+    let packet_checksum = u16::from_be_bytes([data[checksum_offset], data[checksum_offset + 1]]);
+    let calculated_checksum = internet_checksum(&[pseudo_header, &data[..checksum_offset], &[0, 0], &data[checksum_offset + 2..]]);
+
+    // UDPでは計算結果が0の場合0xFFFFとして送信される
+    let calculated_checksum = if layer == ChecksumLayer::Udp && calculated_checksum == 0 {
+        0xFFFF
+    } else {
+        calculated_checksum
+    };
+
+    if packet_checksum == calculated_checksum {
+        None
+    } else {
+        Some(ChecksumFailure {
+            layer,
+            packet_checksum,
+            calculated_checksum,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // チェックサムが0xb861のIPv4ヘッダ
+    const IPV4_HEADER: [u8; 20] = [
+        0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0xb8, 0x61, 0xc0, 0xa8, 0x00, 0x01, 0xc0, 0xa8, 0x00, 0xc7,
+    ];
+
+    #[test]
+    fn computes_internet_checksum() {
+        let mut header = IPV4_HEADER;
+        header[10..12].copy_from_slice(&[0, 0]);
+        assert_eq!(internet_checksum(&[&header]), 0xb861);
+        // 正しいチェックサムを含めて計算すると0になる
+        assert_eq!(internet_checksum(&[&IPV4_HEADER]), 0);
+        // 奇数長の末尾は下位を0で埋める
+        assert_eq!(internet_checksum(&[&[0x01, 0x02, 0x03]]), !0x0402);
+        // 分割しても同じ値になる
+        assert_eq!(internet_checksum(&[&header[..10], &header[10..]]), 0xb861);
+    }
+
+    #[test]
+    fn verifies_checksum_field() {
+        assert!(verify(ChecksumLayer::Ipv4, &[], &IPV4_HEADER, 10).is_none());
+
+        let mut header = IPV4_HEADER;
+        header[8] = 0x3f;
+        let failure = verify(ChecksumLayer::Ipv4, &[], &header, 10).expect("不一致を検出できません");
+        assert_eq!(failure.layer, ChecksumLayer::Ipv4);
+        assert_eq!(failure.packet_checksum, 0xb861);
+        assert_eq!(failure.calculated_checksum, 0xb961);
+    }
+
+    #[test]
+    fn fails_when_checksum_field_is_missing() {
+        assert!(verify(ChecksumLayer::Ipv4, &[], &IPV4_HEADER[..11], 10).is_some());
+        assert!(verify(ChecksumLayer::Tcp, &[], &[], 16).is_some());
+    }
+
+    #[test]
+    fn verifies_udp_checksum_with_pseudo_header() {
+        let pseudo_header = pseudo_header(IpAddr::from([10, 0, 0, 1]), IpAddr::from([10, 0, 0, 2]), 17, 8);
+        let mut datagram = [0x00, 0x35, 0x00, 0x35, 0x00, 0x08, 0x00, 0x00];
+        let sum = internet_checksum(&[&pseudo_header, &datagram]);
+        datagram[6..8].copy_from_slice(&sum.to_be_bytes());
+        assert_eq!(pseudo_header.len(), 12);
+        assert!(verify(ChecksumLayer::Udp, &pseudo_header, &datagram, 6).is_none());
+
+        datagram[0] = 0x01;
+        assert!(verify(ChecksumLayer::Udp, &pseudo_header, &datagram, 6).is_some());
+    }
+}
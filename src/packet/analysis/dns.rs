@@ -0,0 +1,254 @@
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+pub const DNS_PORT: u16 = 53;
+
+const DNS_HEADER_LENGTH: usize = 12;
+// 名前の圧縮ポインタを辿る回数の上限 (ループ対策)
+const MAX_POINTER_JUMPS: usize = 16;
+const MAX_NAME_LENGTH: usize = 255;
+// 1メッセージあたりに解析するレコード数の上限
+const MAX_RECORDS: usize = 64;
+
+/// DNSのレコード種別
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DnsRecordType(pub u16);
+
+impl DnsRecordType {
+    pub const A: Self = Self(1);
+    pub const NS: Self = Self(2);
+    pub const CNAME: Self = Self(5);
+    pub const SOA: Self = Self(6);
+    pub const NULL: Self = Self(10);
+    pub const PTR: Self = Self(12);
+    pub const MX: Self = Self(15);
+    pub const TXT: Self = Self(16);
+    pub const AAAA: Self = Self(28);
+    pub const SRV: Self = Self(33);
+    pub const HTTPS: Self = Self(65);
+    pub const ANY: Self = Self(255);
+}
+
+impl fmt::Display for DnsRecordType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match *self {
+            Self::A => "A",
+            Self::NS => "NS",
+            Self::CNAME => "CNAME",
+            Self::SOA => "SOA",
+            Self::NULL => "NULL",
+            Self::PTR => "PTR",
+            Self::MX => "MX",
+            Self::TXT => "TXT",
+            Self::AAAA => "AAAA",
+            Self::SRV => "SRV",
+            Self::HTTPS => "HTTPS",
+            Self::ANY => "ANY",
+            Self(value) => return write!(f, "TYPE{}", value),
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DnsQuestion {
+    pub name: String,
+    pub record_type: DnsRecordType,
+}
+
+#[derive(Debug, Clone)]
+pub struct DnsRecord {
+    pub name: String,
+    pub record_type: DnsRecordType,
+    pub ttl: u32,
+    // 表示用に整形したRDATA
+    pub data: String,
+}
+
+impl fmt::Display for DnsRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // ゾーンファイルと同じ形式
+        write!(f, "{} {} {} {}", self.name, self.ttl, self.record_type, self.data)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DnsMessage {
+    pub id: u16,
+    pub is_response: bool,
+    pub rcode: u8,
+    pub questions: Vec<DnsQuestion>,
+    pub answers: Vec<DnsRecord>,
+}
+
+/// UDPペイロードをDNSメッセージとして解析する
+pub fn parse_dns_message(data: &[u8]) -> Option<DnsMessage> {
+    if data.len() < DNS_HEADER_LENGTH {
+        return None;
+    }
+
+    let flags = u16::from_be_bytes([data[2], data[3]]);
+    let question_count = u16::from_be_bytes([data[4], data[5]]) as usize;
+    let answer_count = u16::from_be_bytes([data[6], data[7]]) as usize;
+    if question_count + answer_count > MAX_RECORDS {
+        return None;
+    }
+
+    let mut offset = DNS_HEADER_LENGTH;
+    let mut questions = Vec::with_capacity(question_count);
+    for _ in 0..question_count {
+        let (name, next) = read_name(data, offset)?;
+        let fixed = data.get(next..next + 4)?;
+        questions.push(DnsQuestion {
+            name,
+            record_type: DnsRecordType(u16::from_be_bytes([fixed[0], fixed[1]])),
+        });
+        offset = next + 4;
+    }
+
+    let mut answers = Vec::with_capacity(answer_count);
+    for _ in 0..answer_count {
+        let (name, next) = read_name(data, offset)?;
+        let fixed = data.get(next..next + 10)?;
+        let record_type = DnsRecordType(u16::from_be_bytes([fixed[0], fixed[1]]));
+        let ttl = u32::from_be_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]);
+        let length = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+        let rdata_offset = next + 10;
+        let rdata = data.get(rdata_offset..rdata_offset + length)?;
+        answers.push(DnsRecord {
+            name,
+            record_type,
+            ttl,
+            data: format_rdata(data, record_type, rdata_offset, rdata),
+        });
+        offset = rdata_offset + length;
+    }
+
+    Some(DnsMessage {
+        id: u16::from_be_bytes([data[0], data[1]]),
+        is_response: flags & 0x8000 != 0,
+        rcode: (flags & 0xF) as u8,
+        questions,
+        answers,
+    })
+}
+
+/// TCPのデータを2バイトの長さが前置されたDNSメッセージの並びとして解析する
+/// 解析したメッセージと、末尾の不完全なメッセージを除いて読み進めたバイト数を返す
+pub fn parse_dns_over_tcp(data: &[u8]) -> (Vec<DnsMessage>, usize) {
+    let mut messages = Vec::new();
+    let mut consumed = 0;
+    while let Some(prefix) = data.get(consumed..consumed + 2) {
+        let length = u16::from_be_bytes([prefix[0], prefix[1]]) as usize;
+        let Some(message) = data.get(consumed + 2..consumed + 2 + length) else {
+            break;
+        };
+        if let Some(message) = parse_dns_message(message) {
+            messages.push(message);
+        }
+        consumed += 2 + length;
+    }
+    (messages, consumed)
+}
+
+// 圧縮を考慮して名前を読み、名前の直後の位置を返す
+fn read_name(data: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut labels: Vec<String> = Vec::new();
+    let mut length = 0;
+    let mut end = None;
+    let mut jumps = 0;
+
+    loop {
+        let label_length = *data.get(offset)? as usize;
+        match label_length {
+            0 => break,
+            // 圧縮ポインタ
+            l if l & 0xC0 == 0xC0 => {
+                let pointer = ((l & 0x3F) << 8) | *data.get(offset + 1)? as usize;
+                end.get_or_insert(offset + 2);
+                jumps += 1;
+                if jumps > MAX_POINTER_JUMPS {
+                    return None;
+                }
+                offset = pointer;
+            },
+            l if l & 0xC0 != 0 => return None,
+            l => {
+                let label = data.get(offset + 1..offset + 1 + l)?;
+                length += l + 1;
+                if length > MAX_NAME_LENGTH {
+                    return None;
+                }
+                labels.push(escape_text(label));
+                offset += l + 1;
+            },
+        }
+    }
+
+    let name = if labels.is_empty() { ".".to_string() } else { labels.join(".") };
+    Some((name, end.unwrap_or(offset + 1)))
+}
+
+fn format_rdata(message: &[u8], record_type: DnsRecordType, offset: usize, rdata: &[u8]) -> String {
+    match record_type {
+        DnsRecordType::A if rdata.len() == 4 => Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3]).to_string(),
+        DnsRecordType::AAAA if rdata.len() == 16 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(rdata);
+            Ipv6Addr::from(octets).to_string()
+        },
+        DnsRecordType::NS | DnsRecordType::CNAME | DnsRecordType::PTR => read_name(message, offset).map(|(name, _)| name).unwrap_or_default(),
+        DnsRecordType::MX if rdata.len() > 2 => {
+            let preference = u16::from_be_bytes([rdata[0], rdata[1]]);
+            let exchange = read_name(message, offset + 2).map(|(name, _)| name).unwrap_or_default();
+            format!("{} {}", preference, exchange)
+        },
+        DnsRecordType::TXT => {
+            // 長さが前置された文字列の並び
+            let mut strings = Vec::new();
+            let mut rest = rdata;
+            while let Some((&length, tail)) = rest.split_first() {
+                let Some(text) = tail.get(..length as usize) else {
+                    break;
+                };
+                strings.push(escape_text(text));
+                rest = &tail[length as usize..];
+            }
+            strings.join("")
+        },
+        _ => format!("({} バイト)", rdata.len()),
+    }
+}
+
+// ラベルや文字列をログへ書き込める形に変換する
+// 制御文字 (PostgreSQLのTEXTに格納できないNULを含む) とバックスラッシュはゾーンファイルと同じ\DDD形式でエスケープする
+fn escape_text(data: &[u8]) -> String {
+    let mut escaped = String::with_capacity(data.len());
+    for c in String::from_utf8_lossy(data).chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\{:03}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_control_characters_in_names_and_txt() {
+        let mut message = vec![0x12, 0x34, 0x81, 0x80, 0, 1, 0, 1, 0, 0, 0, 0];
+        // 質問: "a\0b\\c.example" TXT
+        message.extend_from_slice(b"\x05a\0b\\c\x07example\x00\x00\x10\x00\x01");
+        // 回答: 質問の名前への圧縮ポインタ、改行を含むTXT
+        message.extend_from_slice(b"\xC0\x0C\x00\x10\x00\x01\x00\x00\x00\x3C\x00\x05\x04x\ny\0");
+
+        let message = parse_dns_message(&message).unwrap();
+        assert_eq!(message.questions[0].name, "a\\000b\\\\c.example");
+        assert_eq!(message.answers[0].data, "x\\010y\\000");
+        assert!(!message.answers[0].to_string().contains(['\0', '\n']));
+    }
+}
@@ -0,0 +1,518 @@
+use super::FirewallPacket;
+use crate::packet::types::IpProtocol;
+use log::{trace, warn};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// TCPフラグ
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+const TCP_ACK: u8 = 0x10;
+
+// 各状態のタイムアウト (netfilterのデフォルト値に準拠)
+const TCP_SYN_SENT_TIMEOUT: Duration = Duration::from_secs(120);
+const TCP_SYN_RECV_TIMEOUT: Duration = Duration::from_secs(60);
+const TCP_ESTABLISHED_TIMEOUT: Duration = Duration::from_secs(432_000);
+const TCP_FIN_WAIT_TIMEOUT: Duration = Duration::from_secs(120);
+const TCP_LAST_ACK_TIMEOUT: Duration = Duration::from_secs(30);
+const TCP_TIME_WAIT_TIMEOUT: Duration = Duration::from_secs(120);
+const TCP_CLOSE_TIMEOUT: Duration = Duration::from_secs(10);
+const UDP_UNREPLIED_TIMEOUT: Duration = Duration::from_secs(30);
+const UDP_REPLIED_TIMEOUT: Duration = Duration::from_secs(180);
+const ICMP_TIMEOUT: Duration = Duration::from_secs(30);
+const GENERIC_TIMEOUT: Duration = Duration::from_secs(600);
+
+// テーブルの上限と期限切れエントリの掃除間隔
+const MAX_ENTRIES: usize = 65536;
+const GC_INTERVAL: Duration = Duration::from_secs(10);
+
+/// ルールから参照できるコネクションの状態
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq)]
+pub enum ConnState {
+    New,
+    Established,
+    Related,
+    Invalid,
+}
+
+impl std::fmt::Display for ConnState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ConnState::New => "new",
+            ConnState::Established => "established",
+            ConnState::Related => "related",
+            ConnState::Invalid => "invalid",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// 5-tupleによるフローの識別子
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq)]
+pub struct FlowKey {
+    pub src_ip: IpAddr,
+    pub dst_ip: IpAddr,
+    pub ip_protocol: IpProtocol,
+    pub src_port: u16,
+    pub dst_port: u16,
+}
+
+impl FlowKey {
+    pub fn from_packet(packet: &FirewallPacket) -> Self {
+        // ICMPなどポートを持たないプロトコルはアドレスとプロトコルのみで識別する
+        let (src_port, dst_port) = if packet.ip_protocol.is_transport_protocol() {
+            (packet.transport.src_port(), packet.transport.dst_port())
+        } else {
+            (0, 0)
+        };
+
+        Self {
+            src_ip: packet.src_ip,
+            dst_ip: packet.dst_ip,
+            ip_protocol: packet.ip_protocol,
+            src_port,
+            dst_port,
+        }
+    }
+
+    pub fn reversed(&self) -> Self {
+        Self {
+            src_ip: self.dst_ip,
+            dst_ip: self.src_ip,
+            ip_protocol: self.ip_protocol,
+            src_port: self.dst_port,
+            dst_port: self.src_port,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TcpState {
+    SynSent,
+    SynRecv,
+    Established,
+    FinWait,
+    LastAck,
+    TimeWait,
+    Close,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Direction {
+    Original,
+    Reply,
+}
+
+#[derive(Debug)]
+struct ConnEntry {
+    tcp_state: Option<TcpState>,
+    fin_direction: Option<Direction>,
+    replied: bool,
+    expires_at: Instant,
+}
+
+impl ConnEntry {
+    fn timeout(&self, ip_protocol: IpProtocol) -> Duration {
+        match self.tcp_state {
+            Some(TcpState::SynSent) => TCP_SYN_SENT_TIMEOUT,
+            Some(TcpState::SynRecv) => TCP_SYN_RECV_TIMEOUT,
+            Some(TcpState::Established) => TCP_ESTABLISHED_TIMEOUT,
+            Some(TcpState::FinWait) => TCP_FIN_WAIT_TIMEOUT,
+            Some(TcpState::LastAck) => TCP_LAST_ACK_TIMEOUT,
+            Some(TcpState::TimeWait) => TCP_TIME_WAIT_TIMEOUT,
+            Some(TcpState::Close) => TCP_CLOSE_TIMEOUT,
+            None if ip_protocol == IpProtocol::UDP => {
+                if self.replied {
+                    UDP_REPLIED_TIMEOUT
+                } else {
+                    UDP_UNREPLIED_TIMEOUT
+                }
+            },
+            None if ip_protocol.is_icmp() => ICMP_TIMEOUT,
+            None => GENERIC_TIMEOUT,
+        }
+    }
+
+    fn update_tcp_state(&mut self, flags: u8, direction: Direction) {
+        let Some(state) = self.tcp_state else {
+            return;
+        };
+
+        let next = if flags & TCP_RST != 0 {
+            TcpState::Close
+        } else if flags & TCP_SYN != 0 {
+            match (state, direction, flags & TCP_ACK != 0) {
+                (TcpState::SynSent | TcpState::SynRecv, Direction::Reply, true) => TcpState::SynRecv,
+                (TcpState::SynSent, Direction::Original, false) => TcpState::SynSent,
+                // 終了済みのフローに対する同一5-tupleでの再接続
+                (TcpState::TimeWait | TcpState::Close, Direction::Original, false) => TcpState::SynSent,
+                _ => state,
+            }
+        } else if flags & TCP_FIN != 0 {
+            match state {
+                TcpState::SynRecv | TcpState::Established => {
+                    self.fin_direction = Some(direction);
+                    TcpState::FinWait
+                },
+                TcpState::FinWait if self.fin_direction != Some(direction) => TcpState::LastAck,
+                _ => state,
+            }
+        } else if flags & TCP_ACK != 0 {
+            match state {
+                TcpState::SynRecv if direction == Direction::Original => TcpState::Established,
+                TcpState::LastAck => TcpState::TimeWait,
+                _ => state,
+            }
+        } else {
+            state
+        };
+
+        if next != state {
+            trace!("TCPコネクションの状態遷移: {:?} -> {:?}", state, next);
+        }
+        self.tcp_state = Some(next);
+    }
+}
+
+struct ConnTable {
+    entries: HashMap<FlowKey, ConnEntry>,
+    last_gc: Instant,
+}
+
+impl ConnTable {
+    fn find(&self, key: &FlowKey, now: Instant) -> Option<(FlowKey, Direction)> {
+        let reversed = key.reversed();
+        [(*key, Direction::Original), (reversed, Direction::Reply)].into_iter().find(|(k, _)| self.entries.get(k).is_some_and(|entry| entry.expires_at > now))
+    }
+
+    fn collect_garbage(&mut self, now: Instant) {
+        if now.duration_since(self.last_gc) < GC_INTERVAL && self.entries.len() < MAX_ENTRIES {
+            return;
+        }
+        self.entries.retain(|_, entry| entry.expires_at > now);
+        self.last_gc = now;
+    }
+}
+
+/// 5-tupleをキーとしたコネクション追跡テーブル
+pub struct ConnectionTracker {
+    table: Mutex<ConnTable>,
+}
+
+impl std::fmt::Debug for ConnectionTracker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let len = self.table.lock().map(|table| table.entries.len()).unwrap_or(0);
+        f.debug_struct("ConnectionTracker").field("entries", &len).finish()
+    }
+}
+
+impl ConnectionTracker {
+    pub fn new() -> Self {
+        Self {
+            table: Mutex::new(ConnTable {
+                entries: HashMap::new(),
+                last_gc: Instant::now(),
+            }),
+        }
+    }
+
+    /// テーブルを変更せずにパケットのコネクション状態を判定する
+    pub fn lookup(&self, packet: &FirewallPacket) -> ConnState {
+        let Ok(table) = self.table.lock() else {
+            return ConnState::Invalid;
+        };
+        let now = Instant::now();
+        let key = FlowKey::from_packet(packet);
+
+        if let Some((found, direction)) = table.find(&key, now) {
+            let replied = table.entries.get(&found).is_some_and(|entry| entry.replied);
+            return if replied || direction == Direction::Reply {
+                ConnState::Established
+            } else {
+                ConnState::New
+            };
+        }
+
+        if let Some(related) = &packet.related_flow {
+            if table.find(related, now).is_some() {
+                return ConnState::Related;
+            }
+        }
+
+        if packet.ip_protocol == IpProtocol::TCP && (packet.transport.tcp_flags() & (TCP_SYN | TCP_ACK | TCP_RST)) != TCP_SYN {
+            // SYN以外で始まるTCPフローは追跡対象外
+            return ConnState::Invalid;
+        }
+
+        ConnState::New
+    }
+
+    /// コネクションを開始した側から送信されたパケットかを判定する (追跡していない場合はNone)
+    pub fn is_from_initiator(&self, packet: &FirewallPacket) -> Option<bool> {
+        let table = self.table.lock().ok()?;
+        let key = FlowKey::from_packet(packet);
+        table.find(&key, Instant::now()).map(|(_, direction)| direction == Direction::Original)
+    }
+
+    /// 許可されたパケットでテーブルを更新する
+    pub fn commit(&self, packet: &FirewallPacket, state: ConnState) {
+        if matches!(state, ConnState::Related | ConnState::Invalid) {
+            return;
+        }
+
+        let Ok(mut table) = self.table.lock() else {
+            return;
+        };
+        let now = Instant::now();
+        table.collect_garbage(now);
+
+        let key = FlowKey::from_packet(packet);
+        let (found, direction) = match table.find(&key, now) {
+            Some(found) => found,
+            None => {
+                if table.entries.len() >= MAX_ENTRIES {
+                    warn!("コネクション追跡テーブルが上限に達しています: {} エントリ", MAX_ENTRIES);
+                    return;
+                }
+                let tcp_state = (packet.ip_protocol == IpProtocol::TCP).then_some(TcpState::SynSent);
+                table.entries.insert(
+                    key,
+                    ConnEntry {
+                        tcp_state,
+                        fin_direction: None,
+                        replied: false,
+                        expires_at: now,
+                    },
+                );
+                (key, Direction::Original)
+            },
+        };
+
+        if let Some(entry) = table.entries.get_mut(&found) {
+            if direction == Direction::Reply {
+                entry.replied = true;
+            }
+            if packet.ip_protocol == IpProtocol::TCP {
+                entry.update_tcp_state(packet.transport.tcp_flags(), direction);
+            }
+            entry.expires_at = now + entry.timeout(packet.ip_protocol);
+        }
+    }
+}
+
+/// ICMPエラーメッセージに埋め込まれた元パケットのフローを取得する
+pub fn related_flow(ip_data: &[u8]) -> Option<FlowKey> {
+    let version = ip_data.first()? >> 4;
+    let (header_length, ip_protocol) = match version {
+        4 => ((ip_data[0] & 0xF) as usize * 4, *ip_data.get(9)?),
+        6 => (40, *ip_data.get(6)?),
+        _ => return None,
+    };
+
+    let icmp = ip_data.get(header_length..)?;
+    let icmp_type = *icmp.first()?;
+    let is_error = match ip_protocol {
+        // Destination Unreachable, Source Quench, Redirect, Time Exceeded, Parameter Problem
+        1 => matches!(icmp_type, 3 | 4 | 5 | 11 | 12),
+        // Destination Unreachable, Packet Too Big, Time Exceeded, Parameter Problem
+        58 => matches!(icmp_type, 1..=4),
+        _ => false,
+    };
+    if !is_error {
+        return None;
+    }
+
+    // ICMPヘッダ(8バイト)の後ろに元パケットのIPヘッダが続く
+    parse_embedded_flow(icmp.get(8..)?)
+}
+
+fn parse_embedded_flow(data: &[u8]) -> Option<FlowKey> {
+    let version = data.first()? >> 4;
+    let (src_ip, dst_ip, ip_protocol, header_length) = match version {
+        4 => {
+            let header = data.get(..20)?;
+            (
+                IpAddr::V4(Ipv4Addr::new(header[12], header[13], header[14], header[15])),
+                IpAddr::V4(Ipv4Addr::new(header[16], header[17], header[18], header[19])),
+                IpProtocol::from(header[9]),
+                (header[0] & 0xF) as usize * 4,
+            )
+        },
+        6 => {
+            let header = data.get(..40)?;
+            let src: [u8; 16] = header[8..24].try_into().ok()?;
+            let dst: [u8; 16] = header[24..40].try_into().ok()?;
+            (IpAddr::V6(Ipv6Addr::from(src)), IpAddr::V6(Ipv6Addr::from(dst)), IpProtocol::from(header[6]), 40)
+        },
+        _ => return None,
+    };
+
+    let (src_port, dst_port) = if ip_protocol.is_transport_protocol() {
+        let ports = data.get(header_length..header_length + 4)?;
+        (u16::from_be_bytes([ports[0], ports[1]]), u16::from_be_bytes([ports[2], ports[3]]))
+    } else {
+        (0, 0)
+    };
+
+    Some(FlowKey {
+        src_ip,
+        dst_ip,
+        ip_protocol,
+        src_port,
+        dst_port,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::analysis::transport::{TcpHeader, TransportHeader, UdpHeader};
+    use crate::packet::types::EtherType;
+    use crate::packet::MacAddr;
+
+    const CLIENT: (&str, u16) = ("10.0.0.1", 40000);
+    const SERVER: (&str, u16) = ("10.0.0.2", 80);
+
+    fn packet(src: (&str, u16), dst: (&str, u16), ip_protocol: IpProtocol, transport: TransportHeader) -> FirewallPacket {
+        FirewallPacket::from_packet(
+            MacAddr([1; 6]),
+            MacAddr([2; 6]),
+            EtherType::IP_V4,
+            src.0.parse().unwrap(),
+            dst.0.parse().unwrap(),
+            ip_protocol,
+            transport,
+            None,
+            60,
+            None,
+        )
+    }
+
+    fn segment(from_client: bool, flags: u8) -> FirewallPacket {
+        let (src, dst) = if from_client { (CLIENT, SERVER) } else { (SERVER, CLIENT) };
+        let tcp = TcpHeader {
+            src_port: src.1,
+            dst_port: dst.1,
+            sequence_number: 0,
+            acknowledgment_number: 0,
+            header_length: 20,
+            flags,
+            window_size: 1024,
+            checksum: 0,
+            urgent_pointer: 0,
+            options: Vec::new(),
+        };
+        packet(src, dst, IpProtocol::TCP, TransportHeader::Tcp(Box::new(tcp)))
+    }
+
+    fn datagram(src: (&str, u16), dst: (&str, u16)) -> FirewallPacket {
+        let udp = UdpHeader {
+            src_port: src.1,
+            dst_port: dst.1,
+            length: 8,
+            checksum: 0,
+        };
+        packet(src, dst, IpProtocol::UDP, TransportHeader::Udp(udp))
+    }
+
+    // lookupで判定した状態でcommitし、判定結果を返す
+    fn pass(tracker: &ConnectionTracker, packet: &FirewallPacket) -> ConnState {
+        let state = tracker.lookup(packet);
+        tracker.commit(packet, state);
+        state
+    }
+
+    fn tcp_state(tracker: &ConnectionTracker) -> Option<TcpState> {
+        let key = FlowKey::from_packet(&segment(true, TCP_SYN));
+        tracker.table.lock().unwrap().entries.get(&key).and_then(|entry| entry.tcp_state)
+    }
+
+    #[test]
+    fn tracks_tcp_handshake_and_close() {
+        let tracker = ConnectionTracker::new();
+
+        assert_eq!(pass(&tracker, &segment(true, TCP_SYN)), ConnState::New);
+        assert_eq!(tcp_state(&tracker), Some(TcpState::SynSent));
+        assert_eq!(pass(&tracker, &segment(false, TCP_SYN | TCP_ACK)), ConnState::Established);
+        assert_eq!(tcp_state(&tracker), Some(TcpState::SynRecv));
+        assert_eq!(pass(&tracker, &segment(true, TCP_ACK)), ConnState::Established);
+        assert_eq!(tcp_state(&tracker), Some(TcpState::Established));
+        assert_eq!(tracker.is_from_initiator(&segment(false, TCP_ACK)), Some(false));
+
+        pass(&tracker, &segment(true, TCP_FIN | TCP_ACK));
+        assert_eq!(tcp_state(&tracker), Some(TcpState::FinWait));
+        // 同じ方向のFINの再送では遷移しない
+        pass(&tracker, &segment(true, TCP_FIN | TCP_ACK));
+        assert_eq!(tcp_state(&tracker), Some(TcpState::FinWait));
+        pass(&tracker, &segment(false, TCP_FIN | TCP_ACK));
+        assert_eq!(tcp_state(&tracker), Some(TcpState::LastAck));
+        pass(&tracker, &segment(true, TCP_ACK));
+        assert_eq!(tcp_state(&tracker), Some(TcpState::TimeWait));
+
+        // 同一5-tupleでの再接続
+        pass(&tracker, &segment(true, TCP_SYN));
+        assert_eq!(tcp_state(&tracker), Some(TcpState::SynSent));
+    }
+
+    #[test]
+    fn closes_tcp_connection_on_reset() {
+        let tracker = ConnectionTracker::new();
+        pass(&tracker, &segment(true, TCP_SYN));
+        pass(&tracker, &segment(false, TCP_SYN | TCP_ACK));
+        pass(&tracker, &segment(false, TCP_RST));
+        assert_eq!(tcp_state(&tracker), Some(TcpState::Close));
+    }
+
+    #[test]
+    fn does_not_track_tcp_flows_not_starting_with_syn() {
+        let tracker = ConnectionTracker::new();
+        for flags in [TCP_ACK, TCP_SYN | TCP_ACK, TCP_RST, TCP_FIN] {
+            assert_eq!(pass(&tracker, &segment(true, flags)), ConnState::Invalid, "flags {:#04x}", flags);
+        }
+        assert_eq!(tcp_state(&tracker), None);
+        assert_eq!(tracker.is_from_initiator(&segment(true, TCP_ACK)), None);
+    }
+
+    #[test]
+    fn establishes_udp_flow_on_reply() {
+        let tracker = ConnectionTracker::new();
+        let request = datagram(CLIENT, SERVER);
+
+        assert_eq!(pass(&tracker, &request), ConnState::New);
+        // 応答を受けるまではNewのまま
+        assert_eq!(pass(&tracker, &request), ConnState::New);
+        assert_eq!(pass(&tracker, &datagram(SERVER, CLIENT)), ConnState::Established);
+        assert_eq!(pass(&tracker, &request), ConnState::Established);
+
+        let key = FlowKey::from_packet(&request);
+        let table = tracker.table.lock().unwrap();
+        assert_eq!(table.entries[&key].timeout(IpProtocol::UDP), UDP_REPLIED_TIMEOUT);
+    }
+
+    #[test]
+    fn relates_icmp_error_to_tracked_flow() {
+        let tracker = ConnectionTracker::new();
+        let request = datagram(CLIENT, SERVER);
+        pass(&tracker, &request);
+
+        // 10.0.0.2から10.0.0.1へのPort Unreachable (元のUDPデータグラムを埋め込む)
+        let mut ip_data = vec![0x45, 0, 0, 56, 0, 0, 0, 0, 64, 1, 0, 0, 10, 0, 0, 2, 10, 0, 0, 1];
+        ip_data.extend_from_slice(&[3, 3, 0, 0, 0, 0, 0, 0]);
+        ip_data.extend_from_slice(&[0x45, 0, 0, 28, 0, 0, 0, 0, 64, 17, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2]);
+        ip_data.extend_from_slice(&[0x9c, 0x40, 0x00, 0x50, 0, 8, 0, 0]);
+        let related = related_flow(&ip_data).unwrap();
+        assert_eq!(related, FlowKey::from_packet(&request));
+
+        let mut error = packet(SERVER, CLIENT, IpProtocol::ICMP, TransportHeader::Unknown);
+        error.related_flow = Some(related);
+        assert_eq!(pass(&tracker, &error), ConnState::Related);
+
+        // エラー以外のICMPは関連付けない
+        ip_data[20] = 8;
+        assert!(related_flow(&ip_data).is_none());
+    }
+}
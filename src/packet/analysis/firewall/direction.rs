@@ -0,0 +1,35 @@
+use std::fmt;
+
+/// ファイアウォールを適用する通信の向き
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq)]
+pub enum FirewallDirection {
+    // データベースから取得してLANへ注入するパケット (DB→wire)
+    Ingress,
+    // LANで収集してデータベースへ保存するパケット (wire→DB)
+    Egress,
+}
+
+impl FirewallDirection {
+    pub const ALL: [FirewallDirection; 2] = [FirewallDirection::Ingress, FirewallDirection::Egress];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FirewallDirection::Ingress => "ingress",
+            FirewallDirection::Egress => "egress",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "ingress" => Some(FirewallDirection::Ingress),
+            "egress" => Some(FirewallDirection::Egress),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for FirewallDirection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
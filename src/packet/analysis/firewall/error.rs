@@ -0,0 +1,28 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum FirewallError {
+    #[error("設定エラー: {0}")]
+    ConfigurationError(String),
+
+    #[error("シグナルハンドラの登録に失敗しました: {0}")]
+    SignalError(String),
+
+    #[error("不明なフィルタ種別です: {0}")]
+    UnknownFilterType(String),
+
+    #[error("ルールの値が不正です: {0}")]
+    InvalidRule(String),
+
+    #[error("不明な方向が指定されました: {0}")]
+    UnknownDirection(String),
+
+    #[error("ルールの優先度が範囲外です: {0}")]
+    InvalidPriority(i16),
+
+    #[error("データベースでエラーが発生しました: {0}")]
+    DatabaseError(String),
+
+    #[error("ファイアウォールのロックに失敗しました")]
+    LockError,
+}
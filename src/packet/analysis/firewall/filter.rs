@@ -1,8 +1,11 @@
+use super::error::FirewallError;
+use super::ConnState;
 use crate::packet::MacAddr;
+use std::fmt;
 use std::net::IpAddr;
 
 #[allow(dead_code)]
-#[derive(Debug, Eq, Hash, PartialEq)]
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
 pub enum Filter {
     // L2 Filters
     SrcMacAddress(MacAddr),
@@ -17,4 +20,96 @@ pub enum Filter {
     // L4 Filters
     SrcPort(u16),
     DstPort(u16),
+    IcmpType(u8),
+
+    // Stateful Filters
+    ConnectionState(ConnState),
+
+    // L7 Filters
+    // TLSのServer Name Indication ("*.example.com"はサブドメインに一致する)
+    Sni(String),
+
+    // Tunnel Filters
+    SrcNodeId(i16),
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Filter::SrcMacAddress(mac) => write!(f, "src_mac={}", mac),
+            Filter::DstMacAddress(mac) => write!(f, "dst_mac={}", mac),
+            Filter::EtherType(ether_type) => write!(f, "ether_type=0x{:04x}", ether_type),
+            Filter::SrcIpAddress(ip) => write!(f, "src_ip={}", ip),
+            Filter::DstIpAddress(ip) => write!(f, "dst_ip={}", ip),
+            Filter::IpProtocol(protocol) => write!(f, "ip_protocol={}", protocol),
+            Filter::SrcPort(port) => write!(f, "src_port={}", port),
+            Filter::DstPort(port) => write!(f, "dst_port={}", port),
+            Filter::IcmpType(icmp_type) => write!(f, "icmp_type={}", icmp_type),
+            Filter::ConnectionState(state) => write!(f, "conn_state={}", state),
+            Filter::Sni(sni) => write!(f, "sni={}", sni),
+            Filter::SrcNodeId(node_id) => write!(f, "src_node_id={}", node_id),
+        }
+    }
+}
+
+impl Filter {
+    /// データベースに保存されたルールの種別と値からフィルタを生成する
+    pub fn parse(filter_type: &str, value: &str) -> Result<Self, FirewallError> {
+        let value = value.trim();
+        let invalid = || FirewallError::InvalidRule(format!("{}={}", filter_type, value));
+
+        let filter = match filter_type {
+            "src_mac" => Filter::SrcMacAddress(parse_mac_address(value).ok_or_else(invalid)?),
+            "dst_mac" => Filter::DstMacAddress(parse_mac_address(value).ok_or_else(invalid)?),
+            "ether_type" => Filter::EtherType(parse_u16(value).ok_or_else(invalid)?),
+            "src_ip" => Filter::SrcIpAddress(value.parse().map_err(|_| invalid())?),
+            "dst_ip" => Filter::DstIpAddress(value.parse().map_err(|_| invalid())?),
+            "ip_protocol" => Filter::IpProtocol(value.parse().map_err(|_| invalid())?),
+            "src_port" => Filter::SrcPort(value.parse().map_err(|_| invalid())?),
+            "dst_port" => Filter::DstPort(value.parse().map_err(|_| invalid())?),
+            "icmp_type" => Filter::IcmpType(value.parse().map_err(|_| invalid())?),
+            "conn_state" => Filter::ConnectionState(match value.to_lowercase().as_str() {
+                "new" => ConnState::New,
+                "established" => ConnState::Established,
+                "related" => ConnState::Related,
+                "invalid" => ConnState::Invalid,
+                _ => return Err(invalid()),
+            }),
+            "sni" if !value.is_empty() => Filter::Sni(value.trim_end_matches('.').to_ascii_lowercase()),
+            "src_node_id" => Filter::SrcNodeId(value.parse().map_err(|_| invalid())?),
+            _ => return Err(FirewallError::UnknownFilterType(filter_type.to_string())),
+        };
+
+        Ok(filter)
+    }
+
+    /// データベースへ保存する種別と値 (parseで元のフィルタに戻せる形式)
+    pub fn to_parts(&self) -> (String, String) {
+        let text = self.to_string();
+        match text.split_once('=') {
+            Some((filter_type, value)) => (filter_type.to_string(), value.to_string()),
+            None => (text, String::new()),
+        }
+    }
+
+    // SNIがフィルタの名前に一致するか (ワイルドカードは親ドメイン自身には一致しない)
+    pub fn matches_sni(pattern: &str, sni: &str) -> bool {
+        let sni = sni.trim_end_matches('.');
+        match pattern.strip_prefix("*.") {
+            Some(suffix) => sni.len() > suffix.len() + 1 && sni.ends_with(suffix) && sni.as_bytes()[sni.len() - suffix.len() - 1] == b'.',
+            None => sni.eq_ignore_ascii_case(pattern),
+        }
+    }
+}
+
+fn parse_mac_address(value: &str) -> Option<MacAddr> {
+    let octets = value.split([':', '-']).map(|octet| u8::from_str_radix(octet, 16).ok()).collect::<Option<Vec<u8>>>()?;
+    Some(MacAddr(octets.try_into().ok()?))
+}
+
+fn parse_u16(value: &str) -> Option<u16> {
+    match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
 }
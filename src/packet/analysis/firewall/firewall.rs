@@ -1,57 +1,224 @@
-use super::{Filter, FirewallPacket, Policy};
+use super::{ConnState, ConnectionTracker, Filter, FirewallPacket, FirewallStats, Policy, RuleCounters, RuleStats};
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// 統計情報での一時的な遮断ルールの優先度 (通常のルールより先に評価される)
+const TEMPORARY_BLOCK_PRIORITY: u8 = u8::MAX;
+
+#[derive(Debug)]
+struct RuleEntry {
+    priority: u8,
+    counters: RuleCounters,
+}
+
+#[derive(Debug)]
+struct TemporaryBlockEntry {
+    expires_at: Instant,
+    reason: String,
+    counters: RuleCounters,
+}
+
+/// 有効な一時的な遮断ルール
+#[derive(Debug, Clone)]
+pub struct TemporaryBlockStatus {
+    pub filter: Filter,
+    pub reason: String,
+    // 期限までの残り時間
+    pub remaining: Duration,
+    pub packets: u64,
+}
 
 #[derive(Debug)]
 pub struct IpFirewall {
-    rules: HashMap<Filter, u8>,
+    rules: HashMap<Filter, RuleEntry>,
+    // 検知器により追加された期限付きの遮断ルール (ポリシーに関わらず拒否する)
+    temporary_blocks: HashMap<Filter, TemporaryBlockEntry>,
     policy: Policy,
+    conntrack: Arc<ConnectionTracker>,
+    default_counters: RuleCounters,
 }
 
 impl IpFirewall {
-    pub fn new(policy: Policy) -> Self {
-        Self { rules: HashMap::new(), policy }
+    /// コネクション追跡テーブルは他方向のファイアウォールと共有できる
+    pub fn new(policy: Policy, conntrack: Arc<ConnectionTracker>) -> Self {
+        Self {
+            rules: HashMap::new(),
+            temporary_blocks: HashMap::new(),
+            policy,
+            conntrack,
+            default_counters: RuleCounters::default(),
+        }
     }
 
     pub fn add_rule(&mut self, filter: Filter, priority: u8) {
-        self.rules.insert(filter, priority);
+        self.rules.insert(
+            filter,
+            RuleEntry {
+                priority,
+                counters: RuleCounters::default(),
+            },
+        );
+    }
+
+    /// ルール一式を入れ替える (同じフィルタのルールはカウンタを引き継ぐ)
+    pub fn replace_rules(&mut self, rules: Vec<(Filter, u8)>) {
+        let mut previous = std::mem::take(&mut self.rules);
+
+        for (filter, priority) in rules {
+            match previous.remove(&filter) {
+                Some(mut rule) => {
+                    rule.priority = priority;
+                    self.rules.insert(filter, rule);
+                },
+                None => self.add_rule(filter, priority),
+            }
+        }
+    }
+
+    /// 期限付きの遮断ルールを追加する (既にある場合は期限を延長する)
+    /// ルールを新たに追加した場合はtrueを返す
+    pub fn add_temporary_block(&mut self, filter: Filter, duration: Duration, reason: String) -> bool {
+        let now = Instant::now();
+        self.temporary_blocks.retain(|_, block| block.expires_at > now);
+
+        match self.temporary_blocks.get_mut(&filter) {
+            Some(block) => {
+                block.expires_at = block.expires_at.max(now + duration);
+                false
+            },
+            None => {
+                self.temporary_blocks.insert(
+                    filter,
+                    TemporaryBlockEntry {
+                        expires_at: now + duration,
+                        reason,
+                        counters: RuleCounters::default(),
+                    },
+                );
+                true
+            },
+        }
+    }
+
+    /// 一時的な遮断ルールを期限より前に削除する
+    /// ルールが有効であった場合はtrueを返す
+    pub fn remove_temporary_block(&mut self, filter: &Filter) -> bool {
+        let now = Instant::now();
+        self.temporary_blocks.remove(filter).is_some_and(|block| block.expires_at > now)
+    }
+
+    /// 有効な一時的な遮断ルールを期限の近い順に返す
+    pub fn temporary_blocks(&self) -> Vec<TemporaryBlockStatus> {
+        let now = Instant::now();
+        let mut blocks: Vec<TemporaryBlockStatus> = self
+            .temporary_blocks
+            .iter()
+            .filter(|(_, block)| block.expires_at > now)
+            .map(|(filter, block)| TemporaryBlockStatus {
+                filter: filter.clone(),
+                reason: block.reason.clone(),
+                remaining: block.expires_at - now,
+                packets: block.counters.snapshot().packets,
+            })
+            .collect();
+        blocks.sort_by_key(|block| block.remaining);
+        blocks
     }
 
     pub fn check(&self, packet: &FirewallPacket) -> bool {
+        let conn_state = self.conntrack.lookup(packet);
+
+        let now = Instant::now();
+        if let Some(block) = self.temporary_blocks.iter().find(|(filter, block)| block.expires_at > now && Self::matches(filter, packet, conn_state)).map(|(_, block)| block) {
+            block.counters.record(packet.length);
+            return false;
+        }
+
         let mut block = false;
         let mut allow = false;
         let mut max_priority = 0;
+        let mut matched_rule = None;
 
-        for (filter, priority) in &self.rules {
-            if *priority > max_priority {
-                let matches = match filter {
-                    // L2 Filters
-                    Filter::SrcMacAddress(mac) => &packet.src_mac == mac,
-                    Filter::DstMacAddress(mac) => &packet.dst_mac == mac,
-                    Filter::EtherType(ether_type) => packet.ether_type.value() == *ether_type,
-
-                    // L3 Filters
-                    Filter::SrcIpAddress(ip) => &packet.src_ip == ip,
-                    Filter::DstIpAddress(ip) => &packet.dst_ip == ip,
-                    Filter::IpProtocol(protocol) => packet.ip_protocol.value() == *protocol,
-
-                    // L4 Filters
-                    Filter::SrcPort(port) => packet.src_port == *port,
-                    Filter::DstPort(port) => packet.dst_port == *port,
-                };
-
-                if matches {
-                    max_priority = *priority;
-                    match self.policy {
-                        Policy::Whitelist => allow = true,
-                        Policy::Blacklist => block = true,
-                    }
+        for (filter, rule) in &self.rules {
+            if rule.priority > max_priority && Self::matches(filter, packet, conn_state) {
+                max_priority = rule.priority;
+                matched_rule = Some(rule);
+                match self.policy {
+                    Policy::Whitelist => allow = true,
+                    Policy::Blacklist => block = true,
                 }
             }
         }
 
-        match self.policy {
+        // 判定を決めたルール(なければデフォルトポリシー)のカウンタを更新
+        match matched_rule {
+            Some(rule) => rule.counters.record(packet.length),
+            None => self.default_counters.record(packet.length),
+        }
+
+        let accepted = match self.policy {
             Policy::Whitelist => allow,
             Policy::Blacklist => !block,
+        };
+
+        // 許可されたパケットのみをコネクションとして記録する
+        if accepted {
+            self.conntrack.commit(packet, conn_state);
+        }
+
+        accepted
+    }
+
+    fn matches(filter: &Filter, packet: &FirewallPacket, conn_state: ConnState) -> bool {
+        match filter {
+            // L2 Filters
+            Filter::SrcMacAddress(mac) => &packet.src_mac == mac,
+            Filter::DstMacAddress(mac) => &packet.dst_mac == mac,
+            Filter::EtherType(ether_type) => packet.ether_type.value() == *ether_type,
+
+            // L3 Filters
+            Filter::SrcIpAddress(ip) => &packet.src_ip == ip,
+            Filter::DstIpAddress(ip) => &packet.dst_ip == ip,
+            Filter::IpProtocol(protocol) => packet.ip_protocol.value() == *protocol,
+
+            // L4 Filters
+            Filter::SrcPort(port) => packet.transport.src_port() == *port,
+            Filter::DstPort(port) => packet.transport.dst_port() == *port,
+            Filter::IcmpType(icmp_type) => packet.transport.icmp_type() == Some(*icmp_type),
+
+            // Stateful Filters
+            Filter::ConnectionState(state) => conn_state == *state,
+
+            // L7 Filters
+            Filter::Sni(pattern) => packet.sni.as_deref().is_some_and(|sni| Filter::matches_sni(pattern, sni)),
+
+            // Tunnel Filters
+            Filter::SrcNodeId(node_id) => packet.src_node_id == Some(*node_id),
+        }
+    }
+
+    pub fn stats(&self) -> FirewallStats {
+        let now = Instant::now();
+        let mut rules: Vec<RuleStats> = self
+            .rules
+            .iter()
+            .map(|(filter, rule)| RuleStats {
+                rule: filter.to_string(),
+                priority: rule.priority,
+                counters: rule.counters.snapshot(),
+            })
+            .chain(self.temporary_blocks.iter().filter(|(_, block)| block.expires_at > now).map(|(filter, block)| RuleStats {
+                rule: format!("temporary_block:{} ({})", filter, block.reason),
+                priority: TEMPORARY_BLOCK_PRIORITY,
+                counters: block.counters.snapshot(),
+            }))
+            .collect();
+        rules.sort_by_key(|r| std::cmp::Reverse(r.priority));
+
+        FirewallStats {
+            rules,
+            default_policy: self.default_counters.snapshot(),
         }
     }
 }
@@ -0,0 +1,253 @@
+use super::error::FirewallError;
+use super::{Filter, FirewallDirection, FirewallStats, TemporaryBlockStatus};
+use crate::config::AppConfig;
+use crate::packet::analysis::checksum::ChecksumStats;
+use crate::packet::analysis::idps::TemporaryBlock;
+use crate::packet::analysis::PacketAnalyzer;
+use crate::packet::repository::{FirewallBlockRecord, FirewallRepository, FirewallRuleRecord};
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use std::collections::{HashMap, HashSet};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::time::{interval, Duration};
+
+// firewall_blocksテーブルから適用した遮断ルール
+struct AppliedBlock {
+    direction: FirewallDirection,
+    filter: Filter,
+    expires_at: DateTime<Utc>,
+}
+
+pub struct FirewallManager {
+    node_id: i16,
+    applied_version: Option<i64>,
+    // 検知器による遮断ルールを全ノードで共有するか
+    publish_blocks: bool,
+    // firewall_blocksテーブルのIDと適用した遮断ルール (行の削除を検出する為)
+    applied_blocks: HashMap<i64, AppliedBlock>,
+}
+
+impl FirewallManager {
+    pub async fn start() -> Result<(), FirewallError> {
+        let config: AppConfig = AppConfig::new().map_err(|e| FirewallError::ConfigurationError(e.to_string()))?;
+
+        // SIGUSR1を受け取った際に統計情報をログへ出力する
+        let mut dump_signal = signal(SignalKind::user_defined1()).map_err(|e| FirewallError::SignalError(e.to_string()))?;
+        let mut stats_timer = interval(Duration::from_secs(config.firewall.stats_interval.max(1)));
+        let mut sync_timer = interval(Duration::from_secs(config.firewall.sync_interval.max(1)));
+
+        info!(
+            "ファイアウォール管理タスクを開始します (統計の書き込み間隔: {}秒, ルールの同期間隔: {}秒)",
+            config.firewall.stats_interval, config.firewall.sync_interval
+        );
+
+        let mut manager = Self {
+            node_id: config.node_id,
+            applied_version: None,
+            publish_blocks: config.idps.prevention.publish,
+            applied_blocks: HashMap::new(),
+        };
+
+        loop {
+            tokio::select! {
+                _ = sync_timer.tick() => {
+                    if let Err(e) = manager.sync_rules().await {
+                        error!("ファイアウォールルールの同期に失敗しました: {}", e);
+                    }
+                    if let Err(e) = manager.sync_blocks().await {
+                        error!("遮断ルールの同期に失敗しました: {}", e);
+                    }
+                }
+                _ = stats_timer.tick() => {
+                    if let Err(e) = manager.write_stats().await {
+                        error!("ファイアウォール統計の書き込みに失敗しました: {}", e);
+                    }
+                }
+                _ = dump_signal.recv() => {
+                    for direction in FirewallDirection::ALL {
+                        match PacketAnalyzer::firewall_stats(direction) {
+                            Ok(stats) => Self::dump_stats(direction, &stats),
+                            Err(e) => error!("ファイアウォール統計の取得に失敗しました: {}", e),
+                        }
+                        match PacketAnalyzer::temporary_blocks(direction) {
+                            Ok(blocks) => Self::dump_blocks(direction, &blocks),
+                            Err(e) => error!("遮断ルールの取得に失敗しました: {}", e),
+                        }
+                    }
+                    Self::dump_checksum_stats(&PacketAnalyzer::checksum_stats());
+                }
+            }
+        }
+    }
+
+    async fn sync_rules(&mut self) -> Result<(), FirewallError> {
+        let version = FirewallRepository::get_rule_version().await.map_err(|e| FirewallError::DatabaseError(e.to_string()))?;
+        if self.applied_version == Some(version) {
+            return Ok(());
+        }
+
+        let records = FirewallRepository::get_rules(self.node_id).await.map_err(|e| FirewallError::DatabaseError(e.to_string()))?;
+
+        // 不正なルールが1件でもあれば、部分的なポリシーを適用しないよう更新全体を中止する
+        let mut rules: HashMap<FirewallDirection, Vec<(Filter, u8)>> = HashMap::new();
+        for record in &records {
+            let direction = FirewallDirection::parse(&record.direction).ok_or_else(|| FirewallError::UnknownDirection(record.direction.clone()))?;
+            rules.entry(direction).or_default().push(Self::to_rule(record)?);
+        }
+
+        for direction in FirewallDirection::ALL {
+            // ルールがない場合に空のルールで置き換えると、ホワイトリスト方式のegressでは全ての通信を遮断してしまう為、既定のルールを使用する
+            let rules = match rules.remove(&direction) {
+                Some(rules) => rules,
+                None => {
+                    let defaults = PacketAnalyzer::default_firewall_rules(direction);
+                    if !defaults.is_empty() {
+                        warn!("データベースに有効な{}ルールがない為、既定のルールを使用します (version={})", direction, version);
+                    }
+                    defaults
+                },
+            };
+
+            let rule_count = rules.len();
+            PacketAnalyzer::replace_firewall_rules(direction, rules)?;
+            info!("{}ファイアウォールのルールを更新しました: version={}, ルール数={}", direction, version, rule_count);
+        }
+
+        self.applied_version = Some(version);
+        Ok(())
+    }
+
+    /// 検知器が追加した遮断ルールを書き込み、他のノードが共有した遮断ルールを適用する
+    /// firewall_blocksテーブルから期限より前に削除された遮断ルールはファイアウォールからも削除する
+    async fn sync_blocks(&mut self) -> Result<(), FirewallError> {
+        let blocks = PacketAnalyzer::drain_blocks();
+        if let Err(e) = FirewallRepository::upsert_blocks(self.node_id, self.publish_blocks, &blocks).await {
+            // 書き込めなかった遮断ルールは次回に再び書き込む
+            PacketAnalyzer::requeue_blocks(blocks);
+            return Err(FirewallError::DatabaseError(e.to_string()));
+        }
+
+        let records = FirewallRepository::get_active_blocks(self.node_id).await.map_err(|e| FirewallError::DatabaseError(e.to_string()))?;
+
+        let now = Utc::now();
+        let mut applied = HashMap::new();
+        for record in records {
+            // 不正な行は他の遮断ルールの適用を妨げないよう読み飛ばす
+            let (direction, filter) = match Self::to_block(&record) {
+                Ok(block) => block,
+                Err(e) => {
+                    error!("遮断ルールID {} (origin_node_id={:?}) を解釈できません: {}", record.id, record.origin_node_id, e);
+                    continue;
+                },
+            };
+            let Ok(duration) = (record.expires_at - now).to_std() else {
+                continue;
+            };
+            PacketAnalyzer::add_temporary_block(
+                direction,
+                TemporaryBlock {
+                    filter: filter.clone(),
+                    duration,
+                    reason: record.reason,
+                },
+            );
+            applied.insert(
+                record.id,
+                AppliedBlock {
+                    direction,
+                    filter,
+                    expires_at: record.expires_at,
+                },
+            );
+        }
+
+        // 期限前に行が削除され、同じフィルタの行が他に残っていない遮断ルールを削除する
+        let active: HashSet<(FirewallDirection, &Filter)> = applied.values().map(|block| (block.direction, &block.filter)).collect();
+        for (id, block) in &self.applied_blocks {
+            if applied.contains_key(id) || block.expires_at <= now || active.contains(&(block.direction, &block.filter)) {
+                continue;
+            }
+            if PacketAnalyzer::remove_temporary_block(block.direction, &block.filter)? {
+                info!("{}ファイアウォールから遮断ルールを削除しました: ID={}, {}", block.direction, id, block.filter);
+            }
+        }
+
+        self.applied_blocks = applied;
+        Ok(())
+    }
+
+    fn to_block(record: &FirewallBlockRecord) -> Result<(FirewallDirection, Filter), FirewallError> {
+        let direction = FirewallDirection::parse(&record.direction).ok_or_else(|| FirewallError::UnknownDirection(record.direction.clone()))?;
+        let filter = Filter::parse(&record.filter_type, &record.filter_value)?;
+        Ok((direction, filter))
+    }
+
+    fn to_rule(record: &FirewallRuleRecord) -> Result<(Filter, u8), FirewallError> {
+        let filter = Filter::parse(&record.filter_type, &record.filter_value).map_err(|e| {
+            error!("ルールID {} (node_id={:?}) を解釈できません: {}", record.id, record.node_id, e);
+            e
+        })?;
+        // 優先度0のルールは一致しても採用されない為、誤った設定として扱う
+        let priority = u8::try_from(record.priority).ok().filter(|priority| *priority > 0).ok_or(FirewallError::InvalidPriority(record.priority))?;
+
+        Ok((filter, priority))
+    }
+
+    async fn write_stats(&self) -> Result<(), FirewallError> {
+        for direction in FirewallDirection::ALL {
+            let stats = PacketAnalyzer::firewall_stats(direction)?;
+            FirewallRepository::insert_stats(self.node_id, direction, &stats).await.map_err(|e| FirewallError::DatabaseError(e.to_string()))?;
+        }
+        Ok(())
+    }
+
+    fn dump_stats(direction: FirewallDirection, stats: &FirewallStats) {
+        info!("{}ファイアウォール統計 ({} ルール)", direction, stats.rules.len());
+        for rule in &stats.rules {
+            info!(
+                "  [priority={:>3}] {}: packets={}, bytes={}",
+                rule.priority, rule.rule, rule.counters.packets, rule.counters.bytes
+            );
+        }
+        info!("  [default] packets={}, bytes={}", stats.default_policy.packets, stats.default_policy.bytes);
+    }
+
+    fn dump_blocks(direction: FirewallDirection, blocks: &[TemporaryBlockStatus]) {
+        info!("{}ファイアウォールの一時的な遮断ルール ({} 件)", direction, blocks.len());
+        for block in blocks {
+            info!("  {}: 残り{}秒, packets={}, 理由={}", block.filter, block.remaining.as_secs(), block.packets, block.reason);
+        }
+    }
+
+    fn dump_checksum_stats(stats: &ChecksumStats) {
+        info!(
+            "チェックサム不一致: IPv4={}, TCP={}, UDP={} (オフロードとして無視={})",
+            stats.ipv4, stats.tcp, stats.udp, stats.offloaded
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(priority: i16) -> FirewallRuleRecord {
+        FirewallRuleRecord {
+            id: 1,
+            node_id: None,
+            direction: "ingress".to_string(),
+            filter_type: "dst_port".to_string(),
+            filter_value: "22".to_string(),
+            priority,
+        }
+    }
+
+    #[test]
+    fn rejects_rule_priority_outside_matching_range() {
+        assert!(matches!(FirewallManager::to_rule(&record(1)), Ok((Filter::DstPort(22), 1))));
+        assert!(matches!(FirewallManager::to_rule(&record(255)), Ok((_, 255))));
+        for priority in [0, -1, 256] {
+            assert!(matches!(FirewallManager::to_rule(&record(priority)), Err(FirewallError::InvalidPriority(p)) if p == priority));
+        }
+    }
+}
@@ -1,9 +1,19 @@
+mod conntrack;
+mod direction;
+mod error;
 mod filter;
 mod firewall;
+mod manager;
 mod packet;
 mod policy;
+mod stats;
 
+pub use conntrack::{related_flow, ConnState, ConnectionTracker, FlowKey};
+pub use direction::FirewallDirection;
+pub use error::FirewallError;
 pub use filter::Filter;
-pub use firewall::IpFirewall;
+pub use firewall::{IpFirewall, TemporaryBlockStatus};
+pub use manager::FirewallManager;
 pub use packet::FirewallPacket;
 pub use policy::Policy;
+pub use stats::{FirewallStats, RuleCounters, RuleStats};
@@ -1,3 +1,5 @@
+use super::FlowKey;
+use crate::packet::analysis::transport::TransportHeader;
 use crate::packet::types::{EtherType, IpProtocol};
 use crate::packet::MacAddr;
 use std::net::IpAddr;
@@ -16,12 +18,36 @@ pub struct FirewallPacket {
     pub ip_protocol: IpProtocol,
 
     // L4 fields
-    pub src_port: u16,
-    pub dst_port: u16,
+    pub transport: TransportHeader,
+
+    // ICMPエラーが参照している元フロー
+    pub related_flow: Option<FlowKey>,
+
+    // L7 fields
+    // TLSのClientHelloに含まれるSNI (小文字)
+    pub sni: Option<String>,
+
+    // 統計用のフレーム長
+    pub length: usize,
+
+    // パケットを送信したノード (ローカルで収集したパケットはNone)
+    pub src_node_id: Option<i16>,
 }
 
 impl FirewallPacket {
-    pub fn from_packet(src_mac: MacAddr, dst_mac: MacAddr, ether_type: EtherType, src_ip: IpAddr, dst_ip: IpAddr, ip_protocol: IpProtocol, src_port: u16, dst_port: u16) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_packet(
+        src_mac: MacAddr,
+        dst_mac: MacAddr,
+        ether_type: EtherType,
+        src_ip: IpAddr,
+        dst_ip: IpAddr,
+        ip_protocol: IpProtocol,
+        transport: TransportHeader,
+        related_flow: Option<FlowKey>,
+        length: usize,
+        src_node_id: Option<i16>,
+    ) -> Self {
         Self {
             src_mac,
             dst_mac,
@@ -33,8 +59,11 @@ impl FirewallPacket {
                 IpAddr::V6(_) => 6,
             },
             ip_protocol,
-            src_port,
-            dst_port,
+            transport,
+            related_flow,
+            sni: None,
+            length,
+            src_node_id,
         }
     }
 }
@@ -0,0 +1,42 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// ルール単位のパケット数・バイト数カウンタ
+#[derive(Debug, Default)]
+pub struct RuleCounters {
+    packets: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl RuleCounters {
+    pub fn record(&self, length: usize) {
+        self.packets.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(length as u64, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> CounterSnapshot {
+        CounterSnapshot {
+            packets: self.packets.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CounterSnapshot {
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct RuleStats {
+    pub rule: String,
+    pub priority: u8,
+    pub counters: CounterSnapshot,
+}
+
+/// ファイアウォール全体の統計情報
+#[derive(Debug, Clone)]
+pub struct FirewallStats {
+    pub rules: Vec<RuleStats>,
+    pub default_policy: CounterSnapshot,
+}
@@ -0,0 +1,142 @@
+use super::FlowRecord;
+use chrono::Utc;
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs, UdpSocket};
+
+const IPFIX_VERSION: u16 = 10;
+const TEMPLATE_SET_ID: u16 = 2;
+const IPV4_TEMPLATE_ID: u16 = 256;
+const IPV6_TEMPLATE_ID: u16 = 257;
+// 逆方向の情報要素 (RFC 5103) のエンタープライズ番号
+const REVERSE_PEN: u32 = 29305;
+// 1メッセージに含めるレコード数 (IPv6でもMTUを超えない数)
+const MAX_RECORDS_PER_MESSAGE: usize = 10;
+
+// (情報要素ID, 長さ, エンタープライズ番号)
+type FieldSpecifier = (u16, u16, Option<u32>);
+
+fn template_fields(ipv6: bool) -> [FieldSpecifier; 13] {
+    let (src_address, dst_address, address_length) = if ipv6 { (27, 28, 16) } else { (8, 12, 4) };
+    [
+        (152, 8, None),                      // flowStartMilliseconds
+        (153, 8, None),                      // flowEndMilliseconds
+        (src_address, address_length, None), // sourceIPv4Address / sourceIPv6Address
+        (dst_address, address_length, None), // destinationIPv4Address / destinationIPv6Address
+        (7, 2, None),                        // sourceTransportPort
+        (11, 2, None),                       // destinationTransportPort
+        (4, 1, None),                        // protocolIdentifier
+        (6, 2, None),                        // tcpControlBits
+        (2, 8, None),                        // packetDeltaCount
+        (1, 8, None),                        // octetDeltaCount
+        (2, 8, Some(REVERSE_PEN)),           // reversePacketDeltaCount
+        (1, 8, Some(REVERSE_PEN)),           // reverseOctetDeltaCount
+        (136, 1, None),                      // flowEndReason
+    ]
+}
+
+/// 書き出したフローをIPFIX (RFC 7011) でコレクタへ送信する
+/// UDPではテンプレートが失われる可能性がある為、毎回データと同じメッセージに含める
+#[derive(Debug)]
+pub struct IpfixExporter {
+    socket: UdpSocket,
+    observation_domain_id: u32,
+    // 送信済みのデータレコード数
+    sequence_number: u32,
+}
+
+impl IpfixExporter {
+    pub fn new(collector: &str, observation_domain_id: u32) -> io::Result<Self> {
+        let address: SocketAddr =
+            collector.to_socket_addrs()?.next().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("コレクタのアドレスを解決できません: {}", collector)))?;
+        let bind_address: SocketAddr = if address.is_ipv4() { "0.0.0.0:0".parse() } else { "[::]:0".parse() }.map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let socket = UdpSocket::bind(bind_address)?;
+        socket.connect(address)?;
+        Ok(Self {
+            socket,
+            observation_domain_id,
+            sequence_number: 0,
+        })
+    }
+
+    pub fn export(&mut self, records: &[FlowRecord]) -> io::Result<()> {
+        for ipv6 in [false, true] {
+            let records: Vec<&FlowRecord> = records.iter().filter(|record| record.src_ip.is_ipv6() == ipv6).collect();
+            for chunk in records.chunks(MAX_RECORDS_PER_MESSAGE) {
+                let message = self.encode_message(chunk, ipv6);
+                self.socket.send(&message)?;
+                self.sequence_number = self.sequence_number.wrapping_add(chunk.len() as u32);
+            }
+        }
+        Ok(())
+    }
+
+    fn encode_message(&self, records: &[&FlowRecord], ipv6: bool) -> Vec<u8> {
+        let template_id = if ipv6 { IPV6_TEMPLATE_ID } else { IPV4_TEMPLATE_ID };
+        let fields = template_fields(ipv6);
+
+        // メッセージヘッダ (長さは最後に埋める)
+        let mut message = Vec::with_capacity(1500);
+        message.extend_from_slice(&IPFIX_VERSION.to_be_bytes());
+        message.extend_from_slice(&0u16.to_be_bytes());
+        message.extend_from_slice(&(Utc::now().timestamp() as u32).to_be_bytes());
+        message.extend_from_slice(&self.sequence_number.to_be_bytes());
+        message.extend_from_slice(&self.observation_domain_id.to_be_bytes());
+
+        // テンプレートセット
+        let set_start = Self::begin_set(&mut message, TEMPLATE_SET_ID);
+        message.extend_from_slice(&template_id.to_be_bytes());
+        message.extend_from_slice(&(fields.len() as u16).to_be_bytes());
+        for (id, length, enterprise) in fields {
+            match enterprise {
+                Some(enterprise) => {
+                    message.extend_from_slice(&(id | 0x8000).to_be_bytes());
+                    message.extend_from_slice(&length.to_be_bytes());
+                    message.extend_from_slice(&enterprise.to_be_bytes());
+                },
+                None => {
+                    message.extend_from_slice(&id.to_be_bytes());
+                    message.extend_from_slice(&length.to_be_bytes());
+                },
+            }
+        }
+        Self::end_set(&mut message, set_start);
+
+        // データセット
+        let set_start = Self::begin_set(&mut message, template_id);
+        for record in records {
+            message.extend_from_slice(&(record.start.timestamp_millis() as u64).to_be_bytes());
+            message.extend_from_slice(&(record.end.timestamp_millis() as u64).to_be_bytes());
+            for address in [record.src_ip, record.dst_ip] {
+                match address {
+                    IpAddr::V4(address) => message.extend_from_slice(&address.octets()),
+                    IpAddr::V6(address) => message.extend_from_slice(&address.octets()),
+                }
+            }
+            message.extend_from_slice(&record.src_port.to_be_bytes());
+            message.extend_from_slice(&record.dst_port.to_be_bytes());
+            message.push(record.ip_protocol);
+            message.extend_from_slice(&(record.tcp_flags as u16).to_be_bytes());
+            for counter in [record.packets, record.bytes, record.reply_packets, record.reply_bytes] {
+                message.extend_from_slice(&counter.to_be_bytes());
+            }
+            message.push(record.end_reason.ipfix_code());
+        }
+        Self::end_set(&mut message, set_start);
+
+        let length = message.len() as u16;
+        message[2..4].copy_from_slice(&length.to_be_bytes());
+        message
+    }
+
+    fn begin_set(message: &mut Vec<u8>, set_id: u16) -> usize {
+        let start = message.len();
+        message.extend_from_slice(&set_id.to_be_bytes());
+        message.extend_from_slice(&0u16.to_be_bytes());
+        start
+    }
+
+    fn end_set(message: &mut [u8], start: usize) {
+        let length = (message.len() - start) as u16;
+        message[start + 2..start + 4].copy_from_slice(&length.to_be_bytes());
+    }
+}
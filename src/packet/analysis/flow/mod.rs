@@ -0,0 +1,5 @@
+mod ipfix;
+mod table;
+
+pub use ipfix::IpfixExporter;
+pub use table::{FlowEndReason, FlowRecord, FlowTable};
@@ -0,0 +1,368 @@
+use crate::config::FlowConfig;
+use crate::logger::eve_logger;
+use crate::packet::analysis::firewall::{FirewallPacket, FlowKey};
+use crate::packet::analysis::idps::EveEvent;
+use crate::packet::analysis::lru::LruIndex;
+use chrono::{DateTime, Utc};
+use log::{trace, warn};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// TCPフラグ
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+const TCP_ACK: u8 = 0x10;
+
+// FIN/RSTで終了したフローの後続のパケット (最後のACKなど) を同じフローとして集計する猶予
+const CLOSE_LINGER: Duration = Duration::from_secs(2);
+
+/// フローを書き出した理由 (IPFIXのflowEndReasonに対応する)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowEndReason {
+    IdleTimeout,
+    ActiveTimeout,
+    EndOfFlow,
+    LackOfResources,
+}
+
+impl FlowEndReason {
+    pub fn ipfix_code(&self) -> u8 {
+        match self {
+            FlowEndReason::IdleTimeout => 1,
+            FlowEndReason::ActiveTimeout => 2,
+            FlowEndReason::EndOfFlow => 3,
+            FlowEndReason::LackOfResources => 5,
+        }
+    }
+}
+
+impl fmt::Display for FlowEndReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FlowEndReason::IdleTimeout => write!(f, "idle_timeout"),
+            FlowEndReason::ActiveTimeout => write!(f, "active_timeout"),
+            FlowEndReason::EndOfFlow => write!(f, "end_of_flow"),
+            FlowEndReason::LackOfResources => write!(f, "lack_of_resources"),
+        }
+    }
+}
+
+/// flowsテーブルへ書き込む双方向のフロー
+#[derive(Debug, Clone)]
+pub struct FlowRecord {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    // 最初に観測したパケットの送信元を開始側とする
+    pub src_ip: IpAddr,
+    pub dst_ip: IpAddr,
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub ip_protocol: u8,
+    // 開始側から送信されたパケット数とIP層のバイト数
+    pub packets: u64,
+    pub bytes: u64,
+    // 応答側から送信されたパケット数とIP層のバイト数
+    pub reply_packets: u64,
+    pub reply_bytes: u64,
+    // 両方向で観測したTCPフラグの論理和
+    pub tcp_flags: u8,
+    pub end_reason: FlowEndReason,
+}
+
+#[derive(Debug)]
+struct FlowEntry {
+    record: FlowRecord,
+    last_seen: Instant,
+    // LruIndexでの使用順
+    order: u64,
+    // 直近に書き出した時刻 (アクティブタイムアウトの起点)
+    active_since: Instant,
+    fin_original: bool,
+    fin_reply: bool,
+    closed_at: Option<Instant>,
+}
+
+impl FlowEntry {
+    fn new(key: &FlowKey, timestamp: DateTime<Utc>, now: Instant, order: u64) -> Self {
+        Self {
+            record: FlowRecord {
+                start: timestamp,
+                end: timestamp,
+                src_ip: key.src_ip,
+                dst_ip: key.dst_ip,
+                src_port: key.src_port,
+                dst_port: key.dst_port,
+                ip_protocol: key.ip_protocol.value(),
+                packets: 0,
+                bytes: 0,
+                reply_packets: 0,
+                reply_bytes: 0,
+                tcp_flags: 0,
+                end_reason: FlowEndReason::IdleTimeout,
+            },
+            last_seen: now,
+            order,
+            active_since: now,
+            fin_original: false,
+            fin_reply: false,
+            closed_at: None,
+        }
+    }
+
+    fn finish(&self, reason: FlowEndReason) -> FlowRecord {
+        FlowRecord {
+            end_reason: reason,
+            ..self.record.clone()
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct FlowState {
+    flows: HashMap<FlowKey, FlowEntry>,
+    // 最も長く通信のないフローを上限に達した際に書き出す為の索引
+    lru: LruIndex<FlowKey>,
+    // 次回の書き出しを待っている、上限や再接続により終了したフロー
+    finished: Vec<FlowRecord>,
+    // finishedの先頭のうち、書き込みに失敗して戻されたフローの数 (EVEには書き出し済み)
+    requeued: usize,
+}
+
+/// 5-tuple毎にパケット数とバイト数を集計し、終了したフローを書き出す
+#[derive(Debug)]
+pub struct FlowTable {
+    enabled: bool,
+    idle_timeout: Duration,
+    active_timeout: Duration,
+    max_flows: usize,
+    state: Mutex<FlowState>,
+}
+
+impl FlowTable {
+    pub fn new(config: &FlowConfig) -> Self {
+        Self {
+            enabled: config.enabled,
+            idle_timeout: Duration::from_secs(config.idle_timeout.max(1)),
+            active_timeout: Duration::from_secs(config.active_timeout.max(1)),
+            max_flows: config.max_flows.max(1),
+            state: Mutex::new(FlowState::default()),
+        }
+    }
+
+    /// パケットをフローに集計する (lengthはIP層のバイト数)
+    pub fn update(&self, packet: &FirewallPacket, length: usize) {
+        if !self.enabled {
+            return;
+        }
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        let now = Instant::now();
+        let timestamp = Utc::now();
+        let flags = packet.transport.tcp_flags();
+
+        let key = FlowKey::from_packet(packet);
+        let reversed = key.reversed();
+        let (key, reply) = if state.flows.contains_key(&key) {
+            (key, false)
+        } else if state.flows.contains_key(&reversed) {
+            (reversed, true)
+        } else {
+            if state.flows.len() >= self.max_flows {
+                Self::evict_oldest(&mut state);
+            }
+            let order = state.lru.touch(None, key);
+            state.flows.insert(key, FlowEntry::new(&key, timestamp, now, order));
+            (key, false)
+        };
+
+        let FlowState { flows, finished, lru, .. } = &mut *state;
+        // 終了したTCPフローと同じ5-tupleでの再接続は新しいフローとして集計する
+        if let Some(entry) = flows.get_mut(&key).filter(|entry| !reply && flags & (TCP_SYN | TCP_ACK) == TCP_SYN && entry.closed_at.is_some()) {
+            let reconnected = FlowEntry::new(&key, timestamp, now, entry.order);
+            finished.push(std::mem::replace(entry, reconnected).finish(FlowEndReason::EndOfFlow));
+        }
+
+        let Some(entry) = flows.get_mut(&key) else {
+            return;
+        };
+        entry.order = lru.touch(Some(entry.order), key);
+        if reply {
+            entry.record.reply_packets += 1;
+            entry.record.reply_bytes += length as u64;
+        } else {
+            entry.record.packets += 1;
+            entry.record.bytes += length as u64;
+        }
+        entry.record.end = timestamp;
+        entry.record.tcp_flags |= flags;
+        entry.last_seen = now;
+
+        if flags & TCP_FIN != 0 {
+            if reply {
+                entry.fin_reply = true;
+            } else {
+                entry.fin_original = true;
+            }
+        }
+        if entry.closed_at.is_none() && (flags & TCP_RST != 0 || (entry.fin_original && entry.fin_reply)) {
+            entry.closed_at = Some(now);
+        }
+    }
+
+    /// タイムアウトや終了により書き出すフローを取り出す
+    /// 継続中のフローはアクティブタイムアウト毎にそれまでの集計を書き出し、カウンタを初期化する
+    pub fn expire(&self) -> Vec<FlowRecord> {
+        let Ok(mut state) = self.state.lock() else {
+            return Vec::new();
+        };
+        let now = Instant::now();
+        let timestamp = Utc::now();
+        let FlowState { flows, finished, lru, requeued } = &mut *state;
+        let mut expired = std::mem::take(finished);
+        let requeued = std::mem::take(requeued);
+
+        flows.retain(|_, entry| {
+            let reason = if entry.closed_at.is_some_and(|closed_at| now.duration_since(closed_at) >= CLOSE_LINGER) {
+                Some(FlowEndReason::EndOfFlow)
+            } else if now.duration_since(entry.last_seen) >= self.idle_timeout {
+                Some(FlowEndReason::IdleTimeout)
+            } else {
+                None
+            };
+            if let Some(reason) = reason {
+                expired.push(entry.finish(reason));
+                lru.remove(entry.order);
+                return false;
+            }
+
+            if now.duration_since(entry.active_since) >= self.active_timeout {
+                if entry.record.packets + entry.record.reply_packets > 0 {
+                    expired.push(entry.finish(FlowEndReason::ActiveTimeout));
+                }
+                let record = &mut entry.record;
+                record.start = timestamp;
+                record.end = timestamp;
+                (record.packets, record.bytes, record.reply_packets, record.reply_bytes, record.tcp_flags) = (0, 0, 0, 0, 0);
+                entry.active_since = now;
+            }
+            true
+        });
+
+        if eve_logger::is_enabled() {
+            for record in &expired[requeued..] {
+                eve_logger::write_event(&EveEvent::flow(record));
+            }
+        }
+        expired
+    }
+
+    /// 書き込みに失敗したフローを次回の書き出しに戻す (上限を超える分は古いフローから破棄する)
+    pub fn requeue(&self, mut records: Vec<FlowRecord>) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        let available = self.max_flows.saturating_sub(state.requeued);
+        if records.len() > available {
+            warn!("書き込みを待っているフローが上限に達した為、{}件のフローを破棄します", records.len() - available);
+            records.drain(..records.len() - available);
+        }
+        state.requeued += records.len();
+        state.finished.splice(0..0, records);
+    }
+
+    // 上限に達した場合は最も長く通信のないフローを書き出す
+    fn evict_oldest(state: &mut FlowState) {
+        let Some(key) = state.lru.oldest(None) else {
+            return;
+        };
+        if let Some(entry) = state.flows.remove(&key) {
+            state.lru.remove(entry.order);
+            trace!(
+                "フロー数が上限に達した為、フローを書き出します: {}:{} -> {}:{}",
+                key.src_ip,
+                key.src_port,
+                key.dst_ip,
+                key.dst_port
+            );
+            state.finished.push(entry.finish(FlowEndReason::LackOfResources));
+        }
+    }
+}
+
+impl Default for FlowTable {
+    fn default() -> Self {
+        Self::new(&FlowConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::analysis::transport::{TransportHeader, UdpHeader};
+    use crate::packet::types::{EtherType, IpProtocol};
+    use crate::packet::MacAddr;
+
+    fn datagram(src: (&str, u16), dst: (&str, u16)) -> FirewallPacket {
+        FirewallPacket::from_packet(
+            MacAddr([1; 6]),
+            MacAddr([2; 6]),
+            EtherType::IP_V4,
+            src.0.parse().unwrap(),
+            dst.0.parse().unwrap(),
+            IpProtocol::UDP,
+            TransportHeader::Udp(UdpHeader {
+                src_port: src.1,
+                dst_port: dst.1,
+                length: 8,
+                checksum: 0,
+            }),
+            None,
+            42,
+            None,
+        )
+    }
+
+    #[test]
+    fn evicts_the_flow_idle_for_the_longest_time() {
+        let table = FlowTable::new(&FlowConfig {
+            max_flows: 2,
+            ..FlowConfig::default()
+        });
+        let resolver = ("10.0.0.2", 53);
+        table.update(&datagram(("10.0.0.1", 1000), resolver), 28);
+        table.update(&datagram(("10.0.0.1", 2000), resolver), 28);
+        // 最初のフローの応答により、2番目のフローが最も長く通信のないフローになる
+        table.update(&datagram(resolver, ("10.0.0.1", 1000)), 28);
+        table.update(&datagram(("10.0.0.1", 3000), resolver), 28);
+
+        let evicted: Vec<FlowRecord> = table.expire().into_iter().filter(|record| record.end_reason == FlowEndReason::LackOfResources).collect();
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].src_port, 2000);
+    }
+
+    #[test]
+    fn returns_requeued_flows_on_next_expire() {
+        let table = FlowTable::new(&FlowConfig {
+            max_flows: 1,
+            ..FlowConfig::default()
+        });
+        let resolver = ("10.0.0.2", 53);
+        for port in [1000, 2000, 3000] {
+            table.update(&datagram(("10.0.0.1", port), resolver), 28);
+        }
+        let records = table.expire();
+        assert_eq!(records.len(), 2);
+
+        // 上限を超える分は古いフローから破棄する
+        table.requeue(records);
+        table.update(&datagram(("10.0.0.1", 4000), resolver), 28);
+        let ports: Vec<u16> = table.expire().iter().map(|record| record.src_port).collect();
+        assert_eq!(ports, [2000, 3000]);
+        assert!(table.expire().is_empty());
+    }
+}
@@ -0,0 +1,504 @@
+use crate::idps_log;
+use crate::packet::analysis::checksum::internet_checksum;
+use crate::packet::analysis::idps::{Alert, AlertCategory, AlertSeverity};
+use chrono::Utc;
+use log::{trace, warn};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const ETHERNET_HEADER_LENGTH: usize = 14;
+const IPV4_MIN_HEADER_LENGTH: usize = 20;
+const MAX_DATAGRAM_LENGTH: usize = 65535;
+
+// 再構築を待つ時間 (Linuxのipfrag_timeに準拠)
+const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(30);
+// 保持するフラグメントの合計サイズと、1データグラムあたりのフラグメント数の上限
+const MAX_BUFFERED_BYTES: usize = 4 * 1024 * 1024;
+const MAX_FRAGMENTS_PER_DATAGRAM: usize = 64;
+const GC_INTERVAL: Duration = Duration::from_secs(1);
+
+// IPv4フラグ
+const IPV4_MORE_FRAGMENTS: u8 = 0x20;
+
+/// フラグメントの属するデータグラムの識別子
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct FragmentKey {
+    src_ip: Ipv4Addr,
+    dst_ip: Ipv4Addr,
+    id: u16,
+    ip_protocol: u8,
+}
+
+#[derive(Debug)]
+struct Fragment {
+    // IPペイロード内のオフセット(バイト)
+    offset: usize,
+    payload: Vec<u8>,
+    // 受信したEthernetフレームそのもの
+    frame: Vec<u8>,
+}
+
+impl Fragment {
+    fn end(&self) -> usize {
+        self.offset + self.payload.len()
+    }
+}
+
+#[derive(Debug)]
+struct FragmentBuffer {
+    fragments: Vec<Fragment>,
+    // 最終フラグメント(MF=0)を受信した時点で確定するペイロード長
+    total_length: Option<usize>,
+    // オフセット0のフラグメントのEthernetヘッダとIPヘッダ
+    first_headers: Option<Vec<u8>>,
+    buffered_bytes: usize,
+    created_at: Instant,
+}
+
+impl FragmentBuffer {
+    fn new(now: Instant) -> Self {
+        Self {
+            fragments: Vec::new(),
+            total_length: None,
+            first_headers: None,
+            buffered_bytes: 0,
+            created_at: now,
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        let Some(total_length) = self.total_length else {
+            return false;
+        };
+        if self.first_headers.is_none() {
+            return false;
+        }
+
+        // オフセット順に隙間なく並んでいるかを確認する
+        let mut offsets: Vec<(usize, usize)> = self.fragments.iter().map(|f| (f.offset, f.end())).collect();
+        offsets.sort_unstable();
+        let mut covered = 0;
+        for (start, end) in offsets {
+            if start > covered {
+                return false;
+            }
+            covered = covered.max(end);
+        }
+        covered >= total_length
+    }
+}
+
+/// 再構築の結果
+pub enum Reassembly {
+    // 残りのフラグメントを待っている
+    Incomplete,
+    // 不正なフラグメント (保持していたデータグラムも破棄する)
+    // 重複フラグメント等の攻撃の可能性がある場合はアラートを含む
+    Invalid(Option<Alert>),
+    Complete {
+        // 再構築したデータグラムをペイロードとするEthernetフレーム
+        datagram: Vec<u8>,
+        // 受信順のフラグメントのフレーム
+        fragments: Vec<Vec<u8>>,
+    },
+}
+
+#[derive(Debug)]
+struct FragmentTable {
+    buffers: HashMap<FragmentKey, FragmentBuffer>,
+    buffered_bytes: usize,
+    last_gc: Instant,
+}
+
+impl FragmentTable {
+    fn remove(&mut self, key: &FragmentKey) -> Option<FragmentBuffer> {
+        let buffer = self.buffers.remove(key)?;
+        self.buffered_bytes -= buffer.buffered_bytes;
+        Some(buffer)
+    }
+
+    fn collect_garbage(&mut self, now: Instant) {
+        if now.duration_since(self.last_gc) < GC_INTERVAL {
+            return;
+        }
+
+        let expired: Vec<FragmentKey> = self.buffers.iter().filter(|(_, buffer)| now.duration_since(buffer.created_at) > REASSEMBLY_TIMEOUT).map(|(key, _)| *key).collect();
+        for key in expired {
+            if let Some(buffer) = self.remove(&key) {
+                idps_log!(
+                    "フラグメントの再構築がタイムアウトしました: {} -> {}, id={}, protocol={}, 受信済みフラグメント数={}",
+                    key.src_ip,
+                    key.dst_ip,
+                    key.id,
+                    key.ip_protocol,
+                    buffer.fragments.len()
+                );
+            }
+        }
+        self.last_gc = now;
+    }
+
+    // 上限を超える場合は最も古いデータグラムから破棄する
+    fn evict_for(&mut self, length: usize) {
+        while self.buffered_bytes + length > MAX_BUFFERED_BYTES {
+            let Some(oldest) = self.buffers.iter().min_by_key(|(_, buffer)| buffer.created_at).map(|(key, _)| *key) else {
+                break;
+            };
+            warn!("フラグメントバッファが上限に達した為、最も古いデータグラムを破棄します: id={}", oldest.id);
+            self.remove(&oldest);
+        }
+    }
+}
+
+/// (送信元, 宛先, ID, プロトコル)をキーとしたIPv4フラグメントの再構築バッファ
+pub struct FragmentReassembler {
+    table: Mutex<FragmentTable>,
+}
+
+impl FragmentReassembler {
+    pub fn new() -> Self {
+        Self {
+            table: Mutex::new(FragmentTable {
+                buffers: HashMap::new(),
+                buffered_bytes: 0,
+                last_gc: Instant::now(),
+            }),
+        }
+    }
+
+    /// IPv4のフラグメントであるかを判定する
+    pub fn is_fragment(ip_data: &[u8]) -> bool {
+        ip_data.len() >= IPV4_MIN_HEADER_LENGTH && ip_data[0] >> 4 == 4 && (ip_data[6] & IPV4_MORE_FRAGMENTS != 0 || fragment_offset(ip_data) != 0)
+    }
+
+    /// フラグメントを追加し、全て揃った場合は再構築したデータグラムを返す
+    pub fn process(&self, ethernet_frame: &[u8]) -> Reassembly {
+        let ip_data = &ethernet_frame[ETHERNET_HEADER_LENGTH..];
+        let header_length = (ip_data[0] & 0xF) as usize * 4;
+        let total_length = u16::from_be_bytes([ip_data[2], ip_data[3]]) as usize;
+        if header_length < IPV4_MIN_HEADER_LENGTH || total_length < header_length || total_length > ip_data.len() {
+            idps_log!("フラグメントのIPヘッダ長が不正です: ヘッダ長={}, 全長={}", header_length, total_length);
+            return Reassembly::Invalid(None);
+        }
+
+        let key = FragmentKey {
+            src_ip: Ipv4Addr::new(ip_data[12], ip_data[13], ip_data[14], ip_data[15]),
+            dst_ip: Ipv4Addr::new(ip_data[16], ip_data[17], ip_data[18], ip_data[19]),
+            id: u16::from_be_bytes([ip_data[4], ip_data[5]]),
+            ip_protocol: ip_data[9],
+        };
+        let more_fragments = ip_data[6] & IPV4_MORE_FRAGMENTS != 0;
+        let fragment = Fragment {
+            offset: fragment_offset(ip_data),
+            payload: ip_data[header_length..total_length].to_vec(),
+            frame: ethernet_frame.to_vec(),
+        };
+
+        // 最大長を超えるデータグラム (Ping of Death等)
+        if header_length + fragment.end() > MAX_DATAGRAM_LENGTH {
+            let message = format!(
+                "最大長を超えるフラグメントを検知しました: {} -> {}, id={}, offset={}, 長さ={}",
+                key.src_ip,
+                key.dst_ip,
+                key.id,
+                fragment.offset,
+                fragment.payload.len()
+            );
+            return self.discard(&key, Some(alert(&key, AlertSeverity::High, message)));
+        }
+
+        // 最終フラグメント以外は8バイト単位である必要がある
+        if more_fragments && (fragment.payload.is_empty() || !fragment.payload.len().is_multiple_of(8)) {
+            idps_log!(
+                "フラグメント長が8の倍数ではありません: {} -> {}, id={}, 長さ={}",
+                key.src_ip,
+                key.dst_ip,
+                key.id,
+                fragment.payload.len()
+            );
+            return self.discard(&key, None);
+        }
+
+        let Ok(mut table) = self.table.lock() else {
+            return Reassembly::Invalid(None);
+        };
+        let now = Instant::now();
+        table.collect_garbage(now);
+        if !table.buffers.contains_key(&key) {
+            table.evict_for(fragment.frame.len());
+        }
+
+        let buffer = table.buffers.entry(key).or_insert_with(|| FragmentBuffer::new(now));
+
+        // 重複するフラグメント (Teardrop等の重複フラグメント攻撃)
+        // 同じ内容の再送のみ許容する
+        if let Some(existing) = buffer.fragments.iter().find(|f| fragment.offset < f.end() && f.offset < fragment.end()) {
+            if existing.offset == fragment.offset && existing.payload == fragment.payload {
+                trace!("重複したフラグメントを無視します: id={}, offset={}", key.id, fragment.offset);
+                return Reassembly::Incomplete;
+            }
+
+            let message = format!(
+                "重複フラグメント攻撃を検知しました: {} -> {}, id={}, protocol={}, 既存=[{}, {}), 受信=[{}, {})",
+                key.src_ip,
+                key.dst_ip,
+                key.id,
+                key.ip_protocol,
+                existing.offset,
+                existing.end(),
+                fragment.offset,
+                fragment.end()
+            );
+            drop(table);
+            return self.discard(&key, Some(alert(&key, AlertSeverity::High, message)));
+        }
+
+        if !more_fragments {
+            if buffer.total_length.is_some_and(|length| length != fragment.end()) {
+                let message = format!("最終フラグメントが複数あります: {} -> {}, id={}", key.src_ip, key.dst_ip, key.id);
+                drop(table);
+                return self.discard(&key, Some(alert(&key, AlertSeverity::Medium, message)));
+            }
+            buffer.total_length = Some(fragment.end());
+        }
+        if buffer.total_length.is_some_and(|length| buffer.fragments.iter().chain([&fragment]).any(|f| f.end() > length)) {
+            let message = format!("最終フラグメントより後ろのフラグメントがあります: {} -> {}, id={}", key.src_ip, key.dst_ip, key.id);
+            drop(table);
+            return self.discard(&key, Some(alert(&key, AlertSeverity::Medium, message)));
+        }
+
+        if buffer.fragments.len() >= MAX_FRAGMENTS_PER_DATAGRAM {
+            idps_log!(
+                "フラグメント数が上限を超えました: {} -> {}, id={}, 上限={}",
+                key.src_ip,
+                key.dst_ip,
+                key.id,
+                MAX_FRAGMENTS_PER_DATAGRAM
+            );
+            drop(table);
+            return self.discard(&key, None);
+        }
+
+        if fragment.offset == 0 {
+            buffer.first_headers = Some(ethernet_frame[..ETHERNET_HEADER_LENGTH + header_length].to_vec());
+        }
+        let length = fragment.frame.len();
+        buffer.buffered_bytes += length;
+        buffer.fragments.push(fragment);
+        table.buffered_bytes += length;
+
+        if !table.buffers.get(&key).is_some_and(|buffer| buffer.is_complete()) {
+            return Reassembly::Incomplete;
+        }
+
+        match table.remove(&key) {
+            Some(buffer) => Self::reassemble(buffer),
+            None => Reassembly::Incomplete,
+        }
+    }
+
+    fn discard(&self, key: &FragmentKey, alert: Option<Alert>) -> Reassembly {
+        if let Ok(mut table) = self.table.lock() {
+            table.remove(key);
+        }
+        Reassembly::Invalid(alert)
+    }
+
+    fn reassemble(buffer: FragmentBuffer) -> Reassembly {
+        let (Some(mut datagram), Some(payload_length)) = (buffer.first_headers, buffer.total_length) else {
+            return Reassembly::Invalid(None);
+        };
+        let header_length = datagram.len() - ETHERNET_HEADER_LENGTH;
+
+        let mut payload = vec![0u8; payload_length];
+        for fragment in &buffer.fragments {
+            payload[fragment.offset..fragment.end()].copy_from_slice(&fragment.payload);
+        }
+        datagram.extend_from_slice(&payload);
+
+        // 全長とフラグメント情報を更新し、ヘッダチェックサムを再計算する
+        let ip_header = &mut datagram[ETHERNET_HEADER_LENGTH..ETHERNET_HEADER_LENGTH + header_length];
+        ip_header[2..4].copy_from_slice(&((header_length + payload_length) as u16).to_be_bytes());
+        ip_header[6] &= !(IPV4_MORE_FRAGMENTS | 0x1F);
+        ip_header[7] = 0;
+        ip_header[10..12].copy_from_slice(&[0, 0]);
+        let checksum = internet_checksum(&[ip_header]);
+        ip_header[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+        trace!("フラグメントを再構築しました: フラグメント数={}, ペイロード長={}", buffer.fragments.len(), payload_length);
+
+        Reassembly::Complete {
+            datagram,
+            fragments: buffer.fragments.into_iter().map(|f| f.frame).collect(),
+        }
+    }
+}
+
+// 先頭以外のフラグメントにはトランスポート層のヘッダがない為、ポートは0とする
+fn alert(key: &FragmentKey, severity: AlertSeverity, message: String) -> Alert {
+    idps_log!("{}", message);
+    Alert {
+        timestamp: Utc::now(),
+        severity,
+        category: AlertCategory::FragmentAnomaly,
+        signature_id: None,
+        message,
+        src_ip: IpAddr::V4(key.src_ip),
+        dst_ip: IpAddr::V4(key.dst_ip),
+        src_port: 0,
+        dst_port: 0,
+        ip_protocol: key.ip_protocol,
+        packet_id: None,
+    }
+}
+
+fn fragment_offset(ip_data: &[u8]) -> usize {
+    u16::from_be_bytes([ip_data[6] & 0x1F, ip_data[7]]) as usize * 8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: u16 = 0x1234;
+
+    // UDPのデータグラムのフラグメントをEthernetフレームとして組み立てる
+    fn fragment(id: u16, offset: usize, more_fragments: bool, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0u8; ETHERNET_HEADER_LENGTH];
+        frame[12..14].copy_from_slice(&[0x08, 0x00]);
+        let flags_offset = (offset / 8) as u16 | if more_fragments { (IPV4_MORE_FRAGMENTS as u16) << 8 } else { 0 };
+        let mut header = [0u8; IPV4_MIN_HEADER_LENGTH];
+        header[0] = 0x45;
+        header[2..4].copy_from_slice(&((IPV4_MIN_HEADER_LENGTH + payload.len()) as u16).to_be_bytes());
+        header[4..6].copy_from_slice(&id.to_be_bytes());
+        header[6..8].copy_from_slice(&flags_offset.to_be_bytes());
+        header[8] = 64;
+        header[9] = 17;
+        header[12..16].copy_from_slice(&[10, 0, 0, 1]);
+        header[16..20].copy_from_slice(&[10, 0, 0, 2]);
+        frame.extend_from_slice(&header);
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    fn datagram(result: Reassembly) -> Vec<u8> {
+        match result {
+            Reassembly::Complete { datagram, .. } => datagram,
+            _ => panic!("データグラムが再構築されていません"),
+        }
+    }
+
+    fn alert(result: Reassembly) -> Option<Alert> {
+        match result {
+            Reassembly::Invalid(alert) => alert,
+            _ => panic!("フラグメントが破棄されていません"),
+        }
+    }
+
+    #[test]
+    fn reassembles_fragments_in_order() {
+        let reassembler = FragmentReassembler::new();
+        let payload: Vec<u8> = (0..24).collect();
+        assert!(FragmentReassembler::is_fragment(&fragment(ID, 0, true, &payload[..8])[ETHERNET_HEADER_LENGTH..]));
+        assert!(matches!(reassembler.process(&fragment(ID, 0, true, &payload[..8])), Reassembly::Incomplete));
+        assert!(matches!(reassembler.process(&fragment(ID, 8, true, &payload[8..16])), Reassembly::Incomplete));
+
+        let datagram = datagram(reassembler.process(&fragment(ID, 16, false, &payload[16..])));
+        let ip_header = &datagram[ETHERNET_HEADER_LENGTH..ETHERNET_HEADER_LENGTH + IPV4_MIN_HEADER_LENGTH];
+        assert_eq!(&datagram[ETHERNET_HEADER_LENGTH + IPV4_MIN_HEADER_LENGTH..], payload.as_slice());
+        assert_eq!(u16::from_be_bytes([ip_header[2], ip_header[3]]), 44);
+        assert!(!FragmentReassembler::is_fragment(ip_header));
+        assert_eq!(internet_checksum(&[ip_header]), 0);
+    }
+
+    #[test]
+    fn reassembles_fragments_out_of_order() {
+        let reassembler = FragmentReassembler::new();
+        let payload: Vec<u8> = (0..20).collect();
+        assert!(matches!(reassembler.process(&fragment(ID, 16, false, &payload[16..])), Reassembly::Incomplete));
+        assert!(matches!(reassembler.process(&fragment(ID, 8, true, &payload[8..16])), Reassembly::Incomplete));
+        // 同じ内容の再送は無視する
+        assert!(matches!(reassembler.process(&fragment(ID, 8, true, &payload[8..16])), Reassembly::Incomplete));
+
+        let result = reassembler.process(&fragment(ID, 0, true, &payload[..8]));
+        let Reassembly::Complete { datagram, fragments } = result else {
+            panic!("データグラムが再構築されていません");
+        };
+        assert_eq!(&datagram[ETHERNET_HEADER_LENGTH + IPV4_MIN_HEADER_LENGTH..], payload.as_slice());
+        assert_eq!(fragments.len(), 3);
+    }
+
+    #[test]
+    fn rejects_overlapping_fragments_with_alert() {
+        let reassembler = FragmentReassembler::new();
+        assert!(matches!(reassembler.process(&fragment(ID, 0, true, &[0; 16])), Reassembly::Incomplete));
+
+        // 既存のフラグメントと異なる内容で重なる (Teardrop)
+        let alert = alert(reassembler.process(&fragment(ID, 8, false, &[1; 8]))).expect("アラートがありません");
+        assert_eq!(alert.category, AlertCategory::FragmentAnomaly);
+        assert_eq!(alert.severity, AlertSeverity::High);
+        assert_eq!(alert.src_ip, IpAddr::from([10, 0, 0, 1]));
+
+        // 保持していたフラグメントも破棄されている
+        assert!(matches!(reassembler.process(&fragment(ID, 16, false, &[2; 8])), Reassembly::Incomplete));
+    }
+
+    #[test]
+    fn rejects_conflicting_last_fragments_with_alert() {
+        let reassembler = FragmentReassembler::new();
+        assert!(matches!(reassembler.process(&fragment(ID, 16, false, &[0; 8])), Reassembly::Incomplete));
+        assert!(alert(reassembler.process(&fragment(ID, 32, false, &[0; 8]))).is_some());
+
+        assert!(matches!(reassembler.process(&fragment(ID, 8, false, &[0; 8])), Reassembly::Incomplete));
+        let alert = alert(reassembler.process(&fragment(ID, 16, true, &[0; 8]))).expect("アラートがありません");
+        assert_eq!(alert.severity, AlertSeverity::Medium);
+    }
+
+    #[test]
+    fn rejects_datagram_exceeding_maximum_length() {
+        let reassembler = FragmentReassembler::new();
+        // オフセットの最大値 (Ping of Death)
+        let alert = alert(reassembler.process(&fragment(ID, 65528, false, &[0; 8]))).expect("アラートがありません");
+        assert_eq!(alert.category, AlertCategory::FragmentAnomaly);
+
+        assert!(matches!(reassembler.process(&fragment(ID, 65504, false, &[0; 8])), Reassembly::Incomplete));
+    }
+
+    #[test]
+    fn rejects_too_many_fragments() {
+        let reassembler = FragmentReassembler::new();
+        for index in 0..MAX_FRAGMENTS_PER_DATAGRAM {
+            assert!(matches!(reassembler.process(&fragment(ID, index * 8, true, &[0; 8])), Reassembly::Incomplete));
+        }
+        assert!(matches!(
+            reassembler.process(&fragment(ID, MAX_FRAGMENTS_PER_DATAGRAM * 8, false, &[0; 8])),
+            Reassembly::Invalid(None)
+        ));
+    }
+
+    #[test]
+    fn rejects_unaligned_fragment() {
+        let reassembler = FragmentReassembler::new();
+        assert!(matches!(reassembler.process(&fragment(ID, 0, true, &[0; 12])), Reassembly::Invalid(None)));
+    }
+
+    #[test]
+    fn drops_incomplete_datagram_after_timeout() {
+        let reassembler = FragmentReassembler::new();
+        assert!(matches!(reassembler.process(&fragment(ID, 0, true, &[0; 8])), Reassembly::Incomplete));
+        {
+            let mut table = reassembler.table.lock().unwrap();
+            let past = Instant::now() - REASSEMBLY_TIMEOUT - Duration::from_secs(1);
+            table.buffers.values_mut().for_each(|buffer| buffer.created_at = past);
+            table.last_gc = past;
+        }
+
+        // タイムアウトしたデータグラムの残りのフラグメントだけでは再構築しない
+        assert!(matches!(reassembler.process(&fragment(ID, 8, false, &[0; 8])), Reassembly::Incomplete));
+        let table = reassembler.table.lock().unwrap();
+        assert_eq!(table.buffers.len(), 1);
+        assert_eq!(table.buffered_bytes, ETHERNET_HEADER_LENGTH + IPV4_MIN_HEADER_LENGTH + 8);
+    }
+}
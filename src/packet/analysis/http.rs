@@ -0,0 +1,199 @@
+use crate::packet::analysis::stream::StreamChunk;
+
+// 解析するヘッダ部の上限 (ストリームで保持する検査済みデータと同じ長さ)
+const MAX_HEADER_LENGTH: usize = 8 * 1024;
+const METHODS: [&str; 9] = ["GET", "POST", "PUT", "DELETE", "HEAD", "OPTIONS", "PATCH", "CONNECT", "TRACE"];
+
+/// HTTP/1.xのリクエストまたはレスポンスのヘッダから抽出した情報
+#[derive(Debug, Clone, Default)]
+pub struct HttpMessage {
+    pub is_response: bool,
+    // HTTP/1.0 または HTTP/1.1
+    pub version: String,
+    // リクエストのみ
+    pub method: Option<String>,
+    pub uri: Option<String>,
+    pub host: Option<String>,
+    pub user_agent: Option<String>,
+    // レスポンスのみ
+    pub status_code: Option<u16>,
+    // 開始行を除いたヘッダ部 (末尾の空行を含まない)
+    pub headers: Vec<u8>,
+}
+
+/// TCPのデータからヘッダ部が揃ったHTTPメッセージを取り出す
+/// ストリームでは今回のデータでヘッダ部の終端に達したメッセージのみを返す
+pub fn parse_http_messages(payload: &[u8], stream: Option<&StreamChunk>) -> Vec<HttpMessage> {
+    let (data, new_offset) = match stream {
+        Some(stream) => (stream.data.as_slice(), stream.new_offset),
+        None => (payload, 0),
+    };
+
+    let mut messages = Vec::new();
+    let mut position = 0;
+    while position < data.len() {
+        match parse_message(&data[position..]) {
+            Some((message, header_length, length)) => {
+                if position + header_length > new_offset {
+                    messages.push(message);
+                }
+                position += length;
+            },
+            // メッセージの開始でなければ次の行へ進む
+            None => match data[position..].iter().position(|b| *b == b'\n') {
+                Some(line_end) => position += line_end + 1,
+                None => break,
+            },
+        }
+    }
+
+    messages
+}
+
+// 開始行とヘッダ部を解析し、ヘッダ部の長さとボディ (Content-Lengthから分かる範囲) を含めた長さを返す
+fn parse_message(data: &[u8]) -> Option<(HttpMessage, usize, usize)> {
+    // 先頭が開始行でなければ終端を探さない
+    let first_word = data.iter().take(10).position(|b| *b == b' ').map(|end| &data[..end])?;
+    if !first_word.starts_with(b"HTTP/1.") && !METHODS.iter().any(|method| method.as_bytes() == first_word) {
+        return None;
+    }
+
+    let searched = &data[..data.len().min(MAX_HEADER_LENGTH)];
+    let header_end = searched.windows(4).position(|window| window == b"\r\n\r\n")?;
+    let text = String::from_utf8_lossy(&data[..header_end]);
+    let mut lines = text.split("\r\n");
+    let start_line = lines.next()?;
+
+    let mut message = HttpMessage::default();
+    let mut parts = start_line.splitn(3, ' ');
+    let (Some(first), Some(second)) = (parts.next(), parts.next()) else {
+        return None;
+    };
+    if first.starts_with("HTTP/1.") {
+        // HTTP/1.1 200 OK
+        if second.len() != 3 {
+            return None;
+        }
+        message.is_response = true;
+        message.version = first.to_string();
+        message.status_code = Some(second.parse().ok()?);
+    } else {
+        // GET /index.html HTTP/1.1
+        let version = parts.next().filter(|version| version.starts_with("HTTP/1."))?;
+        message.method = Some(first.to_string());
+        message.uri = Some(second.to_string());
+        message.version = version.to_string();
+    }
+
+    let mut content_length = 0;
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("host") {
+            message.host = Some(value.to_string());
+        } else if name.eq_ignore_ascii_case("user-agent") {
+            message.user_agent = Some(value.to_string());
+        } else if name.eq_ignore_ascii_case("content-length") {
+            content_length = value.parse().unwrap_or(0);
+        }
+    }
+    let headers_start = data.iter().position(|b| *b == b'\n').map_or(header_end, |line_end| line_end + 1);
+    message.headers = data.get(headers_start..header_end).unwrap_or_default().to_vec();
+
+    // ボディがデータ内に収まらない場合は残りを全て読み飛ばす (ボディ内をメッセージとして解析しない為)
+    let header_length = header_end + 4;
+    Some((message, header_length, header_length.saturating_add(content_length).min(data.len())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REQUEST: &[u8] = b"GET /index.html?q=1 HTTP/1.1\r\nHost: example.com\r\nUser-Agent: curl/8.0\r\n\r\n";
+
+    #[test]
+    fn parses_request() {
+        let messages = parse_http_messages(REQUEST, None);
+        assert_eq!(messages.len(), 1);
+        let message = &messages[0];
+        assert!(!message.is_response);
+        assert_eq!(message.version, "HTTP/1.1");
+        assert_eq!(message.method.as_deref(), Some("GET"));
+        assert_eq!(message.uri.as_deref(), Some("/index.html?q=1"));
+        assert_eq!(message.host.as_deref(), Some("example.com"));
+        assert_eq!(message.user_agent.as_deref(), Some("curl/8.0"));
+        assert_eq!(message.status_code, None);
+        assert_eq!(message.headers, b"Host: example.com\r\nUser-Agent: curl/8.0");
+    }
+
+    #[test]
+    fn parses_response() {
+        let messages = parse_http_messages(b"HTTP/1.0 404 Not Found\r\ncontent-length: 0\r\n\r\n", None);
+        assert_eq!(messages.len(), 1);
+        let message = &messages[0];
+        assert!(message.is_response);
+        assert_eq!(message.version, "HTTP/1.0");
+        assert_eq!(message.status_code, Some(404));
+        assert_eq!(message.method, None);
+        assert_eq!(message.headers, b"content-length: 0");
+    }
+
+    #[test]
+    fn skips_body_by_content_length() {
+        // ボディ内のリクエストに見える行はメッセージとして扱わない
+        let mut data = b"POST /upload HTTP/1.1\r\nContent-Length: 18\r\n\r\n".to_vec();
+        data.extend_from_slice(b"GET / HTTP/1.1\r\n\r\n");
+        data.extend_from_slice(REQUEST);
+
+        let messages = parse_http_messages(&data, None);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].method.as_deref(), Some("POST"));
+        assert_eq!(messages[1].uri.as_deref(), Some("/index.html?q=1"));
+    }
+
+    #[test]
+    fn skips_remaining_data_for_huge_content_length() {
+        let mut data = b"POST /upload HTTP/1.1\r\nContent-Length: 18446744073709551615\r\n\r\n".to_vec();
+        data.extend_from_slice(REQUEST);
+
+        let messages = parse_http_messages(&data, None);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].method.as_deref(), Some("POST"));
+    }
+
+    #[test]
+    fn ignores_truncated_headers() {
+        for length in 0..REQUEST.len() {
+            assert!(parse_http_messages(&REQUEST[..length], None).is_empty(), "length {}", length);
+        }
+    }
+
+    #[test]
+    fn ignores_non_http_data() {
+        assert!(parse_http_messages(b"\x16\x03\x01\x00\x05hello\r\n\r\n", None).is_empty());
+        assert!(parse_http_messages(b"FOO / HTTP/1.1\r\n\r\n", None).is_empty());
+        assert!(parse_http_messages(b"GET / SPDY/3\r\n\r\n", None).is_empty());
+        assert!(parse_http_messages(b"HTTP/1.1 2000 OK\r\n\r\n", None).is_empty());
+    }
+
+    #[test]
+    fn returns_only_messages_completed_by_new_stream_data() {
+        let mut data = REQUEST.to_vec();
+        data.extend_from_slice(b"HTTP/1.1 200 OK\r\n\r\n");
+        // 1つ目のメッセージは検査済み、2つ目のヘッダ部の終端が新たなデータ
+        let stream = StreamChunk {
+            data,
+            new_offset: REQUEST.len() + 4,
+            start: 0,
+            from_client: true,
+            hello_parsed: false,
+            sni: None,
+        };
+
+        let messages = parse_http_messages(&[], Some(&stream));
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].status_code, Some(200));
+    }
+}
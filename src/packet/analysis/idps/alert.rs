@@ -0,0 +1,122 @@
+use crate::packet::analysis::firewall::FirewallPacket;
+use chrono::{DateTime, Utc};
+use std::fmt;
+use std::net::IpAddr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AlertSeverity {
+    Low,
+    Medium,
+    High,
+}
+
+impl AlertSeverity {
+    /// シグネチャの優先度 (1が最も高い) から重大度を決める
+    pub fn from_priority(priority: Option<u32>) -> Self {
+        match priority {
+            Some(1) => AlertSeverity::High,
+            Some(2) | None => AlertSeverity::Medium,
+            Some(_) => AlertSeverity::Low,
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "low" => Some(AlertSeverity::Low),
+            "medium" => Some(AlertSeverity::Medium),
+            "high" => Some(AlertSeverity::High),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for AlertSeverity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AlertSeverity::Low => write!(f, "low"),
+            AlertSeverity::Medium => write!(f, "medium"),
+            AlertSeverity::High => write!(f, "high"),
+        }
+    }
+}
+
+/// アラートを出力した検知の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlertCategory {
+    PortScan,
+    SynFlood,
+    ArpSpoofing,
+    DnsTunneling,
+    Dga,
+    TlsFingerprint,
+    StreamEvasion,
+    FragmentAnomaly,
+    TrafficAnomaly,
+    Signature,
+}
+
+impl fmt::Display for AlertCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AlertCategory::PortScan => write!(f, "port_scan"),
+            AlertCategory::SynFlood => write!(f, "syn_flood"),
+            AlertCategory::ArpSpoofing => write!(f, "arp_spoofing"),
+            AlertCategory::DnsTunneling => write!(f, "dns_tunneling"),
+            AlertCategory::Dga => write!(f, "dga"),
+            AlertCategory::TlsFingerprint => write!(f, "tls_fingerprint"),
+            AlertCategory::StreamEvasion => write!(f, "stream_evasion"),
+            AlertCategory::FragmentAnomaly => write!(f, "fragment_anomaly"),
+            AlertCategory::TrafficAnomaly => write!(f, "traffic_anomaly"),
+            AlertCategory::Signature => write!(f, "signature"),
+        }
+    }
+}
+
+/// idps_alertsテーブルへ書き込む構造化されたアラート
+#[derive(Debug, Clone)]
+pub struct Alert {
+    pub timestamp: DateTime<Utc>,
+    pub severity: AlertSeverity,
+    pub category: AlertCategory,
+    // シグネチャによる検知の場合のみ
+    pub signature_id: Option<u32>,
+    pub message: String,
+    // アラートの契機となったパケットの5タプル
+    pub src_ip: IpAddr,
+    pub dst_ip: IpAddr,
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub ip_protocol: u8,
+    // 契機となったパケットがpacketsテーブルに保存された場合のID
+    pub packet_id: Option<i64>,
+}
+
+impl Alert {
+    pub fn new(packet: &FirewallPacket, severity: AlertSeverity, category: AlertCategory, message: String) -> Self {
+        Self {
+            timestamp: Utc::now(),
+            severity,
+            category,
+            signature_id: None,
+            message,
+            src_ip: packet.src_ip,
+            dst_ip: packet.dst_ip,
+            src_port: packet.transport.src_port(),
+            dst_port: packet.transport.dst_port(),
+            ip_protocol: packet.ip_protocol.value(),
+            packet_id: None,
+        }
+    }
+
+    /// 応答から検知したアラートを、要求を送信した側を送信元とするアラートにする
+    /// (自動遮断の対象が応答したサーバにならないようにする)
+    pub fn reversed(self) -> Self {
+        Self {
+            src_ip: self.dst_ip,
+            dst_ip: self.src_ip,
+            src_port: self.dst_port,
+            dst_port: self.src_port,
+            ..self
+        }
+    }
+}
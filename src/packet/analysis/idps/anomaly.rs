@@ -0,0 +1,378 @@
+use super::{Alert, AlertCategory, AlertSeverity};
+use crate::config::AnomalyConfig;
+use crate::idps_log;
+use crate::packet::analysis::firewall::FirewallPacket;
+use crate::packet::analysis::lru::LruIndex;
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// プロトコル構成の分類 (TCP, UDP, ICMP, その他)
+const PROTOCOL_CLASSES: usize = 4;
+// プロトコル構成を評価するのに必要な、集計間隔あたりの最小パケット数
+const MIN_MIX_PACKETS: u64 = 20;
+// 変動の少ないホストで僅かな変化を検知しないよう、標準偏差の下限を平均に対する割合で設ける
+const MIN_DEVIATION_RATIO: f64 = 0.1;
+// 集計間隔あたりに数える通信先の上限
+const MAX_PEERS_PER_INTERVAL: usize = 65536;
+
+/// 指数移動平均と分散
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Ewma {
+    pub mean: f64,
+    pub variance: f64,
+}
+
+impl Ewma {
+    fn update(&mut self, value: f64, alpha: f64) {
+        let diff = value - self.mean;
+        let increment = alpha * diff;
+        self.mean += increment;
+        self.variance = (1.0 - alpha) * (self.variance + diff * increment);
+    }
+
+    // 平均からの乖離を標準偏差の何倍かで返す
+    fn score(&self, value: f64) -> f64 {
+        let deviation = self.variance.sqrt().max(self.mean.abs() * MIN_DEVIATION_RATIO).max(1.0);
+        (value - self.mean).abs() / deviation
+    }
+}
+
+/// anomaly_baselinesテーブルへ保存するホスト毎のベースライン
+#[derive(Debug, Clone)]
+pub struct HostBaseline {
+    pub host: IpAddr,
+    // 学習した集計間隔の数
+    pub samples: u64,
+    pub packets_per_sec: Ewma,
+    pub bytes_per_sec: Ewma,
+    // 集計間隔あたりの異なる通信先の数
+    pub peers: Ewma,
+    // TCP, UDP, ICMP, その他のパケットの割合の指数移動平均
+    pub protocol_mix: [f64; PROTOCOL_CLASSES],
+    pub updated_at: DateTime<Utc>,
+}
+
+impl HostBaseline {
+    fn new(host: IpAddr) -> Self {
+        Self {
+            host,
+            samples: 0,
+            packets_per_sec: Ewma::default(),
+            bytes_per_sec: Ewma::default(),
+            peers: Ewma::default(),
+            protocol_mix: [0.0; PROTOCOL_CLASSES],
+            updated_at: Utc::now(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct IntervalCounters {
+    packets: u64,
+    bytes: u64,
+    peers: HashSet<IpAddr>,
+    protocols: [u64; PROTOCOL_CLASSES],
+}
+
+#[derive(Debug)]
+struct HostProfile {
+    baseline: HostBaseline,
+    current: IntervalCounters,
+    interval_start: Instant,
+    // 前回の保存以降にベースラインを更新したか
+    dirty: bool,
+    // LruIndexでの使用順
+    order: u64,
+}
+
+#[derive(Debug, Default)]
+struct HostTable {
+    profiles: HashMap<IpAddr, HostProfile>,
+    // 上限に達した際に最も長く通信のないホストから破棄する為の索引
+    lru: LruIndex<IpAddr>,
+}
+
+impl HostTable {
+    // ホストのプロファイルを最も新しく使用したものとして返す
+    // 追跡していないホストは上限に達していれば最も長く通信のないホストを破棄してから追加する
+    fn touch(&mut self, host: IpAddr, baseline: impl FnOnce() -> HostBaseline, now: Instant, max_hosts: usize) -> Option<&mut HostProfile> {
+        if max_hosts == 0 {
+            return None;
+        }
+        let previous = match self.profiles.get(&host) {
+            Some(profile) => Some(profile.order),
+            None => {
+                while self.profiles.len() >= max_hosts {
+                    let Some(oldest) = self.lru.oldest(None) else {
+                        break;
+                    };
+                    // 保存前の更新は失われるが、データベースには前回保存したベースラインが残る
+                    if let Some(evicted) = self.profiles.remove(&oldest) {
+                        self.lru.remove(evicted.order);
+                    }
+                }
+                self.profiles.insert(
+                    host,
+                    HostProfile {
+                        baseline: baseline(),
+                        current: IntervalCounters::default(),
+                        interval_start: now,
+                        dirty: false,
+                        order: 0,
+                    },
+                );
+                None
+            },
+        };
+        let order = self.lru.touch(previous, host);
+        let profile = self.profiles.get_mut(&host)?;
+        profile.order = order;
+        Some(profile)
+    }
+}
+
+/// 送信元ホスト毎に通信量のベースラインを学習し、大きく逸脱した集計間隔を検知する
+/// 通信のなかった集計間隔は学習しない (次のパケットを受信した時点で直前の集計間隔を評価する)
+#[derive(Debug)]
+pub struct AnomalyDetector {
+    enabled: bool,
+    interval: Duration,
+    alpha: f64,
+    warmup_intervals: u64,
+    threshold: f64,
+    mix_threshold: f64,
+    max_hosts: usize,
+    hosts: Mutex<HostTable>,
+}
+
+impl AnomalyDetector {
+    pub fn new(config: &AnomalyConfig) -> Self {
+        Self {
+            enabled: config.enabled,
+            interval: Duration::from_secs(config.interval.max(1)),
+            alpha: config.alpha.clamp(f64::EPSILON, 1.0),
+            warmup_intervals: config.warmup_intervals,
+            threshold: config.threshold,
+            mix_threshold: config.mix_threshold,
+            max_hosts: config.max_hosts,
+            hosts: Mutex::new(HostTable::default()),
+        }
+    }
+
+    pub fn inspect(&self, packet: &FirewallPacket, alerts: &mut Vec<Alert>) {
+        if !self.enabled {
+            return;
+        }
+        let Ok(mut hosts) = self.hosts.lock() else {
+            return;
+        };
+        let now = Instant::now();
+        let host = packet.src_ip;
+        let Some(profile) = hosts.touch(host, || HostBaseline::new(host), now, self.max_hosts) else {
+            return;
+        };
+
+        if now.duration_since(profile.interval_start) >= self.interval {
+            self.evaluate(profile, packet, alerts);
+            profile.current = IntervalCounters::default();
+            profile.interval_start = now;
+        }
+
+        let current = &mut profile.current;
+        current.packets += 1;
+        current.bytes += packet.length as u64;
+        if current.peers.len() < MAX_PEERS_PER_INTERVAL {
+            current.peers.insert(packet.dst_ip);
+        }
+        let class = match packet.ip_protocol.value() {
+            6 => 0,
+            17 => 1,
+            1 | 58 => 2,
+            _ => 3,
+        };
+        current.protocols[class] += 1;
+    }
+
+    /// 前回の保存以降に更新されたベースラインを返す
+    /// 保存に成功するまでは更新済みのまま残る (保存後にmark_savedを呼び出す)
+    pub fn updated_baselines(&self) -> Vec<HostBaseline> {
+        let Ok(hosts) = self.hosts.lock() else {
+            return Vec::new();
+        };
+        hosts.profiles.values().filter(|profile| profile.dirty).map(|profile| profile.baseline.clone()).collect()
+    }
+
+    /// 保存したベースラインを保存済みにする (保存中に再び更新されたホストは更新済みのまま残す)
+    pub fn mark_saved(&self, baselines: &[HostBaseline]) {
+        let Ok(mut hosts) = self.hosts.lock() else {
+            return;
+        };
+        for saved in baselines {
+            if let Some(profile) = hosts.profiles.get_mut(&saved.host).filter(|profile| profile.baseline.samples == saved.samples) {
+                profile.dirty = false;
+            }
+        }
+    }
+
+    /// データベースに保存されていたベースラインから学習を再開する
+    pub fn restore_baselines(&self, baselines: Vec<HostBaseline>) {
+        let Ok(mut hosts) = self.hosts.lock() else {
+            return;
+        };
+        let now = Instant::now();
+        for baseline in baselines.into_iter().take(self.max_hosts) {
+            let host = baseline.host;
+            if let Some(profile) = hosts.touch(host, || HostBaseline::new(host), now, self.max_hosts) {
+                profile.baseline = baseline;
+            }
+        }
+    }
+
+    // 終了した集計間隔をベースラインと比較してから学習する
+    fn evaluate(&self, profile: &mut HostProfile, packet: &FirewallPacket, alerts: &mut Vec<Alert>) {
+        let current = &profile.current;
+        if current.packets == 0 {
+            return;
+        }
+        let seconds = self.interval.as_secs_f64();
+        let packets_per_sec = current.packets as f64 / seconds;
+        let bytes_per_sec = current.bytes as f64 / seconds;
+        let peers = current.peers.len() as f64;
+        let mix = current.protocols.map(|count| count as f64 / current.packets as f64);
+
+        let baseline = &mut profile.baseline;
+        if baseline.samples >= self.warmup_intervals.max(1) {
+            let mut deviations = Vec::new();
+            for (name, ewma, value) in [
+                ("パケット数/秒", &baseline.packets_per_sec, packets_per_sec),
+                ("バイト数/秒", &baseline.bytes_per_sec, bytes_per_sec),
+                ("通信先数", &baseline.peers, peers),
+            ] {
+                let score = ewma.score(value);
+                if score >= self.threshold {
+                    deviations.push(format!("{}={:.1} (平均={:.1}, 乖離={:.1}σ)", name, value, ewma.mean, score));
+                }
+            }
+            if current.packets >= MIN_MIX_PACKETS {
+                // 全変動距離 (0: 同じ構成, 1: 全く異なる構成)
+                let distance = mix.iter().zip(baseline.protocol_mix).map(|(current, average)| (current - average).abs()).sum::<f64>() / 2.0;
+                if distance >= self.mix_threshold {
+                    deviations.push(format!(
+                        "TCP/UDP/ICMP/その他の構成={} (平均={}, 変化={:.2})",
+                        format_mix(&mix),
+                        format_mix(&baseline.protocol_mix),
+                        distance
+                    ));
+                }
+            }
+            if !deviations.is_empty() {
+                let message = format!("ホストの通信量がベースラインから逸脱しています: ホスト={}, {}", baseline.host, deviations.join(", "));
+                idps_log!("{}", message);
+                alerts.push(Alert::new(packet, AlertSeverity::Medium, AlertCategory::TrafficAnomaly, message));
+            }
+        }
+
+        // 最初の集計間隔はそのまま平均とする
+        if baseline.samples == 0 {
+            baseline.packets_per_sec.mean = packets_per_sec;
+            baseline.bytes_per_sec.mean = bytes_per_sec;
+            baseline.peers.mean = peers;
+            baseline.protocol_mix = mix;
+        } else {
+            baseline.packets_per_sec.update(packets_per_sec, self.alpha);
+            baseline.bytes_per_sec.update(bytes_per_sec, self.alpha);
+            baseline.peers.update(peers, self.alpha);
+            for (average, current) in baseline.protocol_mix.iter_mut().zip(mix) {
+                *average += self.alpha * (current - *average);
+            }
+        }
+        baseline.samples += 1;
+        baseline.updated_at = Utc::now();
+        profile.dirty = true;
+    }
+}
+
+fn format_mix(mix: &[f64; PROTOCOL_CLASSES]) -> String {
+    mix.iter().map(|ratio| format!("{:.2}", ratio)).collect::<Vec<_>>().join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::analysis::transport::{TransportHeader, UdpHeader};
+    use crate::packet::types::{EtherType, IpProtocol};
+    use crate::packet::MacAddr;
+
+    fn datagram(src_ip: &str) -> FirewallPacket {
+        FirewallPacket::from_packet(
+            MacAddr([1; 6]),
+            MacAddr([2; 6]),
+            EtherType::IP_V4,
+            src_ip.parse().unwrap(),
+            "10.0.0.100".parse().unwrap(),
+            IpProtocol::UDP,
+            TransportHeader::Udp(UdpHeader {
+                src_port: 40000,
+                dst_port: 53,
+                length: 8,
+                checksum: 0,
+            }),
+            None,
+            42,
+            None,
+        )
+    }
+
+    fn detector(max_hosts: usize) -> AnomalyDetector {
+        AnomalyDetector::new(&AnomalyConfig {
+            max_hosts,
+            ..AnomalyConfig::default()
+        })
+    }
+
+    fn tracked_hosts(detector: &AnomalyDetector) -> Vec<IpAddr> {
+        let mut hosts: Vec<IpAddr> = detector.hosts.lock().unwrap().profiles.keys().copied().collect();
+        hosts.sort();
+        hosts
+    }
+
+    #[test]
+    fn evicts_the_host_idle_for_the_longest_time() {
+        let detector = detector(2);
+        let mut alerts = Vec::new();
+        for host in ["10.0.0.1", "10.0.0.2", "10.0.0.1", "10.0.0.3"] {
+            detector.inspect(&datagram(host), &mut alerts);
+        }
+        assert_eq!(tracked_hosts(&detector), vec!["10.0.0.1".parse::<IpAddr>().unwrap(), "10.0.0.3".parse().unwrap()]);
+    }
+
+    #[test]
+    fn keeps_baselines_updated_until_they_are_saved() {
+        let detector = detector(16);
+        let host: IpAddr = "10.0.0.1".parse().unwrap();
+        detector.restore_baselines(vec![HostBaseline::new(host)]);
+        let mark_updated = |samples| {
+            let mut hosts = detector.hosts.lock().unwrap();
+            let profile = hosts.profiles.get_mut(&host).unwrap();
+            profile.baseline.samples = samples;
+            profile.dirty = true;
+        };
+
+        mark_updated(1);
+        let snapshot = detector.updated_baselines();
+        assert_eq!(snapshot.len(), 1);
+        // 保存に失敗した場合は次回も返す
+        assert_eq!(detector.updated_baselines().len(), 1);
+
+        // 保存中に更新されたベースラインは保存済みにしない
+        mark_updated(2);
+        detector.mark_saved(&snapshot);
+        assert_eq!(detector.updated_baselines().len(), 1);
+
+        detector.mark_saved(&detector.updated_baselines());
+        assert!(detector.updated_baselines().is_empty());
+    }
+}
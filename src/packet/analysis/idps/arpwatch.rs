@@ -0,0 +1,340 @@
+use super::{Alert, AlertCategory, AlertSeverity};
+use crate::config::ArpWatchConfig;
+use crate::idps_log;
+use crate::packet::analysis::arp::{parse_arp_packet, ArpPacket};
+use crate::packet::analysis::firewall::FirewallPacket;
+use crate::packet::types::{EtherType, MacAddr};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::Ipv4Addr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// 更新されない対応を破棄するまでの時間と、保持する対応数の上限
+const BINDING_TIMEOUT: Duration = Duration::from_secs(4 * 60 * 60);
+const MAX_BINDINGS: usize = 65536;
+const GC_INTERVAL: Duration = Duration::from_secs(60);
+// 同じ内容のアラートを再出力するまでの間隔
+const ALERT_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+struct Binding {
+    mac: MacAddr,
+    last_seen: Instant,
+}
+
+#[derive(Debug, Default)]
+struct MacActivity {
+    gratuitous: VecDeque<Instant>,
+    last_flood_alert: Option<Instant>,
+    last_claim_alert: Option<Instant>,
+}
+
+#[derive(Debug)]
+struct ArpTable {
+    // IPアドレス→MACアドレスの対応
+    bindings: HashMap<Ipv4Addr, Binding>,
+    // MACアドレス毎に名乗っているIPアドレス (bindingsの逆引き)
+    claimed: HashMap<MacAddr, HashSet<Ipv4Addr>>,
+    activities: HashMap<MacAddr, MacActivity>,
+    last_change_alert: HashMap<Ipv4Addr, Instant>,
+    last_gc: Instant,
+}
+
+impl ArpTable {
+    fn collect_garbage(&mut self, now: Instant, window: Duration) {
+        let claimed = &mut self.claimed;
+        self.bindings.retain(|ip, binding| {
+            let alive = now.duration_since(binding.last_seen) <= BINDING_TIMEOUT;
+            if !alive {
+                Self::unclaim(claimed, &binding.mac, ip);
+            }
+            alive
+        });
+        self.activities.retain(|_, activity| {
+            activity.gratuitous.retain(|seen_at| now.duration_since(*seen_at) <= window);
+            !activity.gratuitous.is_empty() || activity.last_claim_alert.is_some_and(|last| now.duration_since(last) < ALERT_INTERVAL)
+        });
+        self.last_change_alert.retain(|_, last| now.duration_since(*last) < ALERT_INTERVAL);
+        self.last_gc = now;
+    }
+
+    fn claimed_count(&self, mac: &MacAddr) -> usize {
+        self.claimed.get(mac).map_or(0, HashSet::len)
+    }
+
+    fn unclaim(claimed: &mut HashMap<MacAddr, HashSet<Ipv4Addr>>, mac: &MacAddr, ip: &Ipv4Addr) {
+        if let Some(ips) = claimed.get_mut(mac) {
+            ips.remove(ip);
+            if ips.is_empty() {
+                claimed.remove(mac);
+            }
+        }
+    }
+}
+
+/// ARPで通知されるIP→MACの対応を監視してARPスプーフィングを検知する
+#[derive(Debug)]
+pub struct ArpWatchDetector {
+    gratuitous_window: Duration,
+    gratuitous_threshold: usize,
+    max_ips_per_mac: usize,
+    table: Mutex<ArpTable>,
+}
+
+impl ArpWatchDetector {
+    pub fn new(config: &ArpWatchConfig) -> Self {
+        Self {
+            gratuitous_window: Duration::from_secs(config.gratuitous_window.max(1)),
+            gratuitous_threshold: config.gratuitous_threshold.max(1),
+            max_ips_per_mac: config.max_ips_per_mac.max(1),
+            table: Mutex::new(ArpTable {
+                bindings: HashMap::new(),
+                claimed: HashMap::new(),
+                activities: HashMap::new(),
+                last_change_alert: HashMap::new(),
+                last_gc: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn inspect(&self, packet: &FirewallPacket, ethernet_frame: &[u8], alerts: &mut Vec<Alert>) {
+        if packet.ether_type != EtherType::ARP {
+            return;
+        }
+        let Some(arp) = ethernet_frame.get(14..).and_then(parse_arp_packet) else {
+            return;
+        };
+        let Ok(mut table) = self.table.lock() else {
+            return;
+        };
+
+        let now = Instant::now();
+        if now.duration_since(table.last_gc) >= GC_INTERVAL {
+            table.collect_garbage(now, self.gratuitous_window);
+        }
+
+        // Ethernetヘッダの送信元とARPの送信元MACが異なるのは偽装の典型的な兆候
+        if packet.src_mac != arp.sender_mac {
+            let message = format!(
+                "ARPの送信元MACがEthernetヘッダと一致しません: ethernet={}, arp={}, 送信元IP={}",
+                packet.src_mac, arp.sender_mac, arp.sender_ip
+            );
+            idps_log!("{}", message);
+            alerts.push(Alert::new(packet, AlertSeverity::Medium, AlertCategory::ArpSpoofing, message));
+        }
+
+        // DADのプローブはアドレスを名乗らない
+        if arp.is_probe() {
+            return;
+        }
+
+        if arp.is_gratuitous() {
+            self.check_gratuitous(&mut table, packet, &arp, now, alerts);
+        }
+        self.update_binding(&mut table, packet, &arp, now, alerts);
+    }
+
+    fn check_gratuitous(&self, table: &mut ArpTable, packet: &FirewallPacket, arp: &ArpPacket, now: Instant, alerts: &mut Vec<Alert>) {
+        let activity = table.activities.entry(arp.sender_mac.clone()).or_default();
+        while activity.gratuitous.front().is_some_and(|seen_at| now.duration_since(*seen_at) > self.gratuitous_window) {
+            activity.gratuitous.pop_front();
+        }
+        activity.gratuitous.push_back(now);
+
+        if activity.gratuitous.len() >= self.gratuitous_threshold && activity.last_flood_alert.is_none_or(|last| now.duration_since(last) >= ALERT_INTERVAL) {
+            activity.last_flood_alert = Some(now);
+            let message = format!(
+                "Gratuitous ARPの大量送信を検知しました: MAC={}, IP={}, 件数={} ({}秒間, 閾値={})",
+                arp.sender_mac,
+                arp.sender_ip,
+                activity.gratuitous.len(),
+                self.gratuitous_window.as_secs(),
+                self.gratuitous_threshold
+            );
+            idps_log!("{}", message);
+            alerts.push(Alert::new(packet, AlertSeverity::Medium, AlertCategory::ArpSpoofing, message));
+        }
+    }
+
+    fn update_binding(&self, table: &mut ArpTable, packet: &FirewallPacket, arp: &ArpPacket, now: Instant, alerts: &mut Vec<Alert>) {
+        match table.bindings.get_mut(&arp.sender_ip) {
+            Some(binding) if binding.mac == arp.sender_mac => binding.last_seen = now,
+            Some(binding) => {
+                let previous = std::mem::replace(&mut binding.mac, arp.sender_mac.clone());
+                binding.last_seen = now;
+                ArpTable::unclaim(&mut table.claimed, &previous, &arp.sender_ip);
+                table.claimed.entry(arp.sender_mac.clone()).or_default().insert(arp.sender_ip);
+
+                if table.last_change_alert.get(&arp.sender_ip).is_none_or(|last| now.duration_since(*last) >= ALERT_INTERVAL) {
+                    table.last_change_alert.insert(arp.sender_ip, now);
+                    let message = format!(
+                        "IPアドレスとMACアドレスの対応が変化しました (ARPスプーフィングの可能性): IP={}, 変更前={}, 変更後={}, operation={}, gratuitous={}",
+                        arp.sender_ip,
+                        previous,
+                        arp.sender_mac,
+                        arp.operation,
+                        arp.is_gratuitous()
+                    );
+                    idps_log!("{}", message);
+                    alerts.push(Alert::new(packet, AlertSeverity::High, AlertCategory::ArpSpoofing, message));
+                }
+            },
+            None => {
+                if table.bindings.len() >= MAX_BINDINGS {
+                    return;
+                }
+                table.bindings.insert(
+                    arp.sender_ip,
+                    Binding {
+                        mac: arp.sender_mac.clone(),
+                        last_seen: now,
+                    },
+                );
+                table.claimed.entry(arp.sender_mac.clone()).or_default().insert(arp.sender_ip);
+            },
+        }
+
+        // 1つのMACアドレスが多数のIPアドレスを名乗っていないか
+        if table.claimed_count(&arp.sender_mac) < self.max_ips_per_mac {
+            return;
+        }
+        let activity = table.activities.entry(arp.sender_mac.clone()).or_default();
+        if activity.last_claim_alert.is_some_and(|last| now.duration_since(last) < ALERT_INTERVAL) {
+            return;
+        }
+        activity.last_claim_alert = Some(now);
+
+        let mut ips: Vec<Ipv4Addr> = table.claimed.get(&arp.sender_mac).into_iter().flatten().copied().collect();
+        ips.sort_unstable();
+        let message = format!(
+            "1つのMACアドレスが多数のIPアドレスを名乗っています: MAC={}, IP数={} (閾値={}), IP=[{}]",
+            arp.sender_mac,
+            ips.len(),
+            self.max_ips_per_mac,
+            ips.iter().take(10).map(|ip| ip.to_string()).collect::<Vec<_>>().join(", ")
+        );
+        idps_log!("{}", message);
+        alerts.push(Alert::new(packet, AlertSeverity::High, AlertCategory::ArpSpoofing, message));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::analysis::transport::TransportHeader;
+    use crate::packet::types::IpProtocol;
+    use std::net::IpAddr;
+
+    const ATTACKER: MacAddr = MacAddr([0x02, 0, 0, 0, 0, 0x66]);
+    const VICTIM: MacAddr = MacAddr([0x02, 0, 0, 0, 0, 0x01]);
+
+    fn detector() -> ArpWatchDetector {
+        ArpWatchDetector::new(&ArpWatchConfig {
+            gratuitous_window: 10,
+            gratuitous_threshold: 3,
+            max_ips_per_mac: 4,
+        })
+    }
+
+    // ARPの応答を受信した際のパケットとEthernetフレーム
+    fn reply(mac: &MacAddr, sender_ip: Ipv4Addr, target_ip: Ipv4Addr) -> (FirewallPacket, Vec<u8>) {
+        let mut frame = vec![0xff; 6];
+        frame.extend_from_slice(&mac.0);
+        frame.extend_from_slice(&[0x08, 0x06, 0, 1, 0x08, 0x00, 6, 4, 0, 2]);
+        frame.extend_from_slice(&mac.0);
+        frame.extend_from_slice(&sender_ip.octets());
+        frame.extend_from_slice(&[0; 6]);
+        frame.extend_from_slice(&target_ip.octets());
+
+        let packet = FirewallPacket::from_packet(
+            mac.clone(),
+            MacAddr([0xff; 6]),
+            EtherType::ARP,
+            IpAddr::V4(sender_ip),
+            IpAddr::V4(target_ip),
+            IpProtocol::UNKNOWN,
+            TransportHeader::Unknown,
+            None,
+            frame.len(),
+            None,
+        );
+        (packet, frame)
+    }
+
+    fn inspect(detector: &ArpWatchDetector, mac: &MacAddr, sender_ip: Ipv4Addr, target_ip: Ipv4Addr) -> Vec<Alert> {
+        let (packet, frame) = reply(mac, sender_ip, target_ip);
+        let mut alerts = Vec::new();
+        detector.inspect(&packet, &frame, &mut alerts);
+        alerts
+    }
+
+    #[test]
+    fn alerts_when_binding_changes() {
+        let detector = detector();
+        let gateway = Ipv4Addr::new(192, 168, 0, 1);
+        let host = Ipv4Addr::new(192, 168, 0, 10);
+
+        assert!(inspect(&detector, &VICTIM, gateway, host).is_empty());
+        assert!(inspect(&detector, &VICTIM, gateway, host).is_empty());
+
+        let alerts = inspect(&detector, &ATTACKER, gateway, host);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].severity, AlertSeverity::High);
+        assert!(alerts[0].message.contains("対応が変化しました"));
+
+        // 同じIPアドレスの変化は一定間隔につき1回まで
+        assert!(inspect(&detector, &VICTIM, gateway, host).is_empty());
+
+        let table = detector.table.lock().unwrap();
+        assert_eq!(table.claimed_count(&VICTIM), 1);
+        assert_eq!(table.claimed_count(&ATTACKER), 0);
+    }
+
+    #[test]
+    fn alerts_on_gratuitous_arp_flood_once_per_interval() {
+        let detector = detector();
+        let ip = Ipv4Addr::new(192, 168, 0, 20);
+
+        let alerts: Vec<Vec<Alert>> = (0..6).map(|_| inspect(&detector, &ATTACKER, ip, ip)).collect();
+        assert!(alerts[..2].iter().all(Vec::is_empty));
+        assert_eq!(alerts[2].len(), 1);
+        assert!(alerts[2][0].message.contains("Gratuitous ARP"));
+        assert!(alerts[3..].iter().all(Vec::is_empty));
+    }
+
+    #[test]
+    fn alerts_when_mac_claims_many_ips_once_per_interval() {
+        let detector = detector();
+        let target = Ipv4Addr::new(192, 168, 0, 10);
+
+        let alerts: Vec<Vec<Alert>> = (1..=6).map(|host| inspect(&detector, &ATTACKER, Ipv4Addr::new(192, 168, 0, host), target)).collect();
+        assert!(alerts[..3].iter().all(Vec::is_empty));
+        assert_eq!(alerts[3].len(), 1);
+        assert!(alerts[3][0].message.contains("IP数=4"));
+        assert!(alerts[4..].iter().all(Vec::is_empty));
+        assert_eq!(detector.table.lock().unwrap().claimed_count(&ATTACKER), 6);
+    }
+
+    #[test]
+    fn releases_claimed_ips_of_expired_bindings() {
+        let detector = detector();
+        for host in 1..=3 {
+            inspect(&detector, &ATTACKER, Ipv4Addr::new(192, 168, 0, host), Ipv4Addr::new(192, 168, 0, 10));
+        }
+
+        let mut table = detector.table.lock().unwrap();
+        // 1つ目の対応以外を更新した上で、1つ目の対応のみ期限を過ぎた時刻に掃除する
+        let first = Ipv4Addr::new(192, 168, 0, 1);
+        let expires_at = table.bindings[&first].last_seen + BINDING_TIMEOUT + Duration::from_secs(1);
+        for (ip, binding) in table.bindings.iter_mut() {
+            if *ip != first {
+                binding.last_seen = expires_at;
+            }
+        }
+        table.collect_garbage(expires_at, Duration::from_secs(10));
+        assert_eq!(table.claimed_count(&ATTACKER), 2);
+
+        table.collect_garbage(expires_at + BINDING_TIMEOUT + Duration::from_secs(1), Duration::from_secs(10));
+        assert!(table.claimed.is_empty());
+    }
+}
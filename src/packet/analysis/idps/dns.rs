@@ -0,0 +1,568 @@
+use super::{Alert, AlertCategory, AlertSeverity, EveEvent, InspectContext};
+use crate::config::DnsConfig;
+use crate::idps_log;
+use crate::logger::eve_logger;
+use crate::packet::analysis::dns::{parse_dns_message, parse_dns_over_tcp, DnsMessage, DnsRecordType, DNS_PORT};
+use crate::packet::analysis::firewall::FirewallPacket;
+use crate::packet::analysis::stream::StreamChunk;
+use crate::packet::analysis::TransportHeader;
+use chrono::{DateTime, Utc};
+use log::warn;
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// 書き込みを待つDNSログの上限
+const MAX_PENDING_LOG_ENTRIES: usize = 65536;
+// 追跡する送信元数の上限
+const MAX_TRACKED_CLIENTS: usize = 65536;
+const GC_INTERVAL: Duration = Duration::from_secs(10);
+// 同じ送信元・種別のアラートを再出力するまでの間隔
+const ALERT_INTERVAL: Duration = Duration::from_secs(60);
+// DGAの判定対象とするラベルの最小長
+const DGA_MIN_LABEL_LENGTH: usize = 10;
+const NXDOMAIN: u8 = 3;
+// DNS over TCPの解析位置を保持するストリーム数の上限と、通信がない場合に破棄するまでの時間
+const MAX_TRACKED_TCP_STREAMS: usize = 65536;
+const TCP_STREAM_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// dns_logテーブルへ書き込む1件の問い合わせ
+#[derive(Debug, Clone)]
+pub struct DnsLogEntry {
+    pub timestamp: DateTime<Utc>,
+    pub src_ip: IpAddr,
+    pub dst_ip: IpAddr,
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub transaction_id: u16,
+    pub query: String,
+    pub query_type: String,
+    pub response: bool,
+    // 応答の場合のみ
+    pub rcode: Option<u8>,
+    pub answers: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+struct ClientActivity {
+    // TXT/NULLの問い合わせ時刻
+    txt_queries: VecDeque<Instant>,
+    // NXDOMAINの応答を受信した時刻
+    nxdomains: VecDeque<Instant>,
+    last_alerts: HashMap<&'static str, Instant>,
+}
+
+impl ClientActivity {
+    fn expire(&mut self, now: Instant, window: Duration) {
+        for events in [&mut self.txt_queries, &mut self.nxdomains] {
+            while events.front().is_some_and(|seen_at| now.duration_since(*seen_at) > window) {
+                events.pop_front();
+            }
+        }
+    }
+
+    // 同じ種別のアラートは一定間隔につき1回まで
+    fn should_alert(&mut self, kind: &'static str, now: Instant) -> bool {
+        if self.last_alerts.get(kind).is_some_and(|last| now.duration_since(*last) < ALERT_INTERVAL) {
+            return false;
+        }
+        self.last_alerts.insert(kind, now);
+        true
+    }
+}
+
+// DNS over TCPのストリームの方向毎の解析位置
+#[derive(Debug)]
+struct TcpDnsStream {
+    // 次のメッセージの長さの前置きのストリーム内の位置
+    next: u64,
+    // 長さを読んだが、データが揃っていないメッセージの長さ
+    pending_length: Option<usize>,
+    last_seen: Instant,
+}
+
+impl TcpDnsStream {
+    fn new(stream: &StreamChunk, now: Instant) -> Self {
+        Self {
+            // ストリームの途中から追跡する場合は、新たなデータがメッセージの先頭から始まるとみなす
+            next: if stream.start == 0 { 0 } else { stream.start + stream.new_offset as u64 },
+            pending_length: None,
+            last_seen: now,
+        }
+    }
+
+    // 窓の中の未解析のメッセージを解析し、解析位置を進める
+    fn parse(&mut self, stream: &StreamChunk) -> Vec<DnsMessage> {
+        // 窓より大きいメッセージは長さの前置きが窓から外れる為、読み飛ばす
+        if self.next < stream.start {
+            self.next = match self.pending_length.take() {
+                Some(length) => self.next + 2 + length as u64,
+                None => stream.start + stream.new_offset as u64,
+            };
+        }
+        let Some(offset) = self.next.checked_sub(stream.start).and_then(|offset| usize::try_from(offset).ok()).filter(|offset| *offset <= stream.data.len()) else {
+            return Vec::new();
+        };
+
+        let data = &stream.data[offset..];
+        let (messages, consumed) = parse_dns_over_tcp(data);
+        self.next += consumed as u64;
+        self.pending_length = data.get(consumed..consumed + 2).map(|prefix| u16::from_be_bytes([prefix[0], prefix[1]]) as usize);
+        messages
+    }
+}
+
+#[derive(Debug)]
+struct DnsState {
+    clients: HashMap<IpAddr, ClientActivity>,
+    // (送信元, 送信元ポート, 宛先, 宛先ポート) 毎の解析位置
+    tcp_streams: HashMap<(IpAddr, u16, IpAddr, u16), TcpDnsStream>,
+    log: Vec<DnsLogEntry>,
+    last_gc: Instant,
+}
+
+/// DNSの問い合わせを解析し、DNSトンネリングとDGAによるドメインを検知する
+#[derive(Debug)]
+pub struct DnsDetector {
+    log_queries: bool,
+    window: Duration,
+    tunnel_label_length: usize,
+    tunnel_entropy: f64,
+    txt_threshold: usize,
+    dga_entropy: f64,
+    nxdomain_threshold: usize,
+    state: Mutex<DnsState>,
+}
+
+impl DnsDetector {
+    pub fn new(config: &DnsConfig) -> Self {
+        Self {
+            log_queries: config.log_queries,
+            window: Duration::from_secs(config.window.max(1)),
+            tunnel_label_length: config.tunnel_label_length.max(1),
+            tunnel_entropy: config.tunnel_entropy,
+            txt_threshold: config.txt_threshold.max(1),
+            dga_entropy: config.dga_entropy,
+            nxdomain_threshold: config.nxdomain_threshold.max(1),
+            state: Mutex::new(DnsState {
+                clients: HashMap::new(),
+                tcp_streams: HashMap::new(),
+                log: Vec::new(),
+                last_gc: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn inspect(&self, context: &InspectContext, alerts: &mut Vec<Alert>) {
+        let packet = context.packet;
+        let transport = &packet.transport;
+        if transport.src_port() != DNS_PORT && transport.dst_port() != DNS_PORT {
+            return;
+        }
+
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        let now = Instant::now();
+        if now.duration_since(state.last_gc) >= GC_INTERVAL {
+            let window = self.window;
+            state.clients.retain(|_, activity| {
+                activity.expire(now, window);
+                !activity.txt_queries.is_empty() || !activity.nxdomains.is_empty() || activity.last_alerts.values().any(|last| now.duration_since(*last) < ALERT_INTERVAL)
+            });
+            state.tcp_streams.retain(|_, stream| now.duration_since(stream.last_seen) <= TCP_STREAM_TIMEOUT);
+            state.last_gc = now;
+        }
+
+        let messages = match transport {
+            TransportHeader::Udp(_) => parse_dns_message(context.payload).into_iter().collect(),
+            // 再構築したストリームを前回の解析位置から解析する (セグメントを跨ぐメッセージと、1つのセグメントに含まれる複数のメッセージに対応する)
+            TransportHeader::Tcp(_) => match context.stream {
+                Some(stream) => Self::parse_tcp(&mut state, packet, stream, now),
+                None => Vec::new(),
+            },
+            _ => Vec::new(),
+        };
+        if messages.is_empty() {
+            return;
+        }
+
+        for message in &messages {
+            if eve_logger::is_enabled() {
+                for event in EveEvent::dns(packet, message) {
+                    eve_logger::write_event(&event);
+                }
+            }
+            if self.log_queries {
+                Self::record(&mut state, context, message);
+            }
+
+            // 問い合わせの送信元 (応答の場合は宛先)
+            let client = if message.is_response { packet.dst_ip } else { packet.src_ip };
+            if !state.clients.contains_key(&client) && state.clients.len() >= MAX_TRACKED_CLIENTS {
+                continue;
+            }
+            let activity = state.clients.entry(client).or_default();
+            activity.expire(now, self.window);
+
+            if message.is_response {
+                self.check_nxdomain(activity, packet, client, message, now, alerts);
+            } else {
+                self.check_query(activity, packet, client, message, now, alerts);
+            }
+        }
+    }
+
+    fn parse_tcp(state: &mut DnsState, packet: &FirewallPacket, stream: &StreamChunk, now: Instant) -> Vec<DnsMessage> {
+        let key = (packet.src_ip, packet.transport.src_port(), packet.dst_ip, packet.transport.dst_port());
+        if !state.tcp_streams.contains_key(&key) && state.tcp_streams.len() >= MAX_TRACKED_TCP_STREAMS {
+            return TcpDnsStream::new(stream, now).parse(stream);
+        }
+        let tcp_stream = state.tcp_streams.entry(key).or_insert_with(|| TcpDnsStream::new(stream, now));
+        tcp_stream.last_seen = now;
+        tcp_stream.parse(stream)
+    }
+
+    /// 書き込みを待っているDNSログを取り出す
+    pub fn drain_log(&self) -> Vec<DnsLogEntry> {
+        match self.state.lock() {
+            Ok(mut state) => std::mem::take(&mut state.log),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// 書き込みに失敗したDNSログを書き込み待ちの先頭に戻す (上限を超える分は破棄する)
+    pub fn requeue_log(&self, entries: Vec<DnsLogEntry>) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        let available = MAX_PENDING_LOG_ENTRIES.saturating_sub(state.log.len());
+        if entries.len() > available {
+            warn!("DNSログのバッファが上限に達した為、{}件の問い合わせを破棄します", entries.len() - available);
+        }
+        state.log.splice(0..0, entries.into_iter().take(available));
+    }
+
+    fn record(state: &mut DnsState, context: &InspectContext, message: &DnsMessage) {
+        let packet = context.packet;
+        for question in &message.questions {
+            if state.log.len() >= MAX_PENDING_LOG_ENTRIES {
+                warn!("DNSログのバッファが上限に達した為、問い合わせを破棄します: {}", question.name);
+                return;
+            }
+            state.log.push(DnsLogEntry {
+                timestamp: Utc::now(),
+                src_ip: packet.src_ip,
+                dst_ip: packet.dst_ip,
+                src_port: packet.transport.src_port(),
+                dst_port: packet.transport.dst_port(),
+                transaction_id: message.id,
+                query: question.name.clone(),
+                query_type: question.record_type.to_string(),
+                response: message.is_response,
+                rcode: message.is_response.then_some(message.rcode),
+                answers: message.answers.iter().map(|answer| answer.to_string()).collect(),
+            });
+        }
+    }
+
+    fn check_query(&self, activity: &mut ClientActivity, packet: &FirewallPacket, client: IpAddr, message: &DnsMessage, now: Instant, alerts: &mut Vec<Alert>) {
+        for question in &message.questions {
+            // 長くランダムなラベルはデータを埋め込んだトンネリングの特徴
+            if let Some(label) = question.name.split('.').filter(|label| label.len() >= self.tunnel_label_length).max_by_key(|label| label.len()) {
+                let entropy = shannon_entropy(label.as_bytes());
+                if entropy >= self.tunnel_entropy && activity.should_alert("tunnel_label", now) {
+                    let alert_message = format!(
+                        "DNSトンネリングの可能性があるクエリを検知しました: 送信元={}, クエリ={}, 種別={}, ラベル長={}, エントロピー={:.2}",
+                        client,
+                        question.name,
+                        question.record_type,
+                        label.len(),
+                        entropy
+                    );
+                    idps_log!("{}", alert_message);
+                    alerts.push(Alert::new(packet, AlertSeverity::Medium, AlertCategory::DnsTunneling, alert_message));
+                }
+            }
+
+            // TXT/NULLレコードはトンネリングでデータの受信に使われる
+            if matches!(question.record_type, DnsRecordType::TXT | DnsRecordType::NULL) {
+                activity.txt_queries.push_back(now);
+                if activity.txt_queries.len() >= self.txt_threshold && activity.should_alert("txt_volume", now) {
+                    let alert_message = format!(
+                        "TXT/NULLレコードの問い合わせが多発しています (DNSトンネリングの可能性): 送信元={}, 件数={} ({}秒間, 閾値={}), 直近のクエリ={}",
+                        client,
+                        activity.txt_queries.len(),
+                        self.window.as_secs(),
+                        self.txt_threshold,
+                        question.name
+                    );
+                    idps_log!("{}", alert_message);
+                    alerts.push(Alert::new(packet, AlertSeverity::Medium, AlertCategory::DnsTunneling, alert_message));
+                }
+            }
+
+            if let Some(label) = registered_label(&question.name) {
+                if self.is_generated(label) && activity.should_alert("dga", now) {
+                    let alert_message = format!(
+                        "アルゴリズムで生成された可能性のあるドメインを検知しました (DGA): 送信元={}, クエリ={}, エントロピー={:.2}",
+                        client,
+                        question.name,
+                        shannon_entropy(label.as_bytes())
+                    );
+                    idps_log!("{}", alert_message);
+                    alerts.push(Alert::new(packet, AlertSeverity::Low, AlertCategory::Dga, alert_message));
+                }
+            }
+        }
+    }
+
+    // DGAを使うマルウェアは存在しないドメインを大量に問い合わせる
+    fn check_nxdomain(&self, activity: &mut ClientActivity, packet: &FirewallPacket, client: IpAddr, message: &DnsMessage, now: Instant, alerts: &mut Vec<Alert>) {
+        if message.rcode != NXDOMAIN {
+            return;
+        }
+        activity.nxdomains.push_back(now);
+        if activity.nxdomains.len() >= self.nxdomain_threshold && activity.should_alert("nxdomain", now) {
+            let alert_message = format!(
+                "NXDOMAINの応答が多発しています (DGAの可能性): 宛先={}, 件数={} ({}秒間, 閾値={}), 直近のクエリ={}",
+                client,
+                activity.nxdomains.len(),
+                self.window.as_secs(),
+                self.nxdomain_threshold,
+                message.questions.first().map_or("-", |question| question.name.as_str())
+            );
+            idps_log!("{}", alert_message);
+            // 応答の送信元はリゾルバである為、問い合わせたクライアントを送信元とする
+            alerts.push(Alert::new(packet, AlertSeverity::Medium, AlertCategory::Dga, alert_message).reversed());
+        }
+    }
+
+    // 長く、エントロピーが高く、発音しにくいラベルを生成されたものとみなす
+    fn is_generated(&self, label: &str) -> bool {
+        if label.len() < DGA_MIN_LABEL_LENGTH || shannon_entropy(label.as_bytes()) < self.dga_entropy {
+            return false;
+        }
+
+        let lower = label.to_ascii_lowercase();
+        let letters = lower.chars().filter(char::is_ascii_alphabetic).count();
+        let vowels = lower.chars().filter(|c| matches!(c, 'a' | 'e' | 'i' | 'o' | 'u')).count();
+        let digits = lower.chars().filter(char::is_ascii_digit).count();
+        let mut consonant_run = 0;
+        let mut longest_consonant_run = 0;
+        for c in lower.chars() {
+            if c.is_ascii_alphabetic() && !matches!(c, 'a' | 'e' | 'i' | 'o' | 'u' | 'y') {
+                consonant_run += 1;
+                longest_consonant_run = longest_consonant_run.max(consonant_run);
+            } else {
+                consonant_run = 0;
+            }
+        }
+
+        let vowel_ratio = if letters == 0 { 0.0 } else { vowels as f64 / letters as f64 };
+        let digit_ratio = digits as f64 / label.len() as f64;
+        vowel_ratio < 0.25 || digit_ratio >= 0.3 || longest_consonant_run >= 5
+    }
+}
+
+// 登録されたドメイン名に相当するラベル (TLDの直前のラベル)
+fn registered_label(name: &str) -> Option<&str> {
+    let labels: Vec<&str> = name.trim_end_matches('.').split('.').collect();
+    if labels.len() < 2 {
+        return None;
+    }
+    let candidate = labels[labels.len() - 2];
+    // co.jp等の2階層のサフィックスはもう1つ前のラベルを使う
+    if candidate.len() <= 3 && labels.len() >= 3 {
+        return Some(labels[labels.len() - 3]);
+    }
+    Some(candidate)
+}
+
+// バイト単位のシャノンエントロピー (ビット)
+fn shannon_entropy(data: &[u8]) -> f64 {
+    if data.is_empty() {
+        return 0.0;
+    }
+    let mut counts = [0usize; 256];
+    for byte in data {
+        counts[*byte as usize] += 1;
+    }
+    let length = data.len() as f64;
+    counts
+        .iter()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            let p = *count as f64 / length;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::analysis::firewall::ConnState;
+    use crate::packet::analysis::transport::UdpHeader;
+    use crate::packet::types::{EtherType, IpProtocol};
+    use crate::packet::MacAddr;
+
+    fn query(id: u16, name: &str) -> Vec<u8> {
+        let mut message = Vec::new();
+        message.extend_from_slice(&id.to_be_bytes());
+        message.extend_from_slice(&[0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        for label in name.split('.') {
+            message.push(label.len() as u8);
+            message.extend_from_slice(label.as_bytes());
+        }
+        message.extend_from_slice(&[0x00, 0x00, 0x01, 0x00, 0x01]);
+        let mut framed = (message.len() as u16).to_be_bytes().to_vec();
+        framed.extend_from_slice(&message);
+        framed
+    }
+
+    // 直前のチャンクの末尾にdataを加えたチャンク
+    fn next_chunk(previous: Option<&StreamChunk>, data: &[u8]) -> StreamChunk {
+        let (mut window, start) = previous.map_or((Vec::new(), 0), |chunk| (chunk.data.clone(), chunk.start));
+        let new_offset = window.len();
+        window.extend_from_slice(data);
+        StreamChunk {
+            data: window,
+            new_offset,
+            start,
+            from_client: true,
+            hello_parsed: false,
+            sni: None,
+        }
+    }
+
+    fn ids(messages: &[DnsMessage]) -> Vec<u16> {
+        messages.iter().map(|message| message.id).collect()
+    }
+
+    #[test]
+    fn parses_message_split_across_segments() {
+        let framed = query(1, "example.com");
+        let now = Instant::now();
+        let first = next_chunk(None, &framed[..5]);
+        let mut tcp_stream = TcpDnsStream::new(&first, now);
+        assert!(tcp_stream.parse(&first).is_empty());
+
+        let second = next_chunk(Some(&first), &framed[5..]);
+        assert_eq!(ids(&tcp_stream.parse(&second)), vec![1]);
+        assert_eq!(tcp_stream.next, framed.len() as u64);
+    }
+
+    #[test]
+    fn parses_every_message_in_segment() {
+        let mut data = query(1, "a.example.com");
+        data.extend(query(2, "b.example.com"));
+        let third = query(3, "c.example.com");
+        data.extend_from_slice(&third[..4]);
+
+        let now = Instant::now();
+        let first = next_chunk(None, &data);
+        let mut tcp_stream = TcpDnsStream::new(&first, now);
+        assert_eq!(ids(&tcp_stream.parse(&first)), vec![1, 2]);
+
+        let mut rest = third[4..].to_vec();
+        rest.extend(query(4, "d.example.com"));
+        let second = next_chunk(Some(&first), &rest);
+        assert_eq!(ids(&tcp_stream.parse(&second)), vec![3, 4]);
+    }
+
+    #[test]
+    fn skips_message_larger_than_window() {
+        let now = Instant::now();
+        // 長さの前置きのみ受信し、本体は窓から外れた
+        let first = next_chunk(None, &[0x40, 0x00]);
+        let mut tcp_stream = TcpDnsStream::new(&first, now);
+        assert!(tcp_stream.parse(&first).is_empty());
+        assert_eq!(tcp_stream.pending_length, Some(0x4000));
+
+        let framed = query(5, "example.com");
+        let second = StreamChunk {
+            data: framed.clone(),
+            new_offset: 0,
+            start: 2 + 0x4000,
+            from_client: true,
+            hello_parsed: false,
+            sni: None,
+        };
+        assert_eq!(ids(&tcp_stream.parse(&second)), vec![5]);
+    }
+
+    #[test]
+    fn attributes_nxdomain_alert_to_client() {
+        let detector = DnsDetector::new(&DnsConfig {
+            nxdomain_threshold: 2,
+            ..DnsConfig::default()
+        });
+        let response = FirewallPacket::from_packet(
+            MacAddr([1; 6]),
+            MacAddr([2; 6]),
+            EtherType::IP_V4,
+            "10.0.0.53".parse().unwrap(),
+            "10.0.0.1".parse().unwrap(),
+            IpProtocol::UDP,
+            TransportHeader::Udp(UdpHeader {
+                src_port: DNS_PORT,
+                dst_port: 40000,
+                length: 8,
+                checksum: 0,
+            }),
+            None,
+            100,
+            None,
+        );
+        let mut alerts = Vec::new();
+        for id in 0..2 {
+            let mut payload = query(id, "xkqjzvwpt.example")[2..].to_vec();
+            // QR=1, RCODE=3 (NXDOMAIN)
+            payload[2] |= 0x80;
+            payload[3] |= NXDOMAIN;
+            let context = InspectContext {
+                packet: &response,
+                ethernet_frame: &[],
+                payload: &payload,
+                stream: None,
+                tls: None,
+                http: &[],
+                conn_state: ConnState::Established,
+                from_client: Some(false),
+            };
+            detector.inspect(&context, &mut alerts);
+        }
+
+        let alert = alerts.iter().find(|alert| alert.category == AlertCategory::Dga).unwrap();
+        assert_eq!(alert.src_ip, "10.0.0.1".parse::<IpAddr>().unwrap());
+        assert_eq!((alert.src_port, alert.dst_port), (40000, DNS_PORT));
+    }
+
+    #[test]
+    fn requeues_log_before_new_entries_up_to_limit() {
+        let detector = DnsDetector::new(&DnsConfig::default());
+        let entry = |transaction_id: u16| DnsLogEntry {
+            timestamp: Utc::now(),
+            src_ip: "10.0.0.1".parse().unwrap(),
+            dst_ip: "10.0.0.53".parse().unwrap(),
+            src_port: 40000,
+            dst_port: DNS_PORT,
+            transaction_id,
+            query: "example.com".to_string(),
+            query_type: "A".to_string(),
+            response: false,
+            rcode: None,
+            answers: Vec::new(),
+        };
+
+        detector.requeue_log(vec![entry(2)]);
+        detector.requeue_log(vec![entry(1)]);
+        let ids: Vec<u16> = detector.drain_log().iter().map(|entry| entry.transaction_id).collect();
+        assert_eq!(ids, [1, 2]);
+
+        detector.requeue_log(vec![entry(0); MAX_PENDING_LOG_ENTRIES + 1]);
+        assert_eq!(detector.drain_log().len(), MAX_PENDING_LOG_ENTRIES);
+    }
+}
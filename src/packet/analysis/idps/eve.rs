@@ -0,0 +1,430 @@
+use super::{Alert, AlertSeverity};
+use crate::packet::analysis::dns::DnsMessage;
+use crate::packet::analysis::firewall::FirewallPacket;
+use crate::packet::analysis::flow::{FlowEndReason, FlowRecord};
+use crate::packet::analysis::http::HttpMessage;
+use crate::packet::analysis::tls::{TlsHello, TlsHelloKind};
+use chrono::{DateTime, Local, Utc};
+use serde_json::{json, Map, Value};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::net::IpAddr;
+
+// JSONの数値として精度を失わない範囲に収める
+const FLOW_ID_MASK: u64 = (1 << 53) - 1;
+
+/// Suricata互換のEVE形式のイベントを組み立てる
+/// 各イベントは共通のフィールドに加えて、event_typeと同名のオブジェクトを持つ
+pub struct EveEvent;
+
+impl EveEvent {
+    pub fn alert(alert: &Alert, blocked: bool) -> Value {
+        let mut event = Self::header("alert", alert.timestamp, alert.src_ip, alert.src_port, alert.dst_ip, alert.dst_port, alert.ip_protocol);
+        let mut body = json!({
+            "action": if blocked { "blocked" } else { "allowed" },
+            "gid": 1,
+            "signature": alert.message,
+            "category": alert.category.to_string(),
+            // Suricataと同じく1が最も高い
+            "severity": match alert.severity {
+                AlertSeverity::High => 1,
+                AlertSeverity::Medium => 2,
+                AlertSeverity::Low => 3,
+            },
+        });
+        if let Some(signature_id) = alert.signature_id {
+            body["signature_id"] = json!(signature_id);
+        }
+        event.insert("alert".to_string(), body);
+        Value::Object(event)
+    }
+
+    /// 問い合わせは質問毎に1件、応答はまとめて1件のイベントにする
+    pub fn dns(packet: &FirewallPacket, message: &DnsMessage) -> Vec<Value> {
+        if message.is_response {
+            let question = message.questions.first();
+            let answers: Vec<Value> = message
+                .answers
+                .iter()
+                .map(|answer| {
+                    json!({
+                        "rrname": answer.name,
+                        "rrtype": answer.record_type.to_string(),
+                        "ttl": answer.ttl,
+                        "rdata": answer.data,
+                    })
+                })
+                .collect();
+            let mut body = json!({
+                "version": 2,
+                "type": "answer",
+                "id": message.id,
+                "qr": true,
+                "rcode": Self::rcode_name(message.rcode),
+            });
+            if let Some(question) = question {
+                body["rrname"] = json!(question.name);
+                body["rrtype"] = json!(question.record_type.to_string());
+            }
+            if !answers.is_empty() {
+                body["answers"] = Value::Array(answers);
+            }
+            return vec![Self::packet_event(packet, "dns", body)];
+        }
+
+        message
+            .questions
+            .iter()
+            .enumerate()
+            .map(|(tx_id, question)| {
+                let body = json!({
+                    "version": 2,
+                    "type": "query",
+                    "id": message.id,
+                    "rrname": question.name,
+                    "rrtype": question.record_type.to_string(),
+                    "tx_id": tx_id,
+                });
+                Self::packet_event(packet, "dns", body)
+            })
+            .collect()
+    }
+
+    pub fn tls(packet: &FirewallPacket, hello: &TlsHello, ja3: &str, ja4: Option<&str>) -> Value {
+        let fingerprint = json!({
+            "hash": ja3,
+            "string": hello.ja3_string(),
+        });
+        let mut body = json!({
+            "version": Self::tls_version_name(hello.version()),
+        });
+        match hello.kind {
+            TlsHelloKind::Client => {
+                body["ja3"] = fingerprint;
+                if let Some(sni) = &hello.sni {
+                    body["sni"] = json!(sni);
+                }
+                if !hello.alpn.is_empty() {
+                    body["client_alpns"] = json!(hello.alpn);
+                }
+            },
+            TlsHelloKind::Server => {
+                body["ja3s"] = fingerprint;
+                if !hello.alpn.is_empty() {
+                    body["server_alpns"] = json!(hello.alpn);
+                }
+            },
+        }
+        if let Some(ja4) = ja4 {
+            body["ja4"] = json!(ja4);
+        }
+        Self::packet_event(packet, "tls", body)
+    }
+
+    pub fn http(packet: &FirewallPacket, message: &HttpMessage) -> Value {
+        let mut body = json!({
+            "protocol": message.version,
+        });
+        for (key, value) in [
+            ("hostname", &message.host),
+            ("url", &message.uri),
+            ("http_user_agent", &message.user_agent),
+            ("http_method", &message.method),
+        ] {
+            if let Some(value) = value {
+                body[key] = json!(value);
+            }
+        }
+        if let Some(status_code) = message.status_code {
+            body["status"] = json!(status_code);
+        }
+        Self::packet_event(packet, "http", body)
+    }
+
+    pub fn flow(record: &FlowRecord) -> Value {
+        let mut event = Self::header("flow", record.end, record.src_ip, record.src_port, record.dst_ip, record.dst_port, record.ip_protocol);
+        let state = match record.end_reason {
+            FlowEndReason::EndOfFlow => "closed",
+            _ if record.reply_packets > 0 => "established",
+            _ => "new",
+        };
+        event.insert(
+            "flow".to_string(),
+            json!({
+                "pkts_toserver": record.packets,
+                "pkts_toclient": record.reply_packets,
+                "bytes_toserver": record.bytes,
+                "bytes_toclient": record.reply_bytes,
+                "start": Self::format_timestamp(record.start),
+                "end": Self::format_timestamp(record.end),
+                "age": (record.end - record.start).num_seconds(),
+                "state": state,
+                "reason": if record.end_reason == FlowEndReason::LackOfResources { "forced" } else { "timeout" },
+            }),
+        );
+        if record.ip_protocol == 6 {
+            event.insert("tcp".to_string(), json!({ "tcp_flags": format!("{:02x}", record.tcp_flags) }));
+        }
+        Value::Object(event)
+    }
+
+    fn packet_event(packet: &FirewallPacket, event_type: &str, body: Value) -> Value {
+        let mut event = Self::header(
+            event_type,
+            Utc::now(),
+            packet.src_ip,
+            packet.transport.src_port(),
+            packet.dst_ip,
+            packet.transport.dst_port(),
+            packet.ip_protocol.value(),
+        );
+        event.insert(event_type.to_string(), body);
+        Value::Object(event)
+    }
+
+    fn header(event_type: &str, timestamp: DateTime<Utc>, src_ip: IpAddr, src_port: u16, dst_ip: IpAddr, dst_port: u16, ip_protocol: u8) -> Map<String, Value> {
+        let mut event = Map::new();
+        event.insert("timestamp".to_string(), json!(Self::format_timestamp(timestamp)));
+        event.insert("flow_id".to_string(), json!(Self::flow_id(src_ip, src_port, dst_ip, dst_port, ip_protocol)));
+        event.insert("event_type".to_string(), json!(event_type));
+        event.insert("src_ip".to_string(), json!(src_ip.to_string()));
+        event.insert("src_port".to_string(), json!(src_port));
+        event.insert("dest_ip".to_string(), json!(dst_ip.to_string()));
+        event.insert("dest_port".to_string(), json!(dst_port));
+        event.insert("proto".to_string(), json!(Self::protocol_name(ip_protocol)));
+        event
+    }
+
+    fn format_timestamp(timestamp: DateTime<Utc>) -> String {
+        timestamp.with_timezone(&Local).format("%Y-%m-%dT%H:%M:%S%.6f%z").to_string()
+    }
+
+    // 同じフローの往復のイベントが同じIDになるよう、端点を並べ替えてからハッシュする
+    fn flow_id(src_ip: IpAddr, src_port: u16, dst_ip: IpAddr, dst_port: u16, ip_protocol: u8) -> u64 {
+        let (first, second) = if (src_ip, src_port) <= (dst_ip, dst_port) {
+            ((src_ip, src_port), (dst_ip, dst_port))
+        } else {
+            ((dst_ip, dst_port), (src_ip, src_port))
+        };
+        let mut hasher = DefaultHasher::new();
+        (first, second, ip_protocol).hash(&mut hasher);
+        hasher.finish() & FLOW_ID_MASK
+    }
+
+    fn protocol_name(ip_protocol: u8) -> String {
+        match ip_protocol {
+            1 => "ICMP".to_string(),
+            6 => "TCP".to_string(),
+            17 => "UDP".to_string(),
+            58 => "IPv6-ICMP".to_string(),
+            value => value.to_string(),
+        }
+    }
+
+    fn rcode_name(rcode: u8) -> String {
+        match rcode {
+            0 => "NOERROR".to_string(),
+            1 => "FORMERR".to_string(),
+            2 => "SERVFAIL".to_string(),
+            3 => "NXDOMAIN".to_string(),
+            4 => "NOTIMP".to_string(),
+            5 => "REFUSED".to_string(),
+            value => value.to_string(),
+        }
+    }
+
+    fn tls_version_name(version: u16) -> &'static str {
+        match version {
+            0x0300 => "SSLv3",
+            0x0301 => "TLS 1.0",
+            0x0302 => "TLS 1.1",
+            0x0303 => "TLS 1.2",
+            0x0304 => "TLS 1.3",
+            _ => "UNDETERMINED",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::analysis::dns::{DnsQuestion, DnsRecord, DnsRecordType};
+    use crate::packet::analysis::idps::AlertCategory;
+    use crate::packet::analysis::transport::{TransportHeader, UdpHeader};
+    use crate::packet::types::{EtherType, IpProtocol};
+    use crate::packet::MacAddr;
+
+    const CLIENT: (&str, u16) = ("10.0.0.1", 40000);
+    const SERVER: (&str, u16) = ("10.0.0.2", 53);
+
+    fn datagram(src: (&str, u16), dst: (&str, u16)) -> FirewallPacket {
+        FirewallPacket::from_packet(
+            MacAddr([1; 6]),
+            MacAddr([2; 6]),
+            EtherType::IP_V4,
+            src.0.parse().unwrap(),
+            dst.0.parse().unwrap(),
+            IpProtocol::UDP,
+            TransportHeader::Udp(UdpHeader {
+                src_port: src.1,
+                dst_port: dst.1,
+                length: 8,
+                checksum: 0,
+            }),
+            None,
+            42,
+            None,
+        )
+    }
+
+    fn question() -> DnsQuestion {
+        DnsQuestion {
+            name: "example.com".to_string(),
+            record_type: DnsRecordType::A,
+        }
+    }
+
+    #[test]
+    fn builds_alert_event() {
+        let mut alert = Alert::new(&datagram(CLIENT, SERVER), AlertSeverity::Medium, AlertCategory::Signature, "test".to_string());
+        alert.signature_id = Some(1000001);
+
+        let event = EveEvent::alert(&alert, true);
+        assert_eq!(event["event_type"], "alert");
+        assert_eq!(event["src_ip"], "10.0.0.1");
+        assert_eq!(event["src_port"], 40000);
+        assert_eq!(event["dest_ip"], "10.0.0.2");
+        assert_eq!(event["dest_port"], 53);
+        assert_eq!(event["proto"], "UDP");
+        assert_eq!(event["alert"]["action"], "blocked");
+        assert_eq!(event["alert"]["signature"], "test");
+        assert_eq!(event["alert"]["signature_id"], 1000001);
+        assert_eq!(event["alert"]["category"], "signature");
+        assert_eq!(event["alert"]["severity"], 2);
+
+        // シグネチャ以外の検知ではsignature_idを持たない
+        alert.signature_id = None;
+        let event = EveEvent::alert(&alert, false);
+        assert_eq!(event["alert"]["action"], "allowed");
+        assert!(event["alert"].get("signature_id").is_none());
+    }
+
+    #[test]
+    fn assigns_same_flow_id_to_both_directions() {
+        let query = EveEvent::dns(
+            &datagram(CLIENT, SERVER),
+            &DnsMessage {
+                id: 1,
+                is_response: false,
+                rcode: 0,
+                questions: vec![question()],
+                answers: Vec::new(),
+            },
+        );
+        let response = EveEvent::dns(
+            &datagram(SERVER, CLIENT),
+            &DnsMessage {
+                id: 1,
+                is_response: true,
+                rcode: 0,
+                questions: vec![question()],
+                answers: Vec::new(),
+            },
+        );
+        assert_eq!(query[0]["flow_id"], response[0]["flow_id"]);
+
+        let flow_id = query[0]["flow_id"].as_u64().unwrap();
+        assert!(flow_id <= FLOW_ID_MASK);
+        let (client, server) = (CLIENT.0.parse().unwrap(), SERVER.0.parse().unwrap());
+        assert_eq!(flow_id, EveEvent::flow_id(server, SERVER.1, client, CLIENT.1, 17));
+        // プロトコルやポートが異なれば別のフロー
+        assert_ne!(flow_id, EveEvent::flow_id(client, CLIENT.1, server, SERVER.1, 6));
+        assert_ne!(flow_id, EveEvent::flow_id(client, CLIENT.1 + 1, server, SERVER.1, 17));
+    }
+
+    #[test]
+    fn builds_one_dns_query_event_per_question() {
+        let message = DnsMessage {
+            id: 0x1234,
+            is_response: false,
+            rcode: 0,
+            questions: vec![
+                question(),
+                DnsQuestion {
+                    name: "example.org".to_string(),
+                    record_type: DnsRecordType::A,
+                },
+            ],
+            answers: Vec::new(),
+        };
+
+        let events = EveEvent::dns(&datagram(CLIENT, SERVER), &message);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["event_type"], "dns");
+        assert_eq!(events[0]["dns"]["type"], "query");
+        assert_eq!(events[0]["dns"]["id"], 0x1234);
+        assert_eq!(events[1]["dns"]["rrname"], "example.org");
+        assert_eq!(events[1]["dns"]["tx_id"], 1);
+    }
+
+    #[test]
+    fn builds_single_dns_answer_event() {
+        let message = DnsMessage {
+            id: 0x1234,
+            is_response: true,
+            rcode: 3,
+            questions: vec![question()],
+            answers: vec![DnsRecord {
+                name: "example.com".to_string(),
+                record_type: DnsRecordType::A,
+                ttl: 60,
+                data: "192.0.2.1".to_string(),
+            }],
+        };
+
+        let events = EveEvent::dns(&datagram(SERVER, CLIENT), &message);
+        assert_eq!(events.len(), 1);
+        let dns = &events[0]["dns"];
+        assert_eq!(dns["type"], "answer");
+        assert_eq!(dns["qr"], true);
+        assert_eq!(dns["rcode"], "NXDOMAIN");
+        assert_eq!(dns["rrname"], "example.com");
+        assert_eq!(dns["answers"][0]["rdata"], "192.0.2.1");
+        assert_eq!(dns["answers"][0]["ttl"], 60);
+    }
+
+    #[test]
+    fn builds_flow_event() {
+        let end = Utc::now();
+        let mut record = FlowRecord {
+            start: end - chrono::Duration::seconds(5),
+            end,
+            src_ip: CLIENT.0.parse().unwrap(),
+            dst_ip: SERVER.0.parse().unwrap(),
+            src_port: CLIENT.1,
+            dst_port: SERVER.1,
+            ip_protocol: 6,
+            packets: 3,
+            bytes: 180,
+            reply_packets: 2,
+            reply_bytes: 120,
+            tcp_flags: 0x1b,
+            end_reason: FlowEndReason::EndOfFlow,
+        };
+
+        let event = EveEvent::flow(&record);
+        assert_eq!(event["event_type"], "flow");
+        assert_eq!(event["proto"], "TCP");
+        assert_eq!(event["flow"]["pkts_toserver"], 3);
+        assert_eq!(event["flow"]["bytes_toclient"], 120);
+        assert_eq!(event["flow"]["age"], 5);
+        assert_eq!(event["flow"]["state"], "closed");
+        assert_eq!(event["flow"]["reason"], "timeout");
+        assert_eq!(event["tcp"]["tcp_flags"], "1b");
+
+        record.end_reason = FlowEndReason::LackOfResources;
+        record.reply_packets = 0;
+        let event = EveEvent::flow(&record);
+        assert_eq!(event["flow"]["state"], "new");
+        assert_eq!(event["flow"]["reason"], "forced");
+    }
+}
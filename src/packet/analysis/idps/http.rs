@@ -0,0 +1,157 @@
+use super::{EveEvent, InspectContext};
+use crate::config::HttpConfig;
+use crate::logger::eve_logger;
+use chrono::{DateTime, Utc};
+use log::{trace, warn};
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+// 書き込みを待つHTTPログの上限
+const MAX_PENDING_LOG_ENTRIES: usize = 65536;
+
+/// http_logテーブルへ書き込む1件のリクエストまたはレスポンス
+#[derive(Debug, Clone)]
+pub struct HttpLogEntry {
+    pub timestamp: DateTime<Utc>,
+    pub src_ip: IpAddr,
+    pub dst_ip: IpAddr,
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub response: bool,
+    pub version: String,
+    // リクエストのみ
+    pub method: Option<String>,
+    pub host: Option<String>,
+    pub uri: Option<String>,
+    pub user_agent: Option<String>,
+    // レスポンスのみ
+    pub status_code: Option<u16>,
+}
+
+/// 平文のHTTPメッセージのメタデータを記録する
+#[derive(Debug)]
+pub struct HttpInspector {
+    log_transactions: bool,
+    log: Mutex<Vec<HttpLogEntry>>,
+}
+
+impl HttpInspector {
+    pub fn new(config: &HttpConfig) -> Self {
+        Self {
+            log_transactions: config.log_transactions,
+            log: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn inspect(&self, context: &InspectContext) {
+        if context.http.is_empty() {
+            return;
+        }
+        let packet = context.packet;
+        for message in context.http {
+            trace!(
+                "HTTP: {}:{} -> {}:{}, {} {} {} {}, Host={}, User-Agent={}",
+                packet.src_ip,
+                packet.transport.src_port(),
+                packet.dst_ip,
+                packet.transport.dst_port(),
+                message.version,
+                message.method.as_deref().unwrap_or("-"),
+                message.uri.as_deref().unwrap_or("-"),
+                message.status_code.map_or("-".to_string(), |status_code| status_code.to_string()),
+                message.host.as_deref().unwrap_or("-"),
+                message.user_agent.as_deref().unwrap_or("-")
+            );
+            if eve_logger::is_enabled() {
+                eve_logger::write_event(&EveEvent::http(packet, message));
+            }
+        }
+        if !self.log_transactions {
+            return;
+        }
+
+        let Ok(mut log) = self.log.lock() else {
+            return;
+        };
+        for message in context.http {
+            if log.len() >= MAX_PENDING_LOG_ENTRIES {
+                warn!(
+                    "HTTPログのバッファが上限に達した為、メッセージを破棄します: {}:{} -> {}:{}",
+                    packet.src_ip,
+                    packet.transport.src_port(),
+                    packet.dst_ip,
+                    packet.transport.dst_port()
+                );
+                return;
+            }
+            log.push(HttpLogEntry {
+                timestamp: Utc::now(),
+                src_ip: packet.src_ip,
+                dst_ip: packet.dst_ip,
+                src_port: packet.transport.src_port(),
+                dst_port: packet.transport.dst_port(),
+                response: message.is_response,
+                version: message.version.clone(),
+                method: message.method.clone(),
+                host: message.host.clone(),
+                uri: message.uri.clone(),
+                user_agent: message.user_agent.clone(),
+                status_code: message.status_code,
+            });
+        }
+    }
+
+    /// 書き込みを待っているHTTPログを取り出す
+    pub fn drain_log(&self) -> Vec<HttpLogEntry> {
+        match self.log.lock() {
+            Ok(mut log) => std::mem::take(&mut *log),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// 書き込みに失敗したHTTPログを書き込み待ちの先頭に戻す (上限を超える分は破棄する)
+    pub fn requeue_log(&self, entries: Vec<HttpLogEntry>) {
+        let Ok(mut log) = self.log.lock() else {
+            return;
+        };
+        let available = MAX_PENDING_LOG_ENTRIES.saturating_sub(log.len());
+        if entries.len() > available {
+            warn!("HTTPログのバッファが上限に達した為、{}件のメッセージを破棄します", entries.len() - available);
+        }
+        log.splice(0..0, entries.into_iter().take(available));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(uri: &str) -> HttpLogEntry {
+        HttpLogEntry {
+            timestamp: Utc::now(),
+            src_ip: "10.0.0.1".parse().unwrap(),
+            dst_ip: "10.0.0.2".parse().unwrap(),
+            src_port: 40000,
+            dst_port: 80,
+            response: false,
+            version: "HTTP/1.1".to_string(),
+            method: Some("GET".to_string()),
+            host: Some("example.com".to_string()),
+            uri: Some(uri.to_string()),
+            user_agent: None,
+            status_code: None,
+        }
+    }
+
+    #[test]
+    fn requeues_log_before_new_entries_up_to_limit() {
+        let inspector = HttpInspector::new(&HttpConfig::default());
+        inspector.requeue_log(vec![entry("/2")]);
+        inspector.requeue_log(vec![entry("/1")]);
+        let uris: Vec<Option<String>> = inspector.drain_log().into_iter().map(|entry| entry.uri).collect();
+        assert_eq!(uris, [Some("/1".to_string()), Some("/2".to_string())]);
+
+        inspector.requeue_log(vec![entry("/"); MAX_PENDING_LOG_ENTRIES + 1]);
+        assert_eq!(inspector.drain_log().len(), MAX_PENDING_LOG_ENTRIES);
+    }
+}
@@ -0,0 +1,219 @@
+mod alert;
+mod anomaly;
+mod arpwatch;
+mod dns;
+mod eve;
+mod http;
+mod portscan;
+mod prevention;
+mod signature;
+mod synflood;
+mod tls;
+
+pub use alert::{Alert, AlertCategory, AlertSeverity};
+pub use anomaly::{AnomalyDetector, Ewma, HostBaseline};
+pub use arpwatch::ArpWatchDetector;
+pub use dns::{DnsDetector, DnsLogEntry};
+pub use eve::EveEvent;
+pub use http::{HttpInspector, HttpLogEntry};
+pub use portscan::PortScanDetector;
+pub use prevention::AutoBlocker;
+pub use signature::SignatureEngine;
+pub use synflood::SynFloodDetector;
+pub use tls::TlsDetector;
+
+use crate::config::IdpsConfig;
+use crate::logger::eve_logger;
+use crate::packet::analysis::firewall::{ConnState, Filter, FirewallPacket};
+use crate::packet::analysis::http::HttpMessage;
+use crate::packet::analysis::stream::StreamChunk;
+use crate::packet::analysis::tls::TlsHello;
+use log::{error, warn};
+use std::sync::Mutex;
+use std::time::Duration;
+
+// 書き込みを待つアラートの上限
+const MAX_PENDING_ALERTS: usize = 65536;
+
+/// 検知器がファイアウォールへ要求する一時的な遮断ルール
+#[derive(Debug, Clone)]
+pub struct TemporaryBlock {
+    pub filter: Filter,
+    pub duration: Duration,
+    pub reason: String,
+}
+
+/// 検知器に渡すパケットの情報
+pub struct InspectContext<'a> {
+    pub packet: &'a FirewallPacket,
+    pub ethernet_frame: &'a [u8],
+    // トランスポート層より上のペイロード
+    pub payload: &'a [u8],
+    // 追跡しているTCPフローであれば再構築したストリーム
+    pub stream: Option<&'a StreamChunk>,
+    // TCPのペイロードから解析したTLSのClientHello/ServerHello
+    pub tls: Option<&'a TlsHello>,
+    // ヘッダ部が揃った平文のHTTPメッセージ
+    pub http: &'a [HttpMessage],
+    pub conn_state: ConnState,
+    // 接続を開始した側からのパケットか (不明な場合はNone)
+    pub from_client: Option<bool>,
+}
+
+/// 検知の結果
+#[derive(Debug, Default)]
+pub struct IdpsVerdict {
+    // フレームを破棄すべきか
+    pub drop: bool,
+    pub blocks: Vec<TemporaryBlock>,
+}
+
+/// 収集したパケットに対して各検知器を実行する
+#[derive(Debug)]
+pub struct IdpsEngine {
+    port_scan: PortScanDetector,
+    syn_flood: SynFloodDetector,
+    arp_watch: ArpWatchDetector,
+    dns: DnsDetector,
+    tls: TlsDetector,
+    http: HttpInspector,
+    anomaly: AnomalyDetector,
+    signatures: SignatureEngine,
+    auto_block: AutoBlocker,
+    store_alerts: bool,
+    // 契機となったパケットが保存されないアラート
+    pending_alerts: Mutex<Vec<Alert>>,
+}
+
+impl IdpsEngine {
+    pub fn new(config: &IdpsConfig) -> Self {
+        Self {
+            port_scan: PortScanDetector::new(&config.port_scan),
+            syn_flood: SynFloodDetector::new(&config.syn_flood),
+            arp_watch: ArpWatchDetector::new(&config.arp_watch),
+            dns: DnsDetector::new(&config.dns),
+            tls: TlsDetector::new(&config.tls),
+            http: HttpInspector::new(&config.http),
+            anomaly: AnomalyDetector::new(&config.anomaly),
+            signatures: Self::load_signatures(config),
+            auto_block: AutoBlocker::new(&config.prevention),
+            store_alerts: config.alert.store,
+            pending_alerts: Mutex::new(Vec::new()),
+        }
+    }
+
+    // 読み込みに失敗した場合はシグネチャなしで動作する
+    fn load_signatures(config: &IdpsConfig) -> SignatureEngine {
+        let Some(path) = &config.signature.rules_path else {
+            return SignatureEngine::default();
+        };
+        SignatureEngine::load(path).unwrap_or_else(|e| {
+            error!("{}", e);
+            SignatureEngine::default()
+        })
+    }
+
+    /// 検知器が要求した遮断ルールとフレームを破棄すべきかを返す
+    /// 出力したアラートはalertsへ追加する (保存しない設定の場合は空にする)
+    pub fn inspect(&self, context: &InspectContext, alerts: &mut Vec<Alert>) -> IdpsVerdict {
+        self.arp_watch.inspect(context.packet, context.ethernet_frame, alerts);
+        self.port_scan.inspect(context.packet, context.conn_state, alerts);
+        self.dns.inspect(context, alerts);
+        self.tls.inspect(context, alerts);
+        self.http.inspect(context);
+        self.anomaly.inspect(context.packet, alerts);
+        let mut verdict = IdpsVerdict {
+            drop: self.signatures.evaluate(context, alerts),
+            blocks: self.syn_flood.inspect(context.packet, alerts),
+        };
+        verdict.blocks.extend(self.auto_block.blocks(alerts));
+        if eve_logger::is_enabled() {
+            for alert in alerts.iter() {
+                eve_logger::write_event(&EveEvent::alert(alert, verdict.drop));
+            }
+        }
+        if !self.store_alerts {
+            alerts.clear();
+        }
+        verdict
+    }
+}
+
+impl IdpsEngine {
+    /// 書き込みを待っているDNSログを取り出す
+    pub fn drain_dns_log(&self) -> Vec<DnsLogEntry> {
+        self.dns.drain_log()
+    }
+
+    /// 書き込みに失敗したDNSログを書き込み待ちに戻す
+    pub fn requeue_dns_log(&self, entries: Vec<DnsLogEntry>) {
+        self.dns.requeue_log(entries);
+    }
+
+    /// 書き込みを待っているHTTPログを取り出す
+    pub fn drain_http_log(&self) -> Vec<HttpLogEntry> {
+        self.http.drain_log()
+    }
+
+    /// 書き込みに失敗したHTTPログを書き込み待ちに戻す
+    pub fn requeue_http_log(&self, entries: Vec<HttpLogEntry>) {
+        self.http.requeue_log(entries);
+    }
+
+    /// 前回の保存以降に更新された異常検知のベースラインを返す
+    pub fn updated_baselines(&self) -> Vec<HostBaseline> {
+        self.anomaly.updated_baselines()
+    }
+
+    /// 保存した異常検知のベースラインを保存済みにする
+    pub fn mark_baselines_saved(&self, baselines: &[HostBaseline]) {
+        self.anomaly.mark_saved(baselines);
+    }
+
+    /// 保存されていた異常検知のベースラインを復元する
+    pub fn restore_baselines(&self, baselines: Vec<HostBaseline>) {
+        self.anomaly.restore_baselines(baselines);
+    }
+
+    /// 検知器を通さずに検知したアラート (不正なフラグメント等) を出力し、要求する遮断ルールを返す
+    /// 契機となったフレームは破棄される為、パケットIDなしで書き込む
+    pub fn report(&self, alerts: Vec<Alert>) -> Vec<TemporaryBlock> {
+        let blocks = self.auto_block.blocks(&alerts);
+        if eve_logger::is_enabled() {
+            for alert in &alerts {
+                eve_logger::write_event(&EveEvent::alert(alert, true));
+            }
+        }
+        self.queue_alerts(alerts);
+        blocks
+    }
+
+    /// パケットIDなしで書き込むアラートを追加する
+    pub fn queue_alerts(&self, alerts: Vec<Alert>) {
+        if alerts.is_empty() || !self.store_alerts {
+            return;
+        }
+        let Ok(mut pending) = self.pending_alerts.lock() else {
+            return;
+        };
+        let available = MAX_PENDING_ALERTS.saturating_sub(pending.len());
+        if alerts.len() > available {
+            warn!("アラートのバッファが上限に達した為、{}件のアラートを破棄します", alerts.len() - available);
+        }
+        pending.extend(alerts.into_iter().take(available));
+    }
+
+    /// 書き込みを待っているアラートを取り出す
+    pub fn drain_alerts(&self) -> Vec<Alert> {
+        match self.pending_alerts.lock() {
+            Ok(mut pending) => std::mem::take(&mut *pending),
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+impl Default for IdpsEngine {
+    fn default() -> Self {
+        Self::new(&IdpsConfig::default())
+    }
+}
@@ -0,0 +1,372 @@
+use super::{Alert, AlertCategory, AlertSeverity};
+use crate::config::PortScanConfig;
+use crate::idps_log;
+use crate::packet::analysis::firewall::{ConnState, FirewallPacket};
+use crate::packet::analysis::TransportHeader;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// TCPフラグ
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+const TCP_PSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+const TCP_URG: u8 = 0x20;
+
+// 送信元毎に保持するプローブ数と、追跡する送信元数の上限
+const MAX_PROBES_PER_SOURCE: usize = 4096;
+const MAX_TRACKED_SOURCES: usize = 65536;
+const GC_INTERVAL: Duration = Duration::from_secs(10);
+// アラートに列挙する宛先の最大数
+const MAX_REPORTED_TARGETS: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ScanType {
+    Syn,
+    Fin,
+    Null,
+    Xmas,
+    Udp,
+}
+
+impl fmt::Display for ScanType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ScanType::Syn => "SYN",
+            ScanType::Fin => "FIN",
+            ScanType::Null => "NULL",
+            ScanType::Xmas => "Xmas",
+            ScanType::Udp => "UDP",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl ScanType {
+    /// スキャンのプローブとして使われるパケットであれば種別を返す
+    /// UDPは応答や継続中のフローのパケットを除き、新しいフローを開始するパケットのみを対象とする
+    fn classify(transport: &TransportHeader, conn_state: ConnState) -> Option<Self> {
+        match transport {
+            TransportHeader::Tcp(tcp) => match tcp.flags & (TCP_FIN | TCP_SYN | TCP_RST | TCP_PSH | TCP_ACK | TCP_URG) {
+                TCP_SYN => Some(ScanType::Syn),
+                TCP_FIN => Some(ScanType::Fin),
+                0 => Some(ScanType::Null),
+                flags if flags == TCP_FIN | TCP_PSH | TCP_URG => Some(ScanType::Xmas),
+                _ => None,
+            },
+            TransportHeader::Udp(_) if conn_state == ConnState::New => Some(ScanType::Udp),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Probe {
+    seen_at: Instant,
+    dst_ip: IpAddr,
+    dst_port: u16,
+}
+
+// 時間窓内のプローブと、宛先ホスト毎のポート・宛先ポート毎のホストの集合 (プローブの追加・期限切れ時に更新する)
+#[derive(Debug, Default)]
+struct SourceTracker {
+    probes: VecDeque<Probe>,
+    // 宛先ホスト・ポートの組毎の時間窓内のプローブ数
+    targets: HashMap<(IpAddr, u16), usize>,
+    ports_by_host: HashMap<IpAddr, BTreeSet<u16>>,
+    hosts_by_port: HashMap<u16, BTreeSet<IpAddr>>,
+    last_alert: Option<Instant>,
+}
+
+impl SourceTracker {
+    fn expire(&mut self, now: Instant, window: Duration) {
+        while self.probes.front().is_some_and(|probe| now.duration_since(probe.seen_at) > window) {
+            self.pop_front();
+        }
+    }
+
+    fn push(&mut self, probe: Probe) {
+        if self.probes.len() >= MAX_PROBES_PER_SOURCE {
+            self.pop_front();
+        }
+        let count = self.targets.entry((probe.dst_ip, probe.dst_port)).or_insert(0);
+        *count += 1;
+        if *count == 1 {
+            self.ports_by_host.entry(probe.dst_ip).or_default().insert(probe.dst_port);
+            self.hosts_by_port.entry(probe.dst_port).or_default().insert(probe.dst_ip);
+        }
+        self.probes.push_back(probe);
+    }
+
+    fn pop_front(&mut self) {
+        let Some(probe) = self.probes.pop_front() else {
+            return;
+        };
+        let key = (probe.dst_ip, probe.dst_port);
+        let Some(count) = self.targets.get_mut(&key) else {
+            return;
+        };
+        *count -= 1;
+        if *count > 0 {
+            return;
+        }
+        self.targets.remove(&key);
+        if let Some(ports) = self.ports_by_host.get_mut(&probe.dst_ip) {
+            ports.remove(&probe.dst_port);
+            if ports.is_empty() {
+                self.ports_by_host.remove(&probe.dst_ip);
+            }
+        }
+        if let Some(hosts) = self.hosts_by_port.get_mut(&probe.dst_port) {
+            hosts.remove(&probe.dst_ip);
+            if hosts.is_empty() {
+                self.hosts_by_port.remove(&probe.dst_port);
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ScanTable {
+    sources: HashMap<(IpAddr, ScanType), SourceTracker>,
+    last_gc: Instant,
+}
+
+/// 送信元毎に時間窓内の宛先ホスト・ポートを集計してポートスキャンを検知する
+#[derive(Debug)]
+pub struct PortScanDetector {
+    window: Duration,
+    port_threshold: usize,
+    host_threshold: usize,
+    table: Mutex<ScanTable>,
+}
+
+impl PortScanDetector {
+    pub fn new(config: &PortScanConfig) -> Self {
+        Self {
+            window: Duration::from_secs(config.window.max(1)),
+            port_threshold: config.port_threshold.max(1),
+            host_threshold: config.host_threshold.max(1),
+            table: Mutex::new(ScanTable {
+                sources: HashMap::new(),
+                last_gc: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn inspect(&self, packet: &FirewallPacket, conn_state: ConnState, alerts: &mut Vec<Alert>) {
+        let Some(scan_type) = ScanType::classify(&packet.transport, conn_state) else {
+            return;
+        };
+        let Ok(mut table) = self.table.lock() else {
+            return;
+        };
+
+        let now = Instant::now();
+        if now.duration_since(table.last_gc) >= GC_INTERVAL {
+            let window = self.window;
+            table.sources.retain(|_, tracker| {
+                tracker.expire(now, window);
+                !tracker.probes.is_empty()
+            });
+            table.last_gc = now;
+        }
+
+        let key = (packet.src_ip, scan_type);
+        if !table.sources.contains_key(&key) && table.sources.len() >= MAX_TRACKED_SOURCES {
+            return;
+        }
+
+        let tracker = table.sources.entry(key).or_default();
+        tracker.expire(now, self.window);
+        let probe = Probe {
+            seen_at: now,
+            dst_ip: packet.dst_ip,
+            dst_port: packet.transport.dst_port(),
+        };
+        tracker.push(probe);
+
+        // 同じ送信元・種別のアラートは時間窓につき1回まで
+        if tracker.last_alert.is_some_and(|last| now.duration_since(last) < self.window) {
+            return;
+        }
+
+        if let Some(report) = self.evaluate(tracker, &probe) {
+            tracker.last_alert = Some(now);
+            let message = format!(
+                "ポートスキャンを検知しました: 種別={}, 方向={}, 送信元={}, 宛先ホスト数={}, 宛先ポート数={}, 対象={}",
+                scan_type, report.direction, packet.src_ip, report.host_count, report.port_count, report.targets
+            );
+            idps_log!("{}", message);
+            alerts.push(Alert::new(packet, AlertSeverity::Medium, AlertCategory::PortScan, message));
+        }
+    }
+
+    // 閾値を超え得るのは今回のプローブの宛先ホストと宛先ポートのみの為、それらの集合の大きさのみを確認する
+    fn evaluate(&self, tracker: &SourceTracker, probe: &Probe) -> Option<ScanReport> {
+        // 1つのホストの多数のポートを調べる垂直スキャン
+        if let Some(ports) = tracker.ports_by_host.get(&probe.dst_ip).filter(|ports| ports.len() >= self.port_threshold) {
+            return Some(ScanReport {
+                direction: "垂直",
+                host_count: tracker.ports_by_host.len(),
+                port_count: ports.len(),
+                targets: format!("{} ポート[{}]", probe.dst_ip, summarize(ports.iter())),
+            });
+        }
+
+        // 多数のホストの同じポートを調べる水平スキャン
+        if let Some(hosts) = tracker.hosts_by_port.get(&probe.dst_port).filter(|hosts| hosts.len() >= self.host_threshold) {
+            return Some(ScanReport {
+                direction: "水平",
+                host_count: hosts.len(),
+                port_count: tracker.hosts_by_port.len(),
+                targets: format!("ポート{} ホスト[{}]", probe.dst_port, summarize(hosts.iter())),
+            });
+        }
+
+        None
+    }
+}
+
+struct ScanReport {
+    direction: &'static str,
+    host_count: usize,
+    port_count: usize,
+    targets: String,
+}
+
+// 宛先の一覧を先頭から一定数だけ列挙する
+fn summarize<T: fmt::Display>(items: impl ExactSizeIterator<Item = T>) -> String {
+    let total = items.len();
+    let listed: Vec<String> = items.take(MAX_REPORTED_TARGETS).map(|item| item.to_string()).collect();
+    if total > MAX_REPORTED_TARGETS {
+        format!("{}, ... (他{}件)", listed.join(", "), total - MAX_REPORTED_TARGETS)
+    } else {
+        listed.join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::analysis::transport::{TcpHeader, UdpHeader};
+    use crate::packet::types::{EtherType, IpProtocol};
+    use crate::packet::MacAddr;
+
+    fn packet(dst_ip: &str, transport: TransportHeader) -> FirewallPacket {
+        let ip_protocol = match transport {
+            TransportHeader::Udp(_) => IpProtocol::UDP,
+            _ => IpProtocol::TCP,
+        };
+        FirewallPacket::from_packet(
+            MacAddr([1; 6]),
+            MacAddr([2; 6]),
+            EtherType::IP_V4,
+            "10.0.0.1".parse().unwrap(),
+            dst_ip.parse().unwrap(),
+            ip_protocol,
+            transport,
+            None,
+            60,
+            None,
+        )
+    }
+
+    fn syn(dst_port: u16) -> TransportHeader {
+        TransportHeader::Tcp(Box::new(TcpHeader {
+            src_port: 40000,
+            dst_port,
+            sequence_number: 0,
+            acknowledgment_number: 0,
+            header_length: 20,
+            flags: TCP_SYN,
+            window_size: 1024,
+            checksum: 0,
+            urgent_pointer: 0,
+            options: Vec::new(),
+        }))
+    }
+
+    fn udp(src_port: u16, dst_port: u16) -> TransportHeader {
+        TransportHeader::Udp(UdpHeader {
+            src_port,
+            dst_port,
+            length: 8,
+            checksum: 0,
+        })
+    }
+
+    fn detector() -> PortScanDetector {
+        PortScanDetector::new(&PortScanConfig {
+            window: 60,
+            port_threshold: 5,
+            host_threshold: 5,
+        })
+    }
+
+    #[test]
+    fn detects_vertical_syn_scan() {
+        let detector = detector();
+        let mut alerts = Vec::new();
+        for port in 1..=4 {
+            detector.inspect(&packet("10.0.0.2", syn(port)), ConnState::New, &mut alerts);
+        }
+        // 同じポートへの再送は異なるポートとして数えない
+        detector.inspect(&packet("10.0.0.2", syn(4)), ConnState::New, &mut alerts);
+        assert!(alerts.is_empty());
+
+        detector.inspect(&packet("10.0.0.2", syn(5)), ConnState::New, &mut alerts);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].category, AlertCategory::PortScan);
+    }
+
+    #[test]
+    fn detects_horizontal_scan() {
+        let detector = detector();
+        let mut alerts = Vec::new();
+        for host in 2..=6 {
+            detector.inspect(&packet(&format!("10.0.0.{}", host), syn(22)), ConnState::New, &mut alerts);
+        }
+        assert_eq!(alerts.len(), 1);
+    }
+
+    #[test]
+    fn ignores_udp_packets_of_established_flows() {
+        let detector = detector();
+        let mut alerts = Vec::new();
+        // DNSの応答などは送信元ポート毎に異なる宛先ポートへ送られる
+        for port in 50000..50010 {
+            detector.inspect(&packet("10.0.0.2", udp(53, port)), ConnState::Established, &mut alerts);
+        }
+        assert!(alerts.is_empty());
+
+        for port in 1..=5 {
+            detector.inspect(&packet("10.0.0.2", udp(40000, port)), ConnState::New, &mut alerts);
+        }
+        assert_eq!(alerts.len(), 1);
+    }
+
+    #[test]
+    fn forgets_targets_of_evicted_probes() {
+        let mut tracker = SourceTracker::default();
+        let now = Instant::now();
+        for port in 0..(MAX_PROBES_PER_SOURCE + 10) as u16 {
+            tracker.push(Probe {
+                seen_at: now,
+                dst_ip: "10.0.0.2".parse().unwrap(),
+                dst_port: port,
+            });
+        }
+        assert_eq!(tracker.probes.len(), MAX_PROBES_PER_SOURCE);
+        assert_eq!(tracker.targets.len(), MAX_PROBES_PER_SOURCE);
+        assert_eq!(tracker.ports_by_host.values().map(|ports| ports.len()).sum::<usize>(), MAX_PROBES_PER_SOURCE);
+        assert_eq!(tracker.hosts_by_port.len(), MAX_PROBES_PER_SOURCE);
+
+        tracker.expire(now + Duration::from_secs(120), Duration::from_secs(60));
+        assert!(tracker.targets.is_empty() && tracker.ports_by_host.is_empty() && tracker.hosts_by_port.is_empty());
+    }
+}
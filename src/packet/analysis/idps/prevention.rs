@@ -0,0 +1,146 @@
+use super::{Alert, AlertCategory, AlertSeverity, TemporaryBlock};
+use crate::config::PreventionConfig;
+use crate::packet::analysis::firewall::Filter;
+use log::warn;
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// アラートの送信元を遮断する一時的なルールを生成する
+#[derive(Debug)]
+pub struct AutoBlocker {
+    enabled: bool,
+    duration: Duration,
+    min_severity: AlertSeverity,
+    exclude: HashSet<IpAddr>,
+}
+
+impl AutoBlocker {
+    pub fn new(config: &PreventionConfig) -> Self {
+        let min_severity = AlertSeverity::parse(&config.min_severity).unwrap_or_else(|| {
+            warn!("不明な重大度が指定された為、highとして扱います: {}", config.min_severity);
+            AlertSeverity::High
+        });
+        Self {
+            enabled: config.auto_block,
+            duration: Duration::from_secs(config.block_duration.max(1)),
+            min_severity,
+            exclude: config.exclude.iter().copied().collect(),
+        }
+    }
+
+    pub fn blocks(&self, alerts: &[Alert]) -> Vec<TemporaryBlock> {
+        if !self.enabled {
+            return Vec::new();
+        }
+        let mut sources = HashSet::new();
+        alerts
+            .iter()
+            .filter(|alert| alert.severity >= self.min_severity && Self::blockable(alert))
+            .filter(|alert| !self.exclude.contains(&alert.src_ip) && sources.insert(alert.src_ip))
+            .map(|alert| TemporaryBlock {
+                filter: Filter::SrcIpAddress(alert.src_ip),
+                duration: self.duration,
+                reason: format!("{}: {}", alert.category, alert.message),
+            })
+            .collect()
+    }
+
+    // ARPのアラートは送信元のIPアドレスを詐称している可能性がある為、遮断しない
+    fn blockable(alert: &Alert) -> bool {
+        let source = alert.src_ip;
+        alert.category != AlertCategory::ArpSpoofing && !source.is_unspecified() && !source.is_multicast() && !source.is_loopback() && source != IpAddr::from([255, 255, 255, 255])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn blocker(exclude: &[&str]) -> AutoBlocker {
+        AutoBlocker::new(&PreventionConfig {
+            auto_block: true,
+            block_duration: 300,
+            min_severity: "medium".to_string(),
+            publish: false,
+            exclude: exclude.iter().map(|ip| ip.parse().unwrap()).collect(),
+        })
+    }
+
+    fn alert(src_ip: &str, severity: AlertSeverity, category: AlertCategory) -> Alert {
+        Alert {
+            timestamp: Utc::now(),
+            severity,
+            category,
+            signature_id: None,
+            message: "test".to_string(),
+            src_ip: src_ip.parse().unwrap(),
+            dst_ip: "10.0.0.2".parse().unwrap(),
+            src_port: 40000,
+            dst_port: 22,
+            ip_protocol: 6,
+            packet_id: None,
+        }
+    }
+
+    fn blocked_sources(blocks: &[TemporaryBlock]) -> Vec<Filter> {
+        blocks.iter().map(|block| block.filter.clone()).collect()
+    }
+
+    #[test]
+    fn blocks_source_of_alert_at_or_above_min_severity() {
+        let blocks = blocker(&[]).blocks(&[
+            alert("10.0.0.1", AlertSeverity::Low, AlertCategory::PortScan),
+            alert("10.0.0.3", AlertSeverity::Medium, AlertCategory::PortScan),
+            alert("10.0.0.4", AlertSeverity::High, AlertCategory::SynFlood),
+        ]);
+
+        assert_eq!(
+            blocked_sources(&blocks),
+            [
+                Filter::SrcIpAddress("10.0.0.3".parse().unwrap()),
+                Filter::SrcIpAddress("10.0.0.4".parse().unwrap())
+            ]
+        );
+        assert_eq!(blocks[0].duration, Duration::from_secs(300));
+        assert_eq!(blocks[0].reason, "port_scan: test");
+    }
+
+    #[test]
+    fn blocks_each_source_once() {
+        let blocks = blocker(&[]).blocks(&[
+            alert("10.0.0.1", AlertSeverity::High, AlertCategory::PortScan),
+            alert("10.0.0.1", AlertSeverity::High, AlertCategory::Signature),
+        ]);
+        assert_eq!(blocks.len(), 1);
+    }
+
+    #[test]
+    fn skips_excluded_sources() {
+        let blocks = blocker(&["10.0.0.1"]).blocks(&[alert("10.0.0.1", AlertSeverity::High, AlertCategory::PortScan)]);
+        assert!(blocks.is_empty());
+    }
+
+    #[test]
+    fn skips_arp_alerts_and_unblockable_sources() {
+        let alerts: Vec<Alert> = [
+            ("10.0.0.1", AlertCategory::ArpSpoofing),
+            ("0.0.0.0", AlertCategory::PortScan),
+            ("224.0.0.1", AlertCategory::PortScan),
+            ("127.0.0.1", AlertCategory::PortScan),
+            ("255.255.255.255", AlertCategory::PortScan),
+            ("::1", AlertCategory::PortScan),
+        ]
+        .into_iter()
+        .map(|(src_ip, category)| alert(src_ip, AlertSeverity::High, category))
+        .collect();
+        assert!(blocker(&[]).blocks(&alerts).is_empty());
+    }
+
+    #[test]
+    fn blocks_nothing_when_disabled() {
+        let blocker = AutoBlocker::new(&PreventionConfig::default());
+        assert!(blocker.blocks(&[alert("10.0.0.1", AlertSeverity::High, AlertCategory::PortScan)]).is_empty());
+    }
+}
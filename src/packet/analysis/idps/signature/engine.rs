@@ -0,0 +1,117 @@
+use super::error::SignatureError;
+use super::parser::{parse_rules, Variables};
+use super::prefilter::Prefilter;
+use super::rule::{Rule, RuleAction};
+use crate::idps_log;
+use crate::packet::analysis::idps::{Alert, AlertCategory, AlertSeverity, InspectContext};
+use log::{info, warn};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// 読み込んだシグネチャをパケット毎に評価する
+#[derive(Debug, Default)]
+pub struct SignatureEngine {
+    rules: Vec<Rule>,
+    prefilter: Prefilter,
+}
+
+impl SignatureEngine {
+    /// ルールファイル、または *.rules を含むディレクトリからシグネチャを読み込む
+    pub fn load(path: &str) -> Result<Self, SignatureError> {
+        let path = Path::new(path);
+        let files = if path.is_dir() {
+            let entries = fs::read_dir(path).map_err(|e| SignatureError::ReadError(format!("{}: {}", path.display(), e)))?;
+            let mut files: Vec<PathBuf> =
+                entries.filter_map(|entry| entry.ok().map(|entry| entry.path())).filter(|file| file.extension().is_some_and(|extension| extension == "rules")).collect();
+            files.sort();
+            files
+        } else {
+            vec![path.to_path_buf()]
+        };
+
+        let mut variables = Variables::default();
+        let mut rules: Vec<Rule> = Vec::new();
+        let mut sids = HashSet::new();
+        for file in files {
+            let source = fs::read_to_string(&file).map_err(|e| SignatureError::ReadError(format!("{}: {}", file.display(), e)))?;
+            for rule in parse_rules(&source, &file.display().to_string(), &mut variables) {
+                if !sids.insert(rule.sid) {
+                    warn!("{}: sid {} が重複しています", file.display(), rule.sid);
+                }
+                rules.push(rule);
+            }
+        }
+
+        let prefilter = Prefilter::new(&rules);
+        info!(
+            "シグネチャを{}件読み込みました (事前照合パターン={}件): {}",
+            rules.len(),
+            prefilter.pattern_count(),
+            path.display()
+        );
+        Ok(Self { rules, prefilter })
+    }
+
+    /// 一致したシグネチャのアラートを出力し、フレームを破棄すべき場合はtrueを返す
+    pub fn evaluate(&self, context: &InspectContext, alerts: &mut Vec<Alert>) -> bool {
+        let mut drop = false;
+
+        let candidates = self.prefilter.candidates(context.stream.map_or(context.payload, |stream| stream.data.as_slice()));
+        for rule in candidates.into_iter().map(|index| &self.rules[index]).filter(|rule| rule.matches(context)) {
+            let packet = context.packet;
+            let message = format!(
+                "[{}:{}] {} ({}, 優先度={}) プロトコル={} {}:{} -> {}:{}, アクション={}",
+                rule.sid,
+                rule.rev,
+                rule.msg,
+                rule.classtype.as_deref().unwrap_or("-"),
+                rule.priority.map_or("-".to_string(), |priority| priority.to_string()),
+                packet.ip_protocol.value(),
+                packet.src_ip,
+                packet.transport.src_port(),
+                packet.dst_ip,
+                packet.transport.dst_port(),
+                rule.header.action
+            );
+            idps_log!("{}", message);
+            alerts.push(Alert {
+                signature_id: Some(rule.sid),
+                ..Alert::new(packet, AlertSeverity::from_priority(rule.priority), AlertCategory::Signature, message)
+            });
+            drop |= rule.header.action == RuleAction::Drop;
+        }
+
+        drop
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loads_valid_rules_and_skips_invalid_ones() {
+        let directory = std::env::temp_dir().join(format!("rdb-tunnel-signature-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(
+            directory.join("a.rules"),
+            "alert tcp any any -> any any (msg:\"a\"; content:\"evil\"; sid:1;)\nalert tcp any any -> any any (content:\"|zz|\"; sid:2;)\n",
+        )
+        .unwrap();
+        fs::write(directory.join("b.rules"), "drop udp any any -> any 53 (msg:\"b\"; sid:3;)\nnot a rule\n").unwrap();
+        fs::write(directory.join("ignored.txt"), "alert tcp any any -> any any (sid:4;)\n").unwrap();
+
+        let engine = SignatureEngine::load(directory.to_str().unwrap());
+        fs::remove_dir_all(&directory).unwrap();
+
+        let engine = engine.expect("ルールを読み込めません");
+        assert_eq!(engine.rules.iter().map(|rule| rule.sid).collect::<Vec<_>>(), vec![1, 3]);
+        assert_eq!(engine.prefilter.pattern_count(), 1);
+    }
+
+    #[test]
+    fn fails_when_rules_file_is_missing() {
+        assert!(matches!(SignatureEngine::load("/nonexistent/rdb-tunnel.rules"), Err(SignatureError::ReadError(_))));
+    }
+}
@@ -0,0 +1,22 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum SignatureError {
+    #[error("ルールファイルの読み込みに失敗しました: {0}")]
+    ReadError(String),
+
+    #[error("ルールヘッダが不正です: {0}")]
+    InvalidHeader(String),
+
+    #[error("ルールオプションが不正です: {0}")]
+    InvalidOption(String),
+
+    #[error("未対応のルールオプションです: {0}")]
+    UnsupportedOption(String),
+
+    #[error("正規表現が不正です: {0}")]
+    InvalidPcre(String),
+
+    #[error("未定義の変数です: {0}")]
+    UndefinedVariable(String),
+}
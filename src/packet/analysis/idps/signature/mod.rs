@@ -0,0 +1,7 @@
+mod engine;
+mod error;
+mod parser;
+mod prefilter;
+mod rule;
+
+pub use engine::SignatureEngine;
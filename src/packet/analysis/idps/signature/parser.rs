@@ -0,0 +1,698 @@
+use super::error::SignatureError;
+use super::rule::{
+    AddressSpec, ByteTest, ByteTestOperator, ContentBuffer, ContentMatch, FlowOption, IpNetwork, PayloadOption, PcreMatch, PortSpec, Rule, RuleAction, RuleHeader, RuleProtocol,
+};
+use log::warn;
+use regex::bytes::RegexBuilder;
+use regex_syntax::hir::Look;
+use regex_syntax::ParserBuilder;
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+// アドレスとポートの変数やリストを展開する深さの上限 (自身を参照する変数の循環を防ぐ)
+const MAX_NESTING_DEPTH: usize = 16;
+
+/// `var`/`ipvar`/`portvar` で定義された変数
+#[derive(Debug, Clone)]
+pub struct Variables {
+    values: HashMap<String, String>,
+}
+
+impl Default for Variables {
+    fn default() -> Self {
+        let mut values = HashMap::new();
+        values.insert("HOME_NET".to_string(), "any".to_string());
+        values.insert("EXTERNAL_NET".to_string(), "any".to_string());
+        Self { values }
+    }
+}
+
+impl Variables {
+    fn resolve(&self, token: &str) -> Result<String, SignatureError> {
+        match token.strip_prefix('$') {
+            Some(name) => self.values.get(name).cloned().ok_or_else(|| SignatureError::UndefinedVariable(name.to_string())),
+            None => Ok(token.to_string()),
+        }
+    }
+}
+
+/// ルールファイルの内容を解析する (不正なルールは警告を出力して読み飛ばす)
+pub fn parse_rules(source: &str, origin: &str, variables: &mut Variables) -> Vec<Rule> {
+    let mut rules = Vec::new();
+    let mut pending = String::new();
+    let mut start_line = 0;
+
+    for (index, line) in source.lines().enumerate() {
+        if pending.is_empty() {
+            start_line = index + 1;
+        }
+        let line = line.trim();
+
+        // 行末のバックスラッシュは次の行へ継続する
+        if let Some(continued) = line.strip_suffix('\\') {
+            pending.push_str(continued);
+            pending.push(' ');
+            continue;
+        }
+        pending.push_str(line);
+        let statement = std::mem::take(&mut pending);
+        let statement = statement.trim();
+
+        if statement.is_empty() || statement.starts_with('#') {
+            continue;
+        }
+        if let Err(e) = parse_statement(statement, variables, &mut rules) {
+            warn!("{}:{}: {}", origin, start_line, e);
+        }
+    }
+
+    rules
+}
+
+fn parse_statement(statement: &str, variables: &mut Variables, rules: &mut Vec<Rule>) -> Result<(), SignatureError> {
+    let keyword = statement.split_whitespace().next().unwrap_or_default();
+    if matches!(keyword, "var" | "ipvar" | "portvar") {
+        let mut parts = statement[keyword.len()..].split_whitespace();
+        let (Some(name), Some(value), None) = (parts.next(), parts.next(), parts.next()) else {
+            return Err(SignatureError::InvalidHeader(statement.to_string()));
+        };
+        let value = variables.resolve(value)?;
+        variables.values.insert(name.to_string(), value);
+        return Ok(());
+    }
+
+    rules.push(parse_rule(statement, variables)?);
+    Ok(())
+}
+
+fn parse_rule(statement: &str, variables: &Variables) -> Result<Rule, SignatureError> {
+    let (Some(open), Some(close)) = (statement.find('('), statement.rfind(')')) else {
+        return Err(SignatureError::InvalidHeader(statement.to_string()));
+    };
+    if close < open || !statement[close + 1..].trim().is_empty() {
+        return Err(SignatureError::InvalidHeader(statement.to_string()));
+    }
+
+    let header = parse_header(&statement[..open], variables)?;
+    let mut rule = Rule {
+        header,
+        sid: 0,
+        rev: 1,
+        msg: String::new(),
+        classtype: None,
+        priority: None,
+        flow: Vec::new(),
+        options: Vec::new(),
+    };
+
+    for option in split_options(&statement[open + 1..close]) {
+        let (name, value) = match option.split_once(':') {
+            Some((name, value)) => (name.trim(), Some(value.trim())),
+            None => (option.as_str(), None),
+        };
+        apply_option(&mut rule, name, value)?;
+    }
+
+    if rule.sid == 0 {
+        return Err(SignatureError::InvalidOption("sidが指定されていません".to_string()));
+    }
+    Ok(rule)
+}
+
+fn parse_header(header: &str, variables: &Variables) -> Result<RuleHeader, SignatureError> {
+    let invalid = || SignatureError::InvalidHeader(header.trim().to_string());
+    let fields: Vec<&str> = header.split_whitespace().collect();
+    let [action, protocol, src_addr, src_port, direction, dst_addr, dst_port] = fields.as_slice() else {
+        return Err(invalid());
+    };
+
+    let action = match *action {
+        "alert" => RuleAction::Alert,
+        "drop" | "reject" => RuleAction::Drop,
+        _ => return Err(invalid()),
+    };
+    let protocol = match *protocol {
+        "ip" => RuleProtocol::Ip,
+        "tcp" => RuleProtocol::Tcp,
+        "udp" => RuleProtocol::Udp,
+        "icmp" => RuleProtocol::Icmp,
+        _ => return Err(invalid()),
+    };
+    let bidirectional = match *direction {
+        "->" => false,
+        "<>" => true,
+        _ => return Err(invalid()),
+    };
+
+    Ok(RuleHeader {
+        action,
+        protocol,
+        src_addr: parse_address(src_addr, variables, 0)?,
+        src_port: parse_port(src_port, variables, 0)?,
+        bidirectional,
+        dst_addr: parse_address(dst_addr, variables, 0)?,
+        dst_port: parse_port(dst_port, variables, 0)?,
+    })
+}
+
+fn parse_address(token: &str, variables: &Variables, depth: usize) -> Result<AddressSpec, SignatureError> {
+    let invalid = || SignatureError::InvalidHeader(token.to_string());
+    if depth > MAX_NESTING_DEPTH {
+        return Err(invalid());
+    }
+    let (negated, body) = match token.strip_prefix('!') {
+        Some(body) => (true, body),
+        None => (false, token),
+    };
+    let body = variables.resolve(body)?;
+
+    if body == "any" {
+        return Ok(AddressSpec { negated, networks: Vec::new() });
+    }
+
+    if let Some(list) = body.strip_prefix('[').and_then(|list| list.strip_suffix(']')) {
+        let mut networks = Vec::new();
+        for element in split_list(list) {
+            let spec = parse_address(element, variables, depth + 1)?;
+            // リスト内の否定には対応しない
+            if spec.negated {
+                return Err(invalid());
+            }
+            if spec.networks.is_empty() {
+                return Ok(AddressSpec { negated, networks: Vec::new() });
+            }
+            networks.extend(spec.networks);
+        }
+        return Ok(AddressSpec { negated, networks });
+    }
+
+    // 変数の値が否定を含む場合
+    if body.starts_with('!') {
+        let spec = parse_address(&body, variables, depth + 1)?;
+        return Ok(AddressSpec {
+            negated: negated != spec.negated,
+            networks: spec.networks,
+        });
+    }
+
+    let (address, prefix) = match body.split_once('/') {
+        Some((address, prefix)) => (address, Some(prefix)),
+        None => (body.as_str(), None),
+    };
+    let address: IpAddr = address.parse().map_err(|_| invalid())?;
+    let max_prefix = if address.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+        Some(prefix) => prefix.parse::<u8>().ok().filter(|prefix| *prefix <= max_prefix).ok_or_else(invalid)?,
+        None => max_prefix,
+    };
+
+    Ok(AddressSpec {
+        negated,
+        networks: vec![IpNetwork { address, prefix }],
+    })
+}
+
+fn parse_port(token: &str, variables: &Variables, depth: usize) -> Result<PortSpec, SignatureError> {
+    let invalid = || SignatureError::InvalidHeader(token.to_string());
+    if depth > MAX_NESTING_DEPTH {
+        return Err(invalid());
+    }
+    let (negated, body) = match token.strip_prefix('!') {
+        Some(body) => (true, body),
+        None => (false, token),
+    };
+    let body = variables.resolve(body)?;
+
+    if body == "any" {
+        return Ok(PortSpec { negated, ranges: Vec::new() });
+    }
+
+    if let Some(list) = body.strip_prefix('[').and_then(|list| list.strip_suffix(']')) {
+        let mut ranges = Vec::new();
+        for element in split_list(list) {
+            let spec = parse_port(element, variables, depth + 1)?;
+            if spec.negated {
+                return Err(invalid());
+            }
+            if spec.ranges.is_empty() {
+                return Ok(PortSpec { negated, ranges: Vec::new() });
+            }
+            ranges.extend(spec.ranges);
+        }
+        return Ok(PortSpec { negated, ranges });
+    }
+
+    if body.starts_with('!') {
+        let spec = parse_port(&body, variables, depth + 1)?;
+        return Ok(PortSpec {
+            negated: negated != spec.negated,
+            ranges: spec.ranges,
+        });
+    }
+
+    // 80, 1024:, :1023, 6000:6010
+    let range = match body.split_once(':') {
+        Some((low, high)) => {
+            let low = if low.is_empty() { 0 } else { low.parse::<u16>().map_err(|_| invalid())? };
+            let high = if high.is_empty() { u16::MAX } else { high.parse::<u16>().map_err(|_| invalid())? };
+            (low, high)
+        },
+        None => {
+            let port = body.parse::<u16>().map_err(|_| invalid())?;
+            (port, port)
+        },
+    };
+    if range.0 > range.1 {
+        return Err(invalid());
+    }
+
+    Ok(PortSpec { negated, ranges: vec![range] })
+}
+
+// 角括弧の入れ子を考慮してカンマで分割する
+fn split_list(list: &str) -> Vec<&str> {
+    let mut elements = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    for (index, c) in list.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                elements.push(list[start..index].trim());
+                start = index + 1;
+            },
+            _ => {},
+        }
+    }
+    elements.push(list[start..].trim());
+    elements.into_iter().filter(|element| !element.is_empty()).collect()
+}
+
+// 引用符とエスケープを考慮してセミコロンで分割する
+fn split_options(options: &str) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut escaped = false;
+
+    for c in options.chars() {
+        if escaped {
+            current.push(c);
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' => {
+                current.push(c);
+                escaped = true;
+            },
+            '"' => {
+                current.push(c);
+                quoted = !quoted;
+            },
+            ';' if !quoted => {
+                let option = current.trim();
+                if !option.is_empty() {
+                    result.push(option.to_string());
+                }
+                current.clear();
+            },
+            _ => current.push(c),
+        }
+    }
+    let option = current.trim();
+    if !option.is_empty() {
+        result.push(option.to_string());
+    }
+
+    result
+}
+
+fn apply_option(rule: &mut Rule, name: &str, value: Option<&str>) -> Result<(), SignatureError> {
+    let invalid = || SignatureError::InvalidOption(format!("{}:{}", name, value.unwrap_or_default()));
+    let required = || value.filter(|value| !value.is_empty()).ok_or_else(invalid);
+
+    match name {
+        "msg" => rule.msg = unquote(required()?).ok_or_else(invalid)?,
+        "sid" => rule.sid = required()?.parse().map_err(|_| invalid())?,
+        "rev" => rule.rev = required()?.parse().map_err(|_| invalid())?,
+        "classtype" => rule.classtype = Some(required()?.to_string()),
+        "priority" => rule.priority = Some(required()?.parse().map_err(|_| invalid())?),
+        // 検知に影響しない情報
+        "reference" | "metadata" => {},
+        "flow" => {
+            for flag in required()?.split(',') {
+                let flag = match flag.trim() {
+                    "established" => FlowOption::Established,
+                    "not_established" => FlowOption::NotEstablished,
+                    "stateless" => FlowOption::Stateless,
+                    "to_server" | "from_client" => FlowOption::ToServer,
+                    "to_client" | "from_server" => FlowOption::ToClient,
+                    other => return Err(SignatureError::UnsupportedOption(format!("flow:{}", other))),
+                };
+                rule.flow.push(flag);
+            }
+        },
+        "content" => {
+            let value = required()?;
+            let (negated, value) = match value.strip_prefix('!') {
+                Some(value) => (true, value.trim_start()),
+                None => (false, value),
+            };
+            let pattern = parse_content(&unquote(value).ok_or_else(invalid)?).ok_or_else(invalid)?;
+            if pattern.is_empty() {
+                return Err(invalid());
+            }
+            rule.options.push(PayloadOption::Content(ContentMatch {
+                pattern,
+                negated,
+                ..Default::default()
+            }));
+        },
+        "http_method" | "http_uri" | "http_raw_uri" | "http_header" | "http_raw_header" | "http_host" | "http_user_agent" | "http_stat_code" => {
+            let Some(PayloadOption::Content(content)) = rule.options.last_mut() else {
+                return Err(SignatureError::InvalidOption(format!("{}の前にcontentが指定されていません", name)));
+            };
+            // 正規化は行わない為、raw指定も同じフィールドを照合する
+            content.buffer = match name {
+                "http_method" => ContentBuffer::HttpMethod,
+                "http_uri" | "http_raw_uri" => ContentBuffer::HttpUri,
+                "http_header" | "http_raw_header" => ContentBuffer::HttpHeader,
+                "http_host" => ContentBuffer::HttpHost,
+                "http_user_agent" => ContentBuffer::HttpUserAgent,
+                _ => ContentBuffer::HttpStatCode,
+            };
+        },
+        "nocase" | "offset" | "depth" | "distance" | "within" | "fast_pattern" => {
+            let Some(PayloadOption::Content(content)) = rule.options.last_mut() else {
+                return Err(SignatureError::InvalidOption(format!("{}の前にcontentが指定されていません", name)));
+            };
+            match name {
+                "nocase" => {
+                    content.nocase = true;
+                    content.pattern.make_ascii_lowercase();
+                },
+                "fast_pattern" => {
+                    if content.negated {
+                        return Err(SignatureError::InvalidOption("否定のcontentにfast_patternは指定できません".to_string()));
+                    }
+                    content.fast_pattern = true;
+                },
+                "offset" => content.offset = Some(required()?.parse().map_err(|_| invalid())?),
+                "depth" => content.depth = Some(required()?.parse().map_err(|_| invalid())?),
+                "distance" => content.distance = Some(required()?.parse().map_err(|_| invalid())?),
+                _ => content.within = Some(required()?.parse().map_err(|_| invalid())?),
+            }
+            if (content.offset.is_some() || content.depth.is_some()) && (content.distance.is_some() || content.within.is_some()) {
+                return Err(SignatureError::InvalidOption("offset/depthとdistance/withinは同時に指定できません".to_string()));
+            }
+        },
+        "pcre" => rule.options.push(PayloadOption::Pcre(parse_pcre(required()?)?)),
+        "byte_test" => rule.options.push(PayloadOption::ByteTest(parse_byte_test(required()?).ok_or_else(invalid)?)),
+        _ => return Err(SignatureError::UnsupportedOption(name.to_string())),
+    }
+
+    Ok(())
+}
+
+// 引用符で囲まれた文字列からエスケープを取り除く
+fn unquote(value: &str) -> Option<String> {
+    let inner = value.strip_prefix('"')?.strip_suffix('"')?;
+    let mut result = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            result.push(chars.next()?);
+        } else {
+            result.push(c);
+        }
+    }
+    Some(result)
+}
+
+// "GET |20 2F|" のような文字列とバイト列の混在表記を解析する
+fn parse_content(value: &str) -> Option<Vec<u8>> {
+    let mut pattern = Vec::new();
+    for (index, part) in value.split('|').enumerate() {
+        if index % 2 == 0 {
+            pattern.extend_from_slice(part.as_bytes());
+        } else {
+            let digits: Vec<u8> = part.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+            if !digits.len().is_multiple_of(2) || !digits.iter().all(u8::is_ascii_hexdigit) {
+                return None;
+            }
+            for pair in digits.chunks(2) {
+                pattern.push(u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?);
+            }
+        }
+    }
+    // 閉じられていない | は不正
+    if !value.matches('|').count().is_multiple_of(2) {
+        return None;
+    }
+    Some(pattern)
+}
+
+// "/pattern/flags" 形式の正規表現を解析する
+fn parse_pcre(value: &str) -> Result<PcreMatch, SignatureError> {
+    let invalid = || SignatureError::InvalidPcre(value.to_string());
+    let (negated, value) = match value.strip_prefix('!') {
+        Some(value) => (true, value.trim_start()),
+        None => (false, value),
+    };
+    let expression = value.strip_prefix('"').and_then(|value| value.strip_suffix('"')).ok_or_else(invalid)?;
+    let expression = expression.strip_prefix('/').ok_or_else(invalid)?;
+    let (pattern, flags) = expression.rsplit_once('/').ok_or_else(invalid)?;
+
+    let mut builder = RegexBuilder::new(pattern);
+    builder.unicode(false);
+    // 先頭への固定の判定に使う構文解析器 (フラグはRegexBuilderと揃える)
+    let mut syntax = ParserBuilder::new();
+    syntax.unicode(false).utf8(false);
+    let mut relative = false;
+    for flag in flags.chars() {
+        match flag {
+            'i' => {
+                builder.case_insensitive(true);
+                syntax.case_insensitive(true);
+            },
+            's' => {
+                builder.dot_matches_new_line(true);
+                syntax.dot_matches_new_line(true);
+            },
+            'm' => {
+                builder.multi_line(true);
+                syntax.multi_line(true);
+            },
+            'x' => {
+                builder.ignore_whitespace(true);
+                syntax.ignore_whitespace(true);
+            },
+            'R' => relative = true,
+            _ => return Err(SignatureError::UnsupportedOption(format!("pcreフラグ {}", flag))),
+        }
+    }
+    let regex = builder.build().map_err(|e| SignatureError::InvalidPcre(format!("{}: {}", value, e)))?;
+    // (?i)^ や \A のように先頭以外の記述があっても、全ての一致がデータの先頭から始まるかで判定する
+    // mフラグの ^ は行頭にも一致する為、固定しない
+    let anchored = syntax.build().parse(pattern).map_err(|e| SignatureError::InvalidPcre(format!("{}: {}", value, e)))?.properties().look_set_prefix().contains(Look::Start);
+
+    Ok(PcreMatch {
+        regex,
+        negated,
+        relative,
+        anchored,
+    })
+}
+
+// byte_test:<bytes>,[!]<operator>,<value>,<offset>[,relative][,big|little][,string][,hex|dec|oct]
+fn parse_byte_test(value: &str) -> Option<ByteTest> {
+    let fields: Vec<&str> = value.split(',').map(str::trim).collect();
+    let [bytes, operator, test_value, offset, modifiers @ ..] = fields.as_slice() else {
+        return None;
+    };
+
+    let (negated, operator) = match operator.strip_prefix('!') {
+        Some(operator) => (true, operator),
+        None => (false, *operator),
+    };
+    let operator = match operator {
+        "<" => ByteTestOperator::Less,
+        ">" => ByteTestOperator::Greater,
+        "<=" => ByteTestOperator::LessOrEqual,
+        ">=" => ByteTestOperator::GreaterOrEqual,
+        // "!" 単独は不一致を表す
+        "=" | "" => ByteTestOperator::Equal,
+        "&" => ByteTestOperator::And,
+        "^" => ByteTestOperator::Xor,
+        _ => return None,
+    };
+    let test_value = match test_value.strip_prefix("0x").or_else(|| test_value.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()?,
+        None => test_value.parse().ok()?,
+    };
+
+    let mut byte_test = ByteTest {
+        bytes: bytes.parse().ok()?,
+        operator,
+        negated,
+        value: test_value,
+        offset: offset.parse().ok()?,
+        relative: false,
+        little_endian: false,
+        string_base: None,
+    };
+    let mut string = false;
+    let mut base = 10;
+    for modifier in modifiers {
+        match *modifier {
+            "relative" => byte_test.relative = true,
+            "big" => byte_test.little_endian = false,
+            "little" => byte_test.little_endian = true,
+            "string" => string = true,
+            "dec" => base = 10,
+            "hex" => base = 16,
+            "oct" => base = 8,
+            _ => return None,
+        }
+    }
+    if string {
+        byte_test.string_base = Some(base);
+    }
+
+    let max_bytes = if string { 10 } else { 8 };
+    (1..=max_bytes).contains(&byte_test.bytes).then_some(byte_test)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Vec<Rule> {
+        parse_rules(source, "test", &mut Variables::default())
+    }
+
+    fn options(options: &str) -> Vec<PayloadOption> {
+        let source = format!("alert tcp any any -> any any (msg:\"test\"; {} sid:1;)", options);
+        parse(&source).pop().expect("ルールを解析できません").options
+    }
+
+    #[test]
+    fn parses_header_and_metadata() {
+        let rules = parse("alert tcp 10.0.0.0/8 any -> !192.168.1.1 [80,8000:8080] (msg:\"test \\\"rule\\\"\"; classtype:trojan; priority:1; sid:100; rev:2;)");
+        let [rule] = rules.as_slice() else {
+            panic!("ルールを解析できません");
+        };
+        assert_eq!(rule.sid, 100);
+        assert_eq!(rule.rev, 2);
+        assert_eq!(rule.msg, "test \"rule\"");
+        assert_eq!(rule.classtype.as_deref(), Some("trojan"));
+        assert_eq!(rule.priority, Some(1));
+        assert_eq!(rule.header.src_addr.networks[0].prefix, 8);
+        assert!(rule.header.dst_addr.negated);
+        assert_eq!(rule.header.dst_port.ranges, vec![(80, 80), (8000, 8080)]);
+    }
+
+    #[test]
+    fn parses_content_modifiers() {
+        let options = options("content:\"GET|20 2F|\"; nocase; offset:2; depth:10; content:!\"Evil\"; http_uri;");
+        let [PayloadOption::Content(first), PayloadOption::Content(second)] = options.as_slice() else {
+            panic!("contentを解析できません: {:?}", options);
+        };
+        assert_eq!(first.pattern, b"get /");
+        assert!(first.nocase);
+        assert_eq!((first.offset, first.depth), (Some(2), Some(10)));
+        assert_eq!(first.buffer, ContentBuffer::Payload);
+        assert_eq!(second.pattern, b"Evil");
+        assert!(second.negated);
+        assert_eq!(second.buffer, ContentBuffer::HttpUri);
+    }
+
+    #[test]
+    fn parses_pcre_flags_and_anchoring() {
+        let options = options("pcre:\"/^get/i\"; pcre:!\"/(?s)^a.b/R\"; pcre:\"/\\Ahead/\"; pcre:\"/^line/m\"; pcre:\"/a|^b/\";");
+        let pcres: Vec<&PcreMatch> = options
+            .iter()
+            .map(|option| match option {
+                PayloadOption::Pcre(pcre) => pcre,
+                _ => panic!("pcreを解析できません"),
+            })
+            .collect();
+        assert!(pcres[0].regex.is_match(b"GET /"));
+        assert!(pcres[0].anchored);
+        assert!(pcres[1].negated && pcres[1].relative && pcres[1].anchored);
+        assert!(pcres[2].anchored);
+        // 行頭やいずれかの選択肢のみの固定はデータの先頭に固定しない
+        assert!(!pcres[3].anchored);
+        assert!(!pcres[4].anchored);
+    }
+
+    #[test]
+    fn parses_byte_test() {
+        let options = options("byte_test:2,!&,0x8000,4,relative,little; byte_test:3,>,100,0,string,dec;");
+        let [PayloadOption::ByteTest(binary), PayloadOption::ByteTest(string)] = options.as_slice() else {
+            panic!("byte_testを解析できません: {:?}", options);
+        };
+        assert_eq!((binary.bytes, binary.operator, binary.value, binary.offset), (2, ByteTestOperator::And, 0x8000, 4));
+        assert!(binary.negated && binary.relative && binary.little_endian);
+        assert_eq!(binary.string_base, None);
+        assert_eq!((string.bytes, string.operator, string.value), (3, ByteTestOperator::Greater, 100));
+        assert_eq!(string.string_base, Some(10));
+
+        assert!(parse_byte_test("9,=,1,0").is_none());
+        assert!(parse_byte_test("2,~,1,0").is_none());
+    }
+
+    #[test]
+    fn resolves_variables() {
+        let rules = parse("ipvar HOME_NET 192.168.0.0/16\nportvar WEB [80,443]\nalert tcp any any -> $HOME_NET $WEB (sid:1;)\nalert tcp any any -> $UNDEFINED any (sid:2;)");
+        let [rule] = rules.as_slice() else {
+            panic!("ルールを解析できません");
+        };
+        assert_eq!(rule.header.dst_addr.networks[0].prefix, 16);
+        assert_eq!(rule.header.dst_port.ranges, vec![(80, 80), (443, 443)]);
+    }
+
+    #[test]
+    fn rejects_self_referencing_variables() {
+        let source = [
+            "ipvar LOOP [$LOOP]",
+            "portvar PORTS [80,$PORTS]",
+            "ipvar NEGATED !$NEGATED",
+            "alert tcp any any -> $LOOP any (sid:1;)",
+            "alert tcp any any -> any $PORTS (sid:2;)",
+            "alert tcp $NEGATED any -> any any (sid:3;)",
+            "alert tcp any any -> any any (sid:4;)",
+        ]
+        .join("\n");
+        let sids: Vec<u32> = parse(&source).iter().map(|rule| rule.sid).collect();
+        assert_eq!(sids, vec![4]);
+
+        let variables = Variables::default();
+        let error = parse_address("[[[[[[[[[[[[[[[[[[10.0.0.1]]]]]]]]]]]]]]]]]", &variables, 0).unwrap_err();
+        assert!(matches!(error, SignatureError::InvalidHeader(_)));
+    }
+
+    #[test]
+    fn skips_invalid_rules() {
+        let source = [
+            "alert tcp any any -> any any (msg:\"ok\"; content:\"a\"; sid:1;)",
+            // 16進表記に非ASCII文字を含む
+            "alert tcp any any -> any any (content:\"|aé1|\"; sid:2;)",
+            "alert tcp any any -> any any (content:\"|4|\"; sid:3;)",
+            "alert tcp any any -> any any (content:\"|41\"; sid:4;)",
+            "alert tcp any any -> any any (pcre:\"/(/\"; sid:5;)",
+            "alert tcp any any -> any any (nocase; sid:6;)",
+            "alert tcp any any -> any any (content:\"a\"; unknown_option; sid:7;)",
+            "alert tcp any any -> any any (msg:\"no sid\";)",
+            "alert foo any any -> any any (sid:8;)",
+            "alert tcp any any -> any any (content:\"a\"; offset:1; distance:1; sid:9;)",
+            "alert tcp any any -> any \\",
+            "  any (msg:\"continued\"; sid:10;)",
+        ]
+        .join("\n");
+        let sids: Vec<u32> = parse(&source).iter().map(|rule| rule.sid).collect();
+        assert_eq!(sids, vec![1, 10]);
+    }
+}
@@ -0,0 +1,122 @@
+use super::rule::Rule;
+use aho_corasick::AhoCorasick;
+use log::warn;
+
+/// 全シグネチャのcontentから構築した複数パターン照合による事前絞り込み
+/// (ペイロードを1回走査し、パターンが出現したルールのみを評価対象とする)
+#[derive(Debug, Default)]
+pub struct Prefilter {
+    automaton: Option<AhoCorasick>,
+    // パターン番号→ルール番号
+    pattern_rules: Vec<usize>,
+    // 照合に使えるcontentを持たない為、常に評価するルール
+    unconditional: Vec<usize>,
+    rule_count: usize,
+}
+
+impl Prefilter {
+    pub fn new(rules: &[Rule]) -> Self {
+        let mut patterns = Vec::new();
+        let mut pattern_rules = Vec::new();
+        let mut unconditional = Vec::new();
+        for (index, rule) in rules.iter().enumerate() {
+            match rule.fast_pattern() {
+                Some(content) => {
+                    patterns.push(content.pattern.as_slice());
+                    pattern_rules.push(index);
+                },
+                None => unconditional.push(index),
+            }
+        }
+
+        if patterns.is_empty() {
+            return Self {
+                automaton: None,
+                pattern_rules,
+                unconditional,
+                rule_count: rules.len(),
+            };
+        }
+
+        // nocaseのパターンも含めて1つのオートマトンで照合する (大文字小文字の区別は本評価で行う)
+        match AhoCorasick::builder().ascii_case_insensitive(true).build(&patterns) {
+            Ok(automaton) => Self {
+                automaton: Some(automaton),
+                pattern_rules,
+                unconditional,
+                rule_count: rules.len(),
+            },
+            Err(e) => {
+                warn!("シグネチャの事前照合を構築できない為、全てのルールを評価します: {}", e);
+                Self {
+                    automaton: None,
+                    pattern_rules: Vec::new(),
+                    unconditional: (0..rules.len()).collect(),
+                    rule_count: rules.len(),
+                }
+            },
+        }
+    }
+
+    pub fn pattern_count(&self) -> usize {
+        self.pattern_rules.len()
+    }
+
+    /// 本評価が必要なルールの番号を昇順で返す
+    pub fn candidates(&self, payload: &[u8]) -> Vec<usize> {
+        let Some(automaton) = &self.automaton else {
+            return self.unconditional.clone();
+        };
+
+        let mut selected = vec![false; self.rule_count];
+        for index in &self.unconditional {
+            selected[*index] = true;
+        }
+        for found in automaton.find_overlapping_iter(payload) {
+            selected[self.pattern_rules[found.pattern().as_usize()]] = true;
+        }
+
+        selected.iter().enumerate().filter(|(_, selected)| **selected).map(|(index, _)| index).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::analysis::idps::signature::parser::{parse_rules, Variables};
+
+    fn prefilter(rules: &[&str]) -> Prefilter {
+        let source: Vec<String> = rules.iter().enumerate().map(|(index, options)| format!("alert tcp any any -> any any ({} sid:{};)", options, index + 1)).collect();
+        let rules = parse_rules(&source.join("\n"), "test", &mut Variables::default());
+        Prefilter::new(&rules)
+    }
+
+    #[test]
+    fn selects_rules_whose_pattern_appears() {
+        let prefilter = prefilter(&[
+            "content:\"evil\";",
+            "content:\"ab\"; content:\"longer\";",
+            "content:\"x\"; content:\"FAST\"; fast_pattern; content:\"longest\";",
+            "pcre:\"/a+/\";",
+        ]);
+        assert_eq!(prefilter.pattern_count(), 3);
+
+        // 照合に使えるcontentを持たないルールは常に選択する
+        assert_eq!(prefilter.candidates(b"nothing"), vec![3]);
+        assert_eq!(prefilter.candidates(b"EVIL and longer"), vec![0, 1, 3]);
+        // fast_patternの指定は最長のcontentより優先する
+        assert_eq!(prefilter.candidates(b"longest"), vec![3]);
+        assert_eq!(prefilter.candidates(b"fast"), vec![2, 3]);
+    }
+
+    #[test]
+    fn selects_rule_with_http_buffer_fast_pattern() {
+        let prefilter = prefilter(&[
+            "content:\"GET\"; http_method; content:\"/admin/login\"; http_uri;",
+            "content:\"curl/\"; http_user_agent; nocase;",
+        ]);
+        let request = b"GET /admin/login HTTP/1.1\r\nHost: example.com\r\nUser-Agent: Curl/8.0\r\n\r\n";
+        assert_eq!(prefilter.candidates(request), vec![0, 1]);
+        assert!(prefilter.candidates(b"GET / HTTP/1.1\r\n\r\n").is_empty());
+    }
+}
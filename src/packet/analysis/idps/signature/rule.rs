@@ -0,0 +1,488 @@
+use crate::packet::analysis::firewall::ConnState;
+use crate::packet::analysis::http::HttpMessage;
+use crate::packet::analysis::idps::InspectContext;
+use crate::packet::analysis::stream::StreamChunk;
+use crate::packet::analysis::TransportHeader;
+use regex::bytes::Regex;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleAction {
+    // アラートのみ出力する
+    Alert,
+    // アラートを出力し、フレームを破棄する
+    Drop,
+}
+
+impl fmt::Display for RuleAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuleAction::Alert => write!(f, "alert"),
+            RuleAction::Drop => write!(f, "drop"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleProtocol {
+    Ip,
+    Tcp,
+    Udp,
+    Icmp,
+}
+
+impl RuleProtocol {
+    fn matches(&self, transport: &TransportHeader) -> bool {
+        match self {
+            RuleProtocol::Ip => true,
+            RuleProtocol::Tcp => matches!(transport, TransportHeader::Tcp(_)),
+            RuleProtocol::Udp => matches!(transport, TransportHeader::Udp(_)),
+            RuleProtocol::Icmp => matches!(transport, TransportHeader::Icmp(_) | TransportHeader::IcmpV6(_)),
+        }
+    }
+}
+
+/// CIDR表記のネットワーク
+#[derive(Debug, Clone, Copy)]
+pub struct IpNetwork {
+    pub address: IpAddr,
+    pub prefix: u8,
+}
+
+impl IpNetwork {
+    fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.address, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(*ip) & mask
+            },
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(*ip) & mask
+            },
+            _ => false,
+        }
+    }
+}
+
+/// アドレスの指定 (networksが空の場合はany)
+#[derive(Debug, Clone, Default)]
+pub struct AddressSpec {
+    pub negated: bool,
+    pub networks: Vec<IpNetwork>,
+}
+
+impl AddressSpec {
+    fn matches(&self, ip: &IpAddr) -> bool {
+        if self.networks.is_empty() {
+            return !self.negated;
+        }
+        self.networks.iter().any(|network| network.contains(ip)) != self.negated
+    }
+}
+
+/// ポートの指定 (rangesが空の場合はany)
+#[derive(Debug, Clone, Default)]
+pub struct PortSpec {
+    pub negated: bool,
+    pub ranges: Vec<(u16, u16)>,
+}
+
+impl PortSpec {
+    fn matches(&self, port: u16) -> bool {
+        if self.ranges.is_empty() {
+            return !self.negated;
+        }
+        self.ranges.iter().any(|(low, high)| (*low..=*high).contains(&port)) != self.negated
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RuleHeader {
+    pub action: RuleAction,
+    pub protocol: RuleProtocol,
+    pub src_addr: AddressSpec,
+    pub src_port: PortSpec,
+    // <> の場合は双方向
+    pub bidirectional: bool,
+    pub dst_addr: AddressSpec,
+    pub dst_port: PortSpec,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowOption {
+    Established,
+    NotEstablished,
+    Stateless,
+    ToServer,
+    ToClient,
+}
+
+/// contentを照合する対象 (http_*の修飾子で指定する)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ContentBuffer {
+    #[default]
+    Payload,
+    HttpMethod,
+    HttpUri,
+    HttpHeader,
+    HttpHost,
+    HttpUserAgent,
+    HttpStatCode,
+}
+
+impl ContentBuffer {
+    fn select<'a>(&self, message: &'a HttpMessage) -> Option<Cow<'a, [u8]>> {
+        match self {
+            ContentBuffer::Payload => None,
+            ContentBuffer::HttpMethod => message.method.as_deref().map(|method| Cow::Borrowed(method.as_bytes())),
+            ContentBuffer::HttpUri => message.uri.as_deref().map(|uri| Cow::Borrowed(uri.as_bytes())),
+            ContentBuffer::HttpHeader => Some(Cow::Borrowed(message.headers.as_slice())),
+            ContentBuffer::HttpHost => message.host.as_deref().map(|host| Cow::Borrowed(host.as_bytes())),
+            ContentBuffer::HttpUserAgent => message.user_agent.as_deref().map(|user_agent| Cow::Borrowed(user_agent.as_bytes())),
+            ContentBuffer::HttpStatCode => message.status_code.map(|status_code| Cow::Owned(status_code.to_string().into_bytes())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ContentMatch {
+    pub pattern: Vec<u8>,
+    pub buffer: ContentBuffer,
+    pub negated: bool,
+    pub nocase: bool,
+    pub offset: Option<usize>,
+    pub depth: Option<usize>,
+    pub distance: Option<i64>,
+    pub within: Option<usize>,
+    // 事前照合に使うパターンとして指定されているか
+    pub fast_pattern: bool,
+}
+
+impl ContentMatch {
+    fn is_relative(&self) -> bool {
+        self.distance.is_some() || self.within.is_some()
+    }
+
+    // 一致した場合は一致箇所の終端を返す
+    // payloadはストリーム内の位置baseから始まるデータで、offset/depthはストリーム(パケット)の先頭からの位置とする
+    // min_endを指定した場合は、終端がmin_endより後になる一致箇所のみを探す
+    fn find(&self, payload: &[u8], cursor: usize, base: u64, min_end: Option<usize>) -> Option<usize> {
+        let (start, end) = if self.is_relative() {
+            let start = (cursor as i64 + self.distance.unwrap_or(0)).max(0) as usize;
+            (start, self.within.map_or(payload.len(), |within| start.saturating_add(within)))
+        } else {
+            let start = self.offset.unwrap_or(0) as u64;
+            let end = self.depth.map_or(u64::MAX, |depth| start.saturating_add(depth as u64));
+            // 範囲が全て検査済みのデータより前にある
+            if end <= base {
+                return None;
+            }
+            (start.saturating_sub(base) as usize, usize::try_from(end - base).unwrap_or(usize::MAX))
+        };
+        let start = match min_end {
+            Some(min_end) => start.max((min_end + 1).saturating_sub(self.pattern.len())),
+            None => start,
+        };
+        let end = end.min(payload.len());
+        if start >= end || end - start < self.pattern.len() {
+            return None;
+        }
+
+        let window = &payload[start..end];
+        let position = if self.nocase {
+            window.windows(self.pattern.len()).position(|candidate| candidate.eq_ignore_ascii_case(&self.pattern))
+        } else {
+            window.windows(self.pattern.len()).position(|candidate| candidate == self.pattern.as_slice())
+        }?;
+
+        Some(start + position + self.pattern.len())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PcreMatch {
+    pub regex: Regex,
+    pub negated: bool,
+    pub relative: bool,
+    // 全ての一致がデータの先頭から始まるか (^, \A, (?i)^ など)
+    pub anchored: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteTestOperator {
+    Less,
+    Greater,
+    LessOrEqual,
+    GreaterOrEqual,
+    Equal,
+    And,
+    Xor,
+}
+
+#[derive(Debug, Clone)]
+pub struct ByteTest {
+    pub bytes: usize,
+    pub operator: ByteTestOperator,
+    pub negated: bool,
+    pub value: u64,
+    pub offset: i64,
+    pub relative: bool,
+    pub little_endian: bool,
+    // 数値が文字列で格納されている場合の基数
+    pub string_base: Option<u32>,
+}
+
+impl ByteTest {
+    // 一致した場合は検査したバイト列の終端を返す (絶対指定のoffsetはストリーム内の位置とする)
+    fn matches(&self, payload: &[u8], cursor: usize, base: u64) -> Option<usize> {
+        let origin = if self.relative { cursor as i64 } else { -(base as i64) };
+        let start = usize::try_from(origin + self.offset).ok()?;
+        let bytes = payload.get(start..start + self.bytes)?;
+
+        let value = match self.string_base {
+            Some(radix) => std::str::from_utf8(bytes).ok().and_then(|s| u64::from_str_radix(s.trim(), radix).ok())?,
+            None if self.little_endian => bytes.iter().rev().fold(0u64, |acc, b| (acc << 8) | *b as u64),
+            None => bytes.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64),
+        };
+
+        let result = match self.operator {
+            ByteTestOperator::Less => value < self.value,
+            ByteTestOperator::Greater => value > self.value,
+            ByteTestOperator::LessOrEqual => value <= self.value,
+            ByteTestOperator::GreaterOrEqual => value >= self.value,
+            ByteTestOperator::Equal => value == self.value,
+            ByteTestOperator::And => value & self.value != 0,
+            ByteTestOperator::Xor => value ^ self.value != 0,
+        };
+        (result != self.negated).then_some(start + self.bytes)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum PayloadOption {
+    Content(ContentMatch),
+    Pcre(PcreMatch),
+    ByteTest(ByteTest),
+}
+
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub header: RuleHeader,
+    pub sid: u32,
+    pub rev: u32,
+    pub msg: String,
+    pub classtype: Option<String>,
+    pub priority: Option<u32>,
+    pub flow: Vec<FlowOption>,
+    pub options: Vec<PayloadOption>,
+}
+
+impl Rule {
+    /// 事前照合に使うcontent (指定がなければ否定でない最長のもの)
+    pub fn fast_pattern(&self) -> Option<&ContentMatch> {
+        let contents = self.options.iter().filter_map(|option| match option {
+            PayloadOption::Content(content) if !content.negated => Some(content),
+            _ => None,
+        });
+        let mut longest: Option<&ContentMatch> = None;
+        for content in contents {
+            if content.fast_pattern {
+                return Some(content);
+            }
+            if longest.is_none_or(|longest| content.pattern.len() > longest.pattern.len()) {
+                longest = Some(content);
+            }
+        }
+        longest
+    }
+
+    pub fn matches(&self, context: &InspectContext) -> bool {
+        let packet = context.packet;
+        if !self.header.protocol.matches(&packet.transport) {
+            return false;
+        }
+
+        let (src_port, dst_port) = (packet.transport.src_port(), packet.transport.dst_port());
+        let forward = self.header.src_addr.matches(&packet.src_ip)
+            && self.header.src_port.matches(src_port)
+            && self.header.dst_addr.matches(&packet.dst_ip)
+            && self.header.dst_port.matches(dst_port);
+        let backward = self.header.bidirectional
+            && self.header.src_addr.matches(&packet.dst_ip)
+            && self.header.src_port.matches(dst_port)
+            && self.header.dst_addr.matches(&packet.src_ip)
+            && self.header.dst_port.matches(src_port);
+        if !forward && !backward {
+            return false;
+        }
+
+        if !self.flow.iter().all(|option| Self::matches_flow(*option, context)) {
+            return false;
+        }
+
+        if self.options.is_empty() {
+            return true;
+        }
+        let matches_payload = |http: Option<&HttpMessage>| match context.stream {
+            Some(stream) => self.matches_stream(stream, http),
+            None => self.matches_payload(context.payload, http, 0, None).is_some(),
+        };
+        // HTTPの修飾子を使うルールはメッセージ毎に評価する
+        if self.uses_http() {
+            context.http.iter().any(|message| matches_payload(Some(message)))
+        } else {
+            matches_payload(None)
+        }
+    }
+
+    // ストリームでは検査済みのデータのみで成立する一致を除外する
+    // 最初の一致箇所で成立しない場合は、肯定のcontentを1つずつ新たなデータと重なる箇所から探し直す
+    fn matches_stream(&self, stream: &StreamChunk, http: Option<&HttpMessage>) -> bool {
+        let is_new = |end: usize| end > stream.new_offset;
+        if self.matches_payload(&stream.data, http, stream.start, None).is_some_and(is_new) {
+            return true;
+        }
+        if stream.new_offset == 0 {
+            return false;
+        }
+        self.options
+            .iter()
+            .enumerate()
+            .filter(|(_, option)| matches!(option, PayloadOption::Content(content) if !content.negated && content.buffer == ContentBuffer::Payload))
+            .any(|(index, _)| self.matches_payload(&stream.data, http, stream.start, Some((index, stream.new_offset))).is_some_and(is_new))
+    }
+
+    fn uses_http(&self) -> bool {
+        self.options.iter().any(|option| matches!(option, PayloadOption::Content(content) if content.buffer != ContentBuffer::Payload))
+    }
+
+    fn matches_flow(option: FlowOption, context: &InspectContext) -> bool {
+        match option {
+            FlowOption::Established => context.conn_state == ConnState::Established,
+            FlowOption::NotEstablished => context.conn_state != ConnState::Established,
+            FlowOption::Stateless => true,
+            FlowOption::ToServer => context.from_client == Some(true),
+            FlowOption::ToClient => context.from_client == Some(false),
+        }
+    }
+
+    // オプションを順に評価し、相対指定は直前の一致箇所を起点とする
+    // payloadはストリーム内の位置baseから始まり、constrainedで指定したオプションは終端が指定した位置より後になる箇所のみを探す
+    // 一致した場合は一致箇所の終端 (肯定の一致がなければペイロード長) を返す
+    fn matches_payload(&self, payload: &[u8], http: Option<&HttpMessage>, base: u64, constrained: Option<(usize, usize)>) -> Option<usize> {
+        let mut cursor = 0;
+        let mut end = None;
+        // HTTPのフィールドでの相対指定はフィールド毎の直前の一致箇所を起点とする
+        let mut http_cursors: HashMap<ContentBuffer, usize> = HashMap::new();
+
+        for (index, option) in self.options.iter().enumerate() {
+            match option {
+                PayloadOption::Content(content) if content.buffer != ContentBuffer::Payload => {
+                    let buffer = http.and_then(|message| content.buffer.select(message));
+                    let http_cursor = http_cursors.entry(content.buffer).or_default();
+                    match buffer.and_then(|buffer| content.find(&buffer, *http_cursor, 0, None)) {
+                        Some(_) if content.negated => return None,
+                        Some(found) => *http_cursor = found,
+                        None if content.negated => {},
+                        None => return None,
+                    }
+                    continue;
+                },
+                PayloadOption::Content(content) => match content.find(payload, cursor, base, constrained.filter(|(target, _)| *target == index).map(|(_, min_end)| min_end)) {
+                    Some(_) if content.negated => return None,
+                    Some(found) => cursor = found,
+                    None if content.negated => continue,
+                    None => return None,
+                },
+                PayloadOption::Pcre(pcre) => {
+                    let start = if pcre.relative { cursor.min(payload.len()) } else { 0 };
+                    // 先頭に固定したパターンは、ストリームの先頭が検査済みで破棄されている場合は一致しない
+                    let anchored = !pcre.relative && base > 0 && pcre.anchored;
+                    match pcre.regex.find(&payload[start..]).filter(|_| !anchored) {
+                        Some(_) if pcre.negated => return None,
+                        Some(found) => cursor = start + found.end(),
+                        None if pcre.negated => continue,
+                        None => return None,
+                    }
+                },
+                PayloadOption::ByteTest(byte_test) => {
+                    let found = byte_test.matches(payload, cursor, base)?;
+                    end = end.max(Some(found));
+                    continue;
+                },
+            }
+            end = end.max(Some(cursor));
+        }
+
+        Some(end.unwrap_or(payload.len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::analysis::idps::signature::parser::{parse_rules, Variables};
+
+    fn rule(options: &str) -> Rule {
+        let source = format!("alert tcp any any -> any any (msg:\"test\"; {} sid:1;)", options);
+        parse_rules(&source, "test", &mut Variables::default()).pop().expect("ルールを解析できません")
+    }
+
+    fn chunk(data: &[u8], start: u64, new_offset: usize) -> StreamChunk {
+        StreamChunk {
+            data: data.to_vec(),
+            new_offset,
+            start,
+            from_client: true,
+            hello_parsed: false,
+            sni: None,
+        }
+    }
+
+    #[test]
+    fn anchored_content_uses_stream_offsets() {
+        let rule = rule("content:\"GET\"; offset:0; depth:3;");
+        assert!(rule.matches_stream(&chunk(b"GET / HTTP/1.1", 0, 0), None));
+        // 窓がずれた後、窓の先頭がたまたま一致してもストリームの先頭ではない
+        assert!(!rule.matches_stream(&chunk(b"GET / HTTP/1.1", 500, 2), None));
+
+        // ストリームの10バイト目からの範囲は、窓の先頭からの位置に変換して照合する
+        let rule = self::rule("content:\"PATT\"; offset:10; depth:4;");
+        assert!(rule.matches_stream(&chunk(b"xxPATTyyy", 8, 3), None));
+        assert!(!rule.matches_stream(&chunk(b"PATTyyy", 0, 0), None));
+    }
+
+    #[test]
+    fn absolute_byte_test_uses_stream_offsets() {
+        let rule = rule("byte_test:1,=,0x41,10;");
+        assert!(rule.matches_stream(&chunk(b"xxAyy", 8, 2), None));
+        assert!(!rule.matches_stream(&chunk(b"xxxxxxxxxxAyy", 8, 2), None));
+    }
+
+    #[test]
+    fn finds_pattern_repeated_in_new_data() {
+        let rule = rule("content:\"evil\";");
+        assert!(rule.matches_stream(&chunk(b"evil..evil", 0, 6), None));
+        // 検査済みのデータにのみ含まれる場合は再度検知しない
+        assert!(!rule.matches_stream(&chunk(b"evil......", 0, 6), None));
+        // 新たなデータと重なる一致
+        assert!(rule.matches_stream(&chunk(b"..evil", 0, 4), None));
+
+        let rule = self::rule("content:\"GET\"; content:\"evil\"; distance:0;");
+        assert!(rule.matches_stream(&chunk(b"GET evil GET evil", 0, 9), None));
+        assert!(!rule.matches_stream(&chunk(b"GET evil ", 0, 9), None));
+    }
+
+    #[test]
+    fn anchored_pcre_only_matches_stream_start() {
+        for options in ["pcre:\"/^GET/\";", "pcre:\"/(?i)^get/\";", "pcre:\"/\\AGET/\";"] {
+            let rule = rule(options);
+            assert!(rule.matches_stream(&chunk(b"GET / HTTP/1.1", 0, 0), None), "{}", options);
+            assert!(!rule.matches_stream(&chunk(b"GET / HTTP/1.1", 500, 2), None), "{}", options);
+        }
+    }
+}
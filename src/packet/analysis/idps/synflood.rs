@@ -0,0 +1,233 @@
+use super::{Alert, AlertCategory, AlertSeverity, TemporaryBlock};
+use crate::config::SynFloodConfig;
+use crate::idps_log;
+use crate::packet::analysis::firewall::{Filter, FirewallPacket};
+use crate::packet::analysis::TransportHeader;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// TCPフラグ
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+const TCP_ACK: u8 = 0x10;
+
+// 宛先毎に保持する未完了のハンドシェイク数と、追跡する宛先数の上限
+const MAX_PENDING_PER_DESTINATION: usize = 65536;
+const MAX_TRACKED_DESTINATIONS: usize = 4096;
+const GC_INTERVAL: Duration = Duration::from_secs(10);
+// 送信元を個別に遮断する最大数 (これを超える場合は送信元の偽装または分散型の攻撃とみなし、アラートのみとする)
+const MAX_BLOCKED_SOURCES: usize = 16;
+
+type Endpoint = (IpAddr, u16);
+
+#[derive(Debug, Default)]
+struct DestinationTracker {
+    // 送信元毎のSYNを受信した時刻
+    pending: HashMap<Endpoint, Instant>,
+    last_alert: Option<Instant>,
+}
+
+impl DestinationTracker {
+    fn expire(&mut self, now: Instant, window: Duration) {
+        self.pending.retain(|_, seen_at| now.duration_since(*seen_at) <= window);
+    }
+}
+
+#[derive(Debug)]
+struct HalfOpenTable {
+    destinations: HashMap<Endpoint, DestinationTracker>,
+    last_gc: Instant,
+}
+
+/// 宛先毎に3ウェイハンドシェイクが完了しないSYNを数えてSYNフラッドを検知する
+#[derive(Debug)]
+pub struct SynFloodDetector {
+    window: Duration,
+    half_open_threshold: usize,
+    block_duration: Option<Duration>,
+    table: Mutex<HalfOpenTable>,
+}
+
+impl SynFloodDetector {
+    pub fn new(config: &SynFloodConfig) -> Self {
+        Self {
+            window: Duration::from_secs(config.window.max(1)),
+            half_open_threshold: config.half_open_threshold.max(1),
+            block_duration: config.block.then(|| Duration::from_secs(config.block_duration.max(1))),
+            table: Mutex::new(HalfOpenTable {
+                destinations: HashMap::new(),
+                last_gc: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn inspect(&self, packet: &FirewallPacket, alerts: &mut Vec<Alert>) -> Vec<TemporaryBlock> {
+        let TransportHeader::Tcp(tcp) = &packet.transport else {
+            return Vec::new();
+        };
+        let Ok(mut table) = self.table.lock() else {
+            return Vec::new();
+        };
+
+        let now = Instant::now();
+        if now.duration_since(table.last_gc) >= GC_INTERVAL {
+            let window = self.window;
+            table.destinations.retain(|_, tracker| {
+                tracker.expire(now, window);
+                !tracker.pending.is_empty()
+            });
+            table.last_gc = now;
+        }
+
+        let client = (packet.src_ip, tcp.src_port);
+        let server = (packet.dst_ip, tcp.dst_port);
+
+        match tcp.flags & (TCP_SYN | TCP_RST | TCP_ACK) {
+            // 接続要求
+            TCP_SYN => {},
+            // クライアントからのACKまたはRSTでハンドシェイクは完了(中止)する
+            flags if flags & TCP_SYN == 0 => {
+                if let Some(tracker) = table.destinations.get_mut(&server) {
+                    tracker.pending.remove(&client);
+                }
+                // サーバからのRSTは接続の拒否
+                if let Some(tracker) = table.destinations.get_mut(&client) {
+                    tracker.pending.remove(&server);
+                }
+                return Vec::new();
+            },
+            _ => return Vec::new(),
+        }
+
+        if !table.destinations.contains_key(&server) && table.destinations.len() >= MAX_TRACKED_DESTINATIONS {
+            return Vec::new();
+        }
+
+        let tracker = table.destinations.entry(server).or_default();
+        tracker.expire(now, self.window);
+        if tracker.pending.len() < MAX_PENDING_PER_DESTINATION {
+            tracker.pending.insert(client, now);
+        }
+
+        let half_open = tracker.pending.len();
+        if half_open < self.half_open_threshold || tracker.last_alert.is_some_and(|last| now.duration_since(last) < self.window) {
+            return Vec::new();
+        }
+        tracker.last_alert = Some(now);
+
+        // 送信元IP毎の未完了数
+        let mut sources: HashMap<IpAddr, usize> = HashMap::new();
+        for (src_ip, _) in tracker.pending.keys() {
+            *sources.entry(*src_ip).or_default() += 1;
+        }
+        let mut top_sources: Vec<(IpAddr, usize)> = sources.into_iter().collect();
+        top_sources.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+
+        let message = format!(
+            "SYNフラッドを検知しました: 宛先={}:{}, 未完了のハンドシェイク数={} (閾値={}), 送信元数={}, 主な送信元=[{}]",
+            server.0,
+            server.1,
+            half_open,
+            self.half_open_threshold,
+            top_sources.len(),
+            top_sources.iter().take(5).map(|(ip, count)| format!("{}({})", ip, count)).collect::<Vec<_>>().join(", ")
+        );
+        idps_log!("{}", message);
+        alerts.push(Alert::new(packet, AlertSeverity::High, AlertCategory::SynFlood, message));
+
+        let Some(duration) = self.block_duration else {
+            return Vec::new();
+        };
+        let reason = format!("SYNフラッド (宛先={}:{})", server.0, server.1);
+
+        // 宛先を遮断すると攻撃対象のサービスを停止させてしまう為、遮断するのは送信元のみとする
+        if top_sources.len() > MAX_BLOCKED_SOURCES {
+            idps_log!(
+                "送信元が多すぎる為、SYNフラッドの送信元を遮断しません: 宛先={}:{}, 送信元数={} (上限={})",
+                server.0,
+                server.1,
+                top_sources.len(),
+                MAX_BLOCKED_SOURCES
+            );
+            return Vec::new();
+        }
+        top_sources
+            .into_iter()
+            .map(|(src_ip, _)| TemporaryBlock {
+                filter: Filter::SrcIpAddress(src_ip),
+                duration,
+                reason: reason.clone(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::analysis::transport::TcpHeader;
+    use crate::packet::types::{EtherType, IpProtocol};
+    use crate::packet::MacAddr;
+
+    fn syn(src_ip: &str, src_port: u16) -> FirewallPacket {
+        FirewallPacket::from_packet(
+            MacAddr([1; 6]),
+            MacAddr([2; 6]),
+            EtherType::IP_V4,
+            src_ip.parse().unwrap(),
+            "10.0.0.100".parse().unwrap(),
+            IpProtocol::TCP,
+            TransportHeader::Tcp(Box::new(TcpHeader {
+                src_port,
+                dst_port: 80,
+                sequence_number: 0,
+                acknowledgment_number: 0,
+                header_length: 20,
+                flags: TCP_SYN,
+                window_size: 1024,
+                checksum: 0,
+                urgent_pointer: 0,
+                options: Vec::new(),
+            })),
+            None,
+            60,
+            None,
+        )
+    }
+
+    fn detector() -> SynFloodDetector {
+        SynFloodDetector::new(&SynFloodConfig {
+            window: 10,
+            half_open_threshold: 40,
+            block: true,
+            block_duration: 60,
+        })
+    }
+
+    #[test]
+    fn blocks_sources_of_flood() {
+        let detector = detector();
+        let mut alerts = Vec::new();
+        let mut blocks = Vec::new();
+        for port in 0..40 {
+            blocks.extend(detector.inspect(&syn(&format!("10.0.1.{}", port % 2 + 1), 10000 + port), &mut alerts));
+        }
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(blocks.len(), 2);
+        assert!(blocks.iter().all(|block| matches!(block.filter, Filter::SrcIpAddress(_))));
+    }
+
+    #[test]
+    fn never_blocks_destination_of_distributed_flood() {
+        let detector = detector();
+        let mut alerts = Vec::new();
+        let mut blocks = Vec::new();
+        for port in 0..40 {
+            blocks.extend(detector.inspect(&syn(&format!("10.0.1.{}", port + 1), 10000 + port), &mut alerts));
+        }
+        assert_eq!(alerts.len(), 1);
+        assert!(blocks.is_empty());
+    }
+}
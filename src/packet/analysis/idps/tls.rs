@@ -0,0 +1,93 @@
+use super::{Alert, AlertCategory, AlertSeverity, EveEvent, InspectContext};
+use crate::config::TlsConfig;
+use crate::idps_log;
+use crate::logger::eve_logger;
+use crate::packet::analysis::tls::TlsHelloKind;
+use log::{error, info, trace, warn};
+use std::collections::HashMap;
+use std::fs;
+
+/// TLSのハンドシェイクのフィンガープリントを既知の不正なものと照合する
+#[derive(Debug, Default)]
+pub struct TlsDetector {
+    // JA3/JA3S/JA4のフィンガープリント→説明
+    fingerprints: HashMap<String, String>,
+}
+
+impl TlsDetector {
+    pub fn new(config: &TlsConfig) -> Self {
+        let Some(path) = &config.fingerprints_path else {
+            return Self::default();
+        };
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(e) => {
+                error!("フィンガープリントのファイルを読み込めません: {}: {}", path, e);
+                return Self::default();
+            },
+        };
+
+        // 1行に「フィンガープリント 説明」、#以降はコメント
+        let mut fingerprints = HashMap::new();
+        for (number, line) in source.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (fingerprint, description) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            if fingerprints.insert(fingerprint.to_ascii_lowercase(), description.trim().to_string()).is_some() {
+                warn!("{}:{}: フィンガープリント {} が重複しています", path, number + 1, fingerprint);
+            }
+        }
+        info!("TLSのフィンガープリントを{}件読み込みました: {}", fingerprints.len(), path);
+        Self { fingerprints }
+    }
+
+    pub fn inspect(&self, context: &InspectContext, alerts: &mut Vec<Alert>) {
+        let Some(hello) = context.tls else {
+            return;
+        };
+        let packet = context.packet;
+        let ja3 = hello.ja3();
+        let ja4 = hello.ja4();
+        let label = match hello.kind {
+            TlsHelloKind::Client => "ClientHello",
+            TlsHelloKind::Server => "ServerHello",
+        };
+        trace!(
+            "TLS {}: {}:{} -> {}:{}, バージョン=0x{:04x}, SNI={}, ALPN={}, JA3={}, JA4={}",
+            label,
+            packet.src_ip,
+            packet.transport.src_port(),
+            packet.dst_ip,
+            packet.transport.dst_port(),
+            hello.version(),
+            hello.sni.as_deref().unwrap_or("-"),
+            hello.alpn.join(","),
+            ja3,
+            ja4.as_deref().unwrap_or("-")
+        );
+        if eve_logger::is_enabled() {
+            eve_logger::write_event(&EveEvent::tls(packet, hello, &ja3, ja4.as_deref()));
+        }
+
+        for fingerprint in std::iter::once(&ja3).chain(ja4.as_ref()) {
+            if let Some(description) = self.fingerprints.get(fingerprint) {
+                let message = format!(
+                    "既知の不正なTLSフィンガープリントを検知しました: {} ({}) {}:{} -> {}:{}, SNI={}, JA3={}, JA4={}",
+                    if description.is_empty() { "-" } else { description },
+                    label,
+                    packet.src_ip,
+                    packet.transport.src_port(),
+                    packet.dst_ip,
+                    packet.transport.dst_port(),
+                    hello.sni.as_deref().unwrap_or("-"),
+                    ja3,
+                    ja4.as_deref().unwrap_or("-")
+                );
+                idps_log!("{}", message);
+                alerts.push(Alert::new(packet, AlertSeverity::High, AlertCategory::TlsFingerprint, message));
+            }
+        }
+    }
+}
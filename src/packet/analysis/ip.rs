@@ -1,10 +1,15 @@
 use crate::idps_log;
-use crate::packet::analysis::transport::parse_transport_header;
+use crate::packet::analysis::arp::parse_arp_packet;
+use crate::packet::analysis::checksum::{self, ChecksumFailure, ChecksumLayer};
+use crate::packet::analysis::transport::{parse_transport_header, TransportHeader};
 use crate::packet::analysis::AnalyzeResult;
 use crate::packet::types::{EtherType, IpProtocol};
-use log::info;
+use log::{info, trace};
 use rtnetlink::IpVersion;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::ops::Range;
+
+const ETHERNET_HEADER_LENGTH: usize = 14;
 
 #[derive(Debug)]
 pub struct IpHeader {
@@ -13,18 +18,36 @@ pub struct IpHeader {
     pub src_ip: IpAddr,
     pub dst_ip: IpAddr,
     pub header_length: usize,
+    // IPヘッダを含むパケット長 (Ethernetのパディングを除く)
+    pub total_length: usize,
+    pub fragmented: bool,
+    // フラグメントのオフセット(バイト) 先頭以外のフラグメントにはL4ヘッダが含まれない
+    pub fragment_offset: usize,
+    pub checksum_failure: Option<ChecksumFailure>,
 }
 
-pub async fn parse_ip_packet(ethernet_frame: &[u8], ether_type: EtherType) -> Result<(IpAddr, IpAddr, IpProtocol, u16, u16, u8), AnalyzeResult> {
+/// IPパケット(およびARP)の解析結果
+#[derive(Debug)]
+pub struct IpPacket {
+    pub src_ip: IpAddr,
+    pub dst_ip: IpAddr,
+    pub ip_protocol: IpProtocol,
+    pub transport: TransportHeader,
+    pub checksum_failures: Vec<ChecksumFailure>,
+    // L4ペイロードのEthernetフレーム内での範囲
+    pub payload: Range<usize>,
+}
+
+pub async fn parse_ip_packet(ethernet_frame: &[u8], ether_type: EtherType) -> Result<IpPacket, AnalyzeResult> {
     let src_ip;
     let dst_ip;
-    let mut src_port = 0;
-    let mut dst_port = 0;
-    let mut flags = 0;
+    let mut transport = TransportHeader::Unknown;
+    let mut checksum_failures = Vec::new();
+    let mut payload = 0..0;
     let ip_protocol;
 
     // Ethernetヘッダー以降のデータを取得
-    let ip_data = &ethernet_frame[14..];
+    let ip_data = &ethernet_frame[ETHERNET_HEADER_LENGTH..];
 
     match ether_type {
         EtherType::IP_V4 | EtherType::IP_V6 => match parse_ip_header(ip_data).await {
@@ -33,14 +56,22 @@ pub async fn parse_ip_packet(ethernet_frame: &[u8], ether_type: EtherType) -> Re
                 dst_ip = ip_header.dst_ip;
                 ip_protocol = ip_header.ip_protocol;
 
-                match parse_transport_header(ip_data, src_ip, dst_ip) {
-                    Ok(transport_header) => {
-                        src_port = transport_header.src_port;
-                        dst_port = transport_header.dst_port;
-                        flags = transport_header.flags;
-                    },
-                    Err(_) => {},
+                checksum_failures.extend(ip_header.checksum_failure);
+
+                // IPヘッダ以降をL4のデータとして解析する
+                let transport_data = &ip_data[ip_header.header_length..ip_header.total_length];
+                if ip_header.fragment_offset != 0 {
+                    trace!("先頭以外のフラグメントの為L4ヘッダを解析しません: offset={}", ip_header.fragment_offset);
+                } else if let Ok(transport_header) = parse_transport_header(ip_protocol, transport_data) {
+                    // フラグメントの場合はセグメント全体が揃っていない為、L4のチェックサムは検証できない
+                    if !ip_header.fragmented {
+                        checksum_failures.extend(transport_header.verify_checksum(transport_data, src_ip, dst_ip));
+                    }
+                    transport = transport_header;
                 }
+
+                let payload_start = (ETHERNET_HEADER_LENGTH + ip_header.header_length + transport.header_length()).min(ETHERNET_HEADER_LENGTH + ip_header.total_length);
+                payload = payload_start..ETHERNET_HEADER_LENGTH + ip_header.total_length;
             },
             Err(_e) => {
                 idps_log!("IPヘッダーの解析に失敗しました: タイプ={:?}", ether_type);
@@ -51,6 +82,15 @@ pub async fn parse_ip_packet(ethernet_frame: &[u8], ether_type: EtherType) -> Re
                 return Err(AnalyzeResult::Reject);
             },
         },
+        // ARPは送信元・宛先のプロトコルアドレスを使用する
+        EtherType::ARP => match parse_arp_packet(ip_data) {
+            Some(arp) => {
+                src_ip = IpAddr::V4(arp.sender_ip);
+                dst_ip = IpAddr::V4(arp.target_ip);
+                ip_protocol = IpProtocol::UNKNOWN;
+            },
+            None => return Err(AnalyzeResult::Reject),
+        },
         _ => {
             src_ip = IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0));
             dst_ip = IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0));
@@ -58,7 +98,14 @@ pub async fn parse_ip_packet(ethernet_frame: &[u8], ether_type: EtherType) -> Re
         },
     }
 
-    Ok((src_ip, dst_ip, ip_protocol, src_port, dst_port, flags))
+    Ok(IpPacket {
+        src_ip,
+        dst_ip,
+        ip_protocol,
+        transport,
+        checksum_failures,
+        payload,
+    })
 }
 
 async fn parse_ip_header(data: &[u8]) -> Result<Option<IpHeader>, AnalyzeResult> {
@@ -72,11 +119,24 @@ async fn parse_ip_header(data: &[u8]) -> Result<Option<IpHeader>, AnalyzeResult>
             }
 
             let ihl = (data[0] & 0xF) as usize * 4; // IPヘッダ長
+            if ihl < 20 {
+                // IHLが5未満の場合、IPヘッダの一部をL4ヘッダとして解析してしまう
+                idps_log!("IPv4ヘッダ長が不正です: 宣言値 {} バイト < 最小値20バイト", ihl);
+                return Err(AnalyzeResult::Reject);
+            }
             if data.len() < ihl {
                 idps_log!("IPv4パケット長がヘッダ長より短いです: {} バイト < 宣言値 {} バイト", data.len(), ihl);
                 return Err(AnalyzeResult::Reject);
             }
 
+            // 全長がヘッダ長より短い場合やフレーム長を超える場合はフレーム長を使用する
+            let total_length = match u16::from_be_bytes([data[2], data[3]]) as usize {
+                length if length >= ihl && length <= data.len() => length,
+                _ => data.len(),
+            };
+            let fragment_offset = u16::from_be_bytes([data[6] & 0x1F, data[7]]) as usize * 8;
+            let fragmented = data[6] & 0x20 != 0 || fragment_offset != 0;
+
             let ip_protocol = IpProtocol::from(data[9]); // プロトコルフィールド
             let src_ip = Ipv4Addr::new(data[12], data[13], data[14], data[15]);
             let dst_ip = Ipv4Addr::new(data[16], data[17], data[18], data[19]);
@@ -89,6 +149,10 @@ async fn parse_ip_header(data: &[u8]) -> Result<Option<IpHeader>, AnalyzeResult>
                 src_ip: IpAddr::V4(src_ip),
                 dst_ip: IpAddr::V4(dst_ip),
                 header_length: ihl,
+                total_length,
+                fragmented,
+                fragment_offset,
+                checksum_failure: checksum::verify(ChecksumLayer::Ipv4, &[], &data[..ihl], 10),
             }))
         },
         6 => {
@@ -98,6 +162,7 @@ async fn parse_ip_header(data: &[u8]) -> Result<Option<IpHeader>, AnalyzeResult>
                 return Err(AnalyzeResult::Reject);
             }
 
+            let total_length = (40 + u16::from_be_bytes([data[4], data[5]]) as usize).min(data.len());
             let ip_protocol = IpProtocol::from(data[6]); // Next Header
             let src_ip = Ipv6Addr::new(
                 u16::from_be_bytes([data[8], data[9]]),
@@ -126,6 +191,12 @@ async fn parse_ip_header(data: &[u8]) -> Result<Option<IpHeader>, AnalyzeResult>
                 src_ip: IpAddr::V6(src_ip),
                 dst_ip: IpAddr::V6(dst_ip),
                 header_length: 40,
+                total_length,
+                // Fragment拡張ヘッダ
+                fragmented: data[6] == 44,
+                fragment_offset: 0,
+                // IPv6ヘッダにはチェックサムがない
+                checksum_failure: None,
             }))
         },
         _ => {
@@ -134,3 +205,27 @@ async fn parse_ip_header(data: &[u8]) -> Result<Option<IpHeader>, AnalyzeResult>
         },
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ipv4_header(version_ihl: u8) -> Vec<u8> {
+        let mut header = vec![version_ihl, 0, 0, 28, 0, 1, 0, 0, 64, 17, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2];
+        let checksum = checksum::internet_checksum(&[&header]);
+        header[10..12].copy_from_slice(&checksum.to_be_bytes());
+        header.extend_from_slice(&[0; 8]);
+        header
+    }
+
+    #[tokio::test]
+    async fn rejects_ipv4_header_shorter_than_minimum() {
+        let header = parse_ip_header(&ipv4_header(0x45)).await.ok().flatten().expect("IPヘッダを解析できません");
+        assert_eq!(header.header_length, 20);
+        assert!(header.checksum_failure.is_none());
+
+        for ihl in 0..5 {
+            assert!(parse_ip_header(&ipv4_header(0x40 | ihl)).await.is_err(), "ihl={}", ihl);
+        }
+    }
+}
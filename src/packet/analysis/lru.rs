@@ -0,0 +1,69 @@
+use std::collections::BTreeMap;
+
+/// 最後に使用した順にキーを並べ、最も長く使用されていないキーを取り出す
+/// 使用順は各エントリに保持させ、使用する度にtouchで更新する
+#[derive(Debug)]
+pub struct LruIndex<K> {
+    // 使用順→キー (小さいほど古い)
+    order: BTreeMap<u64, K>,
+    next: u64,
+}
+
+impl<K: Copy + PartialEq> LruIndex<K> {
+    pub fn new() -> Self {
+        Self { order: BTreeMap::new(), next: 0 }
+    }
+
+    /// keyを最も新しく使用したものとして登録し、新しい使用順を返す
+    /// (既に登録されている場合はpreviousに以前の使用順を渡す)
+    pub fn touch(&mut self, previous: Option<u64>, key: K) -> u64 {
+        if let Some(previous) = previous {
+            self.order.remove(&previous);
+        }
+        let order = self.next;
+        self.next += 1;
+        self.order.insert(order, key);
+        order
+    }
+
+    pub fn remove(&mut self, order: u64) {
+        self.order.remove(&order);
+    }
+
+    /// exceptを除いて最も長く使用されていないキー
+    pub fn oldest(&self, except: Option<&K>) -> Option<K> {
+        self.order.values().find(|key| Some(*key) != except).copied()
+    }
+
+    /// 古い順のキー
+    pub fn iter(&self) -> impl Iterator<Item = &K> {
+        self.order.values()
+    }
+}
+
+impl<K: Copy + PartialEq> Default for LruIndex<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn returns_least_recently_touched_key() {
+        let mut index = LruIndex::new();
+        let a = index.touch(None, 'a');
+        let b = index.touch(None, 'b');
+        index.touch(None, 'c');
+        assert_eq!(index.oldest(None), Some('a'));
+
+        index.touch(Some(a), 'a');
+        assert_eq!(index.oldest(None), Some('b'));
+        assert_eq!(index.oldest(Some(&'b')), Some('c'));
+
+        index.remove(b);
+        assert_eq!(index.iter().copied().collect::<Vec<_>>(), vec!['c', 'a']);
+    }
+}
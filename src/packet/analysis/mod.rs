@@ -1,8 +1,24 @@
 mod analyzer;
+mod arp;
+mod checksum;
+mod dns;
 mod ethernet;
 mod firewall;
+mod flow;
+mod fragment;
+mod http;
+mod idps;
 mod ip;
+mod lru;
+mod stream;
+mod tls;
 mod transport;
 
 pub use analyzer::AnalyzeResult;
+pub use analyzer::IngressVerdict;
 pub use analyzer::PacketAnalyzer;
+pub use firewall::{FirewallDirection, FirewallManager, FirewallStats};
+pub use flow::{FlowRecord, IpfixExporter};
+pub use idps::{Alert, DnsLogEntry, Ewma, HostBaseline, HttpLogEntry, TemporaryBlock};
+pub use lru::LruIndex;
+pub use transport::TransportHeader;
@@ -0,0 +1,500 @@
+use crate::config::{StreamConfig, StreamOverlapPolicy};
+use crate::idps_log;
+use crate::packet::analysis::firewall::{FirewallPacket, FlowKey};
+use crate::packet::analysis::idps::{Alert, AlertCategory, AlertSeverity};
+use crate::packet::analysis::lru::LruIndex;
+use crate::packet::analysis::tls::{TlsHello, TlsHelloKind};
+use crate::packet::analysis::TransportHeader;
+use log::{trace, warn};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// TCPフラグ
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+const TCP_ACK: u8 = 0x10;
+
+// セグメントの境界をまたぐパターンを検知する為に保持する、検査済みデータの末尾
+const STREAM_WINDOW: usize = 8 * 1024;
+// 1方向あたりに保持する順序外セグメント数の上限
+const MAX_PENDING_SEGMENTS: usize = 256;
+// 次に期待する位置からこれ以上離れたセグメントは同期が外れたとみなす
+const MAX_SEQUENCE_GAP: i64 = 1024 * 1024;
+const GC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 検知エンジンとプロトコル解析へ渡す、順序が整ったストリームのデータ
+#[derive(Debug)]
+pub struct StreamChunk {
+    // 直前までに検査済みのデータの末尾と、今回新たに整列したデータ
+    pub data: Vec<u8>,
+    // dataのうち新たに整列したデータの開始位置
+    pub new_offset: usize,
+    // data[0]のストリーム内の位置 (0ならdataはストリームの先頭から始まる)
+    pub start: u64,
+    // 接続を開始した側から送信されたデータか
+    pub from_client: bool,
+    // この方向のTLSのハンドシェイクを解析済みか
+    pub hello_parsed: bool,
+    // 解析済みのClientHelloのSNI (フローの全てのパケットに適用する)
+    pub sni: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct StreamDirection {
+    // ストリームの先頭に対応するシーケンス番号 (未確定の場合はNone)
+    base_seq: Option<u32>,
+    // 整列済みのバイト数 (次に期待するデータのストリーム内の位置)
+    delivered: u64,
+    // 順序外で到着したセグメント (ストリーム内の位置→データ、範囲は重複しない)
+    pending: BTreeMap<u64, Vec<u8>>,
+    pending_bytes: usize,
+    // 検査済みデータの末尾
+    window: Vec<u8>,
+    fin: bool,
+    hello_parsed: bool,
+}
+
+impl StreamDirection {
+    fn buffered_bytes(&self) -> usize {
+        self.pending_bytes + self.window.len()
+    }
+
+    // シーケンス番号の周回を考慮して、次に期待する位置からの相対位置を求める
+    fn relative(&self, base_seq: u32, seq: u32) -> i64 {
+        seq.wrapping_sub(base_seq.wrapping_add(self.delivered as u32)) as i32 as i64
+    }
+
+    // 保持しているデータと重複する部分に異なる内容があるかを調べ、方針に従って格納する
+    fn insert(&mut self, start: u64, data: Vec<u8>, policy: StreamOverlapPolicy) -> bool {
+        let end = start + data.len() as u64;
+        let overlapping: Vec<u64> = self.pending.range(..end).filter(|(offset, segment)| **offset + segment.len() as u64 > start).map(|(offset, _)| *offset).collect();
+
+        let mut conflict = false;
+        for offset in &overlapping {
+            let segment = &self.pending[offset];
+            let (from, to) = (start.max(*offset), end.min(*offset + segment.len() as u64));
+            let existing = &segment[(from - offset) as usize..(to - offset) as usize];
+            let received = &data[(from - start) as usize..(to - start) as usize];
+            conflict |= existing != received;
+        }
+
+        match policy {
+            StreamOverlapPolicy::First => {
+                // 既存のセグメントの隙間のみを格納する
+                let mut position = start;
+                for offset in overlapping {
+                    let segment_end = offset + self.pending[&offset].len() as u64;
+                    if offset > position {
+                        self.insert_piece(position, &data[(position - start) as usize..(offset - start) as usize]);
+                    }
+                    position = position.max(segment_end);
+                }
+                if position < end {
+                    self.insert_piece(position, &data[(position - start) as usize..]);
+                }
+            },
+            StreamOverlapPolicy::Last => {
+                // 既存のセグメントから重複する部分を取り除いてから格納する
+                for offset in overlapping {
+                    let Some(segment) = self.pending.remove(&offset) else {
+                        continue;
+                    };
+                    self.pending_bytes -= segment.len();
+                    let segment_end = offset + segment.len() as u64;
+                    if offset < start {
+                        self.insert_piece(offset, &segment[..(start - offset) as usize]);
+                    }
+                    if segment_end > end {
+                        self.insert_piece(end, &segment[(end - offset) as usize..]);
+                    }
+                }
+                self.insert_piece(start, &data);
+            },
+        }
+
+        conflict
+    }
+
+    fn insert_piece(&mut self, offset: u64, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        self.pending_bytes += data.len();
+        self.pending.insert(offset, data.to_vec());
+    }
+
+    // 次に期待する位置から連続するセグメントを整列済みにし、新たに整列したデータを返す
+    fn drain(&mut self, mut data: Vec<u8>) -> Vec<u8> {
+        self.delivered += data.len() as u64;
+        while let Some((&offset, _)) = self.pending.first_key_value() {
+            if offset > self.delivered {
+                break;
+            }
+            let Some(segment) = self.pending.remove(&offset) else {
+                break;
+            };
+            self.pending_bytes -= segment.len();
+            let segment_end = offset + segment.len() as u64;
+            if segment_end > self.delivered {
+                data.extend_from_slice(&segment[(self.delivered - offset) as usize..]);
+                self.delivered = segment_end;
+            }
+        }
+        data
+    }
+
+    fn reset(&mut self, seq: u32) {
+        self.base_seq = Some(seq.wrapping_sub(self.delivered as u32));
+        self.pending.clear();
+        self.pending_bytes = 0;
+        self.window.clear();
+    }
+}
+
+#[derive(Debug)]
+struct TcpStream {
+    // [接続を開始した側, 応答側]
+    directions: [StreamDirection; 2],
+    sni: Option<String>,
+    last_seen: Instant,
+    // LruIndexでの使用順
+    order: u64,
+}
+
+impl TcpStream {
+    fn buffered_bytes(&self) -> usize {
+        self.directions.iter().map(StreamDirection::buffered_bytes).sum()
+    }
+}
+
+#[derive(Debug)]
+struct StreamTable {
+    // 接続を開始した側から見たフローの識別子
+    streams: HashMap<FlowKey, TcpStream>,
+    // 最も長く通信のないフローから破棄する為の索引
+    lru: LruIndex<FlowKey>,
+    buffered_bytes: usize,
+    last_gc: Instant,
+}
+
+impl StreamTable {
+    // パケットが属するフローと、開始側(0)・応答側(1)のどちらから送信されたかを返す
+    fn find(&self, key: FlowKey) -> Option<(FlowKey, usize)> {
+        if self.streams.contains_key(&key) {
+            Some((key, 0))
+        } else if self.streams.contains_key(&key.reversed()) {
+            Some((key.reversed(), 1))
+        } else {
+            None
+        }
+    }
+
+    fn remove(&mut self, key: &FlowKey) {
+        if let Some(stream) = self.streams.remove(key) {
+            self.buffered_bytes -= stream.buffered_bytes();
+            self.lru.remove(stream.order);
+        }
+    }
+
+    fn collect_garbage(&mut self, now: Instant, timeout: Duration) {
+        if now.duration_since(self.last_gc) < GC_INTERVAL {
+            return;
+        }
+        // 索引は通信した順に並んでいる為、タイムアウトしていないフローまでを調べる
+        let expired: Vec<FlowKey> =
+            self.lru.iter().take_while(|key| self.streams.get(*key).is_some_and(|stream| now.duration_since(stream.last_seen) > timeout)).copied().collect();
+        for key in expired {
+            self.remove(&key);
+        }
+        self.last_gc = now;
+    }
+
+    // 上限を超える場合は最も長く通信のないフローから破棄する
+    fn evict_oldest(&mut self, except: Option<&FlowKey>) -> bool {
+        let Some(oldest) = self.lru.oldest(except) else {
+            return false;
+        };
+        warn!(
+            "TCPストリームの上限に達した為、最も古いフローを破棄します: {}:{} -> {}:{}",
+            oldest.src_ip, oldest.src_port, oldest.dst_ip, oldest.dst_port
+        );
+        self.remove(&oldest);
+        true
+    }
+}
+
+/// フロー毎にTCPセグメントをシーケンス番号順に並べ、連続したバイトストリームに再構築する
+#[derive(Debug)]
+pub struct StreamReassembler {
+    overlap_policy: StreamOverlapPolicy,
+    memcap: usize,
+    max_flows: usize,
+    timeout: Duration,
+    table: Mutex<StreamTable>,
+}
+
+impl StreamReassembler {
+    pub fn new(config: &StreamConfig) -> Self {
+        Self {
+            overlap_policy: config.overlap_policy,
+            memcap: config.memcap.max(STREAM_WINDOW),
+            max_flows: config.max_flows.max(1),
+            timeout: Duration::from_secs(config.timeout.max(1)),
+            table: Mutex::new(StreamTable {
+                streams: HashMap::new(),
+                lru: LruIndex::new(),
+                buffered_bytes: 0,
+                last_gc: Instant::now(),
+            }),
+        }
+    }
+
+    /// セグメントを追加し、追跡しているフローであれば整列したデータを返す
+    /// (順序外や再送で新たなデータがない場合は空のnew_dataを返す)
+    pub fn process(&self, packet: &FirewallPacket, payload: &[u8], alerts: &mut Vec<Alert>) -> Option<StreamChunk> {
+        let TransportHeader::Tcp(tcp) = &packet.transport else {
+            return None;
+        };
+        let Ok(mut table) = self.table.lock() else {
+            return None;
+        };
+        let now = Instant::now();
+        table.collect_garbage(now, self.timeout);
+
+        let key = FlowKey::from_packet(packet);
+        let (key, index) = if let Some(found) = table.find(key) {
+            found
+        } else {
+            // 途中から観測したフローはSYN/ACKであれば応答側とみなし、それ以外は送信元を開始側とする
+            if tcp.flags & TCP_RST != 0 || (payload.is_empty() && tcp.flags & TCP_SYN == 0) {
+                return None;
+            }
+            while table.streams.len() >= self.max_flows {
+                if !table.evict_oldest(None) {
+                    break;
+                }
+            }
+            let reply = tcp.flags & TCP_SYN != 0 && tcp.flags & TCP_ACK != 0;
+            let key = if reply { key.reversed() } else { key };
+            let order = table.lru.touch(None, key);
+            table.streams.insert(
+                key,
+                TcpStream {
+                    directions: Default::default(),
+                    sni: None,
+                    last_seen: now,
+                    order,
+                },
+            );
+            (key, if reply { 1 } else { 0 })
+        };
+
+        if tcp.flags & TCP_RST != 0 {
+            table.remove(&key);
+            return None;
+        }
+
+        let StreamTable { streams, lru, .. } = &mut *table;
+        let stream = streams.get_mut(&key)?;
+        stream.last_seen = now;
+        stream.order = lru.touch(Some(stream.order), key);
+        let before = stream.buffered_bytes();
+        let direction = &mut stream.directions[index];
+
+        // SYNはシーケンス番号を1つ消費する
+        let mut seq = tcp.sequence_number;
+        if tcp.flags & TCP_SYN != 0 {
+            seq = seq.wrapping_add(1);
+        }
+        let base_seq = *direction.base_seq.get_or_insert(seq);
+
+        let mut new_data = Vec::new();
+        if !payload.is_empty() {
+            let relative = direction.relative(base_seq, seq);
+            let length = payload.len() as i64;
+
+            if relative > MAX_SEQUENCE_GAP || relative + length < -MAX_SEQUENCE_GAP {
+                trace!(
+                    "シーケンス番号が大きく離れている為、ストリームを再同期します: {}:{} -> {}:{}",
+                    packet.src_ip,
+                    tcp.src_port,
+                    packet.dst_ip,
+                    tcp.dst_port
+                );
+                direction.reset(seq);
+                new_data = direction.drain(payload.to_vec());
+            } else if relative <= 0 {
+                // 検査済みのデータとの重複 (再送)
+                let overlap = (-relative).min(length) as usize;
+                let window_start = direction.window.len() as i64 + relative;
+                if window_start >= 0 && overlap > 0 && direction.window[window_start as usize..window_start as usize + overlap] != payload[..overlap] {
+                    Self::alert_overlap(packet, "検査済みのデータと内容が異なる再送セグメントを受信しました", alerts);
+                }
+                if relative + length > 0 {
+                    new_data = direction.drain(payload[overlap..].to_vec());
+                }
+            } else if direction.pending.len() >= MAX_PENDING_SEGMENTS {
+                warn!(
+                    "順序外セグメント数が上限に達した為、セグメントを破棄します: {}:{} -> {}:{}",
+                    packet.src_ip, tcp.src_port, packet.dst_ip, tcp.dst_port
+                );
+            } else {
+                let start = direction.delivered + relative as u64;
+                if direction.insert(start, payload.to_vec(), self.overlap_policy) {
+                    Self::alert_overlap(packet, "内容が異なる重複セグメントを受信しました", alerts);
+                }
+            }
+        }
+        if tcp.flags & TCP_FIN != 0 {
+            direction.fin = true;
+        }
+
+        // 検査済みのデータの末尾と合わせて渡す
+        let new_offset = direction.window.len();
+        let mut data = std::mem::take(&mut direction.window);
+        data.extend_from_slice(&new_data);
+        direction.window = data[data.len().saturating_sub(STREAM_WINDOW)..].to_vec();
+        let chunk = StreamChunk {
+            start: direction.delivered - data.len() as u64,
+            data,
+            new_offset,
+            from_client: index == 0,
+            hello_parsed: direction.hello_parsed,
+            sni: stream.sni.clone(),
+        };
+
+        let after = stream.buffered_bytes();
+        let closed = stream.directions.iter().all(|direction| direction.fin);
+        table.buffered_bytes = table.buffered_bytes + after - before;
+        if closed {
+            table.remove(&key);
+        }
+        while table.buffered_bytes > self.memcap {
+            if !table.evict_oldest(Some(&key)) {
+                break;
+            }
+        }
+
+        Some(chunk)
+    }
+
+    /// ハンドシェイクを解析したことを記録し、ClientHelloのSNIをフローの以降のパケットに適用する
+    /// その方向で初めて記録した場合はtrueを返す
+    pub fn record_tls_hello(&self, packet: &FirewallPacket, hello: &TlsHello) -> bool {
+        let Ok(mut table) = self.table.lock() else {
+            return false;
+        };
+        let Some((key, index)) = table.find(FlowKey::from_packet(packet)) else {
+            // 解析した直後にフローが終了した
+            return true;
+        };
+        let Some(stream) = table.streams.get_mut(&key) else {
+            return true;
+        };
+        let direction = &mut stream.directions[index];
+        if direction.hello_parsed {
+            return false;
+        }
+        direction.hello_parsed = true;
+        if hello.kind == TlsHelloKind::Client {
+            stream.sni = hello.sni.clone();
+        }
+        true
+    }
+
+    fn alert_overlap(packet: &FirewallPacket, description: &str, alerts: &mut Vec<Alert>) {
+        let message = format!(
+            "{} (IDSの回避の可能性): {}:{} -> {}:{}",
+            description,
+            packet.src_ip,
+            packet.transport.src_port(),
+            packet.dst_ip,
+            packet.transport.dst_port()
+        );
+        idps_log!("{}", message);
+        alerts.push(Alert::new(packet, AlertSeverity::Medium, AlertCategory::StreamEvasion, message));
+    }
+}
+
+impl Default for StreamReassembler {
+    fn default() -> Self {
+        Self::new(&StreamConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::analysis::transport::TcpHeader;
+    use crate::packet::types::{EtherType, IpProtocol};
+    use crate::packet::MacAddr;
+
+    fn segment(from_client: bool, sequence_number: u32, flags: u8, payload: &[u8]) -> FirewallPacket {
+        let (client, server) = (("10.0.0.1", 40000), ("10.0.0.2", 443));
+        let ((src_ip, src_port), (dst_ip, dst_port)) = if from_client { (client, server) } else { (server, client) };
+        FirewallPacket::from_packet(
+            MacAddr([1; 6]),
+            MacAddr([2; 6]),
+            EtherType::IP_V4,
+            src_ip.parse().unwrap(),
+            dst_ip.parse().unwrap(),
+            IpProtocol::TCP,
+            TransportHeader::Tcp(Box::new(TcpHeader {
+                src_port,
+                dst_port,
+                sequence_number,
+                acknowledgment_number: 0,
+                header_length: 20,
+                flags,
+                window_size: 1024,
+                checksum: 0,
+                urgent_pointer: 0,
+                options: Vec::new(),
+            })),
+            None,
+            54 + payload.len(),
+            None,
+        )
+    }
+
+    fn client_hello(sni: &str) -> TlsHello {
+        TlsHello {
+            kind: TlsHelloKind::Client,
+            legacy_version: 0x0303,
+            supported_versions: Vec::new(),
+            cipher_suites: Vec::new(),
+            extensions: Vec::new(),
+            sni: Some(sni.to_string()),
+            alpn: Vec::new(),
+            supported_groups: Vec::new(),
+            ec_point_formats: Vec::new(),
+            signature_algorithms: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn applies_recorded_sni_to_retransmissions_and_replies() {
+        let reassembler = StreamReassembler::default();
+        let mut alerts = Vec::new();
+        reassembler.process(&segment(true, 1000, TCP_SYN, &[]), &[], &mut alerts);
+
+        let hello = segment(true, 1001, TCP_ACK, b"hello");
+        let chunk = reassembler.process(&hello, b"hello", &mut alerts).unwrap();
+        assert!(!chunk.hello_parsed);
+        assert!(reassembler.record_tls_hello(&hello, &client_hello("blocked.example")));
+        assert!(!reassembler.record_tls_hello(&hello, &client_hello("blocked.example")));
+
+        // 再送には新たなデータがないが、解析済みのSNIが適用される
+        let retransmission = reassembler.process(&hello, b"hello", &mut alerts).unwrap();
+        assert!(retransmission.hello_parsed);
+        assert_eq!(retransmission.new_offset, retransmission.data.len());
+        assert_eq!(retransmission.sni.as_deref(), Some("blocked.example"));
+
+        let reply = reassembler.process(&segment(false, 5000, TCP_ACK, b"world"), b"world", &mut alerts).unwrap();
+        assert!(!reply.hello_parsed);
+        assert_eq!(reply.sni.as_deref(), Some("blocked.example"));
+        assert!(alerts.is_empty());
+    }
+}
@@ -0,0 +1,420 @@
+use crate::packet::analysis::stream::StreamChunk;
+use md5::{Digest, Md5};
+use sha2::Sha256;
+use std::fmt::Write;
+
+const CONTENT_TYPE_HANDSHAKE: u8 = 22;
+const TLS_RECORD_HEADER_LENGTH: usize = 5;
+const HANDSHAKE_CLIENT_HELLO: u8 = 1;
+const HANDSHAKE_SERVER_HELLO: u8 = 2;
+// ClientHelloを複数のレコードから組み立てる際の上限
+const MAX_HANDSHAKE_LENGTH: usize = 64 * 1024;
+
+// 拡張の種別
+const EXTENSION_SERVER_NAME: u16 = 0x0000;
+const EXTENSION_SUPPORTED_GROUPS: u16 = 0x000a;
+const EXTENSION_EC_POINT_FORMATS: u16 = 0x000b;
+const EXTENSION_SIGNATURE_ALGORITHMS: u16 = 0x000d;
+const EXTENSION_ALPN: u16 = 0x0010;
+const EXTENSION_SUPPORTED_VERSIONS: u16 = 0x002b;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlsHelloKind {
+    Client,
+    Server,
+}
+
+/// ClientHello/ServerHelloから抽出した情報
+#[derive(Debug, Clone)]
+pub struct TlsHello {
+    pub kind: TlsHelloKind,
+    pub legacy_version: u16,
+    // supported_versions拡張 (ServerHelloでは選択されたバージョンのみ)
+    pub supported_versions: Vec<u16>,
+    // ServerHelloでは選択された1つのみ
+    pub cipher_suites: Vec<u16>,
+    pub extensions: Vec<u16>,
+    pub sni: Option<String>,
+    pub alpn: Vec<String>,
+    pub supported_groups: Vec<u16>,
+    pub ec_point_formats: Vec<u8>,
+    pub signature_algorithms: Vec<u16>,
+}
+
+impl TlsHello {
+    /// 使用する(ClientHelloでは提示した最大の)TLSのバージョン
+    pub fn version(&self) -> u16 {
+        self.supported_versions.iter().copied().filter(|v| !is_grease(*v)).max().unwrap_or(self.legacy_version)
+    }
+
+    /// JA3 (ClientHello) またはJA3S (ServerHello) の元の文字列
+    pub fn ja3_string(&self) -> String {
+        let ciphers = join(self.cipher_suites.iter().filter(|v| !is_grease(**v)), "-");
+        let extensions = join(self.extensions.iter().filter(|v| !is_grease(**v)), "-");
+        match self.kind {
+            TlsHelloKind::Client => format!(
+                "{},{},{},{},{}",
+                self.legacy_version,
+                ciphers,
+                extensions,
+                join(self.supported_groups.iter().filter(|v| !is_grease(**v)), "-"),
+                join(self.ec_point_formats.iter(), "-")
+            ),
+            TlsHelloKind::Server => format!("{},{},{}", self.legacy_version, ciphers, extensions),
+        }
+    }
+
+    /// JA3 (ClientHello) またはJA3S (ServerHello) のフィンガープリント
+    pub fn ja3(&self) -> String {
+        to_hex(&Md5::digest(self.ja3_string().as_bytes()))
+    }
+
+    /// JA4のフィンガープリント (ClientHelloのみ)
+    pub fn ja4(&self) -> Option<String> {
+        if self.kind != TlsHelloKind::Client {
+            return None;
+        }
+
+        let version = match self.version() {
+            0x0304 => "13",
+            0x0303 => "12",
+            0x0302 => "11",
+            0x0301 => "10",
+            0x0300 => "s3",
+            _ => "00",
+        };
+        let ciphers: Vec<u16> = self.cipher_suites.iter().copied().filter(|v| !is_grease(*v)).collect();
+        let extensions: Vec<u16> = self.extensions.iter().copied().filter(|v| !is_grease(*v)).collect();
+        let alpn = match self.alpn.first().map(|alpn| alpn.as_bytes()) {
+            Some([first, .., last]) if first.is_ascii_alphanumeric() && last.is_ascii_alphanumeric() => format!("{}{}", *first as char, *last as char),
+            Some([only]) if only.is_ascii_alphanumeric() => format!("{}{}", *only as char, *only as char),
+            Some(bytes) if !bytes.is_empty() => {
+                let hex = to_hex(bytes);
+                format!("{}{}", &hex[..1], &hex[hex.len() - 1..])
+            },
+            _ => "00".to_string(),
+        };
+        let part_a = format!(
+            "t{}{}{:02}{:02}{}",
+            version,
+            if self.sni.is_some() { 'd' } else { 'i' },
+            ciphers.len().min(99),
+            extensions.len().min(99),
+            alpn
+        );
+
+        let mut sorted_ciphers: Vec<String> = ciphers.iter().map(|v| format!("{:04x}", v)).collect();
+        sorted_ciphers.sort();
+        let part_b = truncated_sha256(&sorted_ciphers.join(","));
+
+        // SNIとALPNは並び替えたハッシュから除外する
+        let mut sorted_extensions: Vec<String> = extensions.iter().filter(|v| !matches!(**v, EXTENSION_SERVER_NAME | EXTENSION_ALPN)).map(|v| format!("{:04x}", v)).collect();
+        sorted_extensions.sort();
+        let mut part_c_source = sorted_extensions.join(",");
+        if !self.signature_algorithms.is_empty() {
+            let algorithms: Vec<String> = self.signature_algorithms.iter().map(|v| format!("{:04x}", v)).collect();
+            part_c_source = format!("{}_{}", part_c_source, algorithms.join(","));
+        }
+        let part_c = truncated_sha256(&part_c_source);
+
+        Some(format!("{}_{}_{}", part_a, part_b, part_c))
+    }
+}
+
+/// TCPのデータからTLSのハンドシェイクを探す
+/// ストリームでは先頭から揃ったハンドシェイクを返す (再送でも同じハンドシェイクを返す)
+pub fn parse_tls_hello(payload: &[u8], stream: Option<&StreamChunk>) -> Option<TlsHello> {
+    match stream {
+        Some(stream) if stream.start == 0 => parse_hello(&stream.data),
+        Some(_) => None,
+        None => parse_hello(payload),
+    }
+}
+
+// レコード層を取り除いてハンドシェイクを組み立てる
+fn parse_hello(data: &[u8]) -> Option<TlsHello> {
+    let mut handshake = Vec::new();
+    let mut offset = 0;
+
+    loop {
+        let header = data.get(offset..offset + TLS_RECORD_HEADER_LENGTH)?;
+        if header[0] != CONTENT_TYPE_HANDSHAKE || header[1] != 3 {
+            return None;
+        }
+        let length = u16::from_be_bytes([header[3], header[4]]) as usize;
+        let fragment = data.get(offset + TLS_RECORD_HEADER_LENGTH..offset + TLS_RECORD_HEADER_LENGTH + length)?;
+        handshake.extend_from_slice(fragment);
+        offset += TLS_RECORD_HEADER_LENGTH + length;
+
+        if handshake.len() >= 4 {
+            let message_length = u32::from_be_bytes([0, handshake[1], handshake[2], handshake[3]]) as usize;
+            if message_length > MAX_HANDSHAKE_LENGTH {
+                return None;
+            }
+            if handshake.len() >= 4 + message_length {
+                let kind = match handshake[0] {
+                    HANDSHAKE_CLIENT_HELLO => TlsHelloKind::Client,
+                    HANDSHAKE_SERVER_HELLO => TlsHelloKind::Server,
+                    _ => return None,
+                };
+                return parse_hello_body(kind, &handshake[4..4 + message_length]);
+            }
+        }
+    }
+}
+
+fn parse_hello_body(kind: TlsHelloKind, body: &[u8]) -> Option<TlsHello> {
+    let mut reader = Reader::new(body);
+    let legacy_version = reader.u16()?;
+    reader.skip(32)?; // random
+    let session_id_length = reader.u8()? as usize;
+    reader.skip(session_id_length)?;
+
+    let cipher_suites = match kind {
+        TlsHelloKind::Client => {
+            let length = reader.u16()? as usize;
+            Reader::new(reader.bytes(length)?).u16_list()
+        },
+        TlsHelloKind::Server => vec![reader.u16()?],
+    };
+    match kind {
+        TlsHelloKind::Client => {
+            let length = reader.u8()? as usize;
+            reader.skip(length)?;
+        },
+        TlsHelloKind::Server => reader.skip(1)?,
+    }
+
+    let mut hello = TlsHello {
+        kind,
+        legacy_version,
+        supported_versions: Vec::new(),
+        cipher_suites,
+        extensions: Vec::new(),
+        sni: None,
+        alpn: Vec::new(),
+        supported_groups: Vec::new(),
+        ec_point_formats: Vec::new(),
+        signature_algorithms: Vec::new(),
+    };
+
+    // 拡張を持たないHello
+    let Some(extensions_length) = reader.u16() else {
+        return Some(hello);
+    };
+    let mut extensions = Reader::new(reader.bytes(extensions_length as usize)?);
+    while let (Some(extension_type), Some(length)) = (extensions.u16(), extensions.u16()) {
+        let data = extensions.bytes(length as usize)?;
+        hello.extensions.push(extension_type);
+        let mut data = Reader::new(data);
+
+        match extension_type {
+            EXTENSION_SERVER_NAME if kind == TlsHelloKind::Client => {
+                let mut names = Reader::new(data.u16().and_then(|length| data.bytes(length as usize)).unwrap_or_default());
+                while let (Some(name_type), Some(length)) = (names.u8(), names.u16()) {
+                    let name = names.bytes(length as usize)?;
+                    if name_type == 0 {
+                        hello.sni = Some(String::from_utf8_lossy(name).to_ascii_lowercase());
+                    }
+                }
+            },
+            EXTENSION_ALPN => {
+                let mut protocols = Reader::new(data.u16().and_then(|length| data.bytes(length as usize)).unwrap_or_default());
+                while let Some(length) = protocols.u8() {
+                    hello.alpn.push(String::from_utf8_lossy(protocols.bytes(length as usize)?).into_owned());
+                }
+            },
+            EXTENSION_SUPPORTED_GROUPS => hello.supported_groups = Reader::new(data.u16().and_then(|length| data.bytes(length as usize)).unwrap_or_default()).u16_list(),
+            EXTENSION_EC_POINT_FORMATS => hello.ec_point_formats = data.u8().and_then(|length| data.bytes(length as usize)).unwrap_or_default().to_vec(),
+            EXTENSION_SIGNATURE_ALGORITHMS => hello.signature_algorithms = Reader::new(data.u16().and_then(|length| data.bytes(length as usize)).unwrap_or_default()).u16_list(),
+            EXTENSION_SUPPORTED_VERSIONS => {
+                hello.supported_versions = match kind {
+                    TlsHelloKind::Client => Reader::new(data.u8().and_then(|length| data.bytes(length as usize)).unwrap_or_default()).u16_list(),
+                    TlsHelloKind::Server => data.u16().into_iter().collect(),
+                };
+            },
+            _ => {},
+        }
+    }
+
+    Some(hello)
+}
+
+// ビッグエンディアンのフィールドを順に読む
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn bytes(&mut self, length: usize) -> Option<&'a [u8]> {
+        if self.data.len() < length {
+            return None;
+        }
+        let (head, tail) = self.data.split_at(length);
+        self.data = tail;
+        Some(head)
+    }
+
+    fn skip(&mut self, length: usize) -> Option<()> {
+        self.bytes(length).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|bytes| bytes[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.bytes(2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u16_list(mut self) -> Vec<u16> {
+        let mut values = Vec::new();
+        while let Some(value) = self.u16() {
+            values.push(value);
+        }
+        values
+    }
+}
+
+// GREASE (RFC 8701) の値はフィンガープリントから除外する
+fn is_grease(value: u16) -> bool {
+    value & 0x0f0f == 0x0a0a && value >> 8 == value & 0xff
+}
+
+fn join<T: ToString>(values: impl Iterator<Item = T>, separator: &str) -> String {
+    values.map(|value| value.to_string()).collect::<Vec<_>>().join(separator)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::with_capacity(bytes.len() * 2), |mut hex, byte| {
+        let _ = write!(hex, "{:02x}", byte);
+        hex
+    })
+}
+
+// JA4で使用するSHA-256の先頭12文字 (対象がなければ0埋め)
+fn truncated_sha256(source: &str) -> String {
+    if source.is_empty() {
+        return "0".repeat(12);
+    }
+    to_hex(&Sha256::digest(source.as_bytes()))[..12].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extension(extension_type: u16, data: &[u8]) -> Vec<u8> {
+        let mut extension = extension_type.to_be_bytes().to_vec();
+        extension.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        extension.extend_from_slice(data);
+        extension
+    }
+
+    // GREASEを含むTLS 1.3のClientHelloの本体と、拡張の開始位置
+    fn client_hello_body() -> (Vec<u8>, usize) {
+        let mut body = vec![0x03, 0x03];
+        body.extend_from_slice(&[0x11; 32]); // random
+        body.push(0); // session_id
+        body.extend_from_slice(&[0x00, 0x08, 0x0a, 0x0a, 0x13, 0x01, 0x13, 0x02, 0xc0, 0x2b]);
+        body.extend_from_slice(&[0x01, 0x00]); // compression_methods
+        let extensions_start = body.len();
+
+        let mut extensions = extension(0x1a1a, &[]);
+        extensions.extend(extension(EXTENSION_SERVER_NAME, b"\x00\x0e\x00\x00\x0bExample.com"));
+        extensions.extend(extension(EXTENSION_SUPPORTED_GROUPS, &[0x00, 0x06, 0x2a, 0x2a, 0x00, 0x1d, 0x00, 0x17]));
+        extensions.extend(extension(EXTENSION_EC_POINT_FORMATS, &[0x01, 0x00]));
+        extensions.extend(extension(EXTENSION_SIGNATURE_ALGORITHMS, &[0x00, 0x02, 0x04, 0x03]));
+        extensions.extend(extension(EXTENSION_ALPN, b"\x00\x0c\x02h2\x08http/1.1"));
+        extensions.extend(extension(EXTENSION_SUPPORTED_VERSIONS, &[0x04, 0x03, 0x04, 0x03, 0x03]));
+        body.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+        body.extend(extensions);
+        (body, extensions_start)
+    }
+
+    // ハンドシェイクを1つ以上のレコードに分割して包む
+    fn records(handshake_type: u8, body: &[u8], split_at: &[usize]) -> Vec<u8> {
+        let mut handshake = vec![handshake_type];
+        handshake.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+        handshake.extend_from_slice(body);
+
+        let mut data = Vec::new();
+        let mut start = 0;
+        for end in split_at.iter().copied().chain([handshake.len()]) {
+            data.extend_from_slice(&[CONTENT_TYPE_HANDSHAKE, 0x03, 0x01]);
+            data.extend_from_slice(&((end - start) as u16).to_be_bytes());
+            data.extend_from_slice(&handshake[start..end]);
+            start = end;
+        }
+        data
+    }
+
+    #[test]
+    fn fingerprints_client_hello() {
+        let (body, _) = client_hello_body();
+        let hello = parse_tls_hello(&records(HANDSHAKE_CLIENT_HELLO, &body, &[]), None).unwrap();
+
+        assert_eq!(hello.kind, TlsHelloKind::Client);
+        assert_eq!(hello.version(), 0x0304);
+        assert_eq!(hello.sni.as_deref(), Some("example.com"));
+        assert_eq!(hello.alpn, ["h2", "http/1.1"]);
+        assert_eq!(hello.ja3_string(), "771,4865-4866-49195,0-10-11-13-16-43,29-23,0");
+        assert_eq!(hello.ja3(), "11138d9933242c3a03b6aad35a296476");
+        assert_eq!(hello.ja4().as_deref(), Some("t13d0306h2_5559582ccdc4_5e519ef2b8a0"));
+    }
+
+    #[test]
+    fn assembles_client_hello_split_across_records() {
+        let (body, _) = client_hello_body();
+        let single = parse_tls_hello(&records(HANDSHAKE_CLIENT_HELLO, &body, &[]), None).unwrap();
+        // ハンドシェイクヘッダの途中と本体の途中で分割する
+        let split = parse_tls_hello(&records(HANDSHAKE_CLIENT_HELLO, &body, &[2, 60]), None).unwrap();
+        assert_eq!(split.ja3_string(), single.ja3_string());
+    }
+
+    #[test]
+    fn fingerprints_server_hello() {
+        let mut body = vec![0x03, 0x03];
+        body.extend_from_slice(&[0x22; 32]); // random
+        body.push(0); // session_id
+        body.extend_from_slice(&[0x13, 0x01, 0x00]); // cipher_suite, compression_method
+        let extensions = extension(EXTENSION_SUPPORTED_VERSIONS, &[0x03, 0x04]);
+        body.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+        body.extend(extensions);
+
+        let hello = parse_tls_hello(&records(HANDSHAKE_SERVER_HELLO, &body, &[]), None).unwrap();
+        assert_eq!(hello.kind, TlsHelloKind::Server);
+        assert_eq!(hello.version(), 0x0304);
+        assert_eq!(hello.ja3_string(), "771,4865,43");
+        assert_eq!(hello.ja3(), "cce84e7a8b742462e40afb585a3e3ccc");
+        assert!(hello.ja4().is_none());
+    }
+
+    #[test]
+    fn rejects_truncated_records() {
+        let (body, _) = client_hello_body();
+        let data = records(HANDSHAKE_CLIENT_HELLO, &body, &[]);
+        for length in 0..data.len() {
+            assert!(parse_tls_hello(&data[..length], None).is_none(), "length {}", length);
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_hello_body() {
+        let (body, extensions_start) = client_hello_body();
+        for length in (0..extensions_start).chain(extensions_start + 2..body.len()) {
+            assert!(parse_hello_body(TlsHelloKind::Client, &body[..length]).is_none(), "length {}", length);
+        }
+    }
+
+    #[test]
+    fn ignores_non_handshake_data() {
+        assert!(parse_tls_hello(b"GET / HTTP/1.1\r\n\r\n", None).is_none());
+        // Application Data
+        assert!(parse_tls_hello(&[23, 0x03, 0x03, 0x00, 0x01, 0x00], None).is_none());
+    }
+}
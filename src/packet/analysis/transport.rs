@@ -1,102 +1,268 @@
 use crate::idps_log;
+use crate::packet::analysis::checksum::{self, ChecksumFailure, ChecksumLayer};
 use crate::packet::analysis::AnalyzeResult;
-use log::{debug, info, trace, warn};
+use crate::packet::types::IpProtocol;
+use log::trace;
 use std::net::IpAddr;
 
-#[derive(Debug)]
-pub struct TransportHeader {
+// 各ヘッダの最小長
+const TCP_MIN_HEADER_LENGTH: usize = 20;
+const UDP_HEADER_LENGTH: usize = 8;
+const ICMP_HEADER_LENGTH: usize = 8;
+
+// TCPオプションの種別
+const TCP_OPTION_END: u8 = 0;
+const TCP_OPTION_NOP: u8 = 1;
+const TCP_OPTION_MSS: u8 = 2;
+const TCP_OPTION_WINDOW_SCALE: u8 = 3;
+const TCP_OPTION_SACK_PERMITTED: u8 = 4;
+const TCP_OPTION_SACK: u8 = 5;
+const TCP_OPTION_TIMESTAMP: u8 = 8;
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TcpOption {
+    MaximumSegmentSize(u16),
+    WindowScale(u8),
+    SackPermitted,
+    Sack(Vec<(u32, u32)>),
+    Timestamp { value: u32, echo_reply: u32 },
+    Unknown { kind: u8, data: Vec<u8> },
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct TcpHeader {
     pub src_port: u16,
     pub dst_port: u16,
+    pub sequence_number: u32,
+    pub acknowledgment_number: u32,
+    pub header_length: usize,
     pub flags: u8,
+    pub window_size: u16,
+    pub checksum: u16,
+    pub urgent_pointer: u16,
+    pub options: Vec<TcpOption>,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub struct UdpHeader {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub length: u16,
+    pub checksum: u16,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub struct IcmpHeader {
+    pub icmp_type: u8,
+    pub code: u8,
+    pub checksum: u16,
+    // Echo等で使われる識別子・シーケンス番号などのフィールド
+    pub rest_of_header: u32,
+}
+
+/// IPプロトコル番号に応じて解析したL4ヘッダ
+#[derive(Debug, Clone)]
+pub enum TransportHeader {
+    Tcp(Box<TcpHeader>),
+    Udp(UdpHeader),
+    Icmp(IcmpHeader),
+    IcmpV6(IcmpHeader),
+    // 未対応のプロトコル、またはIP以外のパケット
+    Unknown,
 }
 
 impl TransportHeader {
-    pub fn verify_tcp_checksum(&self, transport_data: &[u8], src_ip: IpAddr, dst_ip: IpAddr) -> bool {
-        // パケット内のチェックサム値を取得
-        let packet_checksum = u16::from_be_bytes([transport_data[16], transport_data[17]]);
-        trace!("パケット内のチェックサム: 0x{:04x}", packet_checksum);
-
-        // 疑似ヘッダーの準備
-        let mut pseudo_header = Vec::new();
-        match src_ip {
-            IpAddr::V4(v4) => {
-                pseudo_header.extend_from_slice(&v4.octets());
-                pseudo_header.extend_from_slice(&dst_ip.to_string().parse::<std::net::Ipv4Addr>().unwrap().octets());
-                pseudo_header.push(0); // 予約済み
-                pseudo_header.push(6); // TCPプロトコル番号
-                pseudo_header.extend_from_slice(&(transport_data.len() as u16).to_be_bytes());
-            },
-            IpAddr::V6(_) => return false, // IPv6は今回は対象外
+    pub fn src_port(&self) -> u16 {
+        match self {
+            TransportHeader::Tcp(tcp) => tcp.src_port,
+            TransportHeader::Udp(udp) => udp.src_port,
+            _ => 0,
         }
+    }
 
-        // チェックサム計算用にTCPヘッダとデータをコピー
-        let mut tcp_segment = transport_data.to_vec();
-        // チェックサムフィールドを一時的に0にする
-        tcp_segment[16] = 0;
-        tcp_segment[17] = 0;
+    pub fn dst_port(&self) -> u16 {
+        match self {
+            TransportHeader::Tcp(tcp) => tcp.dst_port,
+            TransportHeader::Udp(udp) => udp.dst_port,
+            _ => 0,
+        }
+    }
 
-        // チェックサムの計算
-        let mut sum = calculate_checksum_sum(&pseudo_header);
-        sum += calculate_checksum_sum(&tcp_segment);
+    /// ペイロードの開始位置を求める為のL4ヘッダ長
+    pub fn header_length(&self) -> usize {
+        match self {
+            TransportHeader::Tcp(tcp) => tcp.header_length,
+            TransportHeader::Udp(_) => UDP_HEADER_LENGTH,
+            TransportHeader::Icmp(_) | TransportHeader::IcmpV6(_) => ICMP_HEADER_LENGTH,
+            TransportHeader::Unknown => 0,
+        }
+    }
 
-        // キャリーの処理
-        while sum >> 16 != 0 {
-            sum = (sum & 0xFFFF) + (sum >> 16);
+    /// ICMP/ICMPv6以外のプロトコルではNoneを返す
+    pub fn icmp_type(&self) -> Option<u8> {
+        match self {
+            TransportHeader::Icmp(icmp) | TransportHeader::IcmpV6(icmp) => Some(icmp.icmp_type),
+            _ => None,
         }
+    }
+
+    /// TCP以外のプロトコルでは0を返す
+    pub fn tcp_flags(&self) -> u8 {
+        match self {
+            TransportHeader::Tcp(tcp) => tcp.flags,
+            _ => 0,
+        }
+    }
 
-        let calculated_checksum = !sum as u16;
-        trace!("計算されたチェックサム: 0x{:04x}", calculated_checksum);
+    /// TCP/UDPのチェックサムを検証し、不一致の場合はその内容を返す
+    pub fn verify_checksum(&self, transport_data: &[u8], src_ip: IpAddr, dst_ip: IpAddr) -> Option<ChecksumFailure> {
+        let (layer, ip_protocol, checksum_offset) = match self {
+            TransportHeader::Tcp(_) => (ChecksumLayer::Tcp, IpProtocol::TCP, 16),
+            // IPv4のUDPではチェックサム0は未計算を表す
+            TransportHeader::Udp(udp) if udp.checksum == 0 && src_ip.is_ipv4() => return None,
+            TransportHeader::Udp(_) => (ChecksumLayer::Udp, IpProtocol::UDP, 6),
+            _ => return None,
+        };
 
-        let is_valid = packet_checksum == calculated_checksum;
-        if !is_valid {
-            trace!("TCPチェックサムが不一致: パケット内=0x{:04x}, 計算値=0x{:04x}", packet_checksum, calculated_checksum);
-        } else {
-            trace!("TCPチェックサム: OK");
+        let pseudo_header = checksum::pseudo_header(src_ip, dst_ip, ip_protocol.value(), transport_data.len());
+        let failure = checksum::verify(layer, &pseudo_header, transport_data, checksum_offset);
+        match &failure {
+            Some(failure) => trace!("{}", failure),
+            None => trace!("{}チェックサム: OK", layer),
         }
 
-        is_valid
+        failure
     }
 }
 
-fn calculate_checksum_sum(data: &[u8]) -> u32 {
-    let mut sum = 0u32;
-    for chunk in data.chunks(2) {
-        if chunk.len() == 2 {
-            sum += u16::from_be_bytes([chunk[0], chunk[1]]) as u32;
-        } else {
-            sum += (chunk[0] as u32) << 8;
-        }
+/// IPヘッダを除いたL4のデータをプロトコルに応じて解析する
+pub fn parse_transport_header(ip_protocol: IpProtocol, transport_data: &[u8]) -> Result<TransportHeader, AnalyzeResult> {
+    let header = match ip_protocol {
+        IpProtocol::TCP => parse_tcp_header(transport_data).map(|tcp| TransportHeader::Tcp(Box::new(tcp))),
+        IpProtocol::UDP => parse_udp_header(transport_data).map(TransportHeader::Udp),
+        IpProtocol::ICMP => parse_icmp_header(transport_data).map(TransportHeader::Icmp),
+        IpProtocol::ICMP_V6 => parse_icmp_header(transport_data).map(TransportHeader::IcmpV6),
+        _ => Some(TransportHeader::Unknown),
     }
-    sum
+    .ok_or(AnalyzeResult::Reject)?;
+
+    Ok(header)
 }
 
-pub fn parse_transport_header(data: &[u8], src_ip: IpAddr, dst_ip: IpAddr) -> Result<TransportHeader, AnalyzeResult> {
-    // IPヘッダが必要なので、最低でもIPヘッダ長以上のデータが必要
-    if data.len() < 20 {
-        warn!("TCPヘッダが短すぎます: {} bytes", data.len());
-        return Err(AnalyzeResult::Reject);
+fn parse_tcp_header(data: &[u8]) -> Option<TcpHeader> {
+    if data.len() < TCP_MIN_HEADER_LENGTH {
+        idps_log!("TCPヘッダが短すぎます: {} バイト < 最小値{}バイト", data.len(), TCP_MIN_HEADER_LENGTH);
+        return None;
+    }
+
+    let header_length = (data[12] >> 4) as usize * 4;
+    if header_length < TCP_MIN_HEADER_LENGTH || data.len() < header_length {
+        idps_log!("TCPヘッダ長が不正です: 宣言値 {} バイト, セグメント長 {} バイト", header_length, data.len());
+        return None;
+    }
+
+    Some(TcpHeader {
+        src_port: u16::from_be_bytes([data[0], data[1]]),
+        dst_port: u16::from_be_bytes([data[2], data[3]]),
+        sequence_number: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+        acknowledgment_number: u32::from_be_bytes([data[8], data[9], data[10], data[11]]),
+        header_length,
+        flags: data[13],
+        window_size: u16::from_be_bytes([data[14], data[15]]),
+        checksum: u16::from_be_bytes([data[16], data[17]]),
+        urgent_pointer: u16::from_be_bytes([data[18], data[19]]),
+        options: parse_tcp_options(&data[TCP_MIN_HEADER_LENGTH..header_length]),
+    })
+}
+
+fn parse_tcp_options(mut data: &[u8]) -> Vec<TcpOption> {
+    let mut options = Vec::new();
+
+    while let Some(&kind) = data.first() {
+        match kind {
+            TCP_OPTION_END => break,
+            TCP_OPTION_NOP => {
+                data = &data[1..];
+                continue;
+            },
+            _ => {},
+        }
+
+        // kind, length, data の形式
+        let Some(&length) = data.get(1) else {
+            idps_log!("TCPオプションの長さが欠落しています: kind={}", kind);
+            break;
+        };
+        let length = length as usize;
+        if length < 2 || data.len() < length {
+            idps_log!("TCPオプションの長さが不正です: kind={}, length={}", kind, length);
+            break;
+        }
+
+        let value = &data[2..length];
+        let option = match (kind, value.len()) {
+            (TCP_OPTION_MSS, 2) => TcpOption::MaximumSegmentSize(u16::from_be_bytes([value[0], value[1]])),
+            (TCP_OPTION_WINDOW_SCALE, 1) => TcpOption::WindowScale(value[0]),
+            (TCP_OPTION_SACK_PERMITTED, 0) => TcpOption::SackPermitted,
+            (TCP_OPTION_SACK, len) if len % 8 == 0 => TcpOption::Sack(
+                value
+                    .chunks_exact(8)
+                    .map(|block| {
+                        (
+                            u32::from_be_bytes([block[0], block[1], block[2], block[3]]),
+                            u32::from_be_bytes([block[4], block[5], block[6], block[7]]),
+                        )
+                    })
+                    .collect(),
+            ),
+            (TCP_OPTION_TIMESTAMP, 8) => TcpOption::Timestamp {
+                value: u32::from_be_bytes([value[0], value[1], value[2], value[3]]),
+                echo_reply: u32::from_be_bytes([value[4], value[5], value[6], value[7]]),
+            },
+            _ => TcpOption::Unknown { kind, data: value.to_vec() },
+        };
+
+        options.push(option);
+        data = &data[length..];
     }
 
-    // IPヘッダ長を取得
-    let ihl = ((data[0] & 0xF) * 4) as usize;
+    options
+}
 
-    // トランスポートヘッダの開始位置を計算
-    let transport_data = &data[ihl..];
+fn parse_udp_header(data: &[u8]) -> Option<UdpHeader> {
+    if data.len() < UDP_HEADER_LENGTH {
+        idps_log!("UDPヘッダが短すぎます: {} バイト < 必要な{}バイト", data.len(), UDP_HEADER_LENGTH);
+        return None;
+    }
 
-    // TCPヘッダには少なくとも14バイト必要（フラグまで読むため）
-    if transport_data.len() < 14 {
-        idps_log!("トランスポートヘッダが14byte未満の為、捨てられました");
-        return Err(AnalyzeResult::Reject);
+    let length = u16::from_be_bytes([data[4], data[5]]);
+    if (length as usize) < UDP_HEADER_LENGTH {
+        idps_log!("UDPの長さフィールドが不正です: {} バイト", length);
+        return None;
     }
 
-    let header = TransportHeader {
-        src_port: u16::from_be_bytes([transport_data[0], transport_data[1]]),
-        dst_port: u16::from_be_bytes([transport_data[2], transport_data[3]]),
-        flags: transport_data[13],
-    };
+    Some(UdpHeader {
+        src_port: u16::from_be_bytes([data[0], data[1]]),
+        dst_port: u16::from_be_bytes([data[2], data[3]]),
+        length,
+        checksum: u16::from_be_bytes([data[6], data[7]]),
+    })
+}
 
-    // チェックサム検証
-    header.verify_tcp_checksum(transport_data, src_ip, dst_ip);
+fn parse_icmp_header(data: &[u8]) -> Option<IcmpHeader> {
+    if data.len() < ICMP_HEADER_LENGTH {
+        idps_log!("ICMPヘッダが短すぎます: {} バイト < 必要な{}バイト", data.len(), ICMP_HEADER_LENGTH);
+        return None;
+    }
 
-    Ok(header)
+    Some(IcmpHeader {
+        icmp_type: data[0],
+        code: data[1],
+        checksum: u16::from_be_bytes([data[2], data[3]]),
+        rest_of_header: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+    })
 }
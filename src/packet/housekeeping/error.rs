@@ -0,0 +1,16 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum HousekeepingError {
+    #[error("設定エラー: {0}")]
+    ConfigurationError(String),
+
+    #[error("packetsテーブルの保持ポリシーの設定に失敗しました: {0}")]
+    RetentionPolicyFailed(String),
+
+    #[error("保持期間を過ぎたパケットの削除に失敗しました: {0}")]
+    ExpireFailed(String),
+
+    #[error("processed_packetsテーブルの整理に失敗しました: {0}")]
+    PruneFailed(String),
+}
@@ -0,0 +1,177 @@
+use crate::config::{AppConfig, RetentionConfig, StorageBackend};
+use crate::database::DatabaseError;
+use crate::packet::housekeeping::error::HousekeepingError;
+use crate::packet::repository::RetentionRepository;
+use crate::packet::store::packet_store;
+use chrono::{DateTime, Utc};
+use log::{debug, error, info};
+use std::future::Future;
+use tokio::time::{interval, Duration};
+
+// 1回のトランザクションで削除するprocessed_packetsの行数
+const PRUNE_BATCH_SIZE: i64 = 10000;
+
+/// 保持期間を過ぎたデータを削除する
+pub struct Housekeeper;
+
+impl Housekeeper {
+    pub async fn start() -> Result<(), HousekeepingError> {
+        let config: AppConfig = AppConfig::new().map_err(|e| HousekeepingError::ConfigurationError(e.to_string()))?;
+
+        // TimescaleDBではpacketsテーブルを保持ポリシーでチャンク毎に削除し、それ以外では定期的に削除する
+        let timescale = config.database.storage_backend == StorageBackend::TimescaleDb;
+        if let Some(days) = config.retention.packets_days.filter(|_| timescale) {
+            if let Err(e) = Self::apply_packet_retention(days).await {
+                error!("{}", e);
+            }
+        }
+
+        info!(
+            "ハウスキーピングタスクを開始します (processed_packetsの整理間隔: {}秒, 猶予: {}秒)",
+            config.retention.interval, config.retention.processed_grace
+        );
+        let mut prune_timer = interval(Duration::from_secs(config.retention.interval.max(1)));
+
+        loop {
+            prune_timer.tick().await;
+            if let Some(days) = config.retention.packets_days.filter(|_| !timescale) {
+                if let Err(e) = Self::expire_packets(days).await {
+                    error!("{}", e);
+                }
+            }
+            if let Err(e) = Self::prune_processed_packets(&config.retention).await {
+                error!("{}", e);
+            }
+        }
+    }
+
+    async fn apply_packet_retention(days: i32) -> Result<(), HousekeepingError> {
+        if days <= 0 {
+            return Err(HousekeepingError::RetentionPolicyFailed(format!("保持する日数は1以上を指定してください: {}", days)));
+        }
+        let changed = RetentionRepository::apply_packet_retention(days).await.map_err(|e| HousekeepingError::RetentionPolicyFailed(e.to_string()))?;
+        if changed {
+            info!("packetsテーブルの保持期間を{}日に設定しました", days);
+        } else {
+            debug!("packetsテーブルの保持期間は既に{}日に設定されています", days);
+        }
+        Ok(())
+    }
+
+    async fn expire_packets(days: i32) -> Result<(), HousekeepingError> {
+        let Some(before) = retention_cutoff(days, Utc::now()) else {
+            return Err(HousekeepingError::ExpireFailed(format!("保持する日数は1以上を指定してください: {}", days)));
+        };
+        let expired = packet_store().expire(before).await.map_err(|e| HousekeepingError::ExpireFailed(e.to_string()))?;
+        if expired > 0 {
+            info!("保持期間を過ぎたパケットを削除しました: {} 個 ({}より前)", expired, before);
+        }
+        Ok(())
+    }
+
+    // 稼働中の全ノードが処理し終え、猶予を過ぎた行を削除する
+    async fn prune_processed_packets(config: &RetentionConfig) -> Result<(), HousekeepingError> {
+        let watermark = RetentionRepository::processed_watermark(config.node_timeout).await.map_err(|e| HousekeepingError::PruneFailed(e.to_string()))?;
+        let before = prune_cutoff(watermark, config.processed_grace, Utc::now());
+
+        let total = delete_in_batches(PRUNE_BATCH_SIZE, |limit| RetentionRepository::prune_processed_packets(before, limit))
+            .await
+            .map_err(|e| HousekeepingError::PruneFailed(e.to_string()))?;
+
+        if total > 0 {
+            info!("processed_packetsテーブルを整理しました: {} 行削除 (処理時刻が{}より前)", total, before);
+        }
+        Ok(())
+    }
+}
+
+/// 削除した行数がlimitに満たなくなるまで、limit行ずつ削除を繰り返す
+pub(crate) async fn delete_in_batches<F, Fut>(limit: i64, mut delete: F) -> Result<u64, DatabaseError>
+where
+    F: FnMut(i64) -> Fut,
+    Fut: Future<Output = Result<u64, DatabaseError>>,
+{
+    let mut total = 0;
+    loop {
+        let deleted = delete(limit).await?;
+        total += deleted;
+        if deleted < limit as u64 {
+            return Ok(total);
+        }
+    }
+}
+
+// packetsテーブルから削除する境界の時刻 (日数が0以下の場合はNone)
+fn retention_cutoff(days: i32, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    (days > 0).then(|| now - chrono::Duration::days(days as i64))
+}
+
+// processed_packetsから削除する境界の時刻 (処理中のノードがなければ現在時刻から猶予を引く)
+fn prune_cutoff(watermark: Option<DateTime<Utc>>, grace: u64, now: DateTime<Utc>) -> DateTime<Utc> {
+    let grace = chrono::Duration::from_std(std::time::Duration::from_secs(grace)).unwrap_or_else(|_| chrono::Duration::days(365));
+    watermark.unwrap_or(now) - grace
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    // remaining行のテーブルからlimit行ずつ削除し、呼び出し回数を数える
+    async fn delete_rows(remaining: u64, limit: i64) -> (u64, u32) {
+        let remaining = Cell::new(remaining);
+        let calls = Cell::new(0);
+        let total = delete_in_batches(limit, |limit| {
+            let deleted = remaining.get().min(limit as u64);
+            remaining.set(remaining.get() - deleted);
+            calls.set(calls.get() + 1);
+            async move { Ok(deleted) }
+        })
+        .await
+        .unwrap();
+        (total, calls.get())
+    }
+
+    #[tokio::test]
+    async fn deletes_in_batches_until_partial_batch() {
+        assert_eq!(delete_rows(25, 10).await, (25, 3));
+        // 端数がない場合は0行の削除で終了する
+        assert_eq!(delete_rows(20, 10).await, (20, 3));
+        assert_eq!(delete_rows(0, 10).await, (0, 1));
+    }
+
+    #[tokio::test]
+    async fn stops_deleting_on_error() {
+        let calls = Cell::new(0);
+        let result = delete_in_batches(10, |limit| {
+            calls.set(calls.get() + 1);
+            let result = if calls.get() < 2 {
+                Ok(limit as u64)
+            } else {
+                Err(DatabaseError::QueryExecutionError("error".to_string()))
+            };
+            async move { result }
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[test]
+    fn computes_retention_cutoff() {
+        let now = Utc::now();
+        assert_eq!(retention_cutoff(7, now), Some(now - chrono::Duration::days(7)));
+        assert_eq!(retention_cutoff(0, now), None);
+        assert_eq!(retention_cutoff(-1, now), None);
+    }
+
+    #[test]
+    fn computes_prune_cutoff_from_watermark() {
+        let now = Utc::now();
+        let watermark = now - chrono::Duration::minutes(10);
+        assert_eq!(prune_cutoff(Some(watermark), 300, now), watermark - chrono::Duration::seconds(300));
+        assert_eq!(prune_cutoff(None, 300, now), now - chrono::Duration::seconds(300));
+        // 表現できない猶予は1年として扱う
+        assert_eq!(prune_cutoff(None, u64::MAX, now), now - chrono::Duration::days(365));
+    }
+}
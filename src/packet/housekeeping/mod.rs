@@ -0,0 +1,5 @@
+mod error;
+mod housekeeper;
+
+pub(crate) use housekeeper::delete_in_batches;
+pub use housekeeper::Housekeeper;
@@ -1,7 +1,9 @@
 pub mod analysis;
+pub mod housekeeping;
 pub mod monitor;
 pub mod reader;
 pub mod repository;
+pub mod store;
 pub mod types;
 pub mod writer;
 
@@ -7,4 +7,7 @@ pub enum MonitorError {
 
     #[error("未対応のチャンネルタイプです")]
     UnsupportedChannelType,
+
+    #[error("設定エラー: {0}")]
+    ConfigurationError(String),
 }
@@ -1,5 +1,9 @@
+use crate::config::AppConfig;
+use crate::packet::analysis::PacketAnalyzer;
 use crate::packet::monitor::error::MonitorError;
+use crate::packet::repository::AnomalyBaselineRepository;
 use crate::packet::writer::PacketWriter;
+use crate::packet::MacAddr;
 use log::{error, info, trace};
 use nix::sys::socket::{self, AddressFamily, SockFlag, SockType, SockaddrLike, SockaddrStorage};
 use pnet::datalink::{self, Channel::Ethernet, Config, NetworkInterface};
@@ -15,6 +19,8 @@ pub struct NetworkMonitor;
 
 impl NetworkMonitor {
     pub async fn start(interface: NetworkInterface) -> Result<(), MonitorError> {
+        let app_config: AppConfig = AppConfig::new().map_err(|e| MonitorError::ConfigurationError(e.to_string()))?;
+
         let sock_fd = socket::socket(AddressFamily::Packet, SockType::Raw, SockFlag::empty(), None).map_err(|e| MonitorError::NetworkError(e.to_string()))?;
 
         let config = Config {
@@ -38,8 +44,23 @@ impl NetworkMonitor {
 
         let mut buf = vec![0u8; 65536];
 
+        // 自身が送信したフレームのチェックサム不一致をオフロードによるものと判定する為にMACアドレスを渡す
+        PacketAnalyzer::configure_checksum(app_config.checksum.clone(), interface.mac.map(|mac| MacAddr(mac.octets())));
+        PacketAnalyzer::configure_stream(&app_config.stream);
+        PacketAnalyzer::configure_flows(&app_config.flow);
+        PacketAnalyzer::configure_idps(&app_config.idps);
+        if app_config.idps.anomaly.enabled {
+            match AnomalyBaselineRepository::load(app_config.node_id).await {
+                Ok(baselines) => {
+                    info!("異常検知のベースラインを{}件読み込みました", baselines.len());
+                    PacketAnalyzer::restore_anomaly_baselines(baselines);
+                },
+                Err(e) => error!("異常検知のベースラインを読み込めません: {}", e),
+            }
+        }
+
         info!("インターフェース {} でパケット受信を開始", interface.name);
-        let writer = PacketWriter::default();
+        let writer = PacketWriter::new(app_config.node_id, &app_config.rate_limit);
 
         loop {
             match socket::recvfrom::<SockaddrStorage>(sock_fd.as_raw_fd(), &mut buf) {
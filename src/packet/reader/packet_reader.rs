@@ -1,21 +1,29 @@
 use crate::config::AppConfig;
+use crate::packet::analysis::{IngressVerdict, PacketAnalyzer};
 use crate::packet::reader::error::PacketReaderError;
 use crate::packet::reader::packet_sender::PacketSender;
-use crate::packet::repository::PacketRepository;
+use crate::packet::store::{packet_store, PacketCursor, PacketStore, StoredPacket};
 use chrono::{DateTime, Utc};
-use log::{error, info};
+use log::{debug, error, info};
 use pnet::datalink::NetworkInterface;
+use std::sync::Arc;
 use std::time::Duration;
 
 #[derive(Clone)]
 pub struct PacketReader {
+    store: Arc<dyn PacketStore>,
     last_timestamp: Option<DateTime<Utc>>,
     is_first_fetch: bool,
 }
 
 impl PacketReader {
     pub fn new() -> Self {
+        Self::with_store(packet_store())
+    }
+
+    pub fn with_store(store: Arc<dyn PacketStore>) -> Self {
         Self {
+            store,
             last_timestamp: None,
             is_first_fetch: true,
         }
@@ -40,35 +48,102 @@ impl PacketReader {
     }
 
     async fn fetch_and_send_packets(&mut self, interface: &NetworkInterface, node_id: i16) -> Result<(), PacketReaderError> {
-        match PacketRepository::get_filtered_packets(node_id, self.is_first_fetch, self.last_timestamp.as_ref()).await {
-            Ok(packets) => {
-                if !packets.is_empty() {
-                    info!(
-                        "パケットを取得しました: {} 個 (開始時刻: {}, 終了時刻: {})",
-                        packets.len(),
-                        packets.first().map(|(t, _)| t).unwrap(),
-                        packets.last().map(|(t, _)| t).unwrap()
-                    );
-
-                    // 最後のタイムスタンプを更新
-                    self.last_timestamp = packets.last().map(|(t, _)| *t);
-
-                    // パケットを送信
-                    if let Err(e) = PacketSender::send_packets(interface, packets).await {
-                        error!("パケットの送信に失敗しました: {:?}", e);
-                    }
-                }
-
-                if self.is_first_fetch {
-                    self.is_first_fetch = false;
-                }
-
-                Ok(())
-            },
+        let packets = self.fetch_packets(node_id).await?;
+        if packets.is_empty() {
+            return Ok(());
+        }
+
+        // ingressファイアウォールで許可されたパケットのみを注入する
+        // フラグメントは全て揃うまで保持し、揃った時点のパケットのタイムスタンプでまとめて注入する
+        let fetched_count = packets.len();
+        let mut allowed = Vec::with_capacity(fetched_count);
+        let mut rejected = 0;
+        for packet in packets {
+            match PacketAnalyzer::check_ingress(&packet.raw_packet, packet.node_id).await {
+                IngressVerdict::Accept => allowed.push((packet.timestamp, packet.raw_packet)),
+                IngressVerdict::AcceptFragments(fragments) => allowed.extend(fragments.into_iter().map(|fragment| (packet.timestamp, fragment))),
+                IngressVerdict::Held => {},
+                IngressVerdict::Reject => rejected += 1,
+            }
+        }
+        if rejected > 0 {
+            debug!("ingressファイアウォールでパケットを破棄しました: {} / {} 個", rejected, fetched_count);
+        }
+
+        // パケットを送信
+        if let Err(e) = PacketSender::send_packets(interface, allowed).await {
+            error!("パケットの送信に失敗しました: {:?}", e);
+        }
+        Ok(())
+    }
+
+    // 他ノードのパケットを取得し、処理済みとして記録する
+    // 起動直後の取得 (Initial) で取得したパケットも記録し、以降の取得で再び返さないようにする
+    async fn fetch_packets(&mut self, node_id: i16) -> Result<Vec<StoredPacket>, PacketReaderError> {
+        let packets = match self.store.fetch_since(node_id, self.cursor()).await {
+            Ok(packets) => packets,
             Err(e) => {
                 error!("パケットの取得に失敗しました: {:?}", e);
-                Err(PacketReaderError::DatabaseError(e.to_string()))
+                return Err(PacketReaderError::DatabaseError(e.to_string()));
             },
+        };
+
+        if !packets.is_empty() {
+            info!(
+                "パケットを取得しました: {} 個 (開始時刻: {}, 終了時刻: {})",
+                packets.len(),
+                packets.first().map(|p| p.timestamp).unwrap(),
+                packets.last().map(|p| p.timestamp).unwrap()
+            );
+
+            // 送信に失敗した場合も再送しないよう、送信前に処理済みとして記録する
+            let packet_ids: Vec<i64> = packets.iter().map(|p| p.id).collect();
+            if let Err(e) = self.store.ack(node_id, &packet_ids).await {
+                error!("パケットの処理済みの記録に失敗しました: {:?}", e);
+                return Err(PacketReaderError::DatabaseError(e.to_string()));
+            }
+
+            // 最後のタイムスタンプを更新
+            self.last_timestamp = packets.last().map(|p| p.timestamp);
+        }
+
+        if self.is_first_fetch {
+            self.is_first_fetch = false;
+        }
+
+        Ok(packets)
+    }
+
+    fn cursor(&self) -> PacketCursor {
+        if self.is_first_fetch {
+            return PacketCursor::Initial;
         }
+        PacketCursor::After(self.last_timestamp.unwrap_or_else(|| Utc::now() - chrono::Duration::seconds(5)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::store::{test_packet as packet, MemoryStore};
+
+    #[tokio::test]
+    async fn acks_packets_fetched_with_initial_cursor() {
+        let store = Arc::new(MemoryStore::default());
+        let now = Utc::now();
+        store.insert_batch(2, vec![packet(now - chrono::Duration::seconds(1)), packet(now)]).await.unwrap();
+
+        let mut reader = PacketReader::with_store(store.clone());
+        assert_eq!(reader.cursor(), PacketCursor::Initial);
+        assert_eq!(reader.fetch_packets(1).await.unwrap().len(), 2);
+
+        // 起動直後に取得したパケットも処理済みとして記録されている
+        assert!(store.fetch_since(1, PacketCursor::After(now - chrono::Duration::seconds(3))).await.unwrap().is_empty());
+        assert_eq!(reader.cursor(), PacketCursor::After(now));
+        assert!(reader.fetch_packets(1).await.unwrap().is_empty());
+
+        store.insert_batch(2, vec![packet(now + chrono::Duration::milliseconds(1))]).await.unwrap();
+        let fetched = reader.fetch_packets(1).await.unwrap();
+        assert_eq!(fetched.iter().map(|p| p.id).collect::<Vec<_>>(), vec![3]);
     }
 }
@@ -0,0 +1,111 @@
+use crate::database::{Database, DatabaseError, ExecuteQuery};
+use crate::packet::analysis::Alert;
+use crate::packet::InetAddr;
+use chrono::{DateTime, Utc};
+use log::debug;
+use tokio_postgres::types::ToSql;
+use tokio_postgres::Transaction;
+
+pub struct AlertRepository;
+
+// unnestへ渡す列毎の配列
+struct AlertColumns {
+    node_ids: Vec<i16>,
+    timestamps: Vec<DateTime<Utc>>,
+    severities: Vec<String>,
+    categories: Vec<String>,
+    signature_ids: Vec<Option<i64>>,
+    messages: Vec<String>,
+    src_ips: Vec<InetAddr>,
+    dst_ips: Vec<InetAddr>,
+    src_ports: Vec<i32>,
+    dst_ports: Vec<i32>,
+    ip_protocols: Vec<i32>,
+    packet_ids: Vec<Option<i64>>,
+}
+
+impl AlertColumns {
+    fn new(node_id: i16, alerts: &[Alert]) -> Self {
+        Self {
+            node_ids: vec![node_id; alerts.len()],
+            timestamps: alerts.iter().map(|a| a.timestamp).collect(),
+            severities: alerts.iter().map(|a| a.severity.to_string()).collect(),
+            categories: alerts.iter().map(|a| a.category.to_string()).collect(),
+            signature_ids: alerts.iter().map(|a| a.signature_id.map(i64::from)).collect(),
+            messages: alerts.iter().map(|a| a.message.clone()).collect(),
+            src_ips: alerts.iter().map(|a| InetAddr(a.src_ip)).collect(),
+            dst_ips: alerts.iter().map(|a| InetAddr(a.dst_ip)).collect(),
+            src_ports: alerts.iter().map(|a| a.src_port as i32).collect(),
+            dst_ports: alerts.iter().map(|a| a.dst_port as i32).collect(),
+            ip_protocols: alerts.iter().map(|a| a.ip_protocol as i32).collect(),
+            packet_ids: alerts.iter().map(|a| a.packet_id).collect(),
+        }
+    }
+
+    fn params(&self) -> [&(dyn ToSql + Sync); 12] {
+        [
+            &self.node_ids,
+            &self.timestamps,
+            &self.severities,
+            &self.categories,
+            &self.signature_ids,
+            &self.messages,
+            &self.src_ips,
+            &self.dst_ips,
+            &self.src_ports,
+            &self.dst_ports,
+            &self.ip_protocols,
+            &self.packet_ids,
+        ]
+    }
+}
+
+impl AlertRepository {
+    const INSERT_QUERY: &'static str = "
+        INSERT INTO idps_alerts (
+            node_id, timestamp, severity, category, signature_id, message,
+            src_ip, dst_ip, src_port, dst_port, ip_protocol, packet_id
+        )
+        SELECT *
+        FROM (
+            SELECT
+                unnest($1::SMALLINT[]) as node_id,
+                unnest($2::TIMESTAMPTZ[]) as timestamp,
+                unnest($3::TEXT[]) as severity,
+                unnest($4::TEXT[]) as category,
+                unnest($5::BIGINT[]) as signature_id,
+                unnest($6::TEXT[]) as message,
+                unnest($7::inet[]) as src_ip,
+                unnest($8::inet[]) as dst_ip,
+                unnest($9::INTEGER[]) as src_port,
+                unnest($10::INTEGER[]) as dst_port,
+                unnest($11::INTEGER[]) as ip_protocol,
+                unnest($12::BIGINT[]) as packet_id
+        ) t";
+
+    pub async fn bulk_insert(node_id: i16, alerts: &[Alert]) -> Result<(), DatabaseError> {
+        if alerts.is_empty() {
+            return Ok(());
+        }
+
+        let db = Database::get_database();
+        let columns = AlertColumns::new(node_id, alerts);
+        let result = db.execute(Self::INSERT_QUERY, &columns.params()).await?;
+        debug!("アラートを書き込みました: {} 行", result);
+
+        Ok(())
+    }
+
+    /// パケットと同じトランザクションでアラートを書き込む
+    pub async fn insert_in_transaction(tx: &Transaction<'_>, node_id: i16, alerts: &[Alert]) -> Result<(), DatabaseError> {
+        if alerts.is_empty() {
+            return Ok(());
+        }
+
+        let columns = AlertColumns::new(node_id, alerts);
+        let result = tx.execute(Self::INSERT_QUERY, &columns.params()).await.map_err(|e| DatabaseError::QueryExecutionError(e.to_string()))?;
+        debug!("パケットに関連付けたアラートを書き込みました: {} 行", result);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,147 @@
+use crate::database::{Database, DatabaseError, ExecuteQuery};
+use crate::packet::analysis::{Ewma, HostBaseline};
+use crate::packet::InetAddr;
+use chrono::{DateTime, Utc};
+use log::{debug, warn};
+use std::net::IpAddr;
+
+pub struct AnomalyBaselineRepository;
+
+impl AnomalyBaselineRepository {
+    pub async fn upsert(node_id: i16, baselines: &[HostBaseline]) -> Result<(), DatabaseError> {
+        if baselines.is_empty() {
+            return Ok(());
+        }
+
+        let db = Database::get_database();
+        let upsert_query = "
+            INSERT INTO anomaly_baselines (
+                node_id, host, samples,
+                packets_per_sec_mean, packets_per_sec_variance,
+                bytes_per_sec_mean, bytes_per_sec_variance,
+                peers_mean, peers_variance,
+                tcp_ratio, udp_ratio, icmp_ratio, other_ratio, updated_at
+            )
+            SELECT $1, *
+            FROM (
+                SELECT
+                    unnest($2::inet[]) as host,
+                    unnest($3::BIGINT[]) as samples,
+                    unnest($4::DOUBLE PRECISION[]) as packets_per_sec_mean,
+                    unnest($5::DOUBLE PRECISION[]) as packets_per_sec_variance,
+                    unnest($6::DOUBLE PRECISION[]) as bytes_per_sec_mean,
+                    unnest($7::DOUBLE PRECISION[]) as bytes_per_sec_variance,
+                    unnest($8::DOUBLE PRECISION[]) as peers_mean,
+                    unnest($9::DOUBLE PRECISION[]) as peers_variance,
+                    unnest($10::DOUBLE PRECISION[]) as tcp_ratio,
+                    unnest($11::DOUBLE PRECISION[]) as udp_ratio,
+                    unnest($12::DOUBLE PRECISION[]) as icmp_ratio,
+                    unnest($13::DOUBLE PRECISION[]) as other_ratio,
+                    unnest($14::TIMESTAMPTZ[]) as updated_at
+            ) t
+            ON CONFLICT (node_id, host) DO UPDATE SET
+                samples = EXCLUDED.samples,
+                packets_per_sec_mean = EXCLUDED.packets_per_sec_mean,
+                packets_per_sec_variance = EXCLUDED.packets_per_sec_variance,
+                bytes_per_sec_mean = EXCLUDED.bytes_per_sec_mean,
+                bytes_per_sec_variance = EXCLUDED.bytes_per_sec_variance,
+                peers_mean = EXCLUDED.peers_mean,
+                peers_variance = EXCLUDED.peers_variance,
+                tcp_ratio = EXCLUDED.tcp_ratio,
+                udp_ratio = EXCLUDED.udp_ratio,
+                icmp_ratio = EXCLUDED.icmp_ratio,
+                other_ratio = EXCLUDED.other_ratio,
+                updated_at = EXCLUDED.updated_at";
+
+        let hosts: Vec<InetAddr> = baselines.iter().map(|b| InetAddr(b.host)).collect();
+        let samples: Vec<i64> = baselines.iter().map(|b| b.samples.min(i64::MAX as u64) as i64).collect();
+        let packets_per_sec_means: Vec<f64> = baselines.iter().map(|b| b.packets_per_sec.mean).collect();
+        let packets_per_sec_variances: Vec<f64> = baselines.iter().map(|b| b.packets_per_sec.variance).collect();
+        let bytes_per_sec_means: Vec<f64> = baselines.iter().map(|b| b.bytes_per_sec.mean).collect();
+        let bytes_per_sec_variances: Vec<f64> = baselines.iter().map(|b| b.bytes_per_sec.variance).collect();
+        let peers_means: Vec<f64> = baselines.iter().map(|b| b.peers.mean).collect();
+        let peers_variances: Vec<f64> = baselines.iter().map(|b| b.peers.variance).collect();
+        let tcp_ratios: Vec<f64> = baselines.iter().map(|b| b.protocol_mix[0]).collect();
+        let udp_ratios: Vec<f64> = baselines.iter().map(|b| b.protocol_mix[1]).collect();
+        let icmp_ratios: Vec<f64> = baselines.iter().map(|b| b.protocol_mix[2]).collect();
+        let other_ratios: Vec<f64> = baselines.iter().map(|b| b.protocol_mix[3]).collect();
+        let updated_ats: Vec<DateTime<Utc>> = baselines.iter().map(|b| b.updated_at).collect();
+
+        let result = db
+            .execute(
+                upsert_query,
+                &[
+                    &node_id,
+                    &hosts,
+                    &samples,
+                    &packets_per_sec_means,
+                    &packets_per_sec_variances,
+                    &bytes_per_sec_means,
+                    &bytes_per_sec_variances,
+                    &peers_means,
+                    &peers_variances,
+                    &tcp_ratios,
+                    &udp_ratios,
+                    &icmp_ratios,
+                    &other_ratios,
+                    &updated_ats,
+                ],
+            )
+            .await?;
+        debug!("異常検知のベースラインを保存しました: {} 行", result);
+
+        Ok(())
+    }
+
+    pub async fn load(node_id: i16) -> Result<Vec<HostBaseline>, DatabaseError> {
+        let db = Database::get_database();
+
+        // 更新の新しいホストから読み込む (上限を超えた場合は古いホストを切り捨てる為)
+        let query = "
+            SELECT host(host) AS host, samples,
+                   packets_per_sec_mean, packets_per_sec_variance,
+                   bytes_per_sec_mean, bytes_per_sec_variance,
+                   peers_mean, peers_variance,
+                   tcp_ratio, udp_ratio, icmp_ratio, other_ratio, updated_at
+            FROM anomaly_baselines
+            WHERE node_id = $1
+            ORDER BY updated_at DESC";
+
+        let rows = db.query(query, &[&node_id]).await?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|row| {
+                let host: String = row.get("host");
+                let Ok(host) = host.parse::<IpAddr>() else {
+                    warn!("ベースラインのホストを解析できません: {}", host);
+                    return None;
+                };
+                let samples: i64 = row.get("samples");
+                Some(HostBaseline {
+                    host,
+                    samples: samples.max(0) as u64,
+                    packets_per_sec: Ewma {
+                        mean: row.get("packets_per_sec_mean"),
+                        variance: row.get("packets_per_sec_variance"),
+                    },
+                    bytes_per_sec: Ewma {
+                        mean: row.get("bytes_per_sec_mean"),
+                        variance: row.get("bytes_per_sec_variance"),
+                    },
+                    peers: Ewma {
+                        mean: row.get("peers_mean"),
+                        variance: row.get("peers_variance"),
+                    },
+                    protocol_mix: [
+                        row.get("tcp_ratio"),
+                        row.get("udp_ratio"),
+                        row.get("icmp_ratio"),
+                        row.get("other_ratio"),
+                    ],
+                    updated_at: row.get("updated_at"),
+                })
+            })
+            .collect())
+    }
+}
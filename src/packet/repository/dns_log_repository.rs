@@ -0,0 +1,75 @@
+use crate::database::{Database, DatabaseError, ExecuteQuery};
+use crate::packet::analysis::DnsLogEntry;
+use crate::packet::InetAddr;
+use chrono::{DateTime, Utc};
+use log::debug;
+
+pub struct DnsLogRepository;
+
+impl DnsLogRepository {
+    pub async fn bulk_insert(node_id: i16, entries: &[DnsLogEntry]) -> Result<(), DatabaseError> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        let db = Database::get_database();
+        let insert_query = "
+            INSERT INTO dns_log (
+                timestamp, node_id, src_ip, dst_ip, src_port, dst_port,
+                transaction_id, query, query_type, response, rcode, answers
+            )
+            SELECT timestamp, $1, src_ip, dst_ip, src_port, dst_port,
+                   transaction_id, query, query_type, response, rcode,
+                   ARRAY(SELECT jsonb_array_elements_text(answers::JSONB))
+            FROM (
+                SELECT
+                    unnest($2::TIMESTAMPTZ[]) as timestamp,
+                    unnest($3::inet[]) as src_ip,
+                    unnest($4::inet[]) as dst_ip,
+                    unnest($5::INTEGER[]) as src_port,
+                    unnest($6::INTEGER[]) as dst_port,
+                    unnest($7::INTEGER[]) as transaction_id,
+                    unnest($8::TEXT[]) as query,
+                    unnest($9::TEXT[]) as query_type,
+                    unnest($10::BOOLEAN[]) as response,
+                    unnest($11::SMALLINT[]) as rcode,
+                    unnest($12::TEXT[]) as answers
+            ) t";
+
+        let timestamps: Vec<DateTime<Utc>> = entries.iter().map(|e| e.timestamp).collect();
+        let src_ips: Vec<InetAddr> = entries.iter().map(|e| InetAddr(e.src_ip)).collect();
+        let dst_ips: Vec<InetAddr> = entries.iter().map(|e| InetAddr(e.dst_ip)).collect();
+        let src_ports: Vec<i32> = entries.iter().map(|e| e.src_port as i32).collect();
+        let dst_ports: Vec<i32> = entries.iter().map(|e| e.dst_port as i32).collect();
+        let transaction_ids: Vec<i32> = entries.iter().map(|e| e.transaction_id as i32).collect();
+        let queries: Vec<&str> = entries.iter().map(|e| e.query.as_str()).collect();
+        let query_types: Vec<&str> = entries.iter().map(|e| e.query_type.as_str()).collect();
+        let responses: Vec<bool> = entries.iter().map(|e| e.response).collect();
+        let rcodes: Vec<Option<i16>> = entries.iter().map(|e| e.rcode.map(i16::from)).collect();
+        // レコード数の異なる応答を1つの多次元配列では渡せない為、エントリ毎にJSONの配列として渡してデータベース側で配列に変換する
+        let answers: Vec<String> = entries.iter().map(|e| serde_json::Value::from(e.answers.clone()).to_string()).collect();
+
+        let result = db
+            .execute(
+                insert_query,
+                &[
+                    &node_id,
+                    &timestamps,
+                    &src_ips,
+                    &dst_ips,
+                    &src_ports,
+                    &dst_ports,
+                    &transaction_ids,
+                    &queries,
+                    &query_types,
+                    &responses,
+                    &rcodes,
+                    &answers,
+                ],
+            )
+            .await?;
+        debug!("DNSログを書き込みました: {} 行", result);
+
+        Ok(())
+    }
+}
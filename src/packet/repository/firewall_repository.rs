@@ -0,0 +1,176 @@
+use crate::database::{Database, DatabaseError, ExecuteQuery};
+use crate::packet::analysis::{FirewallDirection, FirewallStats, TemporaryBlock};
+use chrono::{DateTime, Utc};
+use log::debug;
+
+#[derive(Debug)]
+pub struct FirewallRuleRecord {
+    pub id: i64,
+    pub node_id: Option<i16>,
+    pub direction: String,
+    pub filter_type: String,
+    pub filter_value: String,
+    pub priority: i16,
+}
+
+/// firewall_blocksテーブルの有効な一時的な遮断ルール
+#[derive(Debug)]
+pub struct FirewallBlockRecord {
+    pub id: i64,
+    pub origin_node_id: Option<i16>,
+    pub direction: String,
+    pub filter_type: String,
+    pub filter_value: String,
+    pub reason: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+pub struct FirewallRepository;
+
+impl FirewallRepository {
+    // デフォルトポリシーのカウンタを記録する際のルール名
+    const DEFAULT_POLICY_RULE: &'static str = "default";
+
+    pub async fn insert_stats(node_id: i16, direction: FirewallDirection, stats: &FirewallStats) -> Result<(), DatabaseError> {
+        let db = Database::get_database();
+        let timestamp: DateTime<Utc> = Utc::now();
+
+        let insert_query = "
+            INSERT INTO firewall_stats (timestamp, node_id, direction, rule, priority, packets, bytes)
+            SELECT $1, $2, $3, *
+            FROM (
+                SELECT
+                    unnest($4::TEXT[]) as rule,
+                    unnest($5::SMALLINT[]) as priority,
+                    unnest($6::BIGINT[]) as packets,
+                    unnest($7::BIGINT[]) as bytes
+            ) t";
+
+        let mut rules: Vec<String> = stats.rules.iter().map(|r| r.rule.clone()).collect();
+        let mut priorities: Vec<Option<i16>> = stats.rules.iter().map(|r| Some(r.priority as i16)).collect();
+        let mut packets: Vec<i64> = stats.rules.iter().map(|r| r.counters.packets as i64).collect();
+        let mut bytes: Vec<i64> = stats.rules.iter().map(|r| r.counters.bytes as i64).collect();
+
+        rules.push(Self::DEFAULT_POLICY_RULE.to_string());
+        priorities.push(None);
+        packets.push(stats.default_policy.packets as i64);
+        bytes.push(stats.default_policy.bytes as i64);
+
+        let result = db.execute(insert_query, &[&timestamp, &node_id, &direction.as_str(), &rules, &priorities, &packets, &bytes]).await?;
+        debug!("ファイアウォール統計を書き込みました: {} 行", result);
+
+        Ok(())
+    }
+
+    pub async fn get_rule_version() -> Result<i64, DatabaseError> {
+        let db = Database::get_database();
+        let rows = db.query("SELECT version FROM firewall_rule_version", &[]).await?;
+
+        Ok(rows.first().map(|row| row.get("version")).unwrap_or(0))
+    }
+
+    pub async fn get_rules(node_id: i16) -> Result<Vec<FirewallRuleRecord>, DatabaseError> {
+        let db = Database::get_database();
+
+        // ノード固有のルールがグローバルなルールを上書きするよう、グローバルなルールを先に並べる
+        let query = "
+            SELECT id, node_id, direction, filter_type, filter_value, priority
+            FROM firewall_rules
+            WHERE enabled AND (node_id IS NULL OR node_id = $1)
+            ORDER BY node_id NULLS FIRST, id ASC";
+
+        let rows = db.query(query, &[&node_id]).await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| FirewallRuleRecord {
+                id: row.get("id"),
+                node_id: row.get("node_id"),
+                direction: row.get("direction"),
+                filter_type: row.get("filter_type"),
+                filter_value: row.get("filter_value"),
+                priority: row.get("priority"),
+            })
+            .collect())
+    }
+
+    /// 検知器が追加した遮断ルールを書き込む (同じノードの同じフィルタは期限を延長する)
+    pub async fn upsert_blocks(node_id: i16, shared: bool, blocks: &[(FirewallDirection, TemporaryBlock)]) -> Result<(), DatabaseError> {
+        if blocks.is_empty() {
+            return Ok(());
+        }
+
+        let db = Database::get_database();
+        let upsert_query = "
+            INSERT INTO firewall_blocks (origin_node_id, shared, direction, filter_type, filter_value, reason, created_at, expires_at)
+            SELECT $1, $2, *
+            FROM (
+                SELECT
+                    unnest($3::TEXT[]) as direction,
+                    unnest($4::TEXT[]) as filter_type,
+                    unnest($5::TEXT[]) as filter_value,
+                    unnest($6::TEXT[]) as reason,
+                    unnest($7::TIMESTAMPTZ[]) as created_at,
+                    unnest($8::TIMESTAMPTZ[]) as expires_at
+            ) t
+            ON CONFLICT (origin_node_id, direction, filter_type, filter_value) DO UPDATE SET
+                shared = EXCLUDED.shared,
+                reason = EXCLUDED.reason,
+                created_at = CASE WHEN firewall_blocks.expires_at <= EXCLUDED.created_at THEN EXCLUDED.created_at ELSE firewall_blocks.created_at END,
+                expires_at = GREATEST(firewall_blocks.expires_at, EXCLUDED.expires_at)";
+
+        let now: DateTime<Utc> = Utc::now();
+        let directions: Vec<&str> = blocks.iter().map(|(direction, _)| direction.as_str()).collect();
+        let (filter_types, filter_values): (Vec<String>, Vec<String>) = blocks.iter().map(|(_, block)| block.filter.to_parts()).unzip();
+        let reasons: Vec<&str> = blocks.iter().map(|(_, block)| block.reason.as_str()).collect();
+        let created_ats: Vec<DateTime<Utc>> = vec![now; blocks.len()];
+        // 表現できない期間は1年とみなす
+        let expires_ats: Vec<DateTime<Utc>> =
+            blocks.iter().map(|(_, block)| now + chrono::Duration::from_std(block.duration).unwrap_or_else(|_| chrono::Duration::days(365))).collect();
+
+        let result = db
+            .execute(
+                upsert_query,
+                &[
+                    &node_id,
+                    &shared,
+                    &directions,
+                    &filter_types,
+                    &filter_values,
+                    &reasons,
+                    &created_ats,
+                    &expires_ats,
+                ],
+            )
+            .await?;
+        debug!("遮断ルールを書き込みました: {} 行", result);
+
+        Ok(())
+    }
+
+    /// このノードに適用する有効な遮断ルールを取得する
+    pub async fn get_active_blocks(node_id: i16) -> Result<Vec<FirewallBlockRecord>, DatabaseError> {
+        let db = Database::get_database();
+
+        let query = "
+            SELECT id, origin_node_id, direction, filter_type, filter_value, reason, expires_at
+            FROM firewall_blocks
+            WHERE expires_at > NOW() AND (shared OR origin_node_id = $1)
+            ORDER BY id ASC";
+
+        let rows = db.query(query, &[&node_id]).await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| FirewallBlockRecord {
+                id: row.get("id"),
+                origin_node_id: row.get("origin_node_id"),
+                direction: row.get("direction"),
+                filter_type: row.get("filter_type"),
+                filter_value: row.get("filter_value"),
+                reason: row.get("reason"),
+                expires_at: row.get("expires_at"),
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,80 @@
+use crate::database::{Database, DatabaseError, ExecuteQuery};
+use crate::packet::analysis::FlowRecord;
+use crate::packet::InetAddr;
+use chrono::{DateTime, Utc};
+use log::debug;
+
+pub struct FlowRepository;
+
+impl FlowRepository {
+    pub async fn bulk_insert(node_id: i16, records: &[FlowRecord]) -> Result<(), DatabaseError> {
+        if records.is_empty() {
+            return Ok(());
+        }
+
+        let db = Database::get_database();
+        let insert_query = "
+            INSERT INTO flows (
+                start_time, end_time, node_id, src_ip, dst_ip, src_port, dst_port, ip_protocol,
+                packets, bytes, reply_packets, reply_bytes, tcp_flags, end_reason
+            )
+            SELECT start_time, end_time, $1, src_ip, dst_ip, src_port, dst_port, ip_protocol,
+                   packets, bytes, reply_packets, reply_bytes, tcp_flags, end_reason
+            FROM (
+                SELECT
+                    unnest($2::TIMESTAMPTZ[]) as start_time,
+                    unnest($3::TIMESTAMPTZ[]) as end_time,
+                    unnest($4::inet[]) as src_ip,
+                    unnest($5::inet[]) as dst_ip,
+                    unnest($6::INTEGER[]) as src_port,
+                    unnest($7::INTEGER[]) as dst_port,
+                    unnest($8::INTEGER[]) as ip_protocol,
+                    unnest($9::BIGINT[]) as packets,
+                    unnest($10::BIGINT[]) as bytes,
+                    unnest($11::BIGINT[]) as reply_packets,
+                    unnest($12::BIGINT[]) as reply_bytes,
+                    unnest($13::SMALLINT[]) as tcp_flags,
+                    unnest($14::TEXT[]) as end_reason
+            ) t";
+
+        let start_times: Vec<DateTime<Utc>> = records.iter().map(|r| r.start).collect();
+        let end_times: Vec<DateTime<Utc>> = records.iter().map(|r| r.end).collect();
+        let src_ips: Vec<InetAddr> = records.iter().map(|r| InetAddr(r.src_ip)).collect();
+        let dst_ips: Vec<InetAddr> = records.iter().map(|r| InetAddr(r.dst_ip)).collect();
+        let src_ports: Vec<i32> = records.iter().map(|r| r.src_port as i32).collect();
+        let dst_ports: Vec<i32> = records.iter().map(|r| r.dst_port as i32).collect();
+        let ip_protocols: Vec<i32> = records.iter().map(|r| r.ip_protocol as i32).collect();
+        // カウンタがi64を超えることは現実的にない為、飽和させて変換する
+        let packets: Vec<i64> = records.iter().map(|r| r.packets.min(i64::MAX as u64) as i64).collect();
+        let bytes: Vec<i64> = records.iter().map(|r| r.bytes.min(i64::MAX as u64) as i64).collect();
+        let reply_packets: Vec<i64> = records.iter().map(|r| r.reply_packets.min(i64::MAX as u64) as i64).collect();
+        let reply_bytes: Vec<i64> = records.iter().map(|r| r.reply_bytes.min(i64::MAX as u64) as i64).collect();
+        let tcp_flags: Vec<i16> = records.iter().map(|r| r.tcp_flags as i16).collect();
+        let end_reasons: Vec<String> = records.iter().map(|r| r.end_reason.to_string()).collect();
+
+        let result = db
+            .execute(
+                insert_query,
+                &[
+                    &node_id,
+                    &start_times,
+                    &end_times,
+                    &src_ips,
+                    &dst_ips,
+                    &src_ports,
+                    &dst_ports,
+                    &ip_protocols,
+                    &packets,
+                    &bytes,
+                    &reply_packets,
+                    &reply_bytes,
+                    &tcp_flags,
+                    &end_reasons,
+                ],
+            )
+            .await?;
+        debug!("フローを書き込みました: {} 行", result);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,77 @@
+use crate::database::{Database, DatabaseError, ExecuteQuery};
+use crate::packet::analysis::HttpLogEntry;
+use crate::packet::InetAddr;
+use chrono::{DateTime, Utc};
+use log::debug;
+
+pub struct HttpLogRepository;
+
+impl HttpLogRepository {
+    pub async fn bulk_insert(node_id: i16, entries: &[HttpLogEntry]) -> Result<(), DatabaseError> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        let db = Database::get_database();
+        let insert_query = "
+            INSERT INTO http_log (
+                timestamp, node_id, src_ip, dst_ip, src_port, dst_port,
+                response, version, method, host, uri, user_agent, status_code
+            )
+            SELECT timestamp, $1, src_ip, dst_ip, src_port, dst_port,
+                   response, version, method, host, uri, user_agent, status_code
+            FROM (
+                SELECT
+                    unnest($2::TIMESTAMPTZ[]) as timestamp,
+                    unnest($3::inet[]) as src_ip,
+                    unnest($4::inet[]) as dst_ip,
+                    unnest($5::INTEGER[]) as src_port,
+                    unnest($6::INTEGER[]) as dst_port,
+                    unnest($7::BOOLEAN[]) as response,
+                    unnest($8::TEXT[]) as version,
+                    unnest($9::TEXT[]) as method,
+                    unnest($10::TEXT[]) as host,
+                    unnest($11::TEXT[]) as uri,
+                    unnest($12::TEXT[]) as user_agent,
+                    unnest($13::SMALLINT[]) as status_code
+            ) t";
+
+        let timestamps: Vec<DateTime<Utc>> = entries.iter().map(|e| e.timestamp).collect();
+        let src_ips: Vec<InetAddr> = entries.iter().map(|e| InetAddr(e.src_ip)).collect();
+        let dst_ips: Vec<InetAddr> = entries.iter().map(|e| InetAddr(e.dst_ip)).collect();
+        let src_ports: Vec<i32> = entries.iter().map(|e| e.src_port as i32).collect();
+        let dst_ports: Vec<i32> = entries.iter().map(|e| e.dst_port as i32).collect();
+        let responses: Vec<bool> = entries.iter().map(|e| e.response).collect();
+        let versions: Vec<&str> = entries.iter().map(|e| e.version.as_str()).collect();
+        let methods: Vec<Option<&str>> = entries.iter().map(|e| e.method.as_deref()).collect();
+        let hosts: Vec<Option<&str>> = entries.iter().map(|e| e.host.as_deref()).collect();
+        let uris: Vec<Option<&str>> = entries.iter().map(|e| e.uri.as_deref()).collect();
+        let user_agents: Vec<Option<&str>> = entries.iter().map(|e| e.user_agent.as_deref()).collect();
+        // ステータスコードは3桁の為SMALLINTに収まる
+        let status_codes: Vec<Option<i16>> = entries.iter().map(|e| e.status_code.map(|status_code| status_code as i16)).collect();
+
+        let result = db
+            .execute(
+                insert_query,
+                &[
+                    &node_id,
+                    &timestamps,
+                    &src_ips,
+                    &dst_ips,
+                    &src_ports,
+                    &dst_ports,
+                    &responses,
+                    &versions,
+                    &methods,
+                    &hosts,
+                    &uris,
+                    &user_agents,
+                    &status_codes,
+                ],
+            )
+            .await?;
+        debug!("HTTPログを書き込みました: {} 行", result);
+
+        Ok(())
+    }
+}
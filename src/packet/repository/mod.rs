@@ -1,3 +1,17 @@
+mod alert_repository;
+mod anomaly_baseline_repository;
+mod dns_log_repository;
+mod firewall_repository;
+mod flow_repository;
+mod http_log_repository;
 mod packet_repository;
+mod retention_repository;
 
+pub(crate) use alert_repository::AlertRepository;
+pub(crate) use anomaly_baseline_repository::AnomalyBaselineRepository;
+pub(crate) use dns_log_repository::DnsLogRepository;
+pub(crate) use firewall_repository::{FirewallBlockRecord, FirewallRepository, FirewallRuleRecord};
+pub(crate) use flow_repository::FlowRepository;
+pub(crate) use http_log_repository::HttpLogRepository;
 pub(crate) use packet_repository::PacketRepository;
+pub(crate) use retention_repository::RetentionRepository;
@@ -1,10 +1,12 @@
 use crate::database::{Database, DatabaseError, ExecuteQuery};
+use crate::packet::analysis::Alert;
+use crate::packet::repository::AlertRepository;
+use crate::packet::store::{PacketCursor, StoredPacket};
 use crate::packet::types::PacketData;
 use crate::packet::{InetAddr, MacAddr};
 use chrono::{DateTime, Utc};
 use log::{debug, info, warn};
 use std::time::{Duration, Instant};
-use tokio_postgres::types::ToSql;
 
 pub struct PacketRepository;
 
@@ -64,12 +66,28 @@ impl PacketRepository {
 
         db.transaction(|tx| {
             Box::pin(async move {
+                // アラートを関連付けるパケットのみ、挿入前にIDを払い出す
+                let alerted = packets.iter().filter(|p| !p.alerts.is_empty()).count() as i64;
+                let allocated: Vec<i64> = if alerted > 0 {
+                    let rows = tx
+                        .query("SELECT nextval(pg_get_serial_sequence('packets', 'id')) AS id FROM generate_series(1, $1)", &[&alerted])
+                        .await
+                        .map_err(|e| DatabaseError::QueryExecutionError(e.to_string()))?;
+                    rows.iter().map(|row| row.get("id")).collect()
+                } else {
+                    Vec::new()
+                };
+                let mut allocated = allocated.into_iter();
+                let ids: Vec<Option<i64>> = packets.iter().map(|p| if p.alerts.is_empty() { None } else { allocated.next() }).collect();
+
                 let insert_query = "
                     INSERT INTO packets (
-                        node_id, timestamp, src_mac, dst_mac, ether_type, ip_protocol,
+                        id, node_id, timestamp, src_mac, dst_mac, ether_type, ip_protocol,
                         src_ip, dst_ip, src_port, dst_port, raw_packet
                     )
-                    SELECT *
+                    SELECT COALESCE(id, nextval(pg_get_serial_sequence('packets', 'id'))),
+                           node_id, timestamp, src_mac, dst_mac, ether_type, ip_protocol,
+                           src_ip, dst_ip, src_port, dst_port, raw_packet
                     FROM (
                         SELECT
                             unnest($1::SMALLINT[]) as node_id,
@@ -82,7 +100,8 @@ impl PacketRepository {
                             unnest($8::inet[]) as dst_ip,
                             unnest($9::INTEGER[]) as src_port,
                             unnest($10::INTEGER[]) as dst_port,
-                            unnest($11::BYTEA[]) as raw_packet
+                            unnest($11::BYTEA[]) as raw_packet,
+                            unnest($12::BIGINT[]) as id
                     ) t";
 
                 let node_ids: Vec<i16> = vec![node_id; packets.len()];
@@ -114,6 +133,7 @@ impl PacketRepository {
                             &src_ports,
                             &dst_ports,
                             &raw_packets,
+                            &ids,
                         ],
                     )
                     .await
@@ -129,49 +149,72 @@ impl PacketRepository {
                     return Err(DatabaseError::QueryExecutionError("Inserted row count mismatch".to_string()));
                 }
 
+                let alerts: Vec<Alert> = packets.iter().zip(&ids).flat_map(|(p, id)| p.alerts.iter().map(move |alert| Alert { packet_id: *id, ..alert.clone() })).collect();
+                AlertRepository::insert_in_transaction(tx, node_id, &alerts).await?;
+
                 Ok(())
             })
         })
         .await
     }
 
-    pub async fn get_filtered_packets(node_id: i16, is_first: bool, last_timestamp: Option<&DateTime<Utc>>) -> Result<Vec<(DateTime<Utc>, Vec<u8>)>, DatabaseError> {
+    /// 他ノードのパケットをタイムスタンプ順に取得する
+    pub async fn fetch(node_id: i16, cursor: PacketCursor, limit: i64) -> Result<Vec<StoredPacket>, DatabaseError> {
         let db = Database::get_database();
-        let query = if is_first {
-            "SELECT timestamp, raw_packet FROM packets
-            WHERE node_id != $1 AND timestamp >= NOW() - INTERVAL '4 seconds'
-            ORDER BY timestamp ASC LIMIT 1000"
-        } else {
-            "SELECT p.id, p.timestamp, p.raw_packet
-            FROM packets p
-            LEFT JOIN processed_packets pp ON p.id = pp.packet_id AND pp.node_id = $1
-            WHERE p.node_id != $1
-                AND p.timestamp > $2
-                AND pp.packet_id IS NULL
-            ORDER BY p.timestamp ASC
-            LIMIT 1000"
-        };
-
-        let fallback_time = Utc::now() - chrono::Duration::seconds(5);
-        let params: Vec<&(dyn ToSql + Sync)> = if is_first {
-            vec![&node_id]
-        } else {
-            vec![&node_id, last_timestamp.unwrap_or(&fallback_time)]
+        let rows = match cursor {
+            PacketCursor::Initial => {
+                let query = "SELECT id, timestamp, node_id, raw_packet FROM packets
+                    WHERE node_id != $1 AND timestamp >= NOW() - INTERVAL '4 seconds'
+                    ORDER BY timestamp ASC LIMIT $2";
+                db.query(query, &[&node_id, &limit]).await?
+            },
+            PacketCursor::After(last_timestamp) => {
+                let query = "SELECT p.id, p.timestamp, p.node_id, p.raw_packet
+                    FROM packets p
+                    LEFT JOIN processed_packets pp ON p.id = pp.packet_id AND pp.node_id = $1
+                    WHERE p.node_id != $1
+                        AND p.timestamp > $2
+                        AND pp.packet_id IS NULL
+                    ORDER BY p.timestamp ASC
+                    LIMIT $3";
+                db.query(query, &[&node_id, &last_timestamp, &limit]).await?
+            },
         };
 
-        let rows = db.query(query, &params).await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| StoredPacket {
+                id: row.get("id"),
+                timestamp: row.get("timestamp"),
+                node_id: row.get("node_id"),
+                raw_packet: row.get("raw_packet"),
+            })
+            .collect())
+    }
 
-        // パケットIDを記録
-        if !is_first && !rows.is_empty() {
-            let insert_query = "
-                INSERT INTO processed_packets (packet_id, node_id)
-                VALUES (unnest($1::bigint[]), $2)
-            ";
-            let packet_ids: Vec<i64> = rows.iter().map(|row| row.get("id")).collect();
-            db.execute(insert_query, &[&packet_ids, &node_id]).await?;
+    /// 取得したパケットを処理済みとして記録する
+    pub async fn mark_processed(node_id: i16, packet_ids: &[i64]) -> Result<(), DatabaseError> {
+        if packet_ids.is_empty() {
+            return Ok(());
         }
+        let db = Database::get_database();
+        let insert_query = "
+            INSERT INTO processed_packets (packet_id, node_id)
+            SELECT unnest($1::bigint[]), $2
+            ON CONFLICT DO NOTHING
+        ";
+        db.execute(insert_query, &[&packet_ids, &node_id]).await?;
+        Ok(())
+    }
 
-        // タイムスタンプとパケットデータのタプルを返す
-        Ok(rows.into_iter().map(|row| (row.get("timestamp"), row.get("raw_packet"))).collect())
+    /// タイムスタンプがbeforeより前のパケットを最大limit行削除する (TimescaleDBを使用しない場合の保持期間の管理用)
+    pub async fn delete_before(before: DateTime<Utc>, limit: i64) -> Result<u64, DatabaseError> {
+        let db = Database::get_database();
+        let query = "
+            DELETE FROM packets WHERE ctid IN (
+                SELECT ctid FROM packets WHERE timestamp < $1 LIMIT $2
+            )
+        ";
+        db.execute(query, &[&before, &limit]).await
     }
 }
@@ -0,0 +1,89 @@
+use crate::database::{Database, DatabaseError, ExecuteQuery};
+use chrono::{DateTime, Utc};
+use log::debug;
+
+// 複数のノードが同時に保持ポリシーを変更したり、同じ行を削除したりしないようにするアドバイザリロックのキー
+const RETENTION_LOCK_KEY: i64 = 0x7264_625f_7265_746e;
+
+pub struct RetentionRepository;
+
+impl RetentionRepository {
+    /// packetsテーブルの保持ポリシーを設定する (既に同じ期間で登録されている場合は変更しない)
+    /// ポリシーを変更した場合はtrueを返す
+    pub async fn apply_packet_retention(days: i32) -> Result<bool, DatabaseError> {
+        let db = Database::get_database();
+
+        db.transaction(|tx| {
+            Box::pin(async move {
+                tx.execute("SELECT pg_advisory_xact_lock($1)", &[&RETENTION_LOCK_KEY]).await.map_err(|e| DatabaseError::QueryExecutionError(e.to_string()))?;
+
+                let current_query = "
+                    SELECT (config->>'drop_after')::INTERVAL = make_interval(days => $1) AS unchanged
+                    FROM timescaledb_information.jobs
+                    WHERE proc_name = 'policy_retention' AND hypertable_name = 'packets'";
+                let rows = tx.query(current_query, &[&days]).await.map_err(|e| DatabaseError::QueryExecutionError(e.to_string()))?;
+                if rows.first().is_some_and(|row| row.get::<_, Option<bool>>("unchanged") == Some(true)) {
+                    return Ok(false);
+                }
+
+                tx.execute("SELECT remove_retention_policy('packets', if_exists => TRUE)", &[]).await.map_err(|e| DatabaseError::QueryExecutionError(e.to_string()))?;
+                tx.execute("SELECT add_retention_policy('packets', drop_after => make_interval(days => $1))", &[&days])
+                    .await
+                    .map_err(|e| DatabaseError::QueryExecutionError(e.to_string()))?;
+                Ok(true)
+            })
+        })
+        .await
+    }
+
+    /// 稼働中の全ノードが処理し終えた時刻を返す
+    /// (ノード毎の最新の処理時刻のうち最も古いもの、node_timeout秒以上処理していないノードは含めない)
+    pub async fn processed_watermark(node_timeout: u64) -> Result<Option<DateTime<Utc>>, DatabaseError> {
+        let db = Database::get_database();
+
+        let query = "
+            SELECT MIN(last_processed_at) AS watermark
+            FROM (
+                SELECT node_id, MAX(processed_at) AS last_processed_at
+                FROM processed_packets
+                GROUP BY node_id
+            ) progress
+            WHERE last_processed_at >= NOW() - make_interval(secs => $1)";
+
+        let rows = db.query(query, &[&(node_timeout as f64)]).await?;
+        Ok(rows.first().and_then(|row| row.get("watermark")))
+    }
+
+    /// beforeより前に処理されたprocessed_packetsの行を最大limit行削除する
+    /// 他のノードが削除している場合は何もせずに0を返す
+    pub async fn prune_processed_packets(before: DateTime<Utc>, limit: i64) -> Result<u64, DatabaseError> {
+        let db = Database::get_database();
+
+        let deleted = db
+            .transaction(|tx| {
+                Box::pin(async move {
+                    let locked: bool = tx
+                        .query_one("SELECT pg_try_advisory_xact_lock($1) AS locked", &[&RETENTION_LOCK_KEY])
+                        .await
+                        .map_err(|e| DatabaseError::QueryExecutionError(e.to_string()))?
+                        .get("locked");
+                    if !locked {
+                        return Ok(0);
+                    }
+
+                    let delete_query = "
+                        DELETE FROM processed_packets
+                        WHERE ctid IN (
+                            SELECT ctid FROM processed_packets
+                            WHERE processed_at < $1
+                            LIMIT $2
+                        )";
+                    tx.execute(delete_query, &[&before, &limit]).await.map_err(|e| DatabaseError::QueryExecutionError(e.to_string()))
+                })
+            })
+            .await?;
+        debug!("processed_packetsの行を削除しました: {} 行", deleted);
+
+        Ok(deleted)
+    }
+}
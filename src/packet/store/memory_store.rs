@@ -0,0 +1,157 @@
+use crate::database::DatabaseError;
+use crate::packet::analysis::PacketAnalyzer;
+use crate::packet::store::{PacketCursor, PacketStore, StoredPacket, FETCH_LIMIT};
+use crate::packet::types::PacketData;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+
+// 保持するパケット数の上限 (超えた場合は古いパケットから破棄する)
+const MAX_PACKETS: usize = 100_000;
+
+#[derive(Default)]
+struct MemoryStoreInner {
+    // 保存した順 (IDの昇順)
+    packets: VecDeque<StoredPacket>,
+    next_id: i64,
+    // ノード毎の処理済みのパケットID
+    processed: HashMap<i16, HashSet<i64>>,
+}
+
+impl MemoryStoreInner {
+    fn remove_front(&mut self) {
+        if let Some(packet) = self.packets.pop_front() {
+            for ids in self.processed.values_mut() {
+                ids.remove(&packet.id);
+            }
+        }
+    }
+}
+
+/// プロセス内のメモリへ保存する
+/// 他のノードとは共有されない為、パケットの受け渡しのテストや単一ノードでの動作確認に使用する
+/// (パケット以外のルールやログなどはデータベースへ保存する為、データベースへの接続は引き続き必要)
+#[derive(Default)]
+pub struct MemoryStore {
+    inner: Mutex<MemoryStoreInner>,
+}
+
+#[async_trait]
+impl PacketStore for MemoryStore {
+    async fn insert_batch(&self, node_id: i16, packets: Vec<PacketData>) -> Result<(), DatabaseError> {
+        let Ok(mut inner) = self.inner.lock() else {
+            return Err(DatabaseError::StoreLockError);
+        };
+        let mut alerts = Vec::new();
+        for packet in packets {
+            inner.next_id += 1;
+            let id = inner.next_id;
+            // パケットはデータベースに保存されない為、アラートにはパケットIDを関連付けない
+            alerts.extend(packet.alerts);
+            inner.packets.push_back(StoredPacket {
+                id,
+                timestamp: packet.timestamp,
+                node_id,
+                raw_packet: packet.raw_packet,
+            });
+        }
+        while inner.packets.len() > MAX_PACKETS {
+            inner.remove_front();
+        }
+        drop(inner);
+
+        if !alerts.is_empty() {
+            PacketAnalyzer::queue_alerts(alerts);
+        }
+        Ok(())
+    }
+
+    async fn fetch_since(&self, node_id: i16, cursor: PacketCursor) -> Result<Vec<StoredPacket>, DatabaseError> {
+        let Ok(inner) = self.inner.lock() else {
+            return Err(DatabaseError::StoreLockError);
+        };
+        let processed = inner.processed.get(&node_id);
+        let mut packets: Vec<StoredPacket> = match cursor {
+            PacketCursor::Initial => {
+                let since = Utc::now() - chrono::Duration::seconds(4);
+                inner.packets.iter().filter(|p| p.node_id != node_id && p.timestamp >= since).cloned().collect()
+            },
+            PacketCursor::After(last_timestamp) => {
+                inner.packets.iter().filter(|p| p.node_id != node_id && p.timestamp > last_timestamp && !processed.is_some_and(|ids| ids.contains(&p.id))).cloned().collect()
+            },
+        };
+        packets.sort_by_key(|p| p.timestamp);
+        packets.truncate(FETCH_LIMIT as usize);
+        Ok(packets)
+    }
+
+    async fn ack(&self, node_id: i16, packet_ids: &[i64]) -> Result<(), DatabaseError> {
+        if packet_ids.is_empty() {
+            return Ok(());
+        }
+        let Ok(mut inner) = self.inner.lock() else {
+            return Err(DatabaseError::StoreLockError);
+        };
+        inner.processed.entry(node_id).or_default().extend(packet_ids);
+        Ok(())
+    }
+
+    async fn expire(&self, before: DateTime<Utc>) -> Result<u64, DatabaseError> {
+        let Ok(mut inner) = self.inner.lock() else {
+            return Err(DatabaseError::StoreLockError);
+        };
+        let expired: HashSet<i64> = inner.packets.iter().filter(|p| p.timestamp < before).map(|p| p.id).collect();
+        if expired.is_empty() {
+            return Ok(0);
+        }
+        inner.packets.retain(|p| !expired.contains(&p.id));
+        for ids in inner.processed.values_mut() {
+            ids.retain(|id| !expired.contains(id));
+        }
+        Ok(expired.len() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::store::test_packet as packet;
+
+    fn ids(packets: &[StoredPacket]) -> Vec<i64> {
+        packets.iter().map(|p| p.id).collect()
+    }
+
+    #[tokio::test]
+    async fn fetches_other_nodes_packets_after_cursor_until_acked() {
+        let store = MemoryStore::default();
+        let start = Utc::now() - chrono::Duration::seconds(2);
+        let at = |millis| start + chrono::Duration::milliseconds(millis);
+        store.insert_batch(2, vec![packet(at(0)), packet(at(100)), packet(at(200))]).await.unwrap();
+        store.insert_batch(1, vec![packet(at(150))]).await.unwrap();
+
+        // 自ノードのパケットとカーソル以前のパケットは返さない
+        let fetched = store.fetch_since(1, PacketCursor::After(at(0))).await.unwrap();
+        assert_eq!(ids(&fetched), vec![2, 3]);
+
+        store.ack(1, &[2]).await.unwrap();
+        assert_eq!(ids(&store.fetch_since(1, PacketCursor::After(at(0))).await.unwrap()), vec![3]);
+        // 処理済みの記録はノード毎 (タイムスタンプ順に返す)
+        assert_eq!(ids(&store.fetch_since(3, PacketCursor::After(at(0))).await.unwrap()), vec![2, 4, 3]);
+        // 起動直後の取得は処理済みかどうかに関わらず返す
+        assert_eq!(ids(&store.fetch_since(1, PacketCursor::Initial).await.unwrap()), vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn expires_packets_and_their_acks() {
+        let store = MemoryStore::default();
+        let now = Utc::now();
+        store.insert_batch(2, vec![packet(now - chrono::Duration::seconds(10)), packet(now)]).await.unwrap();
+        store.ack(1, &[1, 2]).await.unwrap();
+
+        assert_eq!(store.expire(now - chrono::Duration::seconds(5)).await.unwrap(), 1);
+        let inner = store.inner.lock().unwrap();
+        assert_eq!(inner.packets.iter().map(|p| p.id).collect::<Vec<_>>(), vec![2]);
+        assert_eq!(inner.processed[&1], HashSet::from([2]));
+    }
+}
@@ -0,0 +1,89 @@
+mod memory_store;
+mod sql_store;
+
+use crate::config::StorageBackend;
+use crate::database::DatabaseError;
+use crate::packet::types::PacketData;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::sync::{Arc, OnceLock};
+
+pub use memory_store::MemoryStore;
+pub use sql_store::{RetentionMode, SqlStore};
+
+// 1回の取得で返すパケット数の上限
+pub const FETCH_LIMIT: i64 = 1000;
+
+static PACKET_STORE: OnceLock<Arc<dyn PacketStore>> = OnceLock::new();
+
+/// 保存済みのパケット
+#[derive(Debug, Clone)]
+pub struct StoredPacket {
+    pub id: i64,
+    pub timestamp: DateTime<Utc>,
+    // パケットを保存したノード
+    pub node_id: i16,
+    pub raw_packet: Vec<u8>,
+}
+
+/// パケットの取得位置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketCursor {
+    // 起動直後: 直近4秒間のパケットを処理済みかどうかに関わらず取得する
+    Initial,
+    // 指定した時刻より後の未処理のパケットを取得する
+    After(DateTime<Utc>),
+}
+
+/// ノード間でパケットを受け渡す保存先
+#[async_trait]
+pub trait PacketStore: Send + Sync {
+    /// 自ノードでキャプチャしたパケットを保存する
+    async fn insert_batch(&self, node_id: i16, packets: Vec<PacketData>) -> Result<(), DatabaseError>;
+
+    /// 他ノードのパケットをタイムスタンプ順に取得する
+    async fn fetch_since(&self, node_id: i16, cursor: PacketCursor) -> Result<Vec<StoredPacket>, DatabaseError>;
+
+    /// 取得したパケットを処理済みとして記録し、以降の取得で返さないようにする
+    async fn ack(&self, node_id: i16, packet_ids: &[i64]) -> Result<(), DatabaseError>;
+
+    /// タイムスタンプがbeforeより前のパケットを削除し、削除した数を返す
+    async fn expire(&self, before: DateTime<Utc>) -> Result<u64, DatabaseError>;
+}
+
+/// 保存先を設定する (最初にpacket_storeを呼び出す前に1度だけ呼び出す)
+pub fn configure_packet_store(backend: StorageBackend) {
+    let store: Arc<dyn PacketStore> = match backend {
+        StorageBackend::TimescaleDb => Arc::new(SqlStore::new(RetentionMode::Chunks)),
+        StorageBackend::Postgres => Arc::new(SqlStore::new(RetentionMode::Batches)),
+        StorageBackend::MemoryPackets => Arc::new(MemoryStore::default()),
+    };
+    let _ = PACKET_STORE.set(store);
+}
+
+pub fn packet_store() -> Arc<dyn PacketStore> {
+    PACKET_STORE.get_or_init(|| Arc::new(SqlStore::new(RetentionMode::Chunks))).clone()
+}
+
+/// テスト用に保存するパケット (10.0.0.1 -> 10.0.0.2 のUDP)
+#[cfg(test)]
+pub fn test_packet(timestamp: DateTime<Utc>) -> PacketData {
+    use crate::packet::analysis::TransportHeader;
+    use crate::packet::types::{EtherType, IpProtocol};
+    use crate::packet::{InetAddr, MacAddr};
+
+    PacketData {
+        src_mac: MacAddr([1; 6]),
+        dst_mac: MacAddr([2; 6]),
+        ether_type: EtherType::IP_V4,
+        src_ip: InetAddr("10.0.0.1".parse().unwrap()),
+        dst_ip: InetAddr("10.0.0.2".parse().unwrap()),
+        src_port: 0,
+        dst_port: 0,
+        ip_protocol: IpProtocol::UDP,
+        transport: TransportHeader::Unknown,
+        timestamp,
+        raw_packet: vec![0; 60],
+        alerts: Vec::new(),
+    }
+}
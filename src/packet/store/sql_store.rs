@@ -0,0 +1,53 @@
+use crate::database::DatabaseError;
+use crate::packet::housekeeping::delete_in_batches;
+use crate::packet::repository::PacketRepository;
+use crate::packet::store::{PacketCursor, PacketStore, StoredPacket, FETCH_LIMIT};
+use crate::packet::types::PacketData;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+// 1回のステートメントで削除するパケット数 (長時間のロックを避ける)
+const EXPIRE_BATCH_SIZE: i64 = 10000;
+
+/// 保持期間を過ぎたパケットの削除方法
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetentionMode {
+    // TimescaleDBの保持ポリシーで古いチャンクをまとめて削除する為、行単位では削除しない
+    Chunks,
+    // 通常のテーブルから一定数ずつ削除する
+    Batches,
+}
+
+/// データベースのpacketsテーブルへ保存する (TimescaleDBのハイパーテーブルと通常のテーブルで共通)
+pub struct SqlStore {
+    retention: RetentionMode,
+}
+
+impl SqlStore {
+    pub fn new(retention: RetentionMode) -> Self {
+        Self { retention }
+    }
+}
+
+#[async_trait]
+impl PacketStore for SqlStore {
+    async fn insert_batch(&self, node_id: i16, packets: Vec<PacketData>) -> Result<(), DatabaseError> {
+        PacketRepository::bulk_insert(node_id, packets).await
+    }
+
+    async fn fetch_since(&self, node_id: i16, cursor: PacketCursor) -> Result<Vec<StoredPacket>, DatabaseError> {
+        PacketRepository::fetch(node_id, cursor, FETCH_LIMIT).await
+    }
+
+    async fn ack(&self, node_id: i16, packet_ids: &[i64]) -> Result<(), DatabaseError> {
+        PacketRepository::mark_processed(node_id, packet_ids).await
+    }
+
+    async fn expire(&self, before: DateTime<Utc>) -> Result<u64, DatabaseError> {
+        if self.retention == RetentionMode::Chunks {
+            return Ok(0);
+        }
+
+        delete_in_batches(EXPIRE_BATCH_SIZE, |limit| PacketRepository::delete_before(before, limit)).await
+    }
+}
@@ -1,4 +1,5 @@
 use super::{InetAddr, MacAddr};
+use crate::packet::analysis::{Alert, TransportHeader};
 use crate::packet::types::protocol::{EtherType, IpProtocol};
 use chrono::{DateTime, Utc};
 use std::net::IpAddr;
@@ -13,8 +14,11 @@ pub struct PacketData {
     pub src_port: i32,
     pub dst_port: i32,
     pub ip_protocol: IpProtocol,
+    pub transport: TransportHeader,
     pub timestamp: DateTime<Utc>,
     pub raw_packet: Vec<u8>,
+    // このパケットを契機とするアラート (保存時にパケットIDを関連付ける)
+    pub alerts: Vec<Alert>,
 }
 
 #[derive(Clone, Debug)]
@@ -5,6 +5,21 @@ pub enum WriterError {
     #[error("パケットバッファのフラッシュに失敗しました: {0}")]
     PacketBufferFlushError(String),
 
+    #[error("DNSログの書き込みに失敗しました: {0}")]
+    DnsLogFlushFailed(String),
+
+    #[error("HTTPログの書き込みに失敗しました: {0}")]
+    HttpLogFlushFailed(String),
+
+    #[error("アラートの書き込みに失敗しました: {0}")]
+    AlertFlushFailed(String),
+
+    #[error("フローの書き込みに失敗しました: {0}")]
+    FlowFlushFailed(String),
+
+    #[error("異常検知のベースラインの保存に失敗しました: {0}")]
+    AnomalyBaselineFlushFailed(String),
+
     #[error("設定エラー: {0}")]
     ConfigurationError(String),
 }
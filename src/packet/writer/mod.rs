@@ -1,6 +1,7 @@
 mod error;
 mod packet_buffer;
 mod packet_writer;
+mod rate_limiter;
 
 pub use packet_buffer::PacketBuffer;
 pub use packet_writer::PacketWriter;
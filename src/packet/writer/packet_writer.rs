@@ -1,38 +1,159 @@
-use crate::config::AppConfig;
-use crate::packet::analysis::{AnalyzeResult, PacketAnalyzer};
-use crate::packet::repository::PacketRepository;
+use crate::config::{AppConfig, RateLimitConfig};
+use crate::packet::analysis::{AnalyzeResult, IpfixExporter, PacketAnalyzer};
+use crate::packet::repository::{AlertRepository, AnomalyBaselineRepository, DnsLogRepository, FlowRepository, HttpLogRepository};
+use crate::packet::store::{packet_store, PacketStore};
 use crate::packet::writer::error::WriterError;
+use crate::packet::writer::rate_limiter::RateLimiter;
 use crate::packet::writer::PacketBuffer;
 use log::{error, info, trace};
+use std::sync::Arc;
+use tokio::sync::Mutex;
 use tokio::time::{interval, Duration};
 
 const FLUSH_INTERVAL: Duration = Duration::from_millis(10);
+const DNS_LOG_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+const HTTP_LOG_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+const ALERT_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+const FLOW_EXPIRE_INTERVAL: Duration = Duration::from_secs(1);
+const ANOMALY_BASELINE_SAVE_INTERVAL: Duration = Duration::from_secs(60);
 
 pub struct PacketWriter {
+    store: Arc<dyn PacketStore>,
     buffer: PacketBuffer,
+    rate_limiter: Mutex<RateLimiter>,
 }
 
 impl Default for PacketWriter {
     fn default() -> Self {
-        Self { buffer: PacketBuffer::default() }
+        Self::new(0, &RateLimitConfig::default())
     }
 }
 
 impl PacketWriter {
+    pub fn new(node_id: i16, rate_limit: &RateLimitConfig) -> Self {
+        Self {
+            store: packet_store(),
+            buffer: PacketBuffer::default(),
+            rate_limiter: Mutex::new(RateLimiter::new(node_id, rate_limit)),
+        }
+    }
+
     pub async fn start(&self) -> Result<(), WriterError> {
         info!("パケットライターを開始します");
         let mut interval_timer = interval(FLUSH_INTERVAL);
 
+        let mut dns_log_timer = interval(DNS_LOG_FLUSH_INTERVAL);
+        let mut http_log_timer = interval(HTTP_LOG_FLUSH_INTERVAL);
+        let mut alert_timer = interval(ALERT_FLUSH_INTERVAL);
+        let mut flow_timer = interval(FLOW_EXPIRE_INTERVAL);
+        let mut anomaly_baseline_timer = interval(ANOMALY_BASELINE_SAVE_INTERVAL);
+
         let config: AppConfig = AppConfig::new().map_err(|e| WriterError::ConfigurationError(e.to_string()))?;
 
+        // コレクタへ送信できない場合もflowsテーブルへの書き込みは継続する
+        let mut ipfix_exporter = config.flow.ipfix_collector.as_deref().and_then(|collector| match IpfixExporter::new(collector, config.node_id as u32) {
+            Ok(exporter) => {
+                info!("IPFIXでフローを送信します: {}", collector);
+                Some(exporter)
+            },
+            Err(e) => {
+                error!("IPFIXのコレクタを設定できません: {}: {}", collector, e);
+                None
+            },
+        });
+
         loop {
-            interval_timer.tick().await;
-            if let Err(e) = self.flush_buffer(config.node_id).await {
-                error!("バッファのフラッシュに失敗しました: {}", e);
+            tokio::select! {
+                _ = interval_timer.tick() => {
+                    if let Err(e) = self.flush_buffer(config.node_id).await {
+                        error!("バッファのフラッシュに失敗しました: {}", e);
+                    }
+                }
+                _ = dns_log_timer.tick(), if config.idps.dns.log_queries => {
+                    if let Err(e) = Self::flush_dns_log(config.node_id).await {
+                        error!("{}", e);
+                    }
+                }
+                _ = http_log_timer.tick(), if config.idps.http.log_transactions => {
+                    if let Err(e) = Self::flush_http_log(config.node_id).await {
+                        error!("{}", e);
+                    }
+                }
+                _ = alert_timer.tick(), if config.idps.alert.store => {
+                    if let Err(e) = Self::flush_alerts(config.node_id).await {
+                        error!("{}", e);
+                    }
+                }
+                _ = flow_timer.tick(), if config.flow.enabled => {
+                    if let Err(e) = Self::flush_flows(config.node_id, ipfix_exporter.as_mut()).await {
+                        error!("{}", e);
+                    }
+                }
+                _ = anomaly_baseline_timer.tick(), if config.idps.anomaly.enabled => {
+                    if let Err(e) = Self::flush_anomaly_baselines(config.node_id).await {
+                        error!("{}", e);
+                    }
+                }
             }
         }
     }
 
+    async fn flush_dns_log(node_id: i16) -> Result<(), WriterError> {
+        let entries = PacketAnalyzer::drain_dns_log();
+        if let Err(e) = DnsLogRepository::bulk_insert(node_id, &entries).await {
+            // 書き込めなかった問い合わせは次回に再び書き込む
+            PacketAnalyzer::requeue_dns_log(entries);
+            return Err(WriterError::DnsLogFlushFailed(e.to_string()));
+        }
+        Ok(())
+    }
+
+    async fn flush_http_log(node_id: i16) -> Result<(), WriterError> {
+        let entries = PacketAnalyzer::drain_http_log();
+        if let Err(e) = HttpLogRepository::bulk_insert(node_id, &entries).await {
+            // 書き込めなかったメッセージは次回に再び書き込む
+            PacketAnalyzer::requeue_http_log(entries);
+            return Err(WriterError::HttpLogFlushFailed(e.to_string()));
+        }
+        Ok(())
+    }
+
+    async fn flush_alerts(node_id: i16) -> Result<(), WriterError> {
+        let alerts = PacketAnalyzer::drain_alerts();
+        if let Err(e) = AlertRepository::bulk_insert(node_id, &alerts).await {
+            // 書き込めなかったアラートは次回に再び書き込む (バッファの上限を超える分は破棄する)
+            PacketAnalyzer::queue_alerts(alerts);
+            return Err(WriterError::AlertFlushFailed(e.to_string()));
+        }
+        Ok(())
+    }
+
+    async fn flush_flows(node_id: i16, ipfix_exporter: Option<&mut IpfixExporter>) -> Result<(), WriterError> {
+        let records = PacketAnalyzer::expire_flows();
+        if records.is_empty() {
+            return Ok(());
+        }
+        // flowsテーブルとIPFIXで同じフローを書き出す為、書き込めなかったフローは送信せずに次回に再び書き込む
+        if let Err(e) = FlowRepository::bulk_insert(node_id, &records).await {
+            PacketAnalyzer::requeue_flows(records);
+            return Err(WriterError::FlowFlushFailed(e.to_string()));
+        }
+        if let Some(exporter) = ipfix_exporter {
+            if let Err(e) = exporter.export(&records) {
+                error!("IPFIXでのフローの送信に失敗しました: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    async fn flush_anomaly_baselines(node_id: i16) -> Result<(), WriterError> {
+        let baselines = PacketAnalyzer::updated_anomaly_baselines();
+        // 保存に失敗したベースラインは更新済みのまま残し、次回に再び保存する
+        AnomalyBaselineRepository::upsert(node_id, &baselines).await.map_err(|e| WriterError::AnomalyBaselineFlushFailed(e.to_string()))?;
+        PacketAnalyzer::mark_anomaly_baselines_saved(&baselines);
+        Ok(())
+    }
+
     async fn flush_buffer(&self, node_id: i16) -> Result<(), WriterError> {
         let packets = self.buffer.drain().await;
         if packets.is_empty() {
@@ -40,7 +161,7 @@ impl PacketWriter {
         }
 
         let start = std::time::Instant::now();
-        match PacketRepository::bulk_insert(node_id, packets).await {
+        match self.store.insert_batch(node_id, packets).await {
             Ok(_) => {
                 let duration = start.elapsed();
                 info!("フラッシュ完了: 処理時間 {}ms", duration.as_millis());
@@ -53,7 +174,28 @@ impl PacketWriter {
     pub async fn process_packet(&self, ethernet_frame: &[u8]) -> Result<(), WriterError> {
         match PacketAnalyzer::analyze_packet(ethernet_frame).await {
             AnalyzeResult::Accept(packet_data) => {
-                self.buffer.push(packet_data).await;
+                // バッファへ追加する前にレート制限を適用する
+                if !self.rate_limiter.lock().await.allow(&packet_data) {
+                    trace!("レート制限によりパケットが破棄されました");
+                    PacketAnalyzer::queue_alerts(packet_data.alerts);
+                    return Ok(());
+                }
+                self.buffer.push(*packet_data).await;
+                Ok(())
+            },
+            AnalyzeResult::AcceptFragments(fragments) => {
+                for packet_data in fragments {
+                    if !self.rate_limiter.lock().await.allow(&packet_data) {
+                        trace!("レート制限によりフラグメントが破棄されました");
+                        PacketAnalyzer::queue_alerts(packet_data.alerts);
+                        continue;
+                    }
+                    self.buffer.push(packet_data).await;
+                }
+                Ok(())
+            },
+            AnalyzeResult::Held => {
+                trace!("フラグメントを保持しています");
                 Ok(())
             },
             AnalyzeResult::Reject => {
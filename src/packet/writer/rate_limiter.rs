@@ -0,0 +1,376 @@
+use crate::config::{RateLimitAction, RateLimitConfig};
+use crate::idps_log;
+use crate::packet::analysis::LruIndex;
+use crate::packet::types::IpProtocol;
+use crate::packet::PacketData;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+// 同一バケットの破棄ログを出力する最短間隔
+const DROP_LOG_INTERVAL: Duration = Duration::from_secs(1);
+// 使われていないバケットを破棄するまでの時間と掃除間隔
+const IDLE_BUCKET_TIMEOUT: Duration = Duration::from_secs(60);
+const GC_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct FlowTuple {
+    src_ip: IpAddr,
+    dst_ip: IpAddr,
+    ip_protocol: IpProtocol,
+    src_port: u16,
+    dst_port: u16,
+}
+
+impl fmt::Display for FlowTuple {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{} -> {}:{} (protocol={})",
+            self.src_ip,
+            self.src_port,
+            self.dst_ip,
+            self.dst_port,
+            self.ip_protocol.value()
+        )
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+    // 制限超過後に破棄したパケット数
+    dropped: u64,
+    dropped_since_log: u64,
+    last_log: Option<Instant>,
+    // サンプリング時に何個目の超過パケットかを数える
+    excess: u64,
+    // LruIndexでの使用順
+    order: u64,
+}
+
+impl TokenBucket {
+    fn new(burst: f64, now: Instant) -> Self {
+        Self {
+            tokens: burst,
+            last_refill: now,
+            dropped: 0,
+            dropped_since_log: 0,
+            last_log: None,
+            excess: 0,
+            order: 0,
+        }
+    }
+
+    fn refill(&mut self, rate: f64, burst: f64, now: Instant) {
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(burst);
+        self.last_refill = now;
+    }
+
+    // トークンはhas_tokenでのみ補充される為、経過時間分を補充したとみなして判定する
+    fn is_full(&self, rate: f64, burst: f64, now: Instant) -> bool {
+        self.tokens + now.duration_since(self.last_refill).as_secs_f64() * rate >= burst
+    }
+
+    fn is_idle(&self, rate: f64, burst: f64, now: Instant) -> bool {
+        now.duration_since(self.last_refill) > IDLE_BUCKET_TIMEOUT && self.is_full(rate, burst, now)
+    }
+}
+
+// 共有のバケットで制限したキーの表示名
+const OVERFLOW_KEY: &str = "上限超過分";
+
+/// トークンバケットの集合 (キー毎に同じレートを適用する)
+/// バケット数が上限に達した場合は、最も長く使われていないバケットのトークンが満タンまで補充されていれば破棄して置き換える
+/// 補充されていない場合は制限を解除しないよう破棄せず、新しいキーは全て共有のバケット1つで制限する
+#[derive(Debug)]
+struct BucketSet<K> {
+    name: &'static str,
+    rate: f64,
+    burst: f64,
+    max_buckets: usize,
+    buckets: HashMap<K, TokenBucket>,
+    lru: LruIndex<K>,
+    overflow: TokenBucket,
+}
+
+impl<K: Eq + Hash + Copy + fmt::Display> BucketSet<K> {
+    fn new(name: &'static str, rate: Option<f64>, burst_seconds: f64, max_buckets: usize) -> Option<Self> {
+        let rate = rate.filter(|rate| *rate > 0.0)?;
+        let burst = (rate * burst_seconds).max(1.0);
+        Some(Self {
+            name,
+            rate,
+            burst,
+            max_buckets: max_buckets.max(1),
+            buckets: HashMap::new(),
+            lru: LruIndex::new(),
+            overflow: TokenBucket::new(burst, Instant::now()),
+        })
+    }
+
+    fn has_token(&mut self, key: K, now: Instant) -> bool {
+        let (rate, burst) = (self.rate, self.burst);
+        let previous = self.buckets.get(&key).map(|bucket| bucket.order);
+        if previous.is_none() {
+            if !self.make_room(now) {
+                self.overflow.refill(rate, burst, now);
+                return self.overflow.tokens >= 1.0;
+            }
+            self.buckets.insert(key, TokenBucket::new(burst, now));
+        }
+        let order = self.lru.touch(previous, key);
+        let Some(bucket) = self.buckets.get_mut(&key) else {
+            return true;
+        };
+        bucket.order = order;
+        bucket.refill(rate, burst, now);
+        bucket.tokens >= 1.0
+    }
+
+    // 新しいバケットを追加できる場合はtrueを返す
+    fn make_room(&mut self, now: Instant) -> bool {
+        if self.buckets.len() < self.max_buckets {
+            return true;
+        }
+        let Some(oldest) = self.lru.oldest(None) else {
+            return false;
+        };
+        if !self.buckets.get(&oldest).is_some_and(|bucket| bucket.is_full(self.rate, self.burst, now)) {
+            return false;
+        }
+        if let Some(evicted) = self.buckets.remove(&oldest) {
+            self.lru.remove(evicted.order);
+        }
+        true
+    }
+
+    // has_tokenでバケットを追加できなかったキーは共有のバケットを使用する
+    fn bucket_mut(&mut self, key: &K) -> &mut TokenBucket {
+        match self.buckets.get_mut(key) {
+            Some(bucket) => bucket,
+            None => &mut self.overflow,
+        }
+    }
+
+    fn consume(&mut self, key: K) {
+        self.bucket_mut(&key).tokens -= 1.0;
+    }
+
+    fn take_excess(&mut self, key: K) -> u64 {
+        let bucket = self.bucket_mut(&key);
+        bucket.excess += 1;
+        bucket.excess
+    }
+
+    fn record_drop(&mut self, key: K, now: Instant) {
+        let (name, rate) = (self.name, self.rate);
+        let target = if self.buckets.contains_key(&key) { key.to_string() } else { OVERFLOW_KEY.to_string() };
+        let bucket = self.bucket_mut(&key);
+        bucket.dropped += 1;
+        bucket.dropped_since_log += 1;
+
+        // 攻撃時にログが溢れないよう、バケット毎に一定間隔で集約して出力する
+        if bucket.last_log.map(|last| now.duration_since(last) >= DROP_LOG_INTERVAL).unwrap_or(true) {
+            idps_log!(
+                "レート制限によりパケットを破棄しました: 種別={}, 対象={}, 破棄数={} (累計: {}), 制限={}pps",
+                name,
+                target,
+                bucket.dropped_since_log,
+                bucket.dropped,
+                rate
+            );
+            bucket.dropped_since_log = 0;
+            bucket.last_log = Some(now);
+        }
+    }
+
+    fn collect_garbage(&mut self, now: Instant) {
+        let (rate, burst) = (self.rate, self.burst);
+        let lru = &mut self.lru;
+        self.buckets.retain(|_, bucket| {
+            let idle = bucket.is_idle(rate, burst, now);
+            if idle {
+                lru.remove(bucket.order);
+            }
+            !idle
+        });
+    }
+}
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    action: RateLimitAction,
+    per_source: Option<BucketSet<IpAddr>>,
+    per_flow: Option<BucketSet<FlowTuple>>,
+    per_node: Option<BucketSet<i16>>,
+    node_id: i16,
+    last_gc: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(node_id: i16, config: &RateLimitConfig) -> Self {
+        Self {
+            action: config.action,
+            per_source: BucketSet::new("送信元IP", config.per_source_pps, config.burst_seconds, config.max_buckets),
+            per_flow: BucketSet::new("5-tuple", config.per_flow_pps, config.burst_seconds, config.max_buckets),
+            per_node: BucketSet::new("ノード", config.per_node_pps, config.burst_seconds, 1),
+            node_id,
+            last_gc: Instant::now(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.per_source.is_some() || self.per_flow.is_some() || self.per_node.is_some()
+    }
+
+    /// パケットをバッファへ追加してよいか判定する
+    pub fn allow(&mut self, packet: &PacketData) -> bool {
+        if !self.is_enabled() {
+            return true;
+        }
+
+        let now = Instant::now();
+        if now.duration_since(self.last_gc) >= GC_INTERVAL {
+            self.collect_garbage(now);
+        }
+
+        let source = packet.src_ip.0;
+        let flow = FlowTuple {
+            src_ip: packet.src_ip.0,
+            dst_ip: packet.dst_ip.0,
+            ip_protocol: packet.ip_protocol,
+            src_port: packet.transport.src_port(),
+            dst_port: packet.transport.dst_port(),
+        };
+        let node_id = self.node_id;
+
+        // 全ての制限にトークンが残っている場合のみ消費する
+        let source_ok = self.per_source.as_mut().map(|set| set.has_token(source, now)).unwrap_or(true);
+        let flow_ok = self.per_flow.as_mut().map(|set| set.has_token(flow, now)).unwrap_or(true);
+        let node_ok = self.per_node.as_mut().map(|set| set.has_token(node_id, now)).unwrap_or(true);
+
+        if source_ok && flow_ok && node_ok {
+            if let Some(set) = self.per_source.as_mut() {
+                set.consume(source);
+            }
+            if let Some(set) = self.per_flow.as_mut() {
+                set.consume(flow);
+            }
+            if let Some(set) = self.per_node.as_mut() {
+                set.consume(node_id);
+            }
+            return true;
+        }
+
+        // 最も粒度の細かい超過した制限で破棄を記録する
+        if !flow_ok {
+            self.per_flow.as_mut().map(|set| Self::handle_excess(set, flow, self.action, now))
+        } else if !source_ok {
+            self.per_source.as_mut().map(|set| Self::handle_excess(set, source, self.action, now))
+        } else {
+            self.per_node.as_mut().map(|set| Self::handle_excess(set, node_id, self.action, now))
+        }
+        .unwrap_or(false)
+    }
+
+    // 超過したパケットを破棄するか、サンプリングして通過させるかを決める
+    fn handle_excess<K: Eq + Hash + Copy + fmt::Display>(set: &mut BucketSet<K>, key: K, action: RateLimitAction, now: Instant) -> bool {
+        if let RateLimitAction::Sample(rate) = action {
+            if rate > 0 && set.take_excess(key).is_multiple_of(rate) {
+                return true;
+            }
+        }
+
+        set.record_drop(key, now);
+        false
+    }
+
+    fn collect_garbage(&mut self, now: Instant) {
+        if let Some(set) = self.per_source.as_mut() {
+            set.collect_garbage(now);
+        }
+        if let Some(set) = self.per_flow.as_mut() {
+            set.collect_garbage(now);
+        }
+        if let Some(set) = self.per_node.as_mut() {
+            set.collect_garbage(now);
+        }
+        self.last_gc = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collects_bucket_that_consumed_token_after_idle_timeout() {
+        let mut set: BucketSet<i16> = BucketSet::new("テスト", Some(1.0), 5.0, 16).unwrap();
+        let now = Instant::now();
+        assert!(set.has_token(1, now));
+        set.consume(1);
+
+        set.collect_garbage(now + IDLE_BUCKET_TIMEOUT / 2);
+        assert!(set.buckets.contains_key(&1));
+
+        set.collect_garbage(now + IDLE_BUCKET_TIMEOUT + Duration::from_secs(1));
+        assert!(set.buckets.is_empty());
+    }
+
+    #[test]
+    fn keeps_bucket_that_is_still_refilling() {
+        // 補充に時間のかかる低いレートでは、タイムアウト後もトークンが溜まるまで保持する
+        let mut set: BucketSet<i16> = BucketSet::new("テスト", Some(0.01), 100.0, 16).unwrap();
+        let now = Instant::now();
+        assert!(set.has_token(1, now));
+        set.consume(1);
+
+        set.collect_garbage(now + IDLE_BUCKET_TIMEOUT + Duration::from_secs(1));
+        assert!(set.buckets.contains_key(&1));
+    }
+
+    #[test]
+    fn limits_sources_beyond_max_buckets_with_shared_bucket() {
+        let mut set: BucketSet<u32> = BucketSet::new("テスト", Some(1.0), 2.0, 4).unwrap();
+        let now = Instant::now();
+        for key in 0..4 {
+            assert!(set.has_token(key, now));
+            set.consume(key);
+        }
+
+        // 既存のバケットはトークンが満タンまで補充されていない為、破棄されず共有のバケットで制限される
+        for key in 4..100 {
+            let allowed = set.has_token(key, now);
+            assert_eq!(allowed, key < 6, "key={}", key);
+            if allowed {
+                set.consume(key);
+            } else {
+                set.record_drop(key, now);
+            }
+        }
+        assert_eq!(set.buckets.len(), 4);
+        assert_eq!(set.overflow.dropped, 94);
+        assert!((0..4).all(|key| set.buckets.contains_key(&key)));
+    }
+
+    #[test]
+    fn replaces_least_recently_used_bucket_once_refilled() {
+        let mut set: BucketSet<u32> = BucketSet::new("テスト", Some(1.0), 2.0, 2).unwrap();
+        let now = Instant::now();
+        for key in 0..2 {
+            assert!(set.has_token(key, now));
+            set.consume(key);
+        }
+
+        let later = now + Duration::from_secs(1);
+        assert!(set.has_token(1, later));
+        assert!(set.has_token(2, later));
+        assert!(set.buckets.contains_key(&2) && !set.buckets.contains_key(&0));
+        assert_eq!(set.buckets.len(), 2);
+    }
+}
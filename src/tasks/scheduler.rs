@@ -1,4 +1,6 @@
 use super::TaskState;
+use crate::packet::analysis::FirewallManager;
+use crate::packet::housekeeping::Housekeeper;
 use crate::packet::monitor::NetworkMonitor;
 use crate::packet::reader::PacketReader;
 use crate::packet::writer::PacketWriter;
@@ -14,12 +16,14 @@ use tokio::time::Duration;
 // タイムアウトを延長
 const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
 // 同時実行数の制限
-const MAX_CONCURRENT_TASKS: usize = 3;
+const MAX_CONCURRENT_TASKS: usize = 5;
 
 struct TaskHandles {
     reader: JoinHandle<Result<(), String>>,
     writer: JoinHandle<Result<(), String>>,
     analysis: JoinHandle<Result<(), String>>,
+    firewall: JoinHandle<Result<(), String>>,
+    housekeeping: JoinHandle<Result<(), String>>,
 }
 
 pub struct TaskScheduler {
@@ -46,7 +50,16 @@ impl TaskScheduler {
 
         let handles = self.spawn_all_tasks().await;
 
-        monitor.monitor_tasks(handles.reader, handles.writer, handles.analysis, self.shutdown_tx.subscribe()).await
+        monitor
+            .monitor_tasks(
+                handles.reader,
+                handles.writer,
+                handles.analysis,
+                handles.firewall,
+                handles.housekeeping,
+                self.shutdown_tx.subscribe(),
+            )
+            .await
     }
 
     async fn spawn_all_tasks(&self) -> TaskHandles {
@@ -54,6 +67,8 @@ impl TaskScheduler {
             reader: self.spawn_reader_task().await,
             writer: self.spawn_writer_task().await,
             analysis: self.spawn_analysis_task().await,
+            firewall: self.spawn_firewall_task().await,
+            housekeeping: self.spawn_housekeeping_task().await,
         }
     }
 
@@ -134,4 +149,54 @@ impl TaskScheduler {
             }
         })
     }
+
+    async fn spawn_firewall_task(&self) -> JoinHandle<Result<(), String>> {
+        let mut shutdown_rx = self.shutdown_tx.subscribe();
+        let semaphore = Arc::clone(&self.semaphore);
+
+        tokio::spawn(async move {
+            let _permit = match semaphore.acquire().await {
+                Ok(permit) => permit,
+                Err(_) => return Err("セマフォの取得に失敗しました".to_string()),
+            };
+
+            tokio::select! {
+                result = async {
+                    info!("ファイアウォール管理タスクを起動しました");
+                    FirewallManager::start().await
+                } => {
+                    result.map_err(|e| e.to_string())
+                }
+                _ = shutdown_rx.recv() => {
+                    info!("ファイアウォール管理タスクを停止させました");
+                    Ok(())
+                }
+            }
+        })
+    }
+
+    async fn spawn_housekeeping_task(&self) -> JoinHandle<Result<(), String>> {
+        let mut shutdown_rx = self.shutdown_tx.subscribe();
+        let semaphore = Arc::clone(&self.semaphore);
+
+        tokio::spawn(async move {
+            let _permit = match semaphore.acquire().await {
+                Ok(permit) => permit,
+                Err(_) => return Err("セマフォの取得に失敗しました".to_string()),
+            };
+
+            tokio::select! {
+                result = async {
+                    info!("ハウスキーピングタスクを起動しました");
+                    Housekeeper::start().await
+                } => {
+                    result.map_err(|e| e.to_string())
+                }
+                _ = shutdown_rx.recv() => {
+                    info!("ハウスキーピングタスクを停止させました");
+                    Ok(())
+                }
+            }
+        })
+    }
 }
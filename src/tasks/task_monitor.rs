@@ -23,12 +23,16 @@ impl TaskMonitor {
         reader: JoinHandle<Result<(), String>>,
         writer: JoinHandle<Result<(), String>>,
         analysis: JoinHandle<Result<(), String>>,
+        firewall: JoinHandle<Result<(), String>>,
+        housekeeping: JoinHandle<Result<(), String>>,
         mut shutdown_rx: broadcast::Receiver<()>,
     ) -> Result<(), TaskError> {
         // 初期状態の設定
         self.update_task_state("reader", true).await.map_err(|e| TaskError::TaskExecutionError(e.to_string()))?;
         self.update_task_state("writer", true).await.map_err(|e| TaskError::TaskExecutionError(e.to_string()))?;
         self.update_task_state("analysis", true).await.map_err(|e| TaskError::TaskExecutionError(e.to_string()))?;
+        self.update_task_state("firewall", true).await.map_err(|e| TaskError::TaskExecutionError(e.to_string()))?;
+        self.update_task_state("housekeeping", true).await.map_err(|e| TaskError::TaskExecutionError(e.to_string()))?;
 
         let result = loop {
             tokio::select! {
@@ -50,6 +54,18 @@ impl TaskMonitor {
                     }
                     break Err(TaskError::TaskExecutionError("Analysis task unexpectedly terminated".into()));
                 }
+                result = firewall => {
+                    if let Err(e) = self.handle_task_result(result, "firewall").await {
+                        break Err(TaskError::TaskExecutionError(e.to_string()));
+                    }
+                    break Err(TaskError::TaskExecutionError("Firewall task unexpectedly terminated".into()));
+                }
+                result = housekeeping => {
+                    if let Err(e) = self.handle_task_result(result, "housekeeping").await {
+                        break Err(TaskError::TaskExecutionError(e.to_string()));
+                    }
+                    break Err(TaskError::TaskExecutionError("Housekeeping task unexpectedly terminated".into()));
+                }
                 _ = shutdown_rx.recv() => {
                     info!("Received shutdown signal");
                     match self.wait_for_shutdown().await {
@@ -64,6 +80,8 @@ impl TaskMonitor {
         self.update_task_state("reader", false).await.map_err(|e| TaskError::TaskExecutionError(e.to_string()))?;
         self.update_task_state("writer", false).await.map_err(|e| TaskError::TaskExecutionError(e.to_string()))?;
         self.update_task_state("analysis", false).await.map_err(|e| TaskError::TaskExecutionError(e.to_string()))?;
+        self.update_task_state("firewall", false).await.map_err(|e| TaskError::TaskExecutionError(e.to_string()))?;
+        self.update_task_state("housekeeping", false).await.map_err(|e| TaskError::TaskExecutionError(e.to_string()))?;
 
         result
     }
@@ -109,6 +127,8 @@ impl TaskMonitor {
             "reader" => state.reader_active = active,
             "writer" => state.writer_active = active,
             "analysis" => state.analysis_active = active,
+            "firewall" => state.firewall_active = active,
+            "housekeeping" => state.housekeeping_active = active,
             _ => return Err(TaskError::StateUpdateError(format!("不明なタスク名が指定されました: {}", task_name))),
         }
         Ok(())
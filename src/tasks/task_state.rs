@@ -3,6 +3,8 @@ pub struct TaskState {
     pub reader_active: bool,
     pub writer_active: bool,
     pub analysis_active: bool,
+    pub firewall_active: bool,
+    pub housekeeping_active: bool,
 }
 
 impl TaskState {
@@ -11,10 +13,12 @@ impl TaskState {
             reader_active: false,
             writer_active: false,
             analysis_active: false,
+            firewall_active: false,
+            housekeeping_active: false,
         }
     }
 
     pub fn is_all_inactive(&self) -> bool {
-        !self.reader_active && !self.writer_active && !self.analysis_active
+        !self.reader_active && !self.writer_active && !self.analysis_active && !self.firewall_active && !self.housekeeping_active
     }
 }